@@ -1,5 +1,5 @@
 use std::{
-    fmt::{Display, Formatter},
+    fmt::{Debug, Display, Formatter},
     str::FromStr,
 };
 
@@ -10,7 +10,11 @@ use crate::{
 
 /// Represents the key used in KVx. Consists of a `scope` of type [`Scope`] and
 /// a `name` of type [`SegmentBuf`].
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+///
+/// A key is displayed as its segments joined by [`Scope::SEPARATOR`]. Since
+/// segments cannot contain the separator, see [`Segment::parse_escaped`],
+/// parsing the displayed string always returns the same key.
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Key {
     scope: Scope,
     name: SegmentBuf,
@@ -67,6 +71,79 @@ impl Key {
         &self.scope
     }
 
+    /// Returns an iterator over all segments of the key: the segments of its
+    /// scope followed by its name.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Key;
+    ///
+    /// let key: Key = "a/b/name".parse().unwrap();
+    /// let segments: Vec<&str> = key.segments().map(|s| s.as_str()).collect();
+    ///
+    /// assert_eq!(segments, ["a", "b", "name"]);
+    /// ```
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> + '_ {
+        self.scope
+            .segments()
+            .iter()
+            .map(|segment| segment.as_ref())
+            .chain(std::iter::once(self.name.as_ref()))
+    }
+
+    /// Returns the length in bytes of the string representation of the key,
+    /// without allocating it.
+    pub fn byte_len(&self) -> usize {
+        if self.scope.is_global() {
+            self.name.as_str().len()
+        } else {
+            self.scope.byte_len() + Scope::SEPARATOR.len_utf8() + self.name.as_str().len()
+        }
+    }
+
+    /// Create a new [`Key`] in the same scope, with another name.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::{Key, Segment};
+    ///
+    /// let key: Key = "a/b/name".parse().unwrap();
+    /// let other = key.with_name(Segment::parse("other").unwrap());
+    ///
+    /// assert_eq!(other, "a/b/other".parse().unwrap());
+    /// ```
+    pub fn with_name(&self, name: impl Into<SegmentBuf>) -> Self {
+        Key::new_scoped(self.scope.clone(), name)
+    }
+
+    /// Create a sibling of the key, i.e. a [`Key`] in the same scope with
+    /// another name. This is the same as [`Key::with_name`].
+    pub fn sibling(&self, name: impl Into<SegmentBuf>) -> Self {
+        self.with_name(name)
+    }
+
+    /// Returns the key that the scope of this key is named after, i.e. the
+    /// key named after the last [`Segment`] of the scope in its parent scope,
+    /// or `None` for a key in the global scope.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Key;
+    ///
+    /// let key: Key = "a/b/name".parse().unwrap();
+    ///
+    /// assert_eq!(key.parent_key(), Some("a/b".parse().unwrap()));
+    /// assert_eq!("name".parse::<Key>().unwrap().parent_key(), None);
+    /// ```
+    ///
+    /// [`Segment`]: ../kvx/struct.Segment.html
+    pub fn parent_key(&self) -> Option<Key> {
+        let name = self.scope.as_vec().last()?.clone();
+        let scope = self.scope.parent()?;
+
+        Some(Key::new_scoped(scope, name))
+    }
+
     /// Create a new [`Key`] and add a [`Segment`] to the end of its scope.
     pub fn with_sub_scope(&self, sub_scope: impl Into<SegmentBuf>) -> Self {
         let mut clone = self.clone();
@@ -90,6 +167,25 @@ impl Key {
     pub fn add_super_scope(&mut self, super_scope: impl Into<SegmentBuf>) {
         self.scope.add_super_scope(super_scope);
     }
+
+    /// Returns the key moved from scope `from` to scope `to`, keeping the
+    /// part of its scope below `from`, or `None` if the key is not in `from`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Key;
+    ///
+    /// let key: Key = "a/b/c/name".parse().unwrap();
+    /// let rebased = key.rebase(&"a/b".parse().unwrap(), &"x".parse().unwrap());
+    ///
+    /// assert_eq!(rebased, Some("x/c/name".parse().unwrap()));
+    /// ```
+    pub fn rebase(&self, from: &Scope, to: &Scope) -> Option<Key> {
+        let rest = self.scope.strip_prefix(from)?;
+        let scope = to.clone().into_iter().chain(rest).collect();
+
+        Some(Key::new_scoped(scope, self.name.clone()))
+    }
 }
 
 impl Display for Key {
@@ -102,6 +198,12 @@ impl Display for Key {
     }
 }
 
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Key").field(&self.to_string()).finish()
+    }
+}
+
 impl FromStr for Key {
     type Err = ParseSegmentError;
 
@@ -116,3 +218,199 @@ impl FromStr for Key {
         Ok(Key { name, scope })
     }
 }
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::key::Key;
+
+    impl Serialize for Key {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Key {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let value = String::deserialize(deserializer)?;
+            value.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use proptest::{
+        arbitrary::{any, Arbitrary},
+        strategy::{BoxedStrategy, Strategy},
+    };
+
+    use crate::{key::Key, scope::Scope, segment::SegmentBuf};
+
+    impl Arbitrary for Key {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (any::<Scope>(), any::<SegmentBuf>())
+                .prop_map(|(scope, name)| Key::new_scoped(scope, name))
+                .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::Key;
+    use crate::{scope::Scope, segment::Segment};
+
+    #[test]
+    fn test_debug_scoped() {
+        let key: Key = "a/b/name".parse().unwrap();
+        assert_eq!(format!("{key:?}"), r#"Key("a/b/name")"#);
+    }
+
+    #[test]
+    fn test_debug_global() {
+        let key: Key = "name".parse().unwrap();
+        assert_eq!(format!("{key:?}"), r#"Key("name")"#);
+    }
+
+    #[test]
+    fn test_rebase() {
+        let key: Key = "a/b/c/name".parse().unwrap();
+
+        assert_eq!(
+            key.rebase(&"a/b".parse().unwrap(), &"x".parse().unwrap()),
+            Some("x/c/name".parse().unwrap())
+        );
+        assert_eq!(
+            key.rebase(&"a/b/c".parse().unwrap(), &Scope::global()),
+            Some("name".parse().unwrap())
+        );
+        assert_eq!(
+            key.rebase(&Scope::global(), &"x".parse().unwrap()),
+            Some("x/a/b/c/name".parse().unwrap())
+        );
+        assert_eq!(
+            key.rebase(&"b".parse().unwrap(), &"x".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_with_name() {
+        let key: Key = "a/b/name".parse().unwrap();
+        let other = Segment::parse("other").unwrap();
+
+        assert_eq!(key.with_name(other), "a/b/other".parse().unwrap());
+        assert_eq!(key.sibling(other), "a/b/other".parse().unwrap());
+        assert_eq!(
+            Key::new_global(Segment::parse("name").unwrap()).with_name(other),
+            "other".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parent_key() {
+        let key: Key = "a/b/name".parse().unwrap();
+
+        assert_eq!(key.parent_key(), Some("a/b".parse().unwrap()));
+        assert_eq!(
+            key.parent_key().unwrap().parent_key(),
+            Some("a".parse().unwrap())
+        );
+        assert_eq!(
+            key.parent_key().unwrap().parent_key().unwrap().parent_key(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        let key: Key = "a/b/name".parse().unwrap();
+        assert_eq!(
+            key.segments().map(Segment::as_str).collect::<Vec<_>>(),
+            ["a", "b", "name"]
+        );
+
+        let key: Key = "name".parse().unwrap();
+        assert_eq!(
+            key.segments().map(Segment::as_str).collect::<Vec<_>>(),
+            ["name"]
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let escaped = Segment::parse_escaped("https://example.com/ ").unwrap();
+        let keys = [
+            "name".parse().unwrap(),
+            "a/name".parse().unwrap(),
+            "a/b/c/name".parse().unwrap(),
+            "scope/€uro/näme".parse().unwrap(),
+            "100%/a%2Fb".parse().unwrap(),
+            Key::new_global(escaped.clone()),
+            Key::new_scoped(Scope::from(vec![escaped.clone()]), escaped),
+        ];
+
+        for key in keys {
+            assert_eq!(Key::from_str(&key.to_string()).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_byte_len() {
+        for key in ["name", "a/name", "a/b/name", "scope/€uro/näme"] {
+            let key: Key = key.parse().unwrap();
+            assert_eq!(key.byte_len(), key.to_string().len());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proptest"))]
+mod proptests {
+    use std::str::FromStr;
+
+    use proptest::prelude::*;
+
+    use super::Key;
+
+    proptest! {
+        #[test]
+        fn test_display_round_trip(key in any::<Key>()) {
+            prop_assert_eq!(Key::from_str(&key.to_string()).unwrap(), key);
+        }
+
+        #[test]
+        fn test_byte_len(key in any::<Key>()) {
+            prop_assert_eq!(key.byte_len(), key.to_string().len());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::Key;
+
+    #[test]
+    fn test_serde_round_trip() {
+        for key in ["name", "a/name", "a/b/c/name", "scope/€uro/näme"] {
+            let key: Key = key.parse().unwrap();
+            let json = serde_json::to_string(&key).unwrap();
+
+            assert_eq!(json, format!("\"{}\"", key));
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        for json in ["\"\"", "\"a//name\"", "\" a/name\"", "1"] {
+            assert!(serde_json::from_str::<Key>(json).is_err(), "{}", json);
+        }
+    }
+}
@@ -48,6 +48,23 @@ impl FromStr for NamespaceBuf {
     }
 }
 
+impl TryFrom<&str> for NamespaceBuf {
+    type Error = ParseNamespaceError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl TryFrom<String> for NamespaceBuf {
+    type Error = ParseNamespaceError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Namespace::parse(&value)?;
+        Ok(NamespaceBuf(value))
+    }
+}
+
 impl From<&Namespace> for NamespaceBuf {
     fn from(value: &Namespace) -> Self {
         value.to_owned()
@@ -236,6 +253,51 @@ mod postgres_impls {
     }
 }
 
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::namespace::{Namespace, NamespaceBuf};
+
+    impl Serialize for Namespace {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl Serialize for NamespaceBuf {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for NamespaceBuf {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let value = String::deserialize(deserializer)?;
+            value.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use proptest::{
+        arbitrary::Arbitrary,
+        strategy::{BoxedStrategy, Strategy},
+    };
+
+    use crate::namespace::NamespaceBuf;
+
+    impl Arbitrary for NamespaceBuf {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            "[a-zA-Z0-9_-]{1,64}".prop_map(NamespaceBuf).boxed()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -245,4 +307,41 @@ mod tests {
     fn test_empty_namespace_fails() {
         assert_eq!(Namespace::parse(""), Err(ParseNamespaceError::Empty))
     }
+
+    #[test]
+    fn test_try_from() {
+        let namespace = Namespace::parse("name_space-1").unwrap();
+
+        assert_eq!(NamespaceBuf::try_from("name_space-1"), Ok(namespace.into()));
+        assert_eq!(
+            NamespaceBuf::try_from("name_space-1".to_string()),
+            Ok(namespace.into())
+        );
+        assert_eq!(
+            NamespaceBuf::try_from("name space"),
+            Err(ParseNamespaceError::IllegalCharacter)
+        );
+        assert_eq!(
+            NamespaceBuf::try_from(String::new()),
+            Err(ParseNamespaceError::Empty)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::{Namespace, NamespaceBuf};
+
+    #[test]
+    fn test_serde_round_trip() {
+        let namespace = Namespace::parse("name_space-1").unwrap().to_owned();
+        let json = serde_json::to_string(&namespace).unwrap();
+
+        assert_eq!(json, "\"name_space-1\"");
+        assert_eq!(
+            serde_json::from_str::<NamespaceBuf>(&json).unwrap(),
+            namespace
+        );
+        assert!(serde_json::from_str::<NamespaceBuf>("\"name space\"").is_err());
+    }
 }
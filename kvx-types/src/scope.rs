@@ -1,16 +1,16 @@
 use std::{
     cmp,
-    fmt::{Display, Formatter},
+    fmt::{Debug, Display, Formatter},
     str::FromStr,
 };
 
-use crate::segment::{ParseSegmentError, SegmentBuf};
+use crate::segment::{ParseSegmentError, Segment, SegmentBuf};
 
 /// Used to scope a [`Key`]. Consists of a vector of zero or more
 /// [`SegmentBuf`]s.
 ///
 /// [`Key`]: crate::Key
-#[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[cfg_attr(
     feature = "postgres",
     derive(postgres::types::ToSql, postgres::types::FromSql)
@@ -78,23 +78,74 @@ impl Scope {
     /// Returns the underlying vector of [`SegmentBuf`]s.
     ///
     /// [`SegmentBuf`]: ../kvx/struct.SegmentBuf.html
-    #[cfg(feature = "postgres")]
     pub fn as_vec(&self) -> &Vec<SegmentBuf> {
         &self.segments
     }
 
-    /// Returns the length of the underlying vector.
-    #[cfg(feature = "postgres")]
-    #[allow(clippy::len_without_is_empty)]
+    /// Returns the [`SegmentBuf`]s of the scope, outermost first.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Scope;
+    ///
+    /// let scope: Scope = "a/b".parse().unwrap();
+    /// let segments: Vec<&str> = scope.segments().iter().map(|s| s.as_str()).collect();
+    ///
+    /// assert_eq!(segments, vec!["a", "b"]);
+    /// ```
+    ///
+    /// [`SegmentBuf`]: ../kvx/struct.SegmentBuf.html
+    pub fn segments(&self) -> &[SegmentBuf] {
+        &self.segments
+    }
+
+    /// Returns the length of the underlying vector, as an `i32` so that it
+    /// can be used as a Postgres parameter. See [`Scope::depth`] for the
+    /// length as a `usize`.
     pub fn len(&self) -> i32 {
         self.segments.len() as i32
     }
 
+    /// Returns whether the underlying vector is empty, which is the case for
+    /// the global scope only.
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Returns the length in bytes of the string representation of the
+    /// scope, without allocating it.
+    pub fn byte_len(&self) -> usize {
+        let separators = self.segments.len().saturating_sub(1) * Self::SEPARATOR.len_utf8();
+        self.segments
+            .iter()
+            .map(|segment| segment.as_str().len())
+            .sum::<usize>()
+            + separators
+    }
+
     /// Returns whether the underlying vector is empty.
     pub fn is_global(&self) -> bool {
         self.segments.is_empty()
     }
 
+    /// Returns the number of [`Segment`]s in the scope, zero for the global
+    /// scope.
+    ///
+    /// [`Segment`]: ../kvx/struct.Segment.html
+    pub fn depth(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Returns the scope with its last [`Segment`] removed, or `None` for the
+    /// global scope.
+    ///
+    /// [`Segment`]: ../kvx/struct.Segment.html
+    pub fn parent(&self) -> Option<Scope> {
+        self.segments
+            .split_last()
+            .map(|(_, parent)| Scope::new(parent.to_vec()))
+    }
+
     /// Two scopes match if the longest of the two contains all [`Segment`]s
     /// of the other.
     ///
@@ -113,6 +164,56 @@ impl Scope {
         }
     }
 
+    /// Returns the remaining segments if the scope starts with the prefix, or
+    /// `None` if it does not.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Scope;
+    ///
+    /// let scope: Scope = "a/b/c".parse().unwrap();
+    ///
+    /// assert_eq!(scope.strip_prefix(&"a/b".parse().unwrap()), Some("c".parse().unwrap()));
+    /// assert_eq!(scope.strip_prefix(&scope), Some(Scope::global()));
+    /// assert_eq!(scope.strip_prefix(&"x".parse().unwrap()), None);
+    /// ```
+    pub fn strip_prefix(&self, prefix: &Scope) -> Option<Scope> {
+        self.segments
+            .strip_prefix(prefix.segments.as_slice())
+            .map(|rest| Scope::new(rest.to_vec()))
+    }
+
+    /// Returns the longest scope that all the given scopes start with, e.g.
+    /// to lock a single scope for a transaction that touches several scopes.
+    ///
+    /// Returns the global scope if there are no scopes.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Scope;
+    ///
+    /// let scopes: [Scope; 2] = ["a/b/c".parse().unwrap(), "a/b/d".parse().unwrap()];
+    ///
+    /// assert_eq!(Scope::common_prefix(&scopes), "a/b".parse().unwrap());
+    /// assert_eq!(Scope::common_prefix(&[]), Scope::global());
+    /// ```
+    pub fn common_prefix<'a>(scopes: impl IntoIterator<Item = &'a Scope>) -> Scope {
+        let mut scopes = scopes.into_iter();
+        let Some(first) = scopes.next() else {
+            return Scope::global();
+        };
+
+        let len = scopes.fold(first.segments.len(), |len, scope| {
+            first.segments[..len]
+                .iter()
+                .zip(&scope.segments)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+
+        Scope::new(first.segments[..len].to_vec())
+    }
+
     /// Returns a vector of all prefixes of the scope.
     pub fn sub_scopes(&self) -> Vec<Scope> {
         self.segments
@@ -124,6 +225,28 @@ impl Scope {
             .collect()
     }
 
+    /// Returns an iterator over the segments of the scope, each paired with
+    /// the prefix of the scope up to and including that segment.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kvx_types::Scope;
+    ///
+    /// let scope: Scope = "a/b".parse().unwrap();
+    /// let breadcrumbs: Vec<String> = scope
+    ///     .iter_with_prefixes()
+    ///     .map(|(prefix, segment)| format!("{segment} ({prefix})"))
+    ///     .collect();
+    ///
+    /// assert_eq!(breadcrumbs, ["a (a)", "b (a/b)"]);
+    /// ```
+    pub fn iter_with_prefixes(&self) -> impl Iterator<Item = (Scope, &Segment)> + '_ {
+        self.segments.iter().enumerate().map(|(i, segment)| {
+            let prefix = Scope::new(self.segments[..=i].to_vec());
+            (prefix, segment.as_ref())
+        })
+    }
+
     /// Create a new [`Scope`] and add a [`Segment`] to the end of it.
     ///
     /// [`Segment`]: ../kvx/struct.Segment.html
@@ -171,6 +294,12 @@ impl Display for Scope {
     }
 }
 
+impl Debug for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Scope").field(&self.to_string()).finish()
+    }
+}
+
 impl FromStr for Scope {
     type Err = ParseSegmentError;
 
@@ -221,6 +350,51 @@ impl From<Vec<SegmentBuf>> for Scope {
     }
 }
 
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::scope::Scope;
+
+    impl Serialize for Scope {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Scope {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let value = String::deserialize(deserializer)?;
+            // The global scope is displayed as an empty string, which does
+            // not parse as a scope.
+            if value.is_empty() {
+                return Ok(Scope::global());
+            }
+            value.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use proptest::{
+        arbitrary::{any, Arbitrary},
+        collection::vec,
+        strategy::{BoxedStrategy, Strategy},
+    };
+
+    use crate::{scope::Scope, segment::SegmentBuf};
+
+    impl Arbitrary for Scope {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            vec(any::<SegmentBuf>(), 0..5).prop_map(Scope::new).boxed()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Scope;
@@ -248,6 +422,105 @@ mod tests {
         assert!(!wrong.matches(&full));
     }
 
+    #[test]
+    fn test_segments_and_len() {
+        let scope: Scope = format!("a{sep}b", sep = Scope::SEPARATOR).parse().unwrap();
+        assert_eq!(scope.segments(), scope.as_vec().as_slice());
+        assert_eq!(scope.segments().len(), 2);
+        assert_eq!(scope.len(), 2);
+        assert!(!scope.is_empty());
+        assert!(Scope::global().is_empty());
+        assert_eq!(Scope::global().len(), 0);
+    }
+
+    #[test]
+    fn test_debug() {
+        let scope: Scope = format!("a{sep}b", sep = Scope::SEPARATOR).parse().unwrap();
+        assert_eq!(format!("{scope:?}"), r#"Scope("a/b")"#);
+        assert_eq!(format!("{:?}", Scope::global()), r#"Scope("")"#);
+    }
+
+    #[test]
+    fn test_byte_len() {
+        assert_eq!(Scope::global().byte_len(), 0);
+        for scope in ["a", "a/b", "this/is/a/beautiful/scope", "€uro/näme"] {
+            let scope: Scope = scope.parse().unwrap();
+            assert_eq!(scope.byte_len(), scope.to_string().len());
+        }
+    }
+
+    #[test]
+    fn test_parent_and_depth() {
+        let global = Scope::global();
+        assert_eq!(global.depth(), 0);
+        assert_eq!(global.parent(), None);
+
+        let single: Scope = "a".parse().unwrap();
+        assert_eq!(single.depth(), 1);
+        assert_eq!(single.parent(), Some(Scope::global()));
+
+        let scope: Scope = "a/b/c".parse().unwrap();
+        assert_eq!(scope.depth(), 3);
+        assert_eq!(scope.parent(), Some("a/b".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_iter_with_prefixes() {
+        let scope: Scope = "a/b/c".parse().unwrap();
+        let pairs: Vec<(Scope, String)> = scope
+            .iter_with_prefixes()
+            .map(|(prefix, segment)| (prefix, segment.to_string()))
+            .collect();
+
+        assert_eq!(
+            pairs,
+            vec![
+                ("a".parse().unwrap(), "a".to_string()),
+                ("a/b".parse().unwrap(), "b".to_string()),
+                ("a/b/c".parse().unwrap(), "c".to_string()),
+            ]
+        );
+        assert_eq!(Scope::global().iter_with_prefixes().count(), 0);
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        let scope: Scope = "a/b/c".parse().unwrap();
+
+        assert_eq!(scope.strip_prefix(&Scope::global()), Some(scope.clone()));
+        assert_eq!(
+            scope.strip_prefix(&"a".parse().unwrap()),
+            Some("b/c".parse().unwrap())
+        );
+        assert_eq!(scope.strip_prefix(&scope), Some(Scope::global()));
+        assert_eq!(scope.strip_prefix(&"a/c".parse().unwrap()), None);
+        assert_eq!(scope.strip_prefix(&"a/b/c/d".parse().unwrap()), None);
+        assert_eq!(
+            Scope::global().strip_prefix(&Scope::global()),
+            Some(Scope::global())
+        );
+    }
+
+    #[test]
+    fn test_common_prefix() {
+        let scopes: Vec<Scope> = ["a/b/c", "a/b", "a/b/d/e"]
+            .iter()
+            .map(|scope| scope.parse().unwrap())
+            .collect();
+
+        assert_eq!(Scope::common_prefix(&scopes), "a/b".parse().unwrap());
+        assert_eq!(Scope::common_prefix(&scopes[..1]), scopes[0]);
+        assert_eq!(
+            Scope::common_prefix(&["a/b".parse().unwrap(), "c/b".parse().unwrap()]),
+            Scope::global()
+        );
+        assert_eq!(
+            Scope::common_prefix(&[Scope::global(), "a".parse().unwrap()]),
+            Scope::global()
+        );
+        assert_eq!(Scope::common_prefix(&[]), Scope::global());
+    }
+
     #[test]
     fn test_starts_with() {
         let full: Scope = format!(
@@ -271,3 +544,23 @@ mod tests {
         assert!(!wrong.starts_with(&full));
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::Scope;
+
+    #[test]
+    fn test_serde_round_trip() {
+        for scope in [
+            Scope::global(),
+            "a".parse().unwrap(),
+            "a/b/c".parse().unwrap(),
+        ] {
+            let json = serde_json::to_string(&scope).unwrap();
+
+            assert_eq!(json, format!("\"{}\"", scope));
+            assert_eq!(serde_json::from_str::<Scope>(&json).unwrap(), scope);
+        }
+        assert!(serde_json::from_str::<Scope>("\"a//b\"").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
-    borrow::Borrow,
-    fmt::{Display, Formatter},
+    borrow::{Borrow, Cow},
+    fmt::{Display, Formatter, Write},
     ops::Deref,
     str::FromStr,
 };
@@ -97,6 +97,96 @@ impl Segment {
         }
     }
 
+    /// Create a Segment from any nonempty string, by percent-encoding what
+    /// is not allowed in a segment: the [`Scope::SEPARATOR`] as `%2F`, and
+    /// whitespace at the start or end, e.g. ` ` as `%20`. The `%` itself is
+    /// encoded as `%25`, so that [`Segment::to_unescaped`] returns the
+    /// original string.
+    ///
+    /// Backends store the escaped segment, e.g. the Disk backend uses it as
+    /// the file name.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use kvx_types::ParseSegmentError;
+    /// use kvx_types::Segment;
+    ///
+    /// # fn main() -> Result<(), ParseSegmentError> {
+    /// let segment = Segment::parse_escaped("https://example.com/")?;
+    /// assert_eq!(segment.as_str(), "https:%2F%2Fexample.com%2F");
+    /// assert_eq!(segment.to_unescaped(), "https://example.com/");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// If the string is empty [`ParseSegmentError::Empty`] is returned.
+    pub fn parse_escaped(value: &str) -> Result<SegmentBuf, ParseSegmentError> {
+        if value.is_empty() {
+            return Err(ParseSegmentError::Empty);
+        }
+
+        let last = value.len() - 1;
+        let mut escaped = String::with_capacity(value.len());
+        for (index, c) in value.char_indices() {
+            let at_edge = index == 0 || index == last;
+            if c == '%' || c == Scope::SEPARATOR || (at_edge && matches!(c, '\t' | '\n' | ' ')) {
+                // All of these are ASCII, so they fit in two hex digits.
+                let _ = write!(escaped, "%{:02X}", c as u32);
+            } else {
+                escaped.push(c);
+            }
+        }
+
+        Ok(SegmentBuf(escaped))
+    }
+
+    /// Returns the string that the segment was created from by
+    /// [`Segment::parse_escaped`], by decoding percent-encoded ASCII
+    /// characters. A `%` that is not followed by two hex digits is kept.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use kvx_types::ParseSegmentError;
+    /// use kvx_types::Segment;
+    ///
+    /// # fn main() -> Result<(), ParseSegmentError> {
+    /// assert_eq!(Segment::parse("a%2Fb")?.to_unescaped(), "a/b");
+    /// assert_eq!(Segment::parse("100%")?.to_unescaped(), "100%");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_unescaped(&self) -> Cow<'_, str> {
+        if !self.0.contains('%') {
+            return Cow::Borrowed(&self.0);
+        }
+
+        let mut unescaped = String::with_capacity(self.0.len());
+        let mut rest = &self.0;
+        while let Some(index) = rest.find('%') {
+            unescaped.push_str(&rest[..index]);
+
+            let decoded = rest
+                .get(index + 1..index + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .filter(u8::is_ascii);
+            match decoded {
+                Some(byte) => {
+                    unescaped.push(byte as char);
+                    rest = &rest[index + 3..];
+                }
+                None => {
+                    unescaped.push('%');
+                    rest = &rest[index + 1..];
+                }
+            }
+        }
+        unescaped.push_str(rest);
+
+        Cow::Owned(unescaped)
+    }
+
     /// Return the encapsulated string.
     ///
     /// # Examples
@@ -115,6 +205,56 @@ impl Segment {
         &self.0
     }
 
+    /// Returns the segment for display, shortened to at most `max_chars`
+    /// characters. Shortened segments end with an ellipsis (`…`).
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use kvx_types::ParseSegmentError;
+    /// use kvx_types::Segment;
+    ///
+    /// # fn main() -> Result<(), ParseSegmentError> {
+    /// let segment = Segment::parse("a-long-segment")?;
+    /// assert_eq!(segment.truncate_display(7), "a-long…");
+    /// assert_eq!(segment.truncate_display(20), "a-long-segment");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn truncate_display(&self, max_chars: usize) -> String {
+        match self.0.char_indices().nth(max_chars) {
+            None => self.0.to_string(),
+            Some(_) if max_chars == 0 => String::new(),
+            Some(_) => {
+                let end = self
+                    .0
+                    .char_indices()
+                    .nth(max_chars - 1)
+                    .map(|(i, _)| i)
+                    .unwrap_or_default();
+                format!("{}…", &self.0[..end])
+            }
+        }
+    }
+
+    /// Returns whether the segments are equal, ignoring the case of ASCII
+    /// letters.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use kvx_types::ParseSegmentError;
+    /// use kvx_types::Segment;
+    ///
+    /// # fn main() -> Result<(), ParseSegmentError> {
+    /// let segment = Segment::parse("FOO")?;
+    /// assert!(segment.eq_ignore_ascii_case(Segment::parse("foo")?));
+    /// assert_ne!(segment, Segment::parse("foo")?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn eq_ignore_ascii_case(&self, other: &Segment) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+
     /// Creates a Segment from a string without performing any checks.
     ///
     /// # Safety
@@ -248,10 +388,122 @@ mod postgres_impls {
     }
 }
 
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::segment::{Segment, SegmentBuf};
+
+    impl Serialize for Segment {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl Serialize for SegmentBuf {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(self.as_str())
+        }
+    }
+
+    impl<'de> Deserialize<'de> for SegmentBuf {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let value = String::deserialize(deserializer)?;
+            value.parse().map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod proptest_impls {
+    use proptest::{
+        arbitrary::Arbitrary,
+        strategy::{BoxedStrategy, Strategy},
+    };
+
+    use crate::segment::SegmentBuf;
+
+    impl Arbitrary for SegmentBuf {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        /// Generates nonempty segments that do not start or end with
+        /// whitespace and do not contain a separator.
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            "[^/\t\n ]([^/]{0,16}[^/\t\n ])?"
+                .prop_map(SegmentBuf)
+                .boxed()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Scope, Segment};
 
+    #[test]
+    fn test_truncate_display() {
+        let segment = Segment::parse("näïve-€uro-🦀").unwrap();
+
+        assert_eq!(segment.truncate_display(12), "näïve-€uro-🦀");
+        assert_eq!(segment.truncate_display(100), "näïve-€uro-🦀");
+        assert_eq!(segment.truncate_display(11), "näïve-€uro…");
+        assert_eq!(segment.truncate_display(8), "näïve-€…");
+        assert_eq!(segment.truncate_display(3), "nä…");
+        assert_eq!(segment.truncate_display(1), "…");
+        assert_eq!(segment.truncate_display(0), "");
+
+        for max_chars in 0..20 {
+            assert!(segment.truncate_display(max_chars).chars().count() <= max_chars);
+        }
+    }
+
+    #[test]
+    fn test_eq_ignore_ascii_case() {
+        let segment = Segment::parse("Foo-BAR").unwrap();
+
+        assert!(segment.eq_ignore_ascii_case(Segment::parse("foo-bar").unwrap()));
+        assert!(segment.eq_ignore_ascii_case(Segment::parse("FOO-BAR").unwrap()));
+        assert!(!segment.eq_ignore_ascii_case(Segment::parse("foo-baz").unwrap()));
+        assert!(!Segment::parse("É")
+            .unwrap()
+            .eq_ignore_ascii_case(Segment::parse("é").unwrap()));
+    }
+
+    #[test]
+    fn test_escaped_round_trip() {
+        for value in [
+            "plain",
+            "a/b",
+            "/",
+            "https://example.com/path?q=1",
+            " leading and trailing\t",
+            "\n",
+            "100%",
+            "%2F",
+            "näïve/€uro",
+        ] {
+            let segment = Segment::parse_escaped(value).unwrap();
+            assert!(Segment::parse(segment.as_str()).is_ok(), "{value:?}");
+            assert_eq!(segment.to_unescaped(), value);
+        }
+
+        assert_eq!(Segment::parse_escaped("plain").unwrap().as_str(), "plain");
+        assert_eq!(Segment::parse_escaped("a/%").unwrap().as_str(), "a%2F%25");
+        assert_eq!(
+            Segment::parse_escaped(" a b ").unwrap().as_str(),
+            "%20a b%20"
+        );
+        assert!(Segment::parse_escaped("").is_err());
+    }
+
+    #[test]
+    fn test_unescape_invalid() {
+        for value in ["%", "%2", "%zz", "%+1", "%FF", "a%€"] {
+            assert_eq!(Segment::parse(value).unwrap().to_unescaped(), value);
+        }
+    }
+
     #[test]
     fn test_trailing_separator_fails() {
         assert!(Segment::parse(&format!("test{}", Scope::SEPARATOR)).is_err());
@@ -317,3 +569,18 @@ mod tests {
         assert!(Segment::parse("test").is_ok())
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use super::{Segment, SegmentBuf};
+
+    #[test]
+    fn test_serde_round_trip() {
+        let segment = Segment::parse("segment").unwrap().to_owned();
+        let json = serde_json::to_string(&segment).unwrap();
+
+        assert_eq!(json, "\"segment\"");
+        assert_eq!(serde_json::from_str::<SegmentBuf>(&json).unwrap(), segment);
+        assert!(serde_json::from_str::<SegmentBuf>("\"a/b\"").is_err());
+    }
+}
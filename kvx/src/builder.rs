@@ -0,0 +1,384 @@
+//! Building a store with backend-specific options.
+
+use std::time::Duration;
+
+use url::Url;
+
+use crate::{
+    implementations::{
+        disk::{Compression, Disk, Durability},
+        memory::{LockGranularity, Memory},
+    },
+    local_path, tracks_changes, Error, KeyValueStore, NamespaceBuf, PubKeyValueStoreBackend,
+    Result, SCHEMES,
+};
+
+/// Builds a [`KeyValueStore`] for a URL, with typed options for the backend.
+///
+/// Options set using the builder take precedence over the corresponding
+/// query parameters of the URL, and options for other backends than the
+/// one for the scheme of the URL are ignored. [`KeyValueStore::new`] is the
+/// same as building without setting any options.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use kvx::{Durability, Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+/// use serde_json::Value;
+/// use url::Url;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let dir = tempfile::tempdir()?;
+/// let url = Url::parse(&format!("local://{}", dir.path().display()))?;
+/// let store = KeyValueStore::builder(&url, Namespace::parse("builder")?)
+///     .disk_durability(Durability::Fsync)
+///     .lock_timeout(Duration::from_secs(5))
+///     .build()?;
+///
+/// let key: Key = "key".parse()?;
+/// store.store(&key, Value::from(1))?;
+/// assert_eq!(store.get(&key)?, Some(Value::from(1)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyValueStoreBuilder {
+    storage_uri: Url,
+    namespace: NamespaceBuf,
+    lock_timeout: Option<Duration>,
+    track_changes: Option<bool>,
+    memory_isolated: bool,
+    disk_durability: Option<Durability>,
+    disk_compression: Option<Compression>,
+    disk_tmp_max_age: Option<Option<Duration>>,
+    #[cfg(feature = "postgres")]
+    postgres: crate::implementations::postgres::OptionOverrides,
+}
+
+impl KeyValueStore {
+    /// Returns a builder for a store for the URL and namespace.
+    pub fn builder(storage_uri: &Url, namespace: impl Into<NamespaceBuf>) -> KeyValueStoreBuilder {
+        KeyValueStoreBuilder {
+            storage_uri: storage_uri.clone(),
+            namespace: namespace.into(),
+            lock_timeout: None,
+            track_changes: None,
+            memory_isolated: false,
+            disk_durability: None,
+            disk_compression: None,
+            disk_tmp_max_age: None,
+            #[cfg(feature = "postgres")]
+            postgres: Default::default(),
+        }
+    }
+}
+
+impl KeyValueStoreBuilder {
+    /// Sets how long a transaction on an in-memory or `local://` store waits
+    /// for the locks on its scope before it fails with [`Error::LockTimeout`].
+    ///
+    /// By default in-memory stores wait 10 seconds and `local://` stores
+    /// wait for as long as it takes.
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    /// Sets whether the store tracks changes for
+    /// [`ReadStore::changes_since`](crate::ReadStore::changes_since), like
+    /// the `track_changes` parameter. By default changes are not tracked.
+    pub fn track_changes(mut self, track_changes: bool) -> Self {
+        self.track_changes = Some(track_changes);
+        self
+    }
+
+    /// Sets whether an in-memory store uses an instance of its own, see
+    /// [`KeyValueStore::new_isolated`]. The host of the URL is ignored then.
+    pub fn memory_isolated(mut self, isolated: bool) -> Self {
+        self.memory_isolated = isolated;
+        self
+    }
+
+    /// Sets how values stored in a `local://` store are synced to disk, like
+    /// the `durability` parameter.
+    pub fn disk_durability(mut self, durability: Durability) -> Self {
+        self.disk_durability = Some(durability);
+        self
+    }
+
+    /// Sets how values stored in a `local://` store are compressed, like the
+    /// `compression` parameter.
+    pub fn disk_compression(mut self, compression: Compression) -> Self {
+        self.disk_compression = Some(compression);
+        self
+    }
+
+    /// Sets the age of the temporary files of a `local://` store that are
+    /// removed when it is built, or disables removing them if `None`. By
+    /// default this is [`TMP_FILE_MAX_AGE`](crate::TMP_FILE_MAX_AGE), see
+    /// [`KeyValueStore::sweep_tmp`].
+    pub fn disk_tmp_max_age(mut self, tmp_max_age: Option<Duration>) -> Self {
+        self.disk_tmp_max_age = Some(tmp_max_age);
+        self
+    }
+
+    /// Sets whether Postgres transactions are retried after a conflict, like
+    /// the `transaction_retry` parameter.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_transaction_retry(mut self, retry: bool) -> Self {
+        self.postgres.transaction_retry = Some(retry);
+        self
+    }
+
+    /// Sets the (possibly schema-qualified) name of the Postgres table, like
+    /// the `table` parameter.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_table(mut self, table: &str) -> Self {
+        self.postgres.table = Some(table.to_string());
+        self
+    }
+
+    /// Sets the maximum number of Postgres connections, like the `pool_max`
+    /// parameter.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_pool_max(mut self, max: u32) -> Self {
+        self.postgres.pool_max_size = Some(max);
+        self
+    }
+
+    /// Sets the minimum number of idle Postgres connections, like the
+    /// `pool_min_idle` parameter.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_pool_min_idle(mut self, min_idle: u32) -> Self {
+        self.postgres.pool_min_idle = Some(min_idle);
+        self
+    }
+
+    /// Sets how long to wait for a free Postgres connection, like the
+    /// `pool_timeout` parameter.
+    #[cfg(feature = "postgres")]
+    pub fn postgres_pool_timeout(mut self, timeout: Duration) -> Self {
+        self.postgres.pool_timeout = Some(timeout);
+        self
+    }
+
+    /// Creates the store.
+    ///
+    /// If a factory was registered for the scheme of the URL using
+    /// [`KeyValueStore::register_scheme`], it is used instead of the built-in
+    /// backend and the options of the builder are ignored.
+    pub fn build(self) -> Result<KeyValueStore> {
+        let storage_uri = &self.storage_uri;
+        let namespace = self.namespace;
+
+        let factory = SCHEMES
+            .read()
+            .map_err(|e| Error::MutexLock(e.to_string()))?
+            .get(storage_uri.scheme())
+            .cloned();
+        if let Some(factory) = factory {
+            return Ok(KeyValueStore::from_inner(factory(storage_uri, namespace)?));
+        }
+
+        let track_changes = match self.track_changes {
+            Some(track_changes) => track_changes,
+            None => tracks_changes(storage_uri)?,
+        };
+
+        let inner: Box<dyn PubKeyValueStoreBackend> = match storage_uri.scheme() {
+            "local" => {
+                let durability = match self.disk_durability {
+                    Some(durability) => durability,
+                    None => Durability::from_url(storage_uri)?,
+                };
+                let compression = match self.disk_compression {
+                    Some(compression) => compression,
+                    None => Compression::from_url(storage_uri)?,
+                };
+                let path = local_path(storage_uri);
+                let disk = match self.disk_tmp_max_age {
+                    Some(tmp_max_age) => {
+                        Disk::new_with_tmp_max_age(&path, namespace.as_str(), tmp_max_age)?
+                    }
+                    None => Disk::new(&path, namespace.as_str())?,
+                };
+                let mut disk = disk
+                    .with_durability(durability)
+                    .with_compression(compression)
+                    .with_change_tracking(track_changes);
+                if let Some(lock_timeout) = self.lock_timeout {
+                    disk = disk.with_lock_timeout(lock_timeout);
+                }
+                Box::new(disk)
+            }
+            "memory" => {
+                let mut memory = if self.memory_isolated {
+                    Memory::new_isolated(namespace)
+                        .with_lock_granularity(LockGranularity::from_url(storage_uri)?)
+                } else {
+                    Memory::from_url(storage_uri, namespace)?
+                }
+                .with_change_tracking(track_changes)?;
+                if let Some(lock_timeout) = self.lock_timeout {
+                    memory = memory.with_lock_timeout(lock_timeout);
+                }
+                Box::new(memory)
+            }
+            #[cfg(feature = "postgres")]
+            "postgres" => {
+                let mut postgres = self.postgres;
+                postgres.track_changes = Some(track_changes);
+                crate::implementations::postgres::connect(storage_uri, namespace, &postgres)?
+            }
+            #[cfg(feature = "redis")]
+            "redis" => Box::new(
+                crate::implementations::redis::Redis::new(storage_uri, namespace)?
+                    .with_change_tracking(track_changes),
+            ),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Box::new(
+                crate::implementations::sqlite::Sqlite::new(local_path(storage_uri), namespace)?
+                    .with_change_tracking(track_changes)?,
+            ),
+            scheme => Err(Error::UnknownScheme(scheme.to_owned()))?,
+        };
+
+        Ok(KeyValueStore::from_inner(inner))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use url::Url;
+
+    use crate::{
+        BackendKind, Error, Key, KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore,
+        WriteStore,
+    };
+
+    #[test]
+    fn test_memory_isolated() {
+        let url = Url::parse("memory://").unwrap();
+        let namespace = Namespace::parse("test_builder_isolated").unwrap();
+        let key: Key = "key".parse().unwrap();
+
+        let isolated = KeyValueStore::builder(&url, namespace)
+            .memory_isolated(true)
+            .build()
+            .unwrap();
+        isolated.store(&key, Value::from(1)).unwrap();
+
+        let shared = KeyValueStore::new(&url, namespace).unwrap();
+        assert!(!shared.has(&key).unwrap());
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        for url in [
+            Url::parse("memory://test_builder_lock_timeout").unwrap(),
+            Url::parse(&format!("local://{}", dir.path().display())).unwrap(),
+        ] {
+            let store = KeyValueStore::builder(&url, Namespace::parse("lock_timeout").unwrap())
+                .lock_timeout(Duration::from_millis(50))
+                .build()
+                .unwrap();
+            let key: Key = "scope/key".parse().unwrap();
+
+            let result = store.transaction(key.scope(), &mut |_| {
+                std::thread::scope(|s| {
+                    s.spawn(|| store.transaction(key.scope(), &mut |_| Ok(())))
+                        .join()
+                        .unwrap()
+                })
+            });
+            assert!(matches!(result, Err(Error::LockTimeout(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_namespace_from_str() {
+        let url = Url::parse("memory://test_builder_namespace").unwrap();
+
+        assert!(KeyValueStore::new(&url, "valid_namespace").is_ok());
+        assert!(KeyValueStore::new(&url, "valid_namespace".to_string()).is_ok());
+        assert!(matches!(
+            KeyValueStore::new(&url, "invalid namespace"),
+            Err(Error::Namespace(_))
+        ));
+    }
+
+    #[test]
+    fn test_backend_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        let namespace = Namespace::parse("test_builder_kind").unwrap();
+        let memory = KeyValueStore::new(&Url::parse("memory://").unwrap(), namespace).unwrap();
+        let disk = KeyValueStore::new(
+            &Url::parse(&format!("local://{}", dir.path().display())).unwrap(),
+            namespace,
+        )
+        .unwrap();
+
+        assert_eq!(memory.backend_kind(), BackendKind::Memory);
+        assert_eq!(disk.backend_kind(), BackendKind::Disk);
+        assert_eq!(
+            memory.with_retry(Default::default()).backend_kind(),
+            BackendKind::Memory
+        );
+    }
+
+    #[test]
+    fn test_options_override_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse(&format!(
+            "local://{}?durability=bogus",
+            dir.path().display()
+        ))
+        .unwrap();
+        let namespace = Namespace::parse("test_builder_override").unwrap();
+
+        assert!(KeyValueStore::new(&url, namespace).is_err());
+        assert!(KeyValueStore::builder(&url, namespace)
+            .disk_durability(crate::Durability::Fsync)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_disk_tmp_max_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::parse(&format!("local://{}", dir.path().display())).unwrap();
+        let namespace = Namespace::parse("test_builder_tmp").unwrap();
+
+        let orphaned = dir.path().join("tmp").join(".tmporphan");
+        std::fs::create_dir_all(orphaned.parent().unwrap()).unwrap();
+        std::fs::write(&orphaned, "{}").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&orphaned)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(1))
+            .unwrap();
+
+        KeyValueStore::builder(&url, namespace)
+            .disk_tmp_max_age(None)
+            .build()
+            .unwrap();
+        assert!(orphaned.exists());
+
+        // The file is a second old, so it is only removed without a margin.
+        assert_eq!(
+            KeyValueStore::sweep_tmp(&url, Duration::from_secs(60)).unwrap(),
+            0
+        );
+        KeyValueStore::builder(&url, namespace)
+            .disk_tmp_max_age(Some(Duration::ZERO))
+            .build()
+            .unwrap();
+        assert!(!orphaned.exists());
+    }
+}
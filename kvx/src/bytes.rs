@@ -0,0 +1,52 @@
+//! Storing opaque bytes as values.
+//!
+//! Backends that do not store bytes natively store them as a JSON object
+//! with a single [`BYTES_FIELD`] holding the bytes encoded as base64.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{Map, Value};
+
+/// The field of the JSON object holding bytes encoded as base64.
+pub(crate) const BYTES_FIELD: &str = "kvx_bytes";
+
+/// Returns the JSON value used to store the bytes.
+pub(crate) fn to_value(bytes: &[u8]) -> Value {
+    Value::Object(Map::from_iter([(
+        BYTES_FIELD.to_string(),
+        Value::String(STANDARD.encode(bytes)),
+    )]))
+}
+
+/// Returns the bytes in a value created by [`to_value`], or `None` if the
+/// value does not hold bytes.
+pub(crate) fn from_value(value: &Value) -> Option<Vec<u8>> {
+    match value.as_object()?.get(BYTES_FIELD)? {
+        Value::String(encoded) if value.as_object()?.len() == 1 => STANDARD.decode(encoded).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{from_value, to_value};
+
+    #[test]
+    fn test_round_trip() {
+        for bytes in [&b""[..], b"\0\xff", b"not json {"] {
+            assert_eq!(from_value(&to_value(bytes)), Some(bytes.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_not_bytes() {
+        assert_eq!(from_value(&json!("AAE=")), None);
+        assert_eq!(from_value(&json!({ "kvx_bytes": 1 })), None);
+        assert_eq!(from_value(&json!({ "kvx_bytes": "not base64!" })), None);
+        assert_eq!(
+            from_value(&json!({ "kvx_bytes": "AAE=", "other": 1 })),
+            None
+        );
+    }
+}
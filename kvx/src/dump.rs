@@ -0,0 +1,243 @@
+//! Exporting and importing the keys of a namespace as newline-delimited
+//! JSON.
+//!
+//! Every line holds one record: an object with the `scope` and `name` of a
+//! key, as displayed, and its `value`. The format does not depend on the
+//! backend, so a dump can be used to move a namespace to another backend.
+//! To copy the keys between two stores directly, use [`migrate`].
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::{Error, Key, KeyValueStore, ReadStore, Result, Scope, SegmentBuf, WriteStore};
+
+/// The number of entries that are imported or migrated in a single call to
+/// [`WriteStore::store_many`].
+const BATCH_SIZE: usize = 1000;
+
+/// Copies all keys in the namespace of `from` with their values to the
+/// namespace of `to`, which may use another backend. Returns the number of
+/// entries copied.
+///
+/// Keys in `to` are overwritten if they also exist in `from`, other keys
+/// are kept. Entries are stored in batches, so if the migration fails the
+/// entries of earlier batches have been copied.
+///
+/// # Example
+/// ```
+/// use kvx::{Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+/// use serde_json::Value;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let from = KeyValueStore::new_isolated(Namespace::parse("from")?);
+/// let to = KeyValueStore::new_isolated(Namespace::parse("to")?);
+///
+/// let key: Key = "scope/name".parse()?;
+/// from.store(&key, Value::from(1))?;
+///
+/// assert_eq!(kvx::migrate(&from, &to)?, 1);
+/// assert_eq!(to.get(&key)?, Some(Value::from(1)));
+/// # Ok(())
+/// # }
+/// ```
+pub fn migrate(from: &KeyValueStore, to: &KeyValueStore) -> Result<u64> {
+    copy_entries(from, to, false)
+}
+
+/// Copies the keys in the namespace of `from` that do not exist in the
+/// namespace of `to` with their values, see [`migrate`]. Returns the number
+/// of entries copied.
+///
+/// This can be used to resume a migration that failed, or to migrate to a
+/// store that already has newer values for some keys.
+pub fn migrate_missing(from: &KeyValueStore, to: &KeyValueStore) -> Result<u64> {
+    copy_entries(from, to, true)
+}
+
+fn copy_entries(from: &KeyValueStore, to: &KeyValueStore, skip_existing: bool) -> Result<u64> {
+    let mut copied = 0;
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    for key in from.stream_keys(&Scope::global())? {
+        let key = key?;
+        if let Some(value) = from.get(&key)? {
+            batch.push((key, value));
+        }
+
+        if batch.len() == BATCH_SIZE {
+            copied += store_batch(to, &mut batch, skip_existing)?;
+        }
+    }
+
+    copied += store_batch(to, &mut batch, skip_existing)?;
+    Ok(copied)
+}
+
+/// Stores and clears the batch, skipping the keys that exist in `to` if
+/// `skip_existing` is set. Returns the number of entries stored.
+fn store_batch(
+    to: &KeyValueStore,
+    batch: &mut Vec<(Key, Value)>,
+    skip_existing: bool,
+) -> Result<u64> {
+    if skip_existing && !batch.is_empty() {
+        let keys: Vec<Key> = batch.iter().map(|(key, _)| key.clone()).collect();
+        let mut existing = to.has_each(&keys)?.into_iter();
+        batch.retain(|_| !existing.next().unwrap_or(false));
+    }
+
+    if !batch.is_empty() {
+        to.store_many(batch)?;
+    }
+
+    let stored = batch.len() as u64;
+    batch.clear();
+    Ok(stored)
+}
+
+impl KeyValueStore {
+    /// Writes all keys in the namespace of this store with their values to
+    /// `writer`, one JSON record per line, see [`KeyValueStore::import`].
+    ///
+    /// Keys are read lazily, so keys that are stored or deleted during the
+    /// export may or may not be included.
+    pub fn export(&self, mut writer: impl Write) -> Result<()> {
+        for key in self.stream_keys(&Scope::global())? {
+            let key = key?;
+            if let Some(value) = self.get(&key)? {
+                serde_json::to_writer(&mut writer, &to_record(&key, value))?;
+                writer.write_all(b"\n")?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Stores the records written by [`KeyValueStore::export`] in the
+    /// namespace of this store. Empty lines are skipped.
+    ///
+    /// Keys that are not in the dump are kept. Records are stored in
+    /// batches, so if the import fails the records of earlier batches have
+    /// been stored.
+    pub fn import(&self, reader: impl Read) -> Result<()> {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+        for (index, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record = from_record(serde_json::from_str(&line)?)
+                .ok_or_else(|| Error::Other(format!("invalid record on line {}", index + 1)))?;
+            batch.push(record);
+
+            if batch.len() == BATCH_SIZE {
+                self.store_many(&batch)?;
+                batch.clear();
+            }
+        }
+
+        if !batch.is_empty() {
+            self.store_many(&batch)?;
+        }
+        Ok(())
+    }
+}
+
+fn to_record(key: &Key, value: Value) -> Value {
+    json!({
+        "scope": key.scope().to_string(),
+        "name": key.name().as_str(),
+        "value": value,
+    })
+}
+
+/// Returns the key and value of a record, or `None` if it is invalid.
+fn from_record(record: Value) -> Option<(Key, Value)> {
+    let Value::Object(mut record) = record else {
+        return None;
+    };
+
+    let scope = match record.get("scope")?.as_str()? {
+        "" => Scope::global(),
+        scope => scope.parse().ok()?,
+    };
+    let name: SegmentBuf = record.get("name")?.as_str()?.parse().ok()?;
+    let value = record.remove("value")?;
+
+    Some((Key::new_scoped(scope, name), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use url::Url;
+
+    use crate::{Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+
+    #[test]
+    fn test_export_import() {
+        let source = KeyValueStore::new_isolated(Namespace::parse("test_export").unwrap());
+        let entries: Vec<(Key, Value)> = vec![
+            ("name".parse().unwrap(), Value::from(1)),
+            ("a/name".parse().unwrap(), json!({ "b": [true, null] })),
+            ("a/b/c/other".parse().unwrap(), Value::from("value")),
+        ];
+        source.store_many(&entries).unwrap();
+
+        let mut dump = vec![];
+        source.export(&mut dump).unwrap();
+        assert_eq!(dump.iter().filter(|b| **b == b'\n').count(), entries.len());
+
+        let target = KeyValueStore::new(
+            &Url::parse("local://data").unwrap(),
+            Namespace::parse("test_export_import").unwrap(),
+        )
+        .unwrap();
+        target.clear().unwrap();
+        target.import(dump.as_slice()).unwrap();
+
+        assert_eq!(target.snapshot().unwrap(), source.snapshot().unwrap());
+        target.clear().unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        let from = KeyValueStore::new_isolated(Namespace::parse("test_migrate").unwrap());
+        let to = KeyValueStore::new(
+            &Url::parse("local://data").unwrap(),
+            Namespace::parse("test_migrate").unwrap(),
+        )
+        .unwrap();
+        to.clear().unwrap();
+
+        let existing: Key = "a/existing".parse().unwrap();
+        let new: Key = "a/b/new".parse().unwrap();
+        from.store(&existing, Value::from(1)).unwrap();
+        from.store(&new, Value::from(2)).unwrap();
+        to.store(&existing, Value::from(0)).unwrap();
+
+        assert_eq!(super::migrate_missing(&from, &to).unwrap(), 1);
+        assert_eq!(to.get(&existing).unwrap(), Some(Value::from(0)));
+        assert_eq!(to.get(&new).unwrap(), Some(Value::from(2)));
+        assert_eq!(super::migrate_missing(&from, &to).unwrap(), 0);
+
+        assert_eq!(super::migrate(&from, &to).unwrap(), 2);
+        assert_eq!(to.snapshot().unwrap(), from.snapshot().unwrap());
+        to.clear().unwrap();
+    }
+
+    #[test]
+    fn test_import_invalid() {
+        let store = KeyValueStore::new_isolated(Namespace::parse("test_import").unwrap());
+        let dump = "{\"scope\":\"\",\"name\":\"name\",\"value\":1}\n\n{\"scope\":\"a\"}\n";
+
+        // The batch with the invalid record is not stored.
+        assert!(store.import(dump.as_bytes()).is_err());
+        assert!(store.import("not json".as_bytes()).is_err());
+        assert!(store.is_empty().unwrap());
+    }
+}
@@ -1,6 +1,6 @@
-use std::io;
+use std::{convert::Infallible, io};
 
-use kvx_types::ParseSegmentError;
+use kvx_types::{ParseNamespaceError, ParseSegmentError};
 
 /// Represents all ways a method can fail within KVx.
 #[derive(thiserror::Error, Debug)]
@@ -19,15 +19,39 @@ pub enum Error {
     #[error("postgres pool error {0}")]
     PostgresPool(#[from] r2d2_postgres::r2d2::Error),
 
+    #[cfg(feature = "postgres-tls")]
+    #[error("tls error {0}")]
+    Tls(#[from] native_tls::Error),
+
+    #[cfg(feature = "redis")]
+    #[error("redis error {0}")]
+    Redis(#[from] redis::RedisError),
+
+    #[cfg(feature = "redis")]
+    #[error("redis pool error {0}")]
+    RedisPool(r2d2::Error),
+
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
     #[error("json error {0}")]
     Json(#[from] serde_json::Error),
 
     #[error("invalid segment")]
     Segment(#[from] ParseSegmentError),
 
+    #[error("invalid namespace: {0}")]
+    Namespace(#[from] ParseNamespaceError),
+
     #[error("mutex lock error {0}")]
     MutexLock(String),
 
+    /// A lock, like the lock on the scope of a transaction, could not be
+    /// acquired before the lock timeout
+    #[error("lock timeout: {0}")]
+    LockTimeout(String),
+
     /// [`Key`] has an invalid (form)
     ///
     /// [`Key`]: ../kvx/struct.Key.html
@@ -40,6 +64,16 @@ pub enum Error {
     #[error("unknown scheme {0}")]
     UnknownScheme(String),
 
+    /// An option of a store, set in the storage URL or with the builder, has
+    /// an invalid value
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// A path, like the namespace of a `local://` store, is not a valid
+    /// single path component
+    #[error("invalid path {0}")]
+    InvalidPath(String),
+
     #[error("unknown error")]
     Unknown,
 
@@ -49,10 +83,101 @@ pub enum Error {
     #[error("unknown key")]
     UnknownKey,
 
+    /// A JSON `null` value was stored in a store that rejects them, use
+    /// delete instead
+    #[error("null value")]
+    NullValue,
+
+    /// The value of a key was read as bytes, but it was not stored as bytes
+    #[error("value was not stored as bytes")]
+    NotBytes,
+
     /// Namespace migration issue
     #[error("namespace migration issue: {0}")]
     NamespaceMigration(String),
 
+    /// A transaction conflicted with a concurrent transaction and was not
+    /// committed
+    #[error("transaction conflict")]
+    TransactionConflict,
+
+    /// Returned by a transaction callback to abort the transaction without
+    /// an error, see [`KeyValueStore::transaction`]
+    ///
+    /// [`KeyValueStore::transaction`]: ../kvx/struct.KeyValueStore.html#method.transaction
+    #[error("transaction aborted")]
+    Abort,
+
     #[error("{0}")]
     Other(String),
 }
+
+impl From<Infallible> for Error {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+impl Error {
+    /// Returns whether the error may be transient, so that the operation
+    /// that returned it may succeed when it is retried.
+    ///
+    /// This is the case for transaction conflicts, lock timeouts, IO
+    /// timeouts and interruptions, dropped or refused connections, and busy
+    /// or locked databases.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Io(e) | Error::IoWithContext(_, e) => matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ),
+            #[cfg(feature = "postgres")]
+            Error::Postgres(e) => {
+                use postgres::error::SqlState;
+
+                e.is_closed()
+                    || e.code().is_some_and(|code| {
+                        [
+                            SqlState::T_R_SERIALIZATION_FAILURE,
+                            SqlState::T_R_DEADLOCK_DETECTED,
+                            SqlState::LOCK_NOT_AVAILABLE,
+                            SqlState::CONNECTION_EXCEPTION,
+                            SqlState::CONNECTION_FAILURE,
+                            SqlState::ADMIN_SHUTDOWN,
+                            SqlState::CANNOT_CONNECT_NOW,
+                        ]
+                        .contains(code)
+                    })
+            }
+            // Getting a connection from the pool timed out.
+            #[cfg(feature = "postgres")]
+            Error::PostgresPool(_) => true,
+            #[cfg(feature = "redis")]
+            Error::Redis(e) => {
+                e.is_timeout()
+                    || e.is_connection_dropped()
+                    || e.is_connection_refusal()
+                    || matches!(
+                        e.kind(),
+                        redis::ErrorKind::TryAgain | redis::ErrorKind::BusyLoadingError
+                    )
+            }
+            #[cfg(feature = "redis")]
+            Error::RedisPool(_) => true,
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            Error::LockTimeout(_) => true,
+            Error::TransactionConflict => true,
+            _ => false,
+        }
+    }
+}
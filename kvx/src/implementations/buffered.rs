@@ -0,0 +1,252 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    sync::mpsc::Receiver,
+    time::SystemTime,
+};
+
+use kvx_types::NamespaceBuf;
+use serde_json::Value;
+
+use crate::{
+    BackendKind, ChangeEvent, ChangeKind, Error, Key, KeyValueStoreBackend, ReadStore, Result,
+    Scope, TransactionCallback, WriteStore,
+};
+
+/// A value to be written with its expiry time, if any, or `None` if the key
+/// is deleted.
+pub(crate) type PendingWrite = Option<(Value, Option<SystemTime>)>;
+
+/// A backend that cannot roll back writes itself, and runs the callbacks of
+/// its transactions on a [`BufferedTransaction`] instead.
+pub(crate) trait BufferedBackend: KeyValueStoreBackend {
+    /// Returns the expiry time of the value for a key, if any.
+    fn expiry(&self, key: &Key) -> Result<Option<SystemTime>>;
+
+    /// Applies the writes of a transaction that completed.
+    ///
+    /// By default the writes are applied one by one, so if one fails the
+    /// writes before it have been applied.
+    fn apply(&self, writes: BTreeMap<Key, PendingWrite>) -> Result<()> {
+        for (key, write) in writes {
+            match write {
+                Some((value, None)) => self.store(&key, value)?,
+                Some((value, Some(expires_at))) => {
+                    self.store_with_expiry(&key, value, expires_at)?
+                }
+                None => self.delete(&key)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The store passed to the callback of a transaction on a
+/// [`BufferedBackend`].
+///
+/// Reads go to the store directly, writes are kept in `writes` until the
+/// callback returns. Reads take these pending writes into account. If the
+/// callback fails, e.g. because it aborts the transaction, the writes are
+/// discarded, so nothing it wrote is persisted.
+pub(crate) struct BufferedTransaction<'a, S: BufferedBackend> {
+    store: &'a S,
+    writes: RefCell<BTreeMap<Key, PendingWrite>>,
+}
+
+impl<'a, S: BufferedBackend> BufferedTransaction<'a, S> {
+    /// Runs the callback and applies its writes to the store if it
+    /// succeeds. The caller must hold the locks of the transaction.
+    pub(crate) fn run(store: &'a S, callback: TransactionCallback) -> Result<()> {
+        let transaction = BufferedTransaction {
+            store,
+            writes: RefCell::new(BTreeMap::new()),
+        };
+
+        callback(&transaction)?;
+        store.apply(transaction.writes.into_inner())
+    }
+
+    /// Returns whether a write is pending for a key in the scope.
+    fn writes_in(&self, scope: &Scope) -> bool {
+        self.writes
+            .borrow()
+            .keys()
+            .any(|key| key.scope().starts_with(scope))
+    }
+
+    fn expires_at(&self, key: &Key) -> Result<Option<SystemTime>> {
+        if let Some(write) = self.writes.borrow().get(key) {
+            return Ok(write.as_ref().and_then(|(_, expires_at)| *expires_at));
+        }
+
+        self.store.expiry(key)
+    }
+}
+
+impl<S: BufferedBackend> KeyValueStoreBackend for BufferedTransaction<'_, S> {
+    fn backend_kind(&self) -> BackendKind {
+        self.store.backend_kind()
+    }
+
+    /// Runs the callback as part of this transaction.
+    fn transaction(&self, _scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        callback(self)
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        self.store.watch_scope(scope)
+    }
+}
+
+impl<S: BufferedBackend> ReadStore for BufferedTransaction<'_, S> {
+    fn is_empty(&self) -> Result<bool> {
+        if self.writes.borrow().is_empty() {
+            return self.store.is_empty();
+        }
+
+        Ok(self.list_keys(&Scope::global())?.is_empty())
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        if let Some(write) = self.writes.borrow().get(key) {
+            return Ok(write.is_some());
+        }
+
+        self.store.has(key)
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        if !self.writes_in(scope) {
+            return self.store.has_scope(scope);
+        }
+
+        Ok(!self.list_keys(scope)?.is_empty())
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        if let Some(write) = self.writes.borrow().get(key) {
+            return Ok(write.as_ref().map(|(value, _)| value.clone()));
+        }
+
+        self.store.get(key)
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        let mut keys: BTreeSet<Key> = self.store.list_keys(scope)?.into_iter().collect();
+
+        for (key, value) in self.writes.borrow().iter() {
+            if key.scope().starts_with(scope) {
+                match value {
+                    Some(_) => keys.insert(key.clone()),
+                    None => keys.remove(key),
+                };
+            }
+        }
+
+        Ok(keys.into_iter().collect())
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        if self.writes.borrow().is_empty() {
+            return self.store.list_scopes();
+        }
+
+        let scopes: BTreeSet<Scope> = self
+            .list_keys(&Scope::global())?
+            .iter()
+            .flat_map(|key| key.scope().sub_scopes())
+            .collect();
+        Ok(scopes.into_iter().collect())
+    }
+
+    /// Returns the changes applied before this transaction, the pending
+    /// writes of this transaction are not included.
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        self.store.changes_since(since)
+    }
+}
+
+impl<S: BufferedBackend> WriteStore for BufferedTransaction<'_, S> {
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .insert(key.clone(), Some((value, None)));
+        Ok(())
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .insert(key.clone(), Some((value, Some(expires_at))));
+        Ok(())
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let value = self.get(from)?.ok_or(Error::UnknownKey)?;
+        let expires_at = self.expires_at(from)?;
+        self.delete(from)?;
+        self.writes
+            .borrow_mut()
+            .insert(to.clone(), Some((value, expires_at)));
+        Ok(())
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let value = self.get(from)?.ok_or(Error::UnknownKey)?;
+        let expires_at = self.expires_at(from)?;
+        self.writes
+            .borrow_mut()
+            .insert(to.clone(), Some((value, expires_at)));
+        Ok(())
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        for key in self.list_keys(from)? {
+            if let Some(target) = key.rebase(from, to) {
+                self.move_value(&key, &target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.writes.borrow_mut().insert(key.clone(), None);
+        Ok(())
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        for key in self.list_keys(scope)? {
+            self.delete(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.delete_scope(&Scope::global())
+    }
+
+    fn migrate_namespace(&mut self, _to: NamespaceBuf) -> Result<()> {
+        Err(Error::NamespaceMigration(
+            "cannot migrate a namespace in a transaction".to_string(),
+        ))
+    }
+
+    /// Purging is not part of the transaction, so rather than report that
+    /// nothing was removed, this fails with [`Error::Other`].
+    fn purge_expired(&self) -> Result<usize> {
+        Err(Error::Other(
+            "cannot purge expired values in a transaction".to_string(),
+        ))
+    }
+
+    /// Pruning is not part of the transaction, so rather than report that
+    /// nothing was removed, this fails with [`Error::Other`].
+    fn prune_changes(&self, _before: SystemTime) -> Result<usize> {
+        Err(Error::Other(
+            "cannot prune changes in a transaction".to_string(),
+        ))
+    }
+}
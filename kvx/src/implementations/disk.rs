@@ -1,24 +1,234 @@
 use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::BTreeMap,
     fmt::Display,
     fs,
     fs::{File, OpenOptions},
+    io, iter,
     path::{Component, Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
+use kvx_types::NamespaceBuf;
 use serde_json::Value;
+use url::Url;
 
 use crate::{
-    Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope, SegmentBuf, TransactionCallback,
-    WriteStore,
+    bytes,
+    implementations::buffered::{BufferedBackend, BufferedTransaction, PendingWrite},
+    BackendKind, ChangeKind, Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope, Segment,
+    SegmentBuf, TransactionCallback, WriteStore,
 };
 
 pub const LOCK_FILE_NAME: &str = "lockfile.lock";
 pub const LOCK_FILE_DIR: &str = ".locks";
+/// The directory with a file for each key that expires, containing the
+/// expiry time in milliseconds since the Unix epoch.
+const EXPIRY_DIR: &str = ".expiry";
+/// The directory with a file for each key that was stored or deleted while
+/// changes were tracked, containing either the times it was created and
+/// last updated, or the time it was deleted, in milliseconds since the Unix
+/// epoch.
+const CHANGES_DIR: &str = ".changes";
+const TMP_DIR: &str = "tmp";
+
+/// Temporary files older than this are considered to be orphaned, i.e. left
+/// behind by a process that stopped between writing and persisting them.
+pub const TMP_FILE_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// How long [`Disk::try_transaction`] waits before trying again to take a
+/// lock held by another transaction.
+const LOCK_WAIT: Duration = Duration::from_millis(10);
+
+/// How much effort is spent to make sure that stored values survive a crash
+/// or power loss, set using `durability=none`, `durability=fsync` or
+/// `durability=fsync_dir` in the `local://` URL.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Durability {
+    /// Values are written to a temporary file which is atomically renamed,
+    /// but not synced to disk. A crash may lose recently stored values.
+    #[default]
+    None,
+
+    /// The temporary file is synced to disk before it is renamed, so the
+    /// contents of a value are never lost once its file is visible, but the
+    /// rename itself may still be lost.
+    Fsync,
+
+    /// Like [`Durability::Fsync`], and the parent directory is synced after
+    /// the rename so that the rename is persisted as well. Only has an
+    /// effect on Unix systems.
+    FsyncDir,
+}
+
+impl Durability {
+    pub(crate) fn from_url(url: &Url) -> Result<Self> {
+        let mut durability = Durability::default();
+        for (name, value) in url.query_pairs() {
+            if name == "durability" {
+                durability = match value.as_ref() {
+                    "none" => Durability::None,
+                    "fsync" => Durability::Fsync,
+                    "fsync_dir" => Durability::FsyncDir,
+                    _ => {
+                        return Err(Error::InvalidConfig(format!(
+                            "invalid value for durability: {}",
+                            value
+                        )))
+                    }
+                };
+            }
+        }
+
+        Ok(durability)
+    }
+}
+
+/// The header of a compressed value file, followed by a byte for the
+/// [`Compression`] used. Files without it contain plain JSON, which cannot
+/// start with a NUL byte.
+const COMPRESSED_MAGIC: &[u8] = b"\0kvx";
+
+/// The header of a file with bytes stored using [`WriteStore::store_bytes`],
+/// followed by the bytes as they are.
+const BYTES_MAGIC: &[u8] = b"\0kvxb";
+
+/// How values are compressed on disk, set using `compression=none`,
+/// `compression=gzip` or `compression=zstd` in the `local://` URL.
+///
+/// Values are always read correctly, regardless of how they were written, so
+/// the compression of an existing store can be changed. Compressed values
+/// can only be read with the `compression` feature enabled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Compression {
+    /// Values are stored as pretty printed JSON.
+    #[default]
+    None,
+
+    /// Values are compressed using gzip.
+    #[cfg(feature = "compression")]
+    Gzip,
+
+    /// Values are compressed using zstd.
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl Compression {
+    pub(crate) fn from_url(url: &Url) -> Result<Self> {
+        let mut compression = Compression::default();
+        for (name, value) in url.query_pairs() {
+            if name == "compression" {
+                compression = match value.as_ref() {
+                    "none" => Compression::None,
+                    #[cfg(feature = "compression")]
+                    "gzip" => Compression::Gzip,
+                    #[cfg(feature = "compression")]
+                    "zstd" => Compression::Zstd,
+                    _ => {
+                        return Err(Error::InvalidConfig(format!(
+                            "invalid value for compression: {}",
+                            value
+                        )))
+                    }
+                };
+            }
+        }
+
+        Ok(compression)
+    }
+
+    /// Returns the contents of the file for a value.
+    fn encode(self, value: &Value) -> Result<Vec<u8>> {
+        let json = format!("{:#}", value).into_bytes();
+
+        #[cfg(feature = "compression")]
+        {
+            use std::io::Write;
+
+            let (id, compressed) = match self {
+                Compression::None => return Ok(json),
+                Compression::Gzip => {
+                    let mut encoder =
+                        flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                    encoder.write_all(&json)?;
+                    (b'g', encoder.finish()?)
+                }
+                Compression::Zstd => (b'z', zstd::encode_all(json.as_slice(), 0)?),
+            };
+
+            let mut contents = COMPRESSED_MAGIC.to_vec();
+            contents.push(id);
+            contents.extend(compressed);
+            Ok(contents)
+        }
+
+        #[cfg(not(feature = "compression"))]
+        Ok(json)
+    }
+}
+
+/// Returns the value in the contents of a file, which may be compressed or
+/// contain bytes.
+fn decode(contents: &[u8]) -> Result<Value> {
+    match contents.strip_prefix(BYTES_MAGIC) {
+        Some(bytes) => Ok(bytes::to_value(bytes)),
+        None => Ok(serde_json::from_slice(&decompress(contents)?)?),
+    }
+}
+
+/// Returns the JSON in the contents of a file, which may be compressed.
+fn decompress(contents: &[u8]) -> Result<Cow<'_, [u8]>> {
+    let Some(compressed) = contents.strip_prefix(COMPRESSED_MAGIC) else {
+        return Ok(Cow::Borrowed(contents));
+    };
 
+    #[cfg(feature = "compression")]
+    {
+        use std::io::Read;
+
+        let mut json = vec![];
+        match compressed.split_first() {
+            Some((b'g', compressed)) => {
+                flate2::read::GzDecoder::new(compressed).read_to_end(&mut json)?;
+            }
+            Some((b'z', compressed)) => json = zstd::decode_all(compressed)?,
+            _ => return Err(Error::Other("unknown value compression".to_string())),
+        }
+
+        Ok(Cow::Owned(json))
+    }
+
+    #[cfg(not(feature = "compression"))]
+    {
+        let _ = compressed;
+        Err(Error::Other(
+            "reading compressed values requires the compression feature".to_string(),
+        ))
+    }
+}
+
+/// Stores each key in a file named after the name of the key, in a
+/// directory for each segment of its scope.
+///
+/// Segments are used as file and directory names as they are. Segments
+/// created using [`Segment::parse_escaped`] therefore use the escaped form
+/// on disk as well, e.g. `a%2Fb` for `a/b`, and are read back as the same
+/// escaped segments.
+///
+/// [`Segment::parse_escaped`]: crate::Segment::parse_escaped
 #[derive(Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct Disk {
     root: PathBuf,
     tmp: PathBuf,
+    durability: Durability,
+    compression: Compression,
+    lock_timeout: Option<Duration>,
+    track_changes: bool,
+    migrations: u64,
 }
 
 impl Disk {
@@ -33,9 +243,31 @@ impl Disk {
     /// Different instances of this disk based storage that use different namespaces,
     /// but share the same (base) path will all use the same tmp directory. This is
     /// not an issue as the temporary files will have unique names.
+    ///
+    /// Temporary files older than [`TMP_FILE_MAX_AGE`] are removed when the store
+    /// is created, see [`Disk::sweep_tmp`].
+    ///
+    /// Fails with [`Error::InvalidPath`] if the namespace is not a single
+    /// directory name, e.g. if it contains a path separator or is `..`.
     pub fn new(path: &str, namespace: &str) -> Result<Self> {
+        Self::new_with_tmp_max_age(path, namespace, Some(TMP_FILE_MAX_AGE))
+    }
+
+    /// Creates a store like [`Disk::new`], but removes the temporary files
+    /// older than `tmp_max_age` instead, or none if it is `None`.
+    pub fn new_with_tmp_max_age(
+        path: &str,
+        namespace: &str,
+        tmp_max_age: Option<Duration>,
+    ) -> Result<Self> {
+        let mut components = Path::new(namespace).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == namespace => {}
+            _ => return Err(Error::InvalidPath(namespace.to_string())),
+        }
+
         let root = PathBuf::from(path).join(namespace);
-        let tmp = PathBuf::from(path).join("tmp");
+        let tmp = PathBuf::from(path).join(TMP_DIR);
 
         if !tmp.exists() {
             fs::create_dir_all(&tmp).map_err(|e| {
@@ -46,8 +278,529 @@ impl Disk {
             })?;
         }
 
-        Ok(Disk { root, tmp })
+        let disk = Disk {
+            root,
+            tmp,
+            durability: Durability::default(),
+            compression: Compression::default(),
+            lock_timeout: None,
+            track_changes: false,
+            migrations: 0,
+        };
+        let disk = Disk {
+            migrations: disk.read_migrations()?,
+            ..disk
+        };
+        if let Some(tmp_max_age) = tmp_max_age {
+            disk.sweep_tmp(tmp_max_age)?;
+        }
+
+        Ok(disk)
+    }
+
+    /// Sets how stored values are synced to disk, see [`Durability`].
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    /// Sets how stored values are compressed, see [`Compression`].
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets how long a transaction waits for its locks before it fails with
+    /// [`Error::LockTimeout`]. By default transactions wait for as long as it
+    /// takes.
+    pub fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    /// Sets whether changes are recorded for [`ReadStore::changes_since`],
+    /// see [`CHANGES_DIR`]. By default they are not, as recording a change
+    /// takes extra writes.
+    pub fn with_change_tracking(mut self, track_changes: bool) -> Self {
+        self.track_changes = track_changes;
+        self
+    }
+
+    /// Removes temporary files that were last modified longer than `older_than`
+    /// ago and returns the number of files removed.
+    ///
+    /// Temporary files are normally cleaned up when they are dropped, but they
+    /// can be left behind if the process crashed between writing and persisting
+    /// them. Other instances sharing the same (base) path may be writing
+    /// temporary files concurrently, so `older_than` should exceed the longest
+    /// possible write.
+    pub fn sweep_tmp(&self, older_than: Duration) -> Result<usize> {
+        sweep_tmp_dir(&self.tmp, older_than)
+    }
+
+    /// Removes the temporary files under the (base) path like
+    /// [`Disk::sweep_tmp`], without creating a store for a namespace.
+    pub fn sweep_tmp_at(path: &str, older_than: Duration) -> Result<usize> {
+        let tmp = PathBuf::from(path).join(TMP_DIR);
+        if !tmp.exists() {
+            return Ok(0);
+        }
+
+        sweep_tmp_dir(&tmp, older_than)
+    }
+}
+
+/// Removes the files in the tmp directory that were last modified longer
+/// than `older_than` ago, see [`Disk::sweep_tmp`].
+fn sweep_tmp_dir(tmp: &Path, older_than: Duration) -> Result<usize> {
+    let entries = fs::read_dir(tmp).map_err(|e| {
+        Error::IoWithContext(format!("Cannot read tmp directory: {}", tmp.display()), e)
+    })?;
+
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for entry in entries {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            // the file may have been persisted or removed in the meantime
+            Err(_) => continue,
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+
+        if age > older_than && fs::remove_file(entry.path()).is_ok() {
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+impl Disk {
+    /// Returns the namespaces under the (base) path that contain keys, with
+    /// the number of keys in each.
+    pub fn namespace_summaries(path: &str) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut summaries = vec![];
+        for entry in fs::read_dir(&path)? {
+            let path = entry?.path();
+            if !path.is_dir() || path.ends_with(TMP_DIR) {
+                continue;
+            }
+
+            let namespace = match path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| NamespaceBuf::from_str(name).ok())
+            {
+                Some(namespace) => namespace,
+                None => continue,
+            };
+
+            let count = count_files_recursive(&path)?;
+            if count > 0 {
+                summaries.push((namespace, count));
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    /// Returns the lock for the namespace of this store, kept under the
+    /// (base) path.
+    ///
+    /// Writes that create files hold a shared lock, so they complete either
+    /// before the namespace is migrated, which holds an exclusive lock, or
+    /// fail after it, see [`Disk::check_not_migrated`].
+    fn namespace_lock(&self) -> Result<FileLock> {
+        let base = self.root.parent().ok_or(Error::Unknown)?;
+        let namespace = self.root.file_name().ok_or(Error::Unknown)?;
+
+        FileLock::create(base.join(LOCK_FILE_DIR).join(namespace))
+    }
+
+    /// Returns the path of the file counting how often the namespace of
+    /// this store was migrated to another namespace, kept next to the lock
+    /// for the namespace.
+    fn migrations_path(&self) -> Result<PathBuf> {
+        let base = self.root.parent().ok_or(Error::Unknown)?;
+        let namespace = self.root.file_name().ok_or(Error::Unknown)?;
+
+        let mut file_name = namespace.to_os_string();
+        file_name.push(".migrations");
+        Ok(base.join(LOCK_FILE_DIR).join(file_name))
+    }
+
+    /// Returns how often the namespace of this store was migrated to
+    /// another namespace.
+    fn read_migrations(&self) -> Result<u64> {
+        match fs::read_to_string(self.migrations_path()?) {
+            Ok(count) => count.trim().parse().map_err(|_| Error::Unknown),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fails with [`Error::NamespaceMigration`] if the namespace of this
+    /// store was migrated by another store since this store was created.
+    ///
+    /// Writes call this while holding the lock for the namespace, so that a
+    /// write that waited for a migration does not recreate the directory of
+    /// the old namespace.
+    fn check_not_migrated(&self) -> Result<()> {
+        if self.read_migrations()? != self.migrations {
+            return Err(Error::NamespaceMigration(format!(
+                "namespace {} was migrated by another store",
+                self.root.file_name().unwrap_or_default().to_string_lossy()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Persists a temporary file written for a value as the file for the
+    /// key.
+    fn persist_value(&self, key: &Key, tmp_file: tempfile::NamedTempFile) -> Result<()> {
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        let existed = self.track_changes && self.has(key)?;
+        self.persist_tmp(tmp_file, key.as_path(&self.root))?;
+        self.remove_expiry(key)?;
+        self.record_stored(key, existed)
+    }
+
+    /// Returns the contents of the file for the key, or `None` if the key
+    /// does not exist.
+    fn read_contents(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let path = key.as_path(&self.root);
+        if !path.exists() || self.is_expired(key)? {
+            return Ok(None);
+        }
+
+        match fs::read(path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes a value to a new temporary file for the given key, compressed
+    /// as configured for this store.
+    fn write_tmp_value(&self, key: &Key, value: &Value) -> Result<tempfile::NamedTempFile> {
+        self.write_tmp_contents(key, &self.compression.encode(value)?)
+    }
+
+    /// Writes an uncompressed value to a new temporary file for the given
+    /// key, used for the files kept next to the values.
+    fn write_tmp(&self, key: &Key, value: &Value) -> Result<tempfile::NamedTempFile> {
+        self.write_tmp_contents(key, format!("{:#}", value).as_bytes())
+    }
+
+    /// Writes the contents to a new temporary file for the given key.
+    fn write_tmp_contents(&self, key: &Key, contents: &[u8]) -> Result<tempfile::NamedTempFile> {
+        check_path(key.segments())?;
+
+        // Always use a tempfile to ensure that the file can be written entirely.
+        // If we don't, then we can end up with half-written files in case there
+        // is some issue during writing, e.g. disk is full, or the application is
+        // suddenly stopped, or the server reboots, etc.
+
+        // tempfile ensures that the temporary file is cleaned up in case it
+        // would be left behind because of some issue.
+        let tmp_file = tempfile::NamedTempFile::new_in(&self.tmp).map_err(|e| {
+            Error::IoWithContext(
+                format!(
+                    "Issue writing tmp file for key: {}. Check permissions and space on disk.",
+                    key
+                ),
+                e,
+            )
+        })?;
+
+        fs::write(&tmp_file, contents).map_err(|e| {
+            Error::IoWithContext(
+                format!(
+                    "Issue writing tmp file: {} for key: {}. Check permissions and space on disk.",
+                    tmp_file.as_ref().display(),
+                    key
+                ),
+                e,
+            )
+        })?;
+
+        if self.durability != Durability::None {
+            tmp_file.as_file().sync_all().map_err(|e| {
+                Error::IoWithContext(
+                    format!("Cannot sync tmp file: {}", tmp_file.as_ref().display()),
+                    e,
+                )
+            })?;
+        }
+
+        Ok(tmp_file)
+    }
+
+    /// Moves a temporary file written by [`Disk::write_tmp`] to the given
+    /// path.
+    fn persist_tmp(&self, tmp_file: tempfile::NamedTempFile, path: PathBuf) -> Result<()> {
+        let dir = path.parent().ok_or(Error::Unknown)?;
+
+        if !dir.try_exists().unwrap_or_default() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // persist ensures that the temporary file is persisted at the
+        // target location and any existing file is replaced. On unix
+        // systems this relies on an atomic move.
+        tmp_file.persist(&path).map_err(|e| {
+            Error::IoWithContext(
+                format!(
+                    "Cannot rename temp file {} to {}.",
+                    e.file.path().display(),
+                    path.display()
+                ),
+                e.error,
+            )
+        })?;
+
+        if self.durability == Durability::FsyncDir {
+            sync_dir(dir)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Syncs a directory, so that changes to its entries are persisted.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| Error::IoWithContext(format!("Cannot sync directory: {}", dir.display()), e))
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+impl Disk {
+    /// Returns the path of the expiry file for a key or scope.
+    fn expiry_path(&self, path: &impl AsPath) -> PathBuf {
+        path.as_path(self.root.join(EXPIRY_DIR))
+    }
+
+    /// Returns the expiry time of the key in milliseconds since the Unix
+    /// epoch, if it has one.
+    fn expires_at(&self, key: &Key) -> Result<Option<u64>> {
+        let path = self.expiry_path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        // The expiry may have been removed in the meantime.
+        let Ok(expires_at) = fs::read_to_string(&path) else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_str(&expires_at)?))
+    }
+
+    /// Returns whether the key has an expiry time that has passed.
+    fn is_expired(&self, key: &Key) -> Result<bool> {
+        Ok(self.expires_at(key)?.is_some_and(has_passed))
+    }
+
+    /// Returns whether any key in the namespace has an expiry time.
+    fn has_expiries(&self) -> bool {
+        self.root.join(EXPIRY_DIR).exists()
+    }
+
+    /// Removes the expiry for the key, if it has one.
+    fn remove_expiry(&self, key: &Key) -> Result<()> {
+        remove_file_if_exists(self.expiry_path(key))
+    }
+
+    /// Removes the expiries for all keys in a scope.
+    fn remove_expiries(&self, scope: &Scope) -> Result<()> {
+        let path = self.expiry_path(scope);
+        match fs::remove_dir_all(&path) {
+            Ok(()) => {
+                remove_empty_parent_dirs(path.parent().ok_or(Error::Unknown)?);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Moves the expiry for a key or scope along with its value(s).
+    fn move_expiry(&self, from: &impl AsPath, to: &impl AsPath) -> Result<()> {
+        let from_path = self.expiry_path(from);
+        if !from_path.exists() {
+            return Ok(());
+        }
+
+        let to_path = self.expiry_path(to);
+        fs::create_dir_all(to_path.parent().ok_or(Error::Unknown)?)?;
+        fs::rename(&from_path, to_path)?;
+        remove_empty_parent_dirs(from_path.parent().ok_or(Error::Unknown)?);
+
+        Ok(())
+    }
+
+    /// Returns the path of the change record for a key or scope.
+    fn change_path(&self, path: &impl AsPath) -> PathBuf {
+        path.as_path(self.root.join(CHANGES_DIR))
+    }
+
+    /// Records that a value was stored for the key if changes are tracked,
+    /// keeping the time it was created if `existed`, i.e. if the key had a
+    /// value before.
+    ///
+    /// Keys that had a value before changes were recorded get creation time
+    /// zero, so they are reported as updated.
+    fn record_stored(&self, key: &Key, existed: bool) -> Result<()> {
+        if !self.track_changes {
+            return Ok(());
+        }
+
+        let now = as_millis(SystemTime::now());
+        let created_at = if existed {
+            read_json(self.change_path(key))?
+                .and_then(|record| record.get("created_at").and_then(Value::as_u64))
+                .unwrap_or_default()
+        } else {
+            now
+        };
+
+        let record = serde_json::json!({ "created_at": created_at, "updated_at": now });
+        let tmp_file = self.write_tmp(key, &record)?;
+        self.persist_tmp(tmp_file, self.change_path(key))
+    }
+
+    /// Records that the keys were deleted, if changes are tracked.
+    fn record_all_deleted(&self, keys: &[Key]) -> Result<()> {
+        keys.iter().try_for_each(|key| self.record_deleted(key))
     }
+
+    /// Records that the key was deleted, if changes are tracked.
+    fn record_deleted(&self, key: &Key) -> Result<()> {
+        if !self.track_changes {
+            return Ok(());
+        }
+
+        let record = serde_json::json!({ "deleted_at": as_millis(SystemTime::now()) });
+        let tmp_file = self.write_tmp(key, &record)?;
+        self.persist_tmp(tmp_file, self.change_path(key))
+    }
+
+    /// Copies the value and expiry of a key to another key, writing the copy
+    /// to a temporary file first. Returns `false` if there is no value for
+    /// the key.
+    ///
+    /// The caller must hold the namespace lock.
+    fn copy_file(&self, from: &Key, to: &Key) -> Result<bool> {
+        let expires_at = self.expires_at(from)?;
+        if expires_at.is_some_and(has_passed) {
+            return Ok(false);
+        }
+
+        let value = match fs::read(from.as_path(&self.root)) {
+            Ok(contents) => decode(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        let existed = self.track_changes && self.has(to)?;
+        let tmp_file = self.write_tmp_value(to, &value)?;
+        match expires_at {
+            Some(expires_at) => {
+                let expiry_file = self.write_tmp(to, &Value::from(expires_at))?;
+                self.persist_tmp(expiry_file, self.expiry_path(to))?;
+            }
+            None => self.remove_expiry(to)?,
+        }
+        self.persist_tmp(tmp_file, to.as_path(&self.root))?;
+        self.record_stored(to, existed)?;
+
+        Ok(true)
+    }
+
+    /// Returns the keys that have not expired.
+    fn retain_live(&self, keys: Vec<Key>) -> Result<Vec<Key>> {
+        if !self.has_expiries() {
+            return Ok(keys);
+        }
+
+        let mut live = vec![];
+        for key in keys {
+            if !self.is_expired(&key)? {
+                live.push(key);
+            }
+        }
+
+        Ok(live)
+    }
+}
+
+/// Returns the number of milliseconds since the Unix epoch for a time.
+fn as_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Reads a JSON file, returning `None` if it does not exist.
+fn read_json(path: impl AsRef<Path>) -> Result<Option<Value>> {
+    match fs::read_to_string(path) {
+        Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Removes a file and its empty parent directories, if the file exists.
+fn remove_file_if_exists(path: PathBuf) -> Result<()> {
+    match fs::remove_file(&path) {
+        Ok(()) => {
+            remove_empty_parent_dirs(path.parent().ok_or(Error::Unknown)?);
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns whether an expiry time in milliseconds since the Unix epoch has
+/// passed.
+fn has_passed(expires_at: u64) -> bool {
+    SystemTime::UNIX_EPOCH + Duration::from_millis(expires_at) <= SystemTime::now()
+}
+
+/// Returns the longest scope that contains all the given keys, or `None` if
+/// there are no keys.
+fn common_scope<'a>(keys: impl Iterator<Item = &'a Key>) -> Option<Scope> {
+    let mut keys = keys.peekable();
+    keys.peek()?;
+
+    Some(Scope::common_prefix(keys.map(Key::scope)))
 }
 
 impl Display for Disk {
@@ -58,16 +811,33 @@ impl Display for Disk {
 
 impl ReadStore for Disk {
     fn is_empty(&self) -> Result<bool> {
+        if self.has_expiries() {
+            return Ok(self.count_keys(&Scope::global())? == 0);
+        }
+
         Ok(self
             .root
             .read_dir()
-            .map(|mut d| d.next().is_none())
+            .map(|d| !d.flatten().any(|entry| !is_internal_dir(&entry.path())))
             .unwrap_or(true))
     }
 
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        let path = scope.as_path(&self.root);
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        if self.has_expiries() {
+            return Ok(self.list_keys(scope)?.len());
+        }
+
+        count_files_recursive(path)
+    }
+
     fn has(&self, key: &Key) -> Result<bool> {
         let exists = key.as_path(&self.root).exists();
-        Ok(exists)
+        Ok(exists && !self.is_expired(key)?)
     }
 
     fn has_scope(&self, scope: &Scope) -> Result<bool> {
@@ -76,14 +846,36 @@ impl ReadStore for Disk {
     }
 
     fn get(&self, key: &Key) -> Result<Option<Value>> {
-        let path = key.as_path(&self.root);
-        if path.exists() {
-            let value =
-                fs::read_to_string(key.as_path(&self.root)).map_err(|_| Error::UnknownKey)?;
-            let value: Value = serde_json::from_str(&value)?;
-            Ok(Some(value))
+        self.read_contents(key)?
+            .map(|contents| decode(&contents))
+            .transpose()
+    }
+
+    /// Returns the JSON in the file for the key, decompressed but otherwise
+    /// as it was written, i.e. pretty printed.
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let Some(contents) = self.read_contents(key)? else {
+            return Ok(None);
+        };
+
+        if contents.starts_with(BYTES_MAGIC) {
+            Ok(Some(serde_json::to_vec(&decode(&contents)?)?))
         } else {
-            Ok(None)
+            Ok(Some(decompress(&contents)?.into_owned()))
+        }
+    }
+
+    /// Returns the bytes in the file for the key as they are.
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        let Some(contents) = self.read_contents(key)? else {
+            return Ok(None);
+        };
+
+        match contents.strip_prefix(BYTES_MAGIC) {
+            Some(bytes) => Ok(Some(bytes.to_vec())),
+            None => bytes::from_value(&decode(&contents)?)
+                .map(Some)
+                .ok_or(Error::NotBytes),
         }
     }
 
@@ -93,10 +885,53 @@ impl ReadStore for Disk {
             return Ok(vec![]);
         }
 
-        list_files_recursive(scope.as_path(&self.root))?
+        let keys = list_files_recursive(scope.as_path(&self.root))?
             .into_iter()
             .map(|path| path.as_key(&self.root))
-            .collect()
+            .collect::<Result<_>>()?;
+
+        self.retain_live(keys)
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        let path = scope.as_path(&self.root);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        for path in list_files_recursive(path)? {
+            let key = path.as_key(&self.root)?;
+            if self.is_expired(&key)? {
+                continue;
+            }
+
+            match fs::read(&path) {
+                Ok(contents) => entries.push((key, decode(&contents)?)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        let path = scope.as_path(&self.root);
+        if !path.exists() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let keys = FileWalker::new(path)?.map(|path| path?.as_key(&self.root));
+
+        Ok(Box::new(keys.filter_map(|key| match key {
+            Ok(key) => match self.is_expired(&key) {
+                Ok(true) => None,
+                Ok(false) => Some(Ok(key)),
+                Err(e) => Some(Err(e)),
+            },
+            Err(e) => Some(Err(e)),
+        })))
     }
 
     fn list_scopes(&self) -> Result<Vec<Scope>> {
@@ -105,122 +940,447 @@ impl ReadStore for Disk {
             .map(|path| path.as_scope(&self.root))
             .collect()
     }
+
+    /// Only walks the directory of the parent scope.
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let path = parent.as_path(&self.root);
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        list_dirs_recursive(path)?
+            .into_iter()
+            .map(|path| path.as_scope(&self.root))
+            .collect()
+    }
+
+    /// Only reads the directory of the parent scope.
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let path = parent.as_path(&self.root);
+        if !path.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut children = vec![];
+        for result in fs::read_dir(path)? {
+            let path = result?.path();
+            if is_scope_dir(&path)? {
+                children.push(path.as_scope(&self.root)?);
+            }
+        }
+
+        Ok(children)
+    }
+
+    /// Returns the changes using the change records, see [`CHANGES_DIR`].
+    ///
+    /// The times of changes are recorded in milliseconds.
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        let changes_root = self.root.join(CHANGES_DIR);
+        if !changes_root.exists() {
+            return Ok(vec![]);
+        }
+
+        let since = as_millis(since);
+        let mut changes = vec![];
+        for path in list_files_recursive(&changes_root)? {
+            let key = path.as_key(&changes_root)?;
+            let Some(record) = read_json(&path)? else {
+                continue;
+            };
+
+            let at = |field: &str| record.get(field).and_then(Value::as_u64);
+            if let Some(deleted_at) = at("deleted_at") {
+                if deleted_at >= since && !self.has(&key)? {
+                    changes.push((deleted_at, key, ChangeKind::Deleted));
+                }
+            } else if let Some(updated_at) = at("updated_at") {
+                if updated_at >= since && self.has(&key)? {
+                    let kind = if at("created_at").unwrap_or_default() >= since {
+                        ChangeKind::Created
+                    } else {
+                        ChangeKind::Updated
+                    };
+                    changes.push((updated_at, key, kind));
+                }
+            }
+        }
+
+        changes.sort_by_key(|(at, _, _)| *at);
+        Ok(changes
+            .into_iter()
+            .map(|(_, key, kind)| (key, kind))
+            .collect())
+    }
 }
 
 impl WriteStore for Disk {
     fn store(&self, key: &Key, value: Value) -> Result<()> {
-        let path = key.as_path(&self.root);
-        let dir = key.scope().as_path(&self.root);
+        let tmp_file = self.write_tmp_value(key, &value)?;
+        self.persist_value(key, tmp_file)
+    }
+
+    /// Writes the bytes to the file as they are, after a header that marks
+    /// them as bytes, see [`BYTES_MAGIC`]. They are not compressed.
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        let tmp_file = self.write_tmp_contents(key, &[BYTES_MAGIC, bytes].concat())?;
+        self.persist_value(key, tmp_file)
+    }
 
-        if key.scope().to_string().starts_with(LOCK_FILE_DIR) {
-            return Err(Error::InvalidKey);
+    /// Stores all values while holding the locks for a transaction on the
+    /// common scope of the keys.
+    ///
+    /// All values are written to temporary files before any of them is
+    /// persisted, so nothing is stored if writing any of the values fails.
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        let Some(scope) = common_scope(entries.iter().map(|(key, _)| key)) else {
+            return Ok(());
+        };
+
+        self.with_scope_lock(&scope, self.lock_deadline(), || {
+            let tmp_files = entries
+                .iter()
+                .map(|(key, value)| Ok((key, self.write_tmp_value(key, value)?)))
+                .collect::<Result<Vec<_>>>()?;
+
+            let namespace_lock = self.namespace_lock()?;
+            let _read_lock = namespace_lock.read()?;
+            self.check_not_migrated()?;
+
+            // Persist in order, so later values for the same key replace
+            // earlier ones.
+            for (key, tmp_file) in tmp_files {
+                let existed = self.track_changes && self.has(key)?;
+                self.persist_tmp(tmp_file, key.as_path(&self.root))?;
+                self.remove_expiry(key)?;
+                self.record_stored(key, existed)?;
+            }
+
+            Ok(())
+        })?
+        .ok_or_else(|| Error::LockTimeout(format!("Scope {} already locked", scope)))
+    }
+
+    /// Stores the value and writes its expiry time to a separate file, see
+    /// [`EXPIRY_DIR`].
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        let expires_at = as_millis(expires_at);
+
+        let expiry_file = self.write_tmp(key, &Value::from(expires_at))?;
+        let tmp_file = self.write_tmp_value(key, &value)?;
+
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        // Write the expiry first, so the value is never visible without it.
+        let existed = self.track_changes && self.has(key)?;
+        self.persist_tmp(expiry_file, self.expiry_path(key))?;
+        self.persist_tmp(tmp_file, key.as_path(&self.root))?;
+        self.record_stored(key, existed)
+    }
+
+    /// Removes the expired values.
+    ///
+    /// A value that is stored again for an expired key while this runs may be
+    /// removed as well.
+    fn purge_expired(&self) -> Result<usize> {
+        let expiry_root = self.root.join(EXPIRY_DIR);
+        if !expiry_root.exists() {
+            return Ok(0);
         }
 
-        if !dir.try_exists().unwrap_or_default() {
-            fs::create_dir_all(dir)?;
+        let mut purged = 0;
+        for path in list_files_recursive(&expiry_root)? {
+            let key = path.as_key(&expiry_root)?;
+            if !self.is_expired(&key)? {
+                continue;
+            }
+
+            let path = key.as_path(&self.root);
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    purged += 1;
+                    remove_empty_parent_dirs(path.parent().ok_or(Error::Unknown)?);
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            self.remove_expiry(&key)?;
+            remove_file_if_exists(self.change_path(&key))?;
         }
 
-        // Always use a tempfile to ensure that the file can be written entirely.
-        // If we don't, then we can end up with half-written files in case there
-        // is some issue during writing, e.g. disk is full, or the application is
-        // suddenly stopped, or the server reboots, etc.
+        Ok(purged)
+    }
 
-        // tempfile ensures that the temporary file is cleaned up in case it
-        // would be left behind because of some issue.
-        let tmp_file = tempfile::NamedTempFile::new_in(&self.tmp).map_err(|e| {
-            Error::IoWithContext(
-                format!(
-                    "Issue writing tmp file for key: {}. Check permissions and space on disk.",
-                    key
-                ),
-                e,
-            )
-        })?;
+    /// Removes the change records of the keys that did not change since
+    /// `before`, see [`CHANGES_DIR`]: the tombstones of keys deleted before
+    /// it, and the creation and update times of keys last stored before it.
+    ///
+    /// Values cannot be stored while this runs, but a key that is deleted
+    /// in the meantime may lose its tombstone.
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        let changes_root = self.root.join(CHANGES_DIR);
+        if !changes_root.exists() {
+            return Ok(0);
+        }
+
+        let mut namespace_lock = self.namespace_lock()?;
+        let _write_lock = namespace_lock.write()?;
+
+        let before = as_millis(before);
+        let mut pruned = 0;
+        for path in list_files_recursive(&changes_root)? {
+            let Some(record) = read_json(&path)? else {
+                continue;
+            };
+
+            let changed_at = record
+                .get("deleted_at")
+                .or_else(|| record.get("updated_at"))
+                .and_then(Value::as_u64);
+            if changed_at.is_some_and(|changed_at| changed_at < before) {
+                remove_file_if_exists(path)?;
+                pruned += 1;
+            }
+        }
 
-        fs::write(&tmp_file, format!("{:#}", value).as_bytes()).map_err(|e| {
-            Error::IoWithContext(
-                format!(
-                    "Issue writing tmp file: {} for key: {}. Check permissions and space on disk.",
-                    tmp_file.as_ref().display(),
-                    key
-                ),
-                e,
-            )
-        })?;
+        Ok(pruned)
+    }
 
-        // persist ensures that the temporary file is persisted at the
-        // target location and any existing file is replaced. On unix
-        // systems this relies on an atomic move.
-        tmp_file.persist(&path).map_err(|e| {
-            Error::IoWithContext(
-                format!(
-                    "Cannot rename temp file {} to {}.",
-                    e.file.path().display(),
-                    path.display()
-                ),
-                e.error,
-            )
-        })?;
+    /// Compares and stores the value while holding the locks for a
+    /// transaction on the scope of the key, see [`Disk::transaction`].
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.with_scope_lock(key.scope(), self.lock_deadline(), || {
+            if self.get(key)?.as_ref() != expected {
+                return Ok(false);
+            }
 
-        Ok(())
+            self.store(key, new)?;
+            Ok(true)
+        })?
+        .ok_or_else(|| Error::LockTimeout(format!("Scope {} already locked", key.scope())))
     }
 
     fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        check_path(to.segments())?;
+        if !self.has(from)? {
+            return Err(Error::UnknownKey);
+        }
+
         let from_path = from.as_path(&self.root);
         let to_path = to.as_path(&self.root);
 
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
         let dir = to.scope().as_path(&self.root);
         if !dir.try_exists().unwrap_or_default() {
             fs::create_dir_all(dir)?;
         }
 
+        let existed = self.track_changes && self.has(to)?;
         fs::rename(&from_path, to_path)?;
         remove_empty_parent_dirs(from_path.parent().ok_or(Error::Unknown)?);
 
-        Ok(())
+        self.remove_expiry(to)?;
+        self.move_expiry(from, to)?;
+        self.record_deleted(from)?;
+        self.record_stored(to, existed)
     }
 
+    /// Moves the directory of the scope if the target scope has no
+    /// directory yet, and otherwise moves the values one by one, replacing
+    /// the values in the target scope for the same keys.
     fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        check_path(to.segments().iter().map(|segment| segment.as_ref()))?;
+        if from == to {
+            return Ok(());
+        }
+
         let from_path = from.as_path(&self.root);
         let to_path = to.as_path(&self.root);
 
-        if !to_path.try_exists().unwrap_or_default() {
-            fs::create_dir_all(to_path.clone())?;
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        let keys = self.list_keys(from)?;
+
+        let to_is_empty = match fs::read_dir(&to_path) {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&to_path)?;
+                true
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if to_is_empty {
+            fs::rename(from_path.as_path(), to_path.as_path())?;
+            remove_empty_parent_dirs(from_path);
+
+            self.move_expiry(from, to)?;
+            for key in keys {
+                self.record_deleted(&key)?;
+                if let Some(target) = key.rebase(from, to) {
+                    self.record_stored(&target, false)?;
+                }
+            }
+
+            return Ok(());
+        }
+
+        for key in keys {
+            let Some(target) = key.rebase(from, to) else {
+                continue;
+            };
+
+            let dir = target.scope().as_path(&self.root);
+            if !dir.try_exists().unwrap_or_default() {
+                fs::create_dir_all(dir)?;
+            }
+
+            let existed = self.track_changes && self.has(&target)?;
+            fs::rename(key.as_path(&self.root), target.as_path(&self.root))?;
+
+            self.remove_expiry(&target)?;
+            self.move_expiry(&key, &target)?;
+            self.record_deleted(&key)?;
+            self.record_stored(&target, existed)?;
+        }
+
+        // Only the files of expired values are left.
+        fs::remove_dir_all(&from_path)?;
+        remove_empty_parent_dirs(from_path.parent().ok_or(Error::Unknown)?);
+        self.remove_expiries(from)
+    }
+
+    /// Copies the values one by one, so if copying fails part of the values
+    /// may have been copied.
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        let from_path = from.as_path(&self.root);
+        if !from_path.exists() {
+            return Ok(());
         }
 
-        fs::rename(from_path.as_path(), to_path.as_path())?;
-        remove_empty_parent_dirs(from_path);
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        for path in list_files_recursive(from_path)? {
+            let key = path.as_key(&self.root)?;
+            if let Some(target) = key.rebase(from, to) {
+                self.copy_file(&key, &target)?;
+            }
+        }
 
         Ok(())
     }
 
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        if self.copy_file(from, to)? {
+            Ok(())
+        } else {
+            Err(Error::UnknownKey)
+        }
+    }
+
     fn delete(&self, key: &Key) -> Result<()> {
+        match self.delete_existing(key) {
+            Err(Error::UnknownKey) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Deletes the file of the key. An expired value is deleted, but results
+    /// in an [`Error::UnknownKey`] like a missing file.
+    fn delete_existing(&self, key: &Key) -> Result<()> {
         let path = key.as_path(&self.root);
 
-        fs::remove_file(&path)?;
+        let expired = self.is_expired(key)?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::UnknownKey),
+            res => res?,
+        }
         remove_empty_parent_dirs(path.parent().ok_or(Error::Unknown)?);
 
-        Ok(())
+        if expired {
+            self.remove_expiry(key)?;
+            return Err(Error::UnknownKey);
+        }
+
+        self.remove_expiry(key)?;
+        self.record_deleted(key)
     }
 
+    /// Deleting the global scope keeps the namespace directory with the
+    /// change records and lock files, like [`WriteStore::clear`].
     fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        if scope.is_global() {
+            return self.clear_scope(scope);
+        }
+
         let path = scope.as_path(&self.root);
+        let keys = self.list_keys(scope)?;
 
         fs::remove_dir_all(&path)?;
         remove_empty_parent_dirs(path);
 
-        Ok(())
+        self.remove_expiries(scope)?;
+        self.record_all_deleted(&keys)
     }
 
-    fn clear(&self) -> Result<()> {
-        if self.root.exists() {
-            let _ = fs::remove_dir_all(&self.root);
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        let path = scope.as_path(&self.root);
+        if !path.exists() {
+            return Ok(());
         }
 
-        Ok(())
+        let keys = self.list_keys(scope)?;
+
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if !is_internal_dir(&path) {
+                    fs::remove_dir_all(path)?;
+                }
+            } else {
+                fs::remove_file(path)?;
+            }
+        }
+
+        self.remove_expiries(scope)?;
+        self.record_all_deleted(&keys)
+    }
+
+    /// Removes all values and expiries and records that the values were
+    /// deleted. The namespace directory itself is kept, as are the change
+    /// records and the lock files, which may be in use by other instances.
+    fn clear(&self) -> Result<()> {
+        self.clear_scope(&Scope::global())
     }
 
+    /// Migrates the namespace by renaming its directory.
+    ///
+    /// Writes by other instances for the same namespace that are in progress
+    /// complete before the directory is renamed. Writes by instances that
+    /// still use the original namespace afterwards fail with
+    /// [`Error::NamespaceMigration`].
     fn migrate_namespace(&mut self, namespace: kvx_types::NamespaceBuf) -> Result<()> {
+        let mut namespace_lock = self.namespace_lock()?;
+        let _write_lock = namespace_lock.write()?;
+        self.check_not_migrated()?;
+
         let root_parent = self.root.parent().ok_or(Error::NamespaceMigration(format!(
             "cannot get parent dir for: {}",
             self.root.display()
@@ -229,7 +1389,9 @@ impl WriteStore for Disk {
         let new_root = root_parent.join(namespace.as_str());
 
         if new_root.exists() {
-            // If the target directory already exists, then it must be empty.
+            // If the target directory already exists, then it must not
+            // contain any values. Directories used by the store itself, e.g.
+            // for the change records of a cleared namespace, are replaced.
             if new_root
                 .read_dir()
                 .map_err(|e| {
@@ -239,17 +1401,33 @@ impl WriteStore for Disk {
                         e,
                     ))
                 })?
-                .next()
-                .is_some()
+                .flatten()
+                .any(|entry| !is_internal_dir(&entry.path()))
             {
                 return Err(Error::NamespaceMigration(format!(
                     "target dir {} already exists and is not empty",
                     new_root.display(),
                 )));
             }
+
+            fs::remove_dir_all(&new_root).map_err(|e| {
+                Error::NamespaceMigration(format!(
+                    "cannot remove dir {}. Error: {}",
+                    new_root.display(),
+                    e
+                ))
+            })?;
         }
 
-        fs::rename(&self.root, &new_root).map_err(|e| {
+        // A namespace without keys has no directory, unless it was just
+        // created.
+        let renamed = fs::rename(&self.root, &new_root).or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound if self.root.exists() => fs::rename(&self.root, &new_root),
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        });
+
+        renamed.map_err(|e| {
             Error::NamespaceMigration(format!(
                 "cannot rename dir from {} to {}. Error: {}",
                 self.root.display(),
@@ -257,25 +1435,233 @@ impl WriteStore for Disk {
                 e
             ))
         })?;
+
+        // Stores for the old namespace that wait for the lock fail from now
+        // on, instead of writing to the old directory.
+        let migrations_path = self.migrations_path()?;
+        fs::write(&migrations_path, (self.migrations + 1).to_string()).map_err(|e| {
+            Error::NamespaceMigration(format!(
+                "cannot write {}. Error: {}",
+                migrations_path.display(),
+                e
+            ))
+        })?;
+
         self.root = new_root;
+        self.migrations = self.read_migrations()?;
         Ok(())
     }
 }
 
 impl KeyValueStoreBackend for Disk {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Disk
+    }
+
+    /// Runs the callback while holding a write lock on the lock file of the
+    /// given scope, and read locks on the lock files of all its super scopes.
+    ///
+    /// This way a transaction on `a/b` excludes transactions on `a/b`, on its
+    /// super scopes `a` and the global scope, and on its sub scopes such as
+    /// `a/b/c`, while transactions on the sibling scope `a/c` run
+    /// concurrently. The locks are taken from the global scope down, so
+    /// concurrent transactions cannot deadlock.
+    ///
+    /// The writes of the callback are buffered, see [`BufferedTransaction`],
+    /// and only written to disk when it succeeds, so nothing is persisted if
+    /// it fails or aborts. They are all written to temporary files before
+    /// any of them is persisted, see [`BufferedBackend::apply`], so only a
+    /// failure to rename or remove a file leaves part of them persisted.
+    ///
+    /// A transaction started on this store from the callback on the same
+    /// scope or a sub scope runs without taking any locks, as do
+    /// [`WriteStore::store_many`] and [`WriteStore::compare_and_swap`],
+    /// because the scope is locked by this thread already. One on a super
+    /// scope fails with [`Error::MutexLock`] instead of waiting for itself
+    /// forever.
     fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
-        let lock_file_dir = self.root.join(LOCK_FILE_DIR);
+        let run = || BufferedTransaction::run(self, callback);
+        match self.with_scope_lock(scope, self.lock_deadline(), run)? {
+            Some(()) => Ok(()),
+            None => Err(Error::LockTimeout(format!(
+                "Scope {} already locked",
+                scope
+            ))),
+        }
+    }
+
+    /// Runs the callback like [`Disk::transaction`], unless the locks cannot
+    /// all be taken within `timeout`.
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        let deadline = Some(Instant::now() + timeout);
+        Ok(self
+            .with_scope_lock(scope, deadline, || BufferedTransaction::run(self, callback))?
+            .is_some())
+    }
+}
 
-        let mut file_lock = FileLock::create(scope.as_path(lock_file_dir))?;
-        let _write_lock = file_lock.write()?;
+impl BufferedBackend for Disk {
+    fn expiry(&self, key: &Key) -> Result<Option<SystemTime>> {
+        Ok(self
+            .expires_at(key)?
+            .filter(|expires_at| !has_passed(*expires_at))
+            .map(|expires_at| SystemTime::UNIX_EPOCH + Duration::from_millis(expires_at)))
+    }
 
-        let mut store = self.clone();
-        callback(&mut store)?;
+    /// Writes all values and expiry times to temporary files before any of
+    /// them is persisted or any key is deleted, like
+    /// [`WriteStore::store_many`], so nothing is applied if writing any of
+    /// them fails. Only the renames and removals that follow can still fail
+    /// halfway.
+    fn apply(&self, writes: BTreeMap<Key, PendingWrite>) -> Result<()> {
+        let tmp_files = writes
+            .into_iter()
+            .map(|(key, write)| {
+                let files = match write {
+                    Some((value, expires_at)) => {
+                        let expiry_file = expires_at
+                            .map(|expires_at| {
+                                self.write_tmp(&key, &Value::from(as_millis(expires_at)))
+                            })
+                            .transpose()?;
+                        Some((self.write_tmp_value(&key, &value)?, expiry_file))
+                    }
+                    None => None,
+                };
+                Ok((key, files))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let namespace_lock = self.namespace_lock()?;
+        let _read_lock = namespace_lock.read()?;
+        self.check_not_migrated()?;
+
+        for (key, files) in tmp_files {
+            let Some((tmp_file, expiry_file)) = files else {
+                self.delete(&key)?;
+                continue;
+            };
+
+            let existed = self.track_changes && self.has(&key)?;
+            match expiry_file {
+                // Write the expiry first, so the value is never visible
+                // without it.
+                Some(expiry_file) => {
+                    self.persist_tmp(expiry_file, self.expiry_path(&key))?;
+                    self.persist_tmp(tmp_file, key.as_path(&self.root))?;
+                }
+                None => {
+                    self.persist_tmp(tmp_file, key.as_path(&self.root))?;
+                    self.remove_expiry(&key)?;
+                }
+            }
+            self.record_stored(&key, existed)?;
+        }
 
         Ok(())
     }
 }
 
+thread_local! {
+    /// The lock file directories of the scopes locked by the transactions
+    /// running on this thread, see [`Disk::with_scope_lock`].
+    static LOCKED_SCOPES: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Registers a scope as locked by this thread for as long as it lives, so
+/// that it is also unregistered if the callback panics.
+struct LockedScope;
+
+impl LockedScope {
+    fn register(lock_dir: PathBuf) -> Self {
+        LOCKED_SCOPES.with_borrow_mut(|locked| locked.push(lock_dir));
+        LockedScope
+    }
+}
+
+impl Drop for LockedScope {
+    fn drop(&mut self) {
+        LOCKED_SCOPES.with_borrow_mut(|locked| locked.pop());
+    }
+}
+
+impl Disk {
+    /// Returns the deadline for taking the locks of a transaction started
+    /// now, if there is a lock timeout.
+    fn lock_deadline(&self) -> Option<Instant> {
+        self.lock_timeout.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Takes the locks for a transaction on the scope and runs `f`.
+    ///
+    /// Without a deadline this waits for the locks for as long as it takes,
+    /// otherwise it returns `None` without running `f` if they cannot be
+    /// taken before the deadline. If this thread holds the locks of the
+    /// scope or a super scope already, `f` is run right away.
+    fn with_scope_lock<T>(
+        &self,
+        scope: &Scope,
+        deadline: Option<Instant>,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<Option<T>> {
+        let lock_file_dir = self.root.join(LOCK_FILE_DIR);
+        let lock_dir = scope.as_path(&lock_file_dir);
+
+        let (covered, overlapping) = LOCKED_SCOPES.with_borrow(|locked| {
+            (
+                locked.iter().any(|dir| lock_dir.starts_with(dir)),
+                locked.iter().any(|dir| dir.starts_with(&lock_dir)),
+            )
+        });
+        if covered {
+            return f().map(Some);
+        } else if overlapping {
+            return Err(Error::MutexLock(format!(
+                "Scope {} overlaps with a transaction on this thread",
+                scope
+            )));
+        }
+
+        let super_locks = iter::once(Scope::global())
+            .chain(scope.sub_scopes())
+            .take(scope.depth())
+            .map(|super_scope| FileLock::create(super_scope.as_path(&lock_file_dir)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut _read_locks = Vec::with_capacity(super_locks.len());
+        for super_lock in &super_locks {
+            match super_lock.read_until(deadline)? {
+                Some(read_lock) => _read_locks.push(read_lock),
+                None => return Ok(None),
+            }
+        }
+
+        let mut file_lock = FileLock::create(lock_dir.clone())?;
+        // This cannot be a method of FileLock like `read_until`, as the
+        // guard it returns would borrow the lock mutably in each iteration.
+        let _write_lock = match deadline {
+            None => file_lock.write()?,
+            Some(deadline) => loop {
+                match file_lock.lock.try_write() {
+                    Ok(write_lock) => break write_lock,
+                    Err(e) => {
+                        if !wait_for_lock(e, deadline)? {
+                            return Ok(None);
+                        }
+                    }
+                }
+            },
+        };
+
+        let _locked = LockedScope::register(lock_dir);
+        f().map(Some)
+    }
+}
+
 trait AsPath {
     fn as_path(&self, root: impl AsRef<Path>) -> PathBuf;
 }
@@ -283,10 +1669,9 @@ trait AsPath {
 impl AsPath for Key {
     fn as_path(&self, root: impl AsRef<Path>) -> PathBuf {
         let mut path = root.as_ref().to_path_buf();
-        for segment in self.scope() {
+        for segment in self.segments() {
             path.push(segment.as_str());
         }
-        path.push(self.name().as_str());
         path
     }
 }
@@ -344,6 +1729,12 @@ impl PathBufExt for PathBuf {
     }
 }
 
+/// A lock on a lock file, using `flock` through the `fd-lock` crate.
+///
+/// The lock is held on the open file rather than by the existence of the
+/// file, so the OS releases it when the process holding it exits, even when
+/// it is killed or crashes. A lock file that is left behind is therefore
+/// never stale: it is simply locked again by the next transaction.
 #[derive(Debug)]
 struct FileLock {
     lock: fd_lock::RwLock<File>,
@@ -369,11 +1760,71 @@ impl FileLock {
         Ok(FileLock { lock })
     }
 
+    fn read(&self) -> Result<fd_lock::RwLockReadGuard<'_, File>> {
+        self.lock
+            .read()
+            .map_err(|e| Error::Other(format!("Cannot get file lock: {}", e)))
+    }
+
     fn write(&mut self) -> Result<fd_lock::RwLockWriteGuard<'_, File>> {
         self.lock
             .write()
             .map_err(|e| Error::Other(format!("Cannot get file lock: {}", e)))
     }
+
+    /// Takes a read lock, or returns `None` if it is still held by a writer
+    /// at the deadline. Waits indefinitely without a deadline.
+    fn read_until(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<Option<fd_lock::RwLockReadGuard<'_, File>>> {
+        let Some(deadline) = deadline else {
+            return self.read().map(Some);
+        };
+
+        loop {
+            match self.lock.try_read() {
+                Ok(guard) => return Ok(Some(guard)),
+                Err(e) => {
+                    if !wait_for_lock(e, deadline)? {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Waits before trying again to take a lock that could not be taken because
+/// of `e`. Returns `false` if the deadline has passed, and an error if `e`
+/// was not caused by someone else holding the lock.
+fn wait_for_lock(e: io::Error, deadline: Instant) -> Result<bool> {
+    if e.kind() != io::ErrorKind::WouldBlock {
+        Err(Error::Other(format!("Cannot get file lock: {}", e)))
+    } else if Instant::now() >= deadline {
+        Ok(false)
+    } else {
+        thread::sleep(LOCK_WAIT);
+        Ok(true)
+    }
+}
+
+/// Returns whether the directory is used by the store itself, rather than
+/// for a scope. Keys with these names in their path are rejected, see
+/// [`check_path`], so this holds at any depth.
+fn is_internal_dir(path: &Path) -> bool {
+    path.ends_with(LOCK_FILE_DIR) || path.ends_with(EXPIRY_DIR) || path.ends_with(CHANGES_DIR)
+}
+
+/// Returns an error if any segment of the path of a key or scope is the
+/// name of a directory used by the store itself, see [`is_internal_dir`].
+fn check_path<'a>(mut segments: impl Iterator<Item = &'a Segment>) -> Result<()> {
+    if segments.any(|segment| [LOCK_FILE_DIR, EXPIRY_DIR, CHANGES_DIR].contains(&segment.as_str()))
+    {
+        Err(Error::InvalidKey)
+    } else {
+        Ok(())
+    }
 }
 
 fn list_files_recursive(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
@@ -382,7 +1833,9 @@ fn list_files_recursive(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     for result in fs::read_dir(dir)? {
         let path = result?.path();
         if path.is_dir() {
-            files.extend(list_files_recursive(path)?);
+            if !is_internal_dir(&path) {
+                files.extend(list_files_recursive(path)?);
+            }
         } else {
             files.push(path);
         }
@@ -391,13 +1844,76 @@ fn list_files_recursive(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// Lazily walks all files under a directory, depth first, skipping the lock
+/// file directory.
+struct FileWalker {
+    dirs: Vec<fs::ReadDir>,
+}
+
+impl FileWalker {
+    fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(FileWalker {
+            dirs: vec![fs::read_dir(dir)?],
+        })
+    }
+}
+
+impl Iterator for FileWalker {
+    type Item = Result<PathBuf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let path = match self.dirs.last_mut()?.next() {
+                None => {
+                    self.dirs.pop();
+                    continue;
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                Some(Ok(entry)) => entry.path(),
+            };
+
+            if !path.is_dir() {
+                return Some(Ok(path));
+            }
+
+            if !is_internal_dir(&path) {
+                match fs::read_dir(path) {
+                    Ok(dir) => self.dirs.push(dir),
+                    Err(e) => return Some(Err(e.into())),
+                }
+            }
+        }
+    }
+}
+
+fn count_files_recursive(dir: impl AsRef<Path>) -> Result<usize> {
+    let mut count = 0;
+
+    for result in fs::read_dir(dir)? {
+        let path = result?.path();
+        if path.is_dir() {
+            if !is_internal_dir(&path) {
+                count += count_files_recursive(path)?;
+            }
+        } else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Returns whether the path is a non-empty directory for a scope.
+fn is_scope_dir(path: &Path) -> Result<bool> {
+    Ok(path.is_dir() && !is_internal_dir(path) && path.read_dir()?.next().is_some())
+}
+
 fn list_dirs_recursive(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
     let mut dirs = Vec::new();
 
     for result in fs::read_dir(dir)? {
         let path = result?.path();
-        if path.is_dir() && !path.ends_with(LOCK_FILE_DIR) && path.read_dir()?.next().is_some() {
-            // a non-empty directory exists for the scope, recurse and add
+        if is_scope_dir(&path)? {
             dirs.extend(list_dirs_recursive(&path)?);
             dirs.push(path);
         }
@@ -416,3 +1932,723 @@ fn remove_empty_parent_dirs(path: impl AsRef<Path>) {
         .is_some()
     {}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{mpsc, Barrier},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use serde_json::Value;
+    use url::Url;
+
+    use super::{common_scope, Compression, Disk, Durability, TMP_FILE_MAX_AGE};
+    use crate::{Key, KeyValueStoreBackend, ReadStore, Scope, Segment, WriteStore};
+
+    #[test]
+    fn test_new_rejects_invalid_namespace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("base");
+        let path = path.to_str().unwrap();
+
+        for namespace in ["", ".", "..", "../evil", "a/b", "/abs", "evil/"] {
+            assert!(
+                matches!(
+                    Disk::new(path, namespace),
+                    Err(crate::Error::InvalidPath(_))
+                ),
+                "{}",
+                namespace
+            );
+        }
+        assert!(!dir.path().join("evil").exists());
+        assert!(Disk::new(path, "valid").is_ok());
+    }
+
+    #[test]
+    fn test_common_scope() {
+        let keys: Vec<Key> = ["a/b/c/k1", "a/b/k2", "a/b/d/k3"]
+            .iter()
+            .map(|key| key.parse().unwrap())
+            .collect();
+
+        assert_eq!(common_scope(keys.iter()), Some("a/b".parse().unwrap()));
+        assert_eq!(
+            common_scope(keys[..1].iter()),
+            Some("a/b/c".parse().unwrap())
+        );
+        assert_eq!(
+            common_scope(["a/k1".parse().unwrap(), "b/k2".parse().unwrap()].iter()),
+            Some(Scope::global())
+        );
+        assert_eq!(common_scope([].iter()), None);
+    }
+
+    /// Tries a transaction on `inner` while a transaction on `outer` is held
+    /// and returns whether it could take its locks.
+    ///
+    /// Locks that are free are taken at the first attempt, so the timeout
+    /// only delays the result if the transactions exclude each other.
+    fn completes_while_locked(disk: &Disk, outer: &Scope, inner: &Scope) -> bool {
+        let barrier = Barrier::new(2);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                disk.transaction(outer, &mut |_| {
+                    barrier.wait();
+                    barrier.wait();
+                    Ok(())
+                })
+                .unwrap();
+            });
+
+            barrier.wait();
+            let completed = disk.try_transaction(inner, Duration::from_millis(50), &mut |_| Ok(()));
+            barrier.wait();
+            completed.unwrap()
+        })
+    }
+
+    #[test]
+    fn test_transaction_locks_overlapping_scopes() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "transaction_scope").unwrap();
+
+        let global = Scope::global();
+        let parent: Scope = "a".parse().unwrap();
+        let child: Scope = "a/b".parse().unwrap();
+        let grandchild: Scope = "a/b/c".parse().unwrap();
+        let sibling: Scope = "a/c".parse().unwrap();
+
+        // Transactions on overlapping scopes are mutually exclusive.
+        assert!(!completes_while_locked(&disk, &parent, &parent));
+        assert!(!completes_while_locked(&disk, &parent, &child));
+        assert!(!completes_while_locked(&disk, &child, &parent));
+        assert!(!completes_while_locked(&disk, &grandchild, &parent));
+        assert!(!completes_while_locked(&disk, &global, &grandchild));
+        assert!(!completes_while_locked(&disk, &child, &global));
+
+        // Transactions on sibling scopes are not.
+        assert!(completes_while_locked(&disk, &child, &sibling));
+        assert!(completes_while_locked(&disk, &grandchild, &sibling));
+    }
+
+    #[test]
+    fn test_try_transaction() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "try_transaction").unwrap();
+        let barrier = Barrier::new(2);
+        let timeout = Duration::from_millis(50);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                disk.transaction(&"a".parse().unwrap(), &mut |_| {
+                    barrier.wait();
+                    barrier.wait();
+                    Ok(())
+                })
+                .unwrap();
+            });
+            barrier.wait();
+
+            // Both the write lock on the scope itself and the read lock on
+            // a super scope time out.
+            for scope in ["a", "a/b"] {
+                let mut called = false;
+                let ran = disk
+                    .try_transaction(&scope.parse().unwrap(), timeout, &mut |_| {
+                        called = true;
+                        Ok(())
+                    })
+                    .unwrap();
+                assert!(!ran);
+                assert!(!called);
+            }
+
+            assert!(disk
+                .try_transaction(&"b".parse().unwrap(), timeout, &mut |_| Ok(()))
+                .unwrap());
+            barrier.wait();
+        });
+
+        assert!(disk
+            .try_transaction(&"a/b".parse().unwrap(), timeout, &mut |_| Ok(()))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_compare_and_swap_locks_scope() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "compare_and_swap")
+            .unwrap()
+            .with_lock_timeout(Duration::from_millis(50));
+        let scope: Scope = "a".parse().unwrap();
+        let key: Key = "a/b/key".parse().unwrap();
+
+        // A transaction on the scope or a super scope does not block its own
+        // thread, but one on a sub scope cannot lock its super scope.
+        for scope in [Scope::global(), scope.clone(), key.scope().clone()] {
+            disk.transaction(&scope, &mut |_| {
+                let current = disk.get(&key)?;
+                let next = current.as_ref().and_then(Value::as_i64).unwrap_or(0) + 1;
+                assert!(disk.compare_and_swap(&key, current.as_ref(), Value::from(next))?);
+                Ok(())
+            })
+            .unwrap();
+        }
+        assert_eq!(disk.get(&key).unwrap(), Some(Value::from(3)));
+        assert!(matches!(
+            disk.transaction(key.scope(), &mut |_| disk
+                .transaction(&scope, &mut |_| Ok(()))),
+            Err(crate::Error::MutexLock(_))
+        ));
+
+        // Transactions on a super scope block other threads.
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                disk.transaction(&scope, &mut |_| {
+                    barrier.wait();
+                    barrier.wait();
+                    Ok(())
+                })
+                .unwrap();
+            });
+
+            barrier.wait();
+            let result = disk.compare_and_swap(&key, Some(&Value::from(3)), Value::from(4));
+            barrier.wait();
+            assert!(matches!(result, Err(crate::Error::LockTimeout(_))));
+        });
+        assert_eq!(disk.get(&key).unwrap(), Some(Value::from(3)));
+    }
+
+    #[test]
+    fn test_store_many_in_transaction() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "store_many")
+            .unwrap()
+            .with_lock_timeout(Duration::from_millis(50));
+        let entries: Vec<(Key, Value)> = vec![
+            ("a/b/k1".parse().unwrap(), Value::from(1)),
+            ("a/c/k2".parse().unwrap(), Value::from(2)),
+        ];
+
+        disk.transaction(&"a".parse().unwrap(), &mut |_| disk.store_many(&entries))
+            .unwrap();
+        assert_eq!(disk.list_keys(&Scope::global()).unwrap().len(), 2);
+
+        // A transaction on a sub scope of the common scope blocks it, as the
+        // values are stored like in a transaction on the common scope.
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                disk.transaction(&"a/b".parse().unwrap(), &mut |_| {
+                    barrier.wait();
+                    barrier.wait();
+                    Ok(())
+                })
+                .unwrap();
+            });
+
+            barrier.wait();
+            let result = disk.store_many(&entries);
+            barrier.wait();
+            assert!(matches!(result, Err(crate::Error::LockTimeout(_))));
+        });
+    }
+
+    #[test]
+    fn test_transaction_applies_all_or_nothing() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "all_or_nothing").unwrap();
+        let existing: Key = "a/k0".parse().unwrap();
+        disk.store(&existing, Value::from(0)).unwrap();
+
+        // The key in the internal directory sorts last, so its write fails
+        // after the others were buffered.
+        let result = disk.transaction(&Scope::global(), &mut |t| {
+            t.delete(&existing)?;
+            t.store(&"a/k1".parse().unwrap(), Value::from(1))?;
+            t.store_with_expiry(
+                &"a/k2".parse().unwrap(),
+                Value::from(2),
+                SystemTime::now() + Duration::from_secs(60),
+            )?;
+            t.store(&"b/.expiry/k".parse().unwrap(), Value::from(3))
+        });
+        assert!(matches!(result, Err(crate::Error::InvalidKey)));
+        assert_eq!(disk.list_keys(&Scope::global()).unwrap(), vec![existing]);
+        assert!(!disk.has_expiries());
+
+        // Maintenance is not part of a transaction, so it fails rather than
+        // report that nothing was removed.
+        disk.transaction(&Scope::global(), &mut |t| {
+            assert!(t.purge_expired().is_err());
+            assert!(t.prune_changes(SystemTime::now()).is_err());
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_leftover_lock_file() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "leftover_lock").unwrap();
+        let scope: Scope = "a".parse().unwrap();
+
+        // A lock file left behind by a process that was killed during a
+        // transaction, which still has the content written by that process.
+        let lock_dir = disk.root.join(super::LOCK_FILE_DIR).join("a");
+        fs::create_dir_all(&lock_dir).unwrap();
+        fs::write(lock_dir.join(super::LOCK_FILE_NAME), "pid 1234").unwrap();
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            disk.transaction(&scope, &mut |_| Ok(())).unwrap();
+            done_tx.send(()).unwrap();
+        });
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_store_bytes_as_they_are() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "bytes").unwrap();
+        let key: Key = "scope/blob".parse().unwrap();
+
+        disk.store_bytes(&key, b"\x01\x02").unwrap();
+        assert_eq!(
+            fs::read(base.path().join("bytes/scope/blob")).unwrap(),
+            b"\0kvxb\x01\x02"
+        );
+        assert_eq!(disk.get_bytes(&key).unwrap(), Some(vec![1, 2]));
+        assert_eq!(
+            disk.get_raw(&key).unwrap(),
+            Some(br#"{"kvx_bytes":"AQI="}"#.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_escaped_key() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "escaped").unwrap();
+
+        let scope = Scope::from_segment(Segment::parse_escaped("/etc").unwrap());
+        let key = Key::new_scoped(
+            scope.clone(),
+            Segment::parse_escaped("https://example.com/").unwrap(),
+        );
+        disk.store(&key, Value::from(1)).unwrap();
+
+        assert!(base
+            .path()
+            .join("escaped/%2Fetc/https:%2F%2Fexample.com%2F")
+            .is_file());
+
+        let keys = disk.list_keys(&scope).unwrap();
+        assert_eq!(keys, vec![key.clone()]);
+        assert_eq!(keys[0].name().to_unescaped(), "https://example.com/");
+        assert_eq!(disk.get(&key).unwrap(), Some(Value::from(1)));
+    }
+
+    #[test]
+    fn test_internal_dir_names() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "internal").unwrap();
+        let key: Key = "a/k".parse().unwrap();
+        disk.store(&key, Value::from(1)).unwrap();
+
+        for name in [".locks/k", "a/.expiry/k", "a/b/.changes", ".changes"] {
+            let internal: Key = name.parse().unwrap();
+            assert!(disk.store(&internal, Value::from(1)).is_err(), "{}", name);
+            assert!(disk.move_value(&key, &internal).is_err(), "{}", name);
+        }
+        assert!(disk
+            .move_scope(&"a".parse().unwrap(), &"b/.expiry".parse().unwrap())
+            .is_err());
+        assert_eq!(disk.list_keys(&Scope::global()).unwrap(), vec![key]);
+    }
+
+    #[test]
+    fn test_clear_scope_keeps_dir() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "clear_scope").unwrap();
+
+        let scope: Scope = "a".parse().unwrap();
+        disk.store(&"a/k1".parse().unwrap(), Value::from(1))
+            .unwrap();
+        disk.store(&"a/b/k2".parse().unwrap(), Value::from(2))
+            .unwrap();
+
+        disk.clear_scope(&scope).unwrap();
+        assert!(disk.list_keys(&scope).unwrap().is_empty());
+        assert!(disk.has_scope(&scope).unwrap());
+
+        disk.delete_scope(&scope).unwrap();
+        assert!(!disk.has_scope(&scope).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_namespace_waits_for_writes() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let writer = Disk::new(path, "migrate_wait").unwrap();
+        let mut migrated = Disk::new(path, "migrate_wait").unwrap();
+        writer.store(&"k".parse().unwrap(), Value::from(1)).unwrap();
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let (migrated_tx, migrated_rx) = mpsc::channel();
+
+        // The channels are moved into the scope, so the threads are released
+        // if an assertion fails.
+        let (writer, migrated) = (&writer, &mut migrated);
+        thread::scope(move |s| {
+            s.spawn(move || {
+                let namespace_lock = writer.namespace_lock().unwrap();
+                let _read_lock = namespace_lock.read().unwrap();
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            });
+
+            locked_rx.recv().unwrap();
+            s.spawn(move || {
+                migrated
+                    .migrate_namespace("migrate_wait_new".parse().unwrap())
+                    .unwrap();
+                migrated_tx.send(()).unwrap();
+            });
+
+            // The migration cannot complete while the write holds its lock.
+            assert_eq!(
+                migrated_rx.recv_timeout(Duration::from_millis(50)),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            );
+            assert!(writer.root.exists());
+
+            release_tx.send(()).unwrap();
+            migrated_rx.recv().unwrap();
+        });
+        assert!(!writer.root.exists());
+    }
+
+    #[test]
+    fn test_migrate_namespace_concurrent_store() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let writer = Disk::new(path, "migrate_store").unwrap();
+        let mut migrated = Disk::new(path, "migrate_store").unwrap();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let stored = thread::scope(|s| {
+            let handle = s.spawn(|| {
+                (0..200)
+                    .filter(|i| {
+                        if *i == 10 {
+                            started_tx.send(()).unwrap();
+                        }
+                        match writer.store(&format!("k{}", i).parse().unwrap(), Value::from(*i)) {
+                            Ok(()) => true,
+                            Err(crate::Error::NamespaceMigration(_)) => false,
+                            Err(e) => panic!("unexpected error: {}", e),
+                        }
+                    })
+                    .count()
+            });
+
+            started_rx.recv().unwrap();
+            migrated
+                .migrate_namespace("migrate_store_new".parse().unwrap())
+                .unwrap();
+            handle.join().unwrap()
+        });
+
+        // Every successful write ended up in the new namespace, the writes
+        // after the migration failed without recreating the old one.
+        assert!(!base.path().join("migrate_store").exists());
+        let new_keys = migrated.list_keys(&Scope::global()).unwrap().len();
+        assert_eq!(new_keys, stored);
+        assert!(matches!(
+            writer.store(&"k".parse().unwrap(), Value::from(1)),
+            Err(crate::Error::NamespaceMigration(_))
+        ));
+        assert!(!base.path().join("migrate_store").exists());
+    }
+
+    #[test]
+    fn test_clear_keeps_root() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "clear_root")
+            .unwrap()
+            .with_change_tracking(true);
+        let since = SystemTime::now();
+
+        disk.store(&"a/b/name".parse().unwrap(), Value::from(1))
+            .unwrap();
+        disk.store_with_expiry(
+            &"name".parse().unwrap(),
+            Value::from(2),
+            SystemTime::now() + Duration::from_secs(60),
+        )
+        .unwrap();
+        disk.clear().unwrap();
+
+        assert!(disk.is_empty().unwrap());
+        assert!(disk.root.exists());
+        assert!(!disk.has_expiries());
+        assert_eq!(disk.changes_since(since).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_delete_global_scope_keeps_changes() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "delete_global")
+            .unwrap()
+            .with_change_tracking(true);
+        let deleted: Key = "deleted".parse().unwrap();
+        let stored: Key = "a/stored".parse().unwrap();
+
+        disk.store(&deleted, Value::from(1)).unwrap();
+        disk.delete(&deleted).unwrap();
+
+        // Changes are recorded with millisecond precision.
+        thread::sleep(Duration::from_millis(20));
+        let since = SystemTime::now();
+        thread::sleep(Duration::from_millis(20));
+        disk.store(&stored, Value::from(2)).unwrap();
+
+        disk.delete_scope(&Scope::global()).unwrap();
+
+        assert!(disk.is_empty().unwrap());
+        assert!(disk.root.join(super::CHANGES_DIR).exists());
+        let changes = disk.changes_since(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(changes.contains(&(deleted, crate::ChangeKind::Deleted)));
+        assert_eq!(
+            disk.changes_since(since).unwrap(),
+            vec![(stored, crate::ChangeKind::Deleted)]
+        );
+    }
+
+    #[test]
+    fn test_changes_not_tracked_by_default() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "untracked").unwrap();
+        let key: Key = "a/name".parse().unwrap();
+
+        disk.store(&key, Value::from(1)).unwrap();
+        disk.move_value(&key, &"b/name".parse().unwrap()).unwrap();
+        disk.delete_scope(&Scope::global()).unwrap();
+
+        assert!(!disk.root.join(super::CHANGES_DIR).exists());
+        assert!(matches!(
+            disk.changes_since(SystemTime::UNIX_EPOCH),
+            Err(crate::Error::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_clear_error() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "clear_error").unwrap();
+        disk.store(&"a/name".parse().unwrap(), Value::from(1))
+            .unwrap();
+
+        // The directory of the namespace cannot be read if it is replaced by
+        // a regular file, regardless of permissions.
+        fs::remove_dir_all(&disk.root).unwrap();
+        fs::write(&disk.root, "").unwrap();
+
+        assert!(disk.clear().is_err());
+        assert!(disk.root.is_file());
+    }
+
+    #[test]
+    fn test_migrate_namespace_without_dir() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let mut disk = Disk::new(path, "migrate_empty").unwrap();
+
+        assert!(!disk.root.exists());
+        disk.migrate_namespace("migrate_empty_new".parse().unwrap())
+            .unwrap();
+        assert!(disk.is_empty().unwrap());
+
+        disk.store(&"k".parse().unwrap(), Value::from(1)).unwrap();
+        assert!(base.path().join("migrate_empty_new").join("k").exists());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let base = tempfile::tempdir().unwrap();
+        let disk = Disk::new(base.path().to_str().unwrap(), "purge_expired").unwrap();
+
+        let expired: Key = "a/expired".parse().unwrap();
+        let live: Key = "a/live".parse().unwrap();
+        disk.store_with_expiry(
+            &expired,
+            Value::from(1),
+            SystemTime::now() - Duration::from_secs(60),
+        )
+        .unwrap();
+        disk.store_with_expiry(
+            &live,
+            Value::from(2),
+            SystemTime::now() + Duration::from_secs(60),
+        )
+        .unwrap();
+
+        assert_eq!(disk.purge_expired().unwrap(), 1);
+        assert!(!disk.root.join("a").join("expired").exists());
+        assert!(!disk.expiry_path(&expired).exists());
+
+        // Moving a value moves its expiry along.
+        let moved: Key = "b/moved".parse().unwrap();
+        disk.move_value(&live, &moved).unwrap();
+        assert!(disk.expiry_path(&moved).exists());
+        assert_eq!(
+            disk.list_keys(&Scope::global()).unwrap(),
+            vec![moved.clone()]
+        );
+
+        disk.delete(&moved).unwrap();
+        assert!(!disk.root.join(super::EXPIRY_DIR).exists());
+    }
+
+    #[test]
+    fn test_sweep_tmp() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let disk = Disk::new(path, "sweep_tmp").unwrap();
+
+        let orphaned = disk.tmp.join(".tmporphan");
+        fs::write(&orphaned, "{}").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&orphaned)
+            .unwrap()
+            .set_modified(SystemTime::now() - TMP_FILE_MAX_AGE - Duration::from_secs(60))
+            .unwrap();
+
+        let fresh = disk.tmp.join(".tmpfresh");
+        fs::write(&fresh, "{}").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&fresh)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(1))
+            .unwrap();
+
+        // Sweeping on construction can be disabled.
+        Disk::new_with_tmp_max_age(path, "sweep_tmp", None).unwrap();
+        assert!(orphaned.exists());
+
+        Disk::new(path, "sweep_tmp").unwrap();
+        assert!(!orphaned.exists());
+        assert!(fresh.exists());
+
+        assert_eq!(disk.sweep_tmp(TMP_FILE_MAX_AGE).unwrap(), 0);
+        assert_eq!(disk.sweep_tmp(Duration::ZERO).unwrap(), 1);
+        assert!(!fresh.exists());
+    }
+
+    #[test]
+    fn test_durability() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let key: Key = "a/key".parse().unwrap();
+
+        for durability in [Durability::None, Durability::Fsync, Durability::FsyncDir] {
+            let disk = Disk::new(path, "durability")
+                .unwrap()
+                .with_durability(durability);
+            disk.store(&key, Value::from(1)).unwrap();
+            disk.store_many(&[(key.clone(), Value::from(2))]).unwrap();
+
+            assert_eq!(disk.get(&key).unwrap(), Some(Value::from(2)));
+            disk.clear().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_durability_from_url() {
+        let durability = |url: &str| Durability::from_url(&Url::parse(url).unwrap());
+
+        assert_eq!(durability("local:///tmp").unwrap(), Durability::None);
+        assert_eq!(
+            durability("local:///tmp?durability=fsync").unwrap(),
+            Durability::Fsync
+        );
+        assert_eq!(
+            durability("local:///tmp?durability=fsync_dir").unwrap(),
+            Durability::FsyncDir
+        );
+        assert!(matches!(
+            durability("local:///tmp?durability=always"),
+            Err(crate::Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_compression_from_url() {
+        let compression = |url: &str| Compression::from_url(&Url::parse(url).unwrap());
+
+        assert_eq!(compression("local:///tmp").unwrap(), Compression::None);
+        assert_eq!(
+            compression("local:///tmp?compression=none").unwrap(),
+            Compression::None
+        );
+        #[cfg(feature = "compression")]
+        assert_eq!(
+            compression("local:///tmp?compression=zstd").unwrap(),
+            Compression::Zstd
+        );
+        assert!(matches!(
+            compression("local:///tmp?compression=lz4"),
+            Err(crate::Error::InvalidConfig(_))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression() {
+        let base = tempfile::tempdir().unwrap();
+        let path = base.path().to_str().unwrap();
+        let plain = Disk::new(path, "compression").unwrap();
+        let gzip = Disk::new(path, "compression")
+            .unwrap()
+            .with_compression(Compression::Gzip);
+        let zstd = Disk::new(path, "compression")
+            .unwrap()
+            .with_compression(Compression::Zstd);
+
+        let value = Value::from("a value that compresses well ".repeat(100));
+        for (name, disk) in [("plain", &plain), ("gzip", &gzip), ("zstd", &zstd)] {
+            let key: Key = format!("a/{}", name).parse().unwrap();
+            disk.store(&key, value.clone()).unwrap();
+
+            let contents = fs::read(base.path().join("compression/a").join(name)).unwrap();
+            assert_eq!(contents.starts_with(b"\0kvx"), disk != &plain);
+        }
+
+        // Every store reads the values regardless of their compression.
+        let keys = plain.list_keys(&"a".parse().unwrap()).unwrap();
+        assert_eq!(keys.len(), 3);
+        for disk in [&plain, &gzip, &zstd] {
+            for key in &keys {
+                assert_eq!(disk.get(key).unwrap(), Some(value.clone()));
+            }
+            assert_eq!(disk.list_entries(&Scope::global()).unwrap().len(), 3);
+        }
+
+        // Copies are written using the compression of the store.
+        gzip.copy_value(&"a/plain".parse().unwrap(), &"b".parse().unwrap())
+            .unwrap();
+        assert!(fs::read(base.path().join("compression/b"))
+            .unwrap()
+            .starts_with(b"\0kvx"));
+    }
+}
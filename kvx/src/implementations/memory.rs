@@ -1,116 +1,452 @@
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt::Display,
-    str::FromStr,
-    sync::{Mutex, MutexGuard},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant, SystemTime},
 };
 
 use kvx_types::NamespaceBuf;
 use lazy_static::lazy_static;
+use url::Url;
 
 use crate::{
-    Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope, TransactionCallback, WriteStore,
+    implementations::buffered::{BufferedBackend, BufferedTransaction, PendingWrite},
+    BackendKind, ChangeEvent, ChangeKind, Error, Key, KeyValueStoreBackend, ReadStore,
+    ReadTransactionCallback, Result, Scope, TransactionCallback, WriteStore,
 };
 
+/// A stored value with the time it expires, if any, and the times the key
+/// was created and last updated.
+#[derive(Clone, Debug)]
+struct StoredValue {
+    value: serde_json::Value,
+    expires_at: Option<SystemTime>,
+    created_at: SystemTime,
+    updated_at: SystemTime,
+}
+
+impl StoredValue {
+    fn is_live(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at > SystemTime::now())
+            .unwrap_or(true)
+    }
+}
+
 #[derive(Debug)]
-pub struct MemoryStore(HashMap<NamespaceBuf, HashMap<Key, serde_json::Value>>);
+pub struct MemoryStore {
+    values: HashMap<NamespaceBuf, HashMap<Key, StoredValue>>,
+    /// The time each deleted key was deleted, used for
+    /// [`ReadStore::changes_since`], for the namespaces that track changes.
+    tombstones: HashMap<NamespaceBuf, HashMap<Key, SystemTime>>,
+    /// The senders for the receivers returned by
+    /// [`KeyValueStoreBackend::watch_scope`], with the scope they watch.
+    watchers: HashMap<NamespaceBuf, Vec<(Scope, Sender<ChangeEvent>)>>,
+}
 
 impl MemoryStore {
     fn new() -> Self {
-        MemoryStore(HashMap::new())
+        MemoryStore {
+            values: HashMap::new(),
+            tombstones: HashMap::new(),
+            watchers: HashMap::new(),
+        }
+    }
+
+    /// Returns a receiver for the changes to keys in the scope, including
+    /// its sub scopes.
+    fn watch(&mut self, namespace: &NamespaceBuf, scope: &Scope) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers
+            .entry(namespace.clone())
+            .or_default()
+            .push((scope.clone(), sender));
+        receiver
+    }
+
+    /// Sends the event to the watchers of a scope that contains a key of the
+    /// event, and forgets the watchers whose receiver was dropped.
+    fn notify(&mut self, namespace: &NamespaceBuf, event: ChangeEvent) {
+        let Some(watchers) = self.watchers.get_mut(namespace) else {
+            return;
+        };
+
+        watchers.retain(|(scope, sender)| {
+            let watched = match &event {
+                ChangeEvent::Stored(key) | ChangeEvent::Deleted(key) => {
+                    key.scope().starts_with(scope)
+                }
+                ChangeEvent::Moved { from, to } => {
+                    from.scope().starts_with(scope) || to.scope().starts_with(scope)
+                }
+            };
+            !watched || sender.send(event.clone()).is_ok()
+        });
     }
 
     fn has(&self, namespace: &NamespaceBuf, key: &Key) -> bool {
-        self.0
+        self.values
             .get(namespace)
-            .map(|m| m.contains_key(key))
+            .and_then(|m| m.get(key))
+            .map(StoredValue::is_live)
             .unwrap_or(false)
     }
 
     fn namespace_is_empty(&self, namespace: &NamespaceBuf) -> bool {
-        self.0.get(namespace).map(|m| m.is_empty()).unwrap_or(true)
+        self.values
+            .get(namespace)
+            .map(|m| !m.values().any(StoredValue::is_live))
+            .unwrap_or(true)
+    }
+
+    fn namespace_len(&self, namespace: &NamespaceBuf) -> usize {
+        self.values
+            .get(namespace)
+            .map(|m| m.values().filter(|v| v.is_live()).count())
+            .unwrap_or_default()
     }
 
     fn has_scope(&self, namespace: &NamespaceBuf, scope: &Scope) -> bool {
-        self.0
+        self.values
             .get(namespace)
-            .map(|m| m.keys().any(|k| k.scope().starts_with(scope)))
+            .map(|m| {
+                m.iter()
+                    .any(|(k, v)| v.is_live() && k.scope().starts_with(scope))
+            })
             .unwrap_or_default()
     }
 
+    fn has_value(&self, namespace: &NamespaceBuf, key: &Key, value: &serde_json::Value) -> bool {
+        self.values
+            .get(namespace)
+            .and_then(|m| m.get(key))
+            .is_some_and(|v| v.is_live() && &v.value == value)
+    }
+
     fn get(&self, namespace: &NamespaceBuf, key: &Key) -> Option<serde_json::Value> {
-        self.0.get(namespace).and_then(|m| m.get(key).cloned())
+        self.values
+            .get(namespace)
+            .and_then(|m| m.get(key))
+            .filter(|v| v.is_live())
+            .map(|v| v.value.clone())
+    }
+
+    fn expires_at(&self, namespace: &NamespaceBuf, key: &Key) -> Option<SystemTime> {
+        self.values
+            .get(namespace)
+            .and_then(|m| m.get(key))
+            .filter(|v| v.is_live())
+            .and_then(|v| v.expires_at)
     }
 
     fn insert(&mut self, namespace: &NamespaceBuf, key: &Key, value: serde_json::Value) {
-        let map = self.0.entry(namespace.clone()).or_default();
-        map.insert(key.clone(), value);
+        self.insert_with_expiry(namespace, key, value, None)
     }
 
-    fn delete(&mut self, namespace: &NamespaceBuf, key: &Key) -> Result<()> {
-        self.0
-            .get_mut(namespace)
-            .ok_or(Error::UnknownKey)?
+    /// Stores the value, see [`MemoryStore::put`], and notifies the watchers.
+    fn insert_with_expiry(
+        &mut self,
+        namespace: &NamespaceBuf,
+        key: &Key,
+        value: serde_json::Value,
+        expires_at: Option<SystemTime>,
+    ) {
+        self.put(namespace, key, value, expires_at);
+        self.notify(namespace, ChangeEvent::Stored(key.clone()));
+    }
+
+    /// Stores the value, keeping the creation time of the key if it already
+    /// has a value, and removes the tombstone of the key if there is one.
+    fn put(
+        &mut self,
+        namespace: &NamespaceBuf,
+        key: &Key,
+        value: serde_json::Value,
+        expires_at: Option<SystemTime>,
+    ) {
+        let now = SystemTime::now();
+        let map = self.values.entry(namespace.clone()).or_default();
+        let created_at = map
+            .get(key)
+            .filter(|v| v.is_live())
+            .map(|v| v.created_at)
+            .unwrap_or(now);
+
+        map.insert(
+            key.clone(),
+            StoredValue {
+                value,
+                expires_at,
+                created_at,
+                updated_at: now,
+            },
+        );
+
+        if let Some(tombstones) = self.tombstones.get_mut(namespace) {
+            tombstones.remove(key);
+        }
+    }
+
+    /// Removes the value of the key, see [`MemoryStore::take`], and notifies
+    /// the watchers.
+    fn remove(&mut self, namespace: &NamespaceBuf, key: &Key) -> Option<StoredValue> {
+        let value = self.take(namespace, key)?;
+        self.notify(namespace, ChangeEvent::Deleted(key.clone()));
+        Some(value)
+    }
+
+    /// Removes the value of the key and records a tombstone for it if the
+    /// namespace tracks changes, if it has a value that did not expire.
+    fn take(&mut self, namespace: &NamespaceBuf, key: &Key) -> Option<StoredValue> {
+        let value = self
+            .values
+            .get_mut(namespace)?
             .remove(key)
-            .ok_or(Error::UnknownKey)?;
+            .filter(StoredValue::is_live)?;
+
+        if let Some(tombstones) = self.tombstones.get_mut(namespace) {
+            tombstones.insert(key.clone(), SystemTime::now());
+        }
+
+        Some(value)
+    }
+
+    /// Starts recording tombstones for the keys deleted in the namespace.
+    fn track_changes(&mut self, namespace: &NamespaceBuf) {
+        self.tombstones.entry(namespace.clone()).or_default();
+    }
+
+    /// Removes the tombstones of keys deleted before `before` and returns
+    /// the number of tombstones removed.
+    fn prune_changes(&mut self, namespace: &NamespaceBuf, before: SystemTime) -> usize {
+        self.tombstones
+            .get_mut(namespace)
+            .map(|tombstones| {
+                let len = tombstones.len();
+                tombstones.retain(|_, deleted_at| *deleted_at >= before);
+                len - tombstones.len()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns all keys matching the predicate, also those with an expired
+    /// value.
+    fn keys_where(&self, namespace: &NamespaceBuf, f: impl Fn(&Key) -> bool) -> Vec<Key> {
+        self.values
+            .get(namespace)
+            .map(|m| m.keys().filter(|k| f(k)).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes the values of all keys matching the predicate, see
+    /// [`MemoryStore::remove`].
+    fn remove_where(
+        &mut self,
+        namespace: &NamespaceBuf,
+        f: impl Fn(&Key) -> bool,
+    ) -> Vec<(Key, StoredValue)> {
+        self.keys_where(namespace, f)
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.remove(namespace, &key)?;
+                Some((key, value))
+            })
+            .collect()
+    }
+
+    fn delete_existing(&mut self, namespace: &NamespaceBuf, key: &Key) -> Result<()> {
+        self.remove(namespace, key).ok_or(Error::UnknownKey)?;
         Ok(())
     }
 
     fn move_value(&mut self, namespace: &NamespaceBuf, from: &Key, to: &Key) -> Result<()> {
-        match self.0.get_mut(namespace) {
-            None => Err(Error::UnknownKey),
-            Some(map) => match map.remove(from) {
-                Some(value) => {
-                    map.insert(to.clone(), value);
-                    Ok(())
-                }
-                None => Err(Error::UnknownKey),
+        let value = self.take(namespace, from).ok_or(Error::UnknownKey)?;
+        self.put(namespace, to, value.value, value.expires_at);
+        self.notify(
+            namespace,
+            ChangeEvent::Moved {
+                from: from.clone(),
+                to: to.clone(),
             },
+        );
+        Ok(())
+    }
+
+    fn copy_value(&mut self, namespace: &NamespaceBuf, from: &Key, to: &Key) -> Result<()> {
+        let value = self
+            .values
+            .get(namespace)
+            .and_then(|m| m.get(from))
+            .filter(|v| v.is_live())
+            .cloned()
+            .ok_or(Error::UnknownKey)?;
+        self.insert_with_expiry(namespace, to, value.value, value.expires_at);
+
+        Ok(())
+    }
+
+    fn changes_since(&self, namespace: &NamespaceBuf, since: SystemTime) -> Vec<(Key, ChangeKind)> {
+        let mut changes = vec![];
+        if let Some(map) = self.values.get(namespace) {
+            for (key, value) in map {
+                if value.is_live() && value.updated_at >= since {
+                    let kind = if value.created_at >= since {
+                        ChangeKind::Created
+                    } else {
+                        ChangeKind::Updated
+                    };
+                    changes.push((value.updated_at, key.clone(), kind));
+                }
+            }
+        }
+
+        if let Some(tombstones) = self.tombstones.get(namespace) {
+            for (key, deleted_at) in tombstones {
+                if *deleted_at >= since && !self.has(namespace, key) {
+                    changes.push((*deleted_at, key.clone(), ChangeKind::Deleted));
+                }
+            }
         }
+
+        changes.sort_by_key(|(at, _, _)| *at);
+        changes
+            .into_iter()
+            .map(|(_, key, kind)| (key, kind))
+            .collect()
     }
 
     fn list_keys(&self, namespace: &NamespaceBuf, scope: &Scope) -> Vec<Key> {
-        self.0
+        self.values
             .get(namespace)
             .map(|m| {
-                m.keys()
-                    .filter(|k| k.scope().starts_with(scope))
-                    .cloned()
+                m.iter()
+                    .filter(|(k, v)| v.is_live() && k.scope().starts_with(scope))
+                    .map(|(k, _)| k.clone())
                     .collect::<Vec<Key>>()
             })
             .unwrap_or_default()
     }
 
+    fn list_entries(
+        &self,
+        namespace: &NamespaceBuf,
+        scope: &Scope,
+    ) -> Vec<(Key, serde_json::Value)> {
+        self.values
+            .get(namespace)
+            .map(|m| {
+                m.iter()
+                    .filter(|(k, v)| v.is_live() && k.scope().starts_with(scope))
+                    .map(|(k, v)| (k.clone(), v.value.clone()))
+                    .collect::<Vec<(Key, serde_json::Value)>>()
+            })
+            .unwrap_or_default()
+    }
+
+    fn count_keys(&self, namespace: &NamespaceBuf, scope: &Scope) -> usize {
+        self.values
+            .get(namespace)
+            .map(|m| {
+                m.iter()
+                    .filter(|(k, v)| v.is_live() && k.scope().starts_with(scope))
+                    .count()
+            })
+            .unwrap_or_default()
+    }
+
     fn list_scopes(&self, namespace: &NamespaceBuf) -> Vec<Scope> {
+        self.list_scopes_under(namespace, &Scope::global())
+    }
+
+    fn list_scopes_under(&self, namespace: &NamespaceBuf, parent: &Scope) -> Vec<Scope> {
         let scopes: BTreeSet<Scope> = self
-            .0
+            .values
             .get(namespace)
-            .map(|m| m.keys().flat_map(|k| k.scope().sub_scopes()).collect())
+            .map(|m| {
+                m.iter()
+                    .filter(|(k, v)| v.is_live() && k.scope().starts_with(parent))
+                    .flat_map(|(k, _)| k.scope().sub_scopes())
+                    .filter(|scope| scope.depth() > parent.depth())
+                    .collect()
+            })
             .unwrap_or_default();
 
         scopes.into_iter().collect()
     }
 
+    fn list_child_scopes(&self, namespace: &NamespaceBuf, parent: &Scope) -> Vec<Scope> {
+        let children: BTreeSet<Scope> = self
+            .values
+            .get(namespace)
+            .map(|m| {
+                m.iter()
+                    .filter(|(k, v)| {
+                        v.is_live()
+                            && k.scope().starts_with(parent)
+                            && k.scope().depth() > parent.depth()
+                    })
+                    .map(|(k, _)| Scope::new(k.scope().as_vec()[..=parent.depth()].to_vec()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        children.into_iter().collect()
+    }
+
     fn delete_scope(&mut self, namespace: &NamespaceBuf, scope: &Scope) -> Result<()> {
-        if let Some(map) = self.0.get_mut(namespace) {
-            map.retain(|k, _| !k.scope().starts_with(scope));
-        }
+        self.remove_where(namespace, |k| k.scope().starts_with(scope));
+        Ok(())
+    }
 
+    fn delete_scope_exact(&mut self, namespace: &NamespaceBuf, scope: &Scope) -> Result<()> {
+        self.remove_where(namespace, |k| k.scope() == scope);
         Ok(())
     }
 
     fn move_scope(&mut self, namespace: &NamespaceBuf, from: &Scope, to: &Scope) -> Result<()> {
-        if let Some(map) = self.0.get_mut(namespace) {
-            *map = map
-                .drain()
-                .map(|(k, v)| {
-                    if k.scope() == from {
-                        (Key::new_scoped(to.clone(), k.name()), v)
-                    } else {
-                        (k, v)
-                    }
-                })
-                .collect::<HashMap<Key, serde_json::Value>>();
+        let values: Vec<(Key, StoredValue)> = self
+            .keys_where(namespace, |k| k.scope().starts_with(from))
+            .into_iter()
+            .filter_map(|key| {
+                let value = self.take(namespace, &key)?;
+                Some((key, value))
+            })
+            .collect();
+
+        for (key, value) in values {
+            if let Some(moved) = key.rebase(from, to) {
+                self.put(namespace, &moved, value.value, value.expires_at);
+                self.notify(
+                    namespace,
+                    ChangeEvent::Moved {
+                        from: key,
+                        to: moved,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn copy_scope(&mut self, namespace: &NamespaceBuf, from: &Scope, to: &Scope) -> Result<()> {
+        let copies = self
+            .values
+            .get(namespace)
+            .map(|m| {
+                m.iter()
+                    .filter(|(_, v)| v.is_live())
+                    .filter_map(|(k, v)| Some((k.rebase(from, to)?, v.clone())))
+                    .collect::<Vec<(Key, StoredValue)>>()
+            })
+            .unwrap_or_default();
+
+        for (key, value) in copies {
+            self.insert_with_expiry(namespace, &key, value.value, value.expires_at);
         }
 
         Ok(())
@@ -123,79 +459,320 @@ impl MemoryStore {
                 to.as_str()
             )))
         } else {
-            match self.0.remove(from) {
+            match self.values.remove(from) {
                 None => Err(Error::NamespaceMigration(format!(
                     "original in-memory namespace {} does not exist",
                     from.as_str()
                 ))),
                 Some(map) => {
-                    self.0.insert(to.clone(), map);
+                    self.values.insert(to.clone(), map);
+                    if let Some(tombstones) = self.tombstones.remove(from) {
+                        self.tombstones.insert(to.clone(), tombstones);
+                    }
+                    let watchers = self.watchers.remove(from).unwrap_or_default();
+                    self.watchers
+                        .entry(to.clone())
+                        .or_default()
+                        .extend(watchers);
                     Ok(())
                 }
             }
         }
     }
 
+    /// Removes the expired values in the namespace and returns the number of
+    /// values removed.
+    fn purge_expired(&mut self, namespace: &NamespaceBuf) -> usize {
+        self.values
+            .get_mut(namespace)
+            .map(|map| {
+                let len = map.len();
+                map.retain(|_, v| v.is_live());
+                len - map.len()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn clear(&mut self, namespace: &NamespaceBuf) -> Result<()> {
-        self.0.insert(namespace.clone(), HashMap::new());
+        self.remove_where(namespace, |_| true);
+        self.values.insert(namespace.clone(), HashMap::new());
         Ok(())
     }
+
+    fn snapshot(&self, namespace: &NamespaceBuf) -> HashMap<Key, serde_json::Value> {
+        self.list_entries(namespace, &Scope::global())
+            .into_iter()
+            .collect()
+    }
+
+    fn restore(&mut self, namespace: &NamespaceBuf, entries: HashMap<Key, serde_json::Value>) {
+        self.remove_where(namespace, |_| true);
+        for (key, value) in entries {
+            self.insert(namespace, &key, value);
+        }
+    }
+}
+
+/// The values and scope locks of an in-memory store instance.
+#[derive(Debug)]
+struct Instance {
+    store: Mutex<MemoryStore>,
+    locks: Mutex<Vec<ScopeLock>>,
+}
+
+impl Instance {
+    fn new() -> Self {
+        Instance {
+            store: Mutex::new(MemoryStore::new()),
+            locks: Mutex::new(Vec::new()),
+        }
+    }
 }
 
+/// The instance id used in the [`Display`] of isolated stores, which is not a
+/// valid host so it cannot be confused with the id of a shared instance.
+const ISOLATED_INSTANCE_ID: &str = "<isolated>";
+
+/// How long a transaction waits before checking again whether a locked
+/// scope was released.
+const LOCK_WAIT: Duration = Duration::from_millis(10);
+
+/// How long a transaction waits for the lock on its scope by default.
+/// Dependent on use cases it may not be that exceptional for locks to be
+/// kept for even longer, see [`Memory::with_lock_timeout`].
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
 lazy_static! {
-    static ref STORE: Mutex<MemoryStore> = Mutex::new(MemoryStore::new());
-    static ref LOCKS: Mutex<HashSet<ScopeLock>> = Mutex::new(HashSet::new());
+    /// All in-memory instances by their instance id. The empty id is used
+    /// for the shared default instance.
+    static ref INSTANCES: Mutex<HashMap<String, Arc<Instance>>> = Mutex::new(HashMap::new());
 }
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
-struct ScopeLock(String);
+/// A lock on a scope, held by the thread that took it.
+///
+/// Locks held by the same thread never conflict, so operations that take
+/// the lock of a scope can be used in a transaction on that scope, or on a
+/// super scope. Shared locks, used for read-only transactions, do not
+/// conflict with each other.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScopeLock {
+    namespace: NamespaceBuf,
+    scope: Scope,
+    owner: ThreadId,
+    shared: bool,
+}
 
 impl ScopeLock {
-    fn new(namespace: &NamespaceBuf, scope: &Scope) -> Self {
-        ScopeLock(format!("{}/{}", namespace, scope))
+    fn new(namespace: &NamespaceBuf, scope: &Scope, shared: bool) -> Self {
+        ScopeLock {
+            namespace: namespace.clone(),
+            scope: scope.clone(),
+            owner: thread::current().id(),
+            shared,
+        }
+    }
+
+    /// Returns whether a transaction holding this lock excludes a transaction
+    /// holding the other lock.
+    fn conflicts(&self, other: &ScopeLock, granularity: LockGranularity) -> bool {
+        self.namespace == other.namespace
+            && self.owner != other.owner
+            && !(self.shared && other.shared)
+            && match granularity {
+                LockGranularity::Exact => self.scope == other.scope,
+                LockGranularity::Hierarchical => self.scope.matches(&other.scope),
+            }
+    }
+}
+
+/// Releases a scope lock held by a transaction when dropped, so that the lock
+/// is also released if the transaction callback fails or panics.
+struct ScopeLockGuard<'a> {
+    memory: &'a Memory,
+    lock: ScopeLock,
+}
+
+impl Drop for ScopeLockGuard<'_> {
+    fn drop(&mut self) {
+        // The locks are never held while running a callback, so they can
+        // only be poisoned by a panic in this module. Releasing the lock
+        // anyway is better than blocking the scope forever.
+        let mut locks = match self.memory.instance.locks.lock() {
+            Ok(locks) => locks,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(index) = locks.iter().position(|lock| lock == &self.lock) {
+            locks.swap_remove(index);
+        }
+    }
+}
+
+/// Which transactions exclude each other, set using `locking=exact` or
+/// `locking=hierarchical` in the `memory://` URL.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) enum LockGranularity {
+    /// Only transactions on the same scope exclude each other, so a
+    /// transaction on `a` does not block one on `a/b`.
+    Exact,
+
+    /// Transactions on overlapping scopes exclude each other, so a
+    /// transaction on `a` blocks one on `a/b`, but transactions on the
+    /// sibling scopes `a/b` and `a/c` do not block each other.
+    #[default]
+    Hierarchical,
+}
+
+impl LockGranularity {
+    pub(crate) fn from_url(url: &Url) -> Result<Self> {
+        let mut granularity = LockGranularity::default();
+        for (name, value) in url.query_pairs() {
+            if name == "locking" {
+                granularity = match value.as_ref() {
+                    "exact" => LockGranularity::Exact,
+                    "hierarchical" => LockGranularity::Hierarchical,
+                    _ => {
+                        return Err(Error::InvalidConfig(format!(
+                            "invalid value for locking: {}",
+                            value
+                        )))
+                    }
+                };
+            }
+        }
+
+        Ok(granularity)
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct Memory {
-    // Used to prevent namespace collisions in the shared (lazy static) in memory structure.
-    namespace_prefix: Option<String>,
-    effective_namespace: NamespaceBuf,
-    inner: &'static Mutex<MemoryStore>,
-    locks: &'static Mutex<HashSet<ScopeLock>>,
+    instance_id: String,
+    namespace: NamespaceBuf,
+    instance: Arc<Instance>,
+    lock_granularity: LockGranularity,
+    lock_timeout: Duration,
+    track_changes: bool,
 }
 
 impl Memory {
-    pub(crate) fn new(namespace_prefix: Option<&str>, namespace: NamespaceBuf) -> Result<Self> {
-        let namespace_prefix = namespace_prefix.map(|s| s.to_string());
-        let effective_namespace = Self::effective_namespace(&namespace_prefix, namespace)?;
+    /// Creates an in-memory store for the namespace in the instance with the
+    /// given id.
+    ///
+    /// Stores with the same instance id share their values and locks, stores
+    /// with different instance ids are fully isolated from each other. If no
+    /// instance id is given the shared default instance is used. For the
+    /// `memory://` scheme the host is used as the instance id, so
+    /// `memory://a` and `memory://b` are isolated and all stores created for
+    /// `memory://` share the default instance.
+    pub(crate) fn new(instance_id: Option<&str>, namespace: NamespaceBuf) -> Result<Self> {
+        let instance_id = instance_id.unwrap_or_default().to_string();
+        let instance = INSTANCES
+            .lock()
+            .map_err(|e| Error::MutexLock(e.to_string()))?
+            .entry(instance_id.clone())
+            .or_insert_with(|| Arc::new(Instance::new()))
+            .clone();
 
         Ok(Memory {
-            namespace_prefix,
-            effective_namespace,
-            inner: &STORE,
-            locks: &LOCKS,
+            instance_id,
+            namespace,
+            instance,
+            lock_granularity: LockGranularity::default(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            track_changes: false,
         })
     }
 
-    fn effective_namespace(
-        namespace_prefix: &Option<String>,
-        namespace: NamespaceBuf,
-    ) -> Result<NamespaceBuf> {
-        if let Some(pfx) = namespace_prefix {
-            NamespaceBuf::from_str(&format!("{}_{}", pfx, namespace)).map_err(|e| {
-                Error::UnknownScheme(format!(
-                    "cannot parse prefix '{}' for memory store: {}",
-                    pfx, e
-                ))
-            })
-        } else {
-            Ok(namespace)
+    /// Creates an in-memory store for the namespace in a new instance that is
+    /// not shared with any other store, so its values and locks are isolated
+    /// from all other stores, even those with the same namespace.
+    pub(crate) fn new_isolated(namespace: NamespaceBuf) -> Self {
+        Memory {
+            instance_id: ISOLATED_INSTANCE_ID.to_string(),
+            namespace,
+            instance: Arc::new(Instance::new()),
+            lock_granularity: LockGranularity::default(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            track_changes: false,
+        }
+    }
+
+    /// Creates an in-memory store for the namespace from a `memory://` URL,
+    /// using the host as the instance id and the `locking` parameter as the
+    /// [`LockGranularity`].
+    pub(crate) fn from_url(url: &Url, namespace: NamespaceBuf) -> Result<Self> {
+        Ok(Memory::new(url.host_str(), namespace)?
+            .with_lock_granularity(LockGranularity::from_url(url)?))
+    }
+
+    /// Sets which transactions of this store exclude each other.
+    pub(crate) fn with_lock_granularity(mut self, lock_granularity: LockGranularity) -> Self {
+        self.lock_granularity = lock_granularity;
+        self
+    }
+
+    /// Sets how long a transaction waits for the lock on its scope before
+    /// it fails with [`Error::LockTimeout`].
+    pub(crate) fn with_lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Sets whether this store reports changes, see
+    /// [`ReadStore::changes_since`]. If so, the instance records tombstones
+    /// for the keys deleted in the namespace from now on, also for the other
+    /// stores using the namespace in the instance.
+    pub(crate) fn with_change_tracking(mut self, track_changes: bool) -> Result<Self> {
+        if track_changes {
+            self.lock()?.track_changes(&self.namespace);
         }
+        self.track_changes = track_changes;
+        Ok(self)
+    }
+
+    /// Returns the namespaces in the instance with the given id that contain
+    /// keys, with the number of keys in each.
+    pub(crate) fn namespace_summaries(
+        instance_id: Option<&str>,
+    ) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let instance = INSTANCES
+            .lock()
+            .map_err(|e| Error::MutexLock(e.to_string()))?
+            .get(instance_id.unwrap_or_default())
+            .cloned();
+
+        let Some(instance) = instance else {
+            return Ok(vec![]);
+        };
+
+        let store = instance
+            .store
+            .lock()
+            .map_err(|e| Error::MutexLock(e.to_string()))?;
+
+        Ok(store
+            .values
+            .iter()
+            .map(|(namespace, m)| {
+                (
+                    namespace.clone(),
+                    m.values().filter(|v| v.is_live()).count(),
+                )
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect())
     }
 
     pub(super) fn lock(&self) -> Result<MutexGuard<'_, MemoryStore>> {
-        self.inner
+        self.instance
+            .store
+            .lock()
+            .map_err(|e| Error::MutexLock(e.to_string()))
+    }
+
+    fn locks(&self) -> Result<MutexGuard<'_, Vec<ScopeLock>>> {
+        self.instance
+            .locks
             .lock()
             .map_err(|e| Error::MutexLock(e.to_string()))
     }
@@ -203,149 +780,821 @@ impl Memory {
 
 impl Display for Memory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "KeyValueStore::Memory({})", self.effective_namespace)
+        if self.instance_id.is_empty() {
+            write!(f, "KeyValueStore::Memory({})", self.namespace)
+        } else {
+            write!(
+                f,
+                "KeyValueStore::Memory({}/{})",
+                self.instance_id, self.namespace
+            )
+        }
     }
 }
 
-struct ReadOnlyMemory {
-    namespace: NamespaceBuf,
-    inner: MemoryStore,
+/// A read-only view of the data in a namespace, used for read-only
+/// transactions while the lock on the [`MemoryStore`] is held.
+struct ReadOnlyMemory<'a> {
+    namespace: &'a NamespaceBuf,
+    inner: &'a MemoryStore,
+    track_changes: bool,
 }
 
-impl ReadStore for ReadOnlyMemory {
+impl ReadStore for ReadOnlyMemory<'_> {
     fn is_empty(&self) -> Result<bool> {
         // We have one shared inner MemoryStore for all namespaces.
         // In this context this instance is considered empty if the
         // shared store is empty for this namespace.
-        Ok(self.inner.namespace_is_empty(&self.namespace))
+        Ok(self.inner.namespace_is_empty(self.namespace))
+    }
+
+    fn total_keys(&self) -> Result<usize> {
+        Ok(self.inner.namespace_len(self.namespace))
     }
 
     fn has(&self, key: &Key) -> Result<bool> {
-        Ok(self.inner.has(&self.namespace, key))
+        Ok(self.inner.has(self.namespace, key))
     }
 
     fn has_scope(&self, scope: &Scope) -> Result<bool> {
-        Ok(self.inner.has_scope(&self.namespace, scope))
+        Ok(self.inner.has_scope(self.namespace, scope))
+    }
+
+    fn has_value(&self, key: &Key, value: &serde_json::Value) -> Result<bool> {
+        Ok(self.inner.has_value(self.namespace, key, value))
     }
 
     fn get(&self, key: &Key) -> Result<Option<serde_json::Value>> {
-        Ok(self.inner.get(&self.namespace, key))
+        Ok(self.inner.get(self.namespace, key))
     }
 
     fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
-        Ok(self.inner.list_keys(&self.namespace, scope))
+        Ok(self.inner.list_keys(self.namespace, scope))
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, serde_json::Value)>> {
+        Ok(self.inner.list_entries(self.namespace, scope))
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        Ok(self.inner.count_keys(self.namespace, scope))
     }
 
     fn list_scopes(&self) -> Result<Vec<Scope>> {
-        Ok(self.inner.list_scopes(&self.namespace))
+        Ok(self.inner.list_scopes(self.namespace))
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self.inner.list_scopes_under(self.namespace, parent))
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self.inner.list_child_scopes(self.namespace, parent))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        Ok(self.inner.changes_since(self.namespace, since))
     }
 }
 
-impl KeyValueStoreBackend for Memory {
-    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
-        // Try to get a lock for 10 seconds. We may need to make this configurable.
-        // Dependent on use cases it may actually not be that exceptional for locks
-        // to be kept for even longer.
-        let wait_ms = 10;
-        let tries = 1000;
-
-        let scope_lock = ScopeLock::new(&self.effective_namespace, scope);
-
-        for i in 0..tries {
-            let mut locks = self
-                .locks
-                .lock()
-                .map_err(|e| Error::MutexLock(e.to_string()))?;
-
-            if locks.contains(&scope_lock) {
-                if i >= tries {
-                    return Err(Error::MutexLock(format!("Scope {} already locked", scope)));
-                } else {
-                    drop(locks);
-                    std::thread::sleep(std::time::Duration::from_millis(wait_ms));
+impl Memory {
+    /// Waits at most `timeout` for a lock on the scope. Returns `None` if
+    /// the scope is still locked by another transaction by then.
+    ///
+    /// The lock is released when the returned guard is dropped, so also if
+    /// the transaction callback fails, e.g. when it aborts the transaction,
+    /// or panics.
+    fn lock_scope(&self, scope: &Scope, timeout: Duration) -> Result<Option<ScopeLockGuard<'_>>> {
+        self.take_scope_lock(ScopeLock::new(&self.namespace, scope, false), timeout)
+    }
+
+    /// Waits at most `timeout` for a shared lock on the scope, like
+    /// [`Memory::lock_scope`]. Other shared locks on the scope may be held
+    /// at the same time.
+    fn lock_scope_shared(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+    ) -> Result<Option<ScopeLockGuard<'_>>> {
+        self.take_scope_lock(ScopeLock::new(&self.namespace, scope, true), timeout)
+    }
+
+    fn take_scope_lock(
+        &self,
+        scope_lock: ScopeLock,
+        timeout: Duration,
+    ) -> Result<Option<ScopeLockGuard<'_>>> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let mut locks = self.locks()?;
+
+            if !locks
+                .iter()
+                .any(|lock| lock.conflicts(&scope_lock, self.lock_granularity))
+            {
+                locks.push(scope_lock.clone());
+                return Ok(Some(ScopeLockGuard {
+                    memory: self,
+                    lock: scope_lock,
+                }));
+            }
+
+            drop(locks);
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(LOCK_WAIT);
+        }
+    }
+}
+
+impl BufferedBackend for Memory {
+    fn expiry(&self, key: &Key) -> Result<Option<SystemTime>> {
+        Ok(self.lock()?.expires_at(&self.namespace, key))
+    }
+
+    /// Applies all writes while holding the lock on all data, so they are
+    /// applied at once.
+    fn apply(&self, writes: BTreeMap<Key, PendingWrite>) -> Result<()> {
+        let mut inner = self.lock()?;
+        for (key, write) in writes {
+            match write {
+                Some((value, expires_at)) => {
+                    inner.insert_with_expiry(&self.namespace, &key, value, expires_at)
+                }
+                None => {
+                    inner.remove(&self.namespace, &key);
                 }
-            } else {
-                locks.insert(scope_lock.clone());
-                break;
             }
         }
 
-        callback(self)?;
+        Ok(())
+    }
+}
 
-        let mut locks = self
-            .locks
-            .lock()
-            .map_err(|e| Error::MutexLock(e.to_string()))?;
+impl KeyValueStoreBackend for Memory {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Memory
+    }
+
+    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        let _guard = self
+            .lock_scope(scope, self.lock_timeout)?
+            .ok_or_else(|| Error::LockTimeout(format!("Scope {} already locked", scope)))?;
 
-        locks.remove(&scope_lock);
+        BufferedTransaction::run(self, callback)
+    }
 
-        Ok(())
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        match self.lock_scope(scope, timeout)? {
+            Some(_guard) => BufferedTransaction::run(self, callback).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Runs the callback while holding a shared lock on the scope, so it
+    /// waits for transactions on overlapping scopes, and the lock on all
+    /// data.
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        let _guard = self
+            .lock_scope_shared(scope, self.lock_timeout)?
+            .ok_or_else(|| Error::LockTimeout(format!("Scope {} already locked", scope)))?;
+        let inner = self.lock()?;
+
+        callback(&ReadOnlyMemory {
+            namespace: &self.namespace,
+            inner: &inner,
+            track_changes: self.track_changes,
+        })
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        Ok(self.lock()?.watch(&self.namespace, scope))
     }
 }
 
 impl ReadStore for Memory {
     fn is_empty(&self) -> Result<bool> {
-        self.lock()
-            .map(|l| l.namespace_is_empty(&self.effective_namespace))
+        self.lock().map(|l| l.namespace_is_empty(&self.namespace))
+    }
+
+    fn total_keys(&self) -> Result<usize> {
+        self.lock().map(|l| l.namespace_len(&self.namespace))
     }
 
     fn has(&self, key: &Key) -> Result<bool> {
-        Ok(self.lock()?.has(&self.effective_namespace, key))
+        Ok(self.lock()?.has(&self.namespace, key))
     }
 
     fn has_scope(&self, scope: &Scope) -> Result<bool> {
-        Ok(self.lock()?.has_scope(&self.effective_namespace, scope))
+        Ok(self.lock()?.has_scope(&self.namespace, scope))
+    }
+
+    fn has_value(&self, key: &Key, value: &serde_json::Value) -> Result<bool> {
+        Ok(self.lock()?.has_value(&self.namespace, key, value))
     }
 
     fn get(&self, key: &Key) -> Result<Option<serde_json::Value>> {
-        Ok(self.lock()?.get(&self.effective_namespace, key))
+        Ok(self.lock()?.get(&self.namespace, key))
     }
 
     fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
-        Ok(self.lock()?.list_keys(&self.effective_namespace, scope))
+        Ok(self.lock()?.list_keys(&self.namespace, scope))
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, serde_json::Value)>> {
+        Ok(self.lock()?.list_entries(&self.namespace, scope))
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        Ok(self.lock()?.count_keys(&self.namespace, scope))
     }
 
     fn list_scopes(&self) -> Result<Vec<Scope>> {
-        Ok(self.lock()?.list_scopes(&self.effective_namespace))
+        Ok(self.lock()?.list_scopes(&self.namespace))
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self.lock()?.list_scopes_under(&self.namespace, parent))
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self.lock()?.list_child_scopes(&self.namespace, parent))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        Ok(self.lock()?.changes_since(&self.namespace, since))
+    }
+
+    fn snapshot(&self) -> Result<HashMap<Key, serde_json::Value>> {
+        Ok(self.lock()?.snapshot(&self.namespace))
     }
 }
 
 impl WriteStore for Memory {
     fn store(&self, key: &Key, value: serde_json::Value) -> Result<()> {
-        self.lock()?.insert(&self.effective_namespace, key, value);
+        self.lock()?.insert(&self.namespace, key, value);
+        Ok(())
+    }
+
+    fn store_many(&self, entries: &[(Key, serde_json::Value)]) -> Result<()> {
+        let mut inner = self.lock()?;
+        for (key, value) in entries {
+            inner.insert(&self.namespace, key, value.clone());
+        }
+        Ok(())
+    }
+
+    fn restore(&self, entries: HashMap<Key, serde_json::Value>) -> Result<()> {
+        self.lock()?.restore(&self.namespace, entries);
+        Ok(())
+    }
+
+    fn store_with_expiry(
+        &self,
+        key: &Key,
+        value: serde_json::Value,
+        expires_at: SystemTime,
+    ) -> Result<()> {
+        self.lock()?
+            .insert_with_expiry(&self.namespace, key, value, Some(expires_at));
         Ok(())
     }
 
+    fn purge_expired(&self) -> Result<usize> {
+        Ok(self.lock()?.purge_expired(&self.namespace))
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        Ok(self.lock()?.prune_changes(&self.namespace, before))
+    }
+
+    /// Compares and stores the value while holding the lock on the scope of
+    /// the key, like a transaction on that scope.
+    fn compare_and_swap(
+        &self,
+        key: &Key,
+        expected: Option<&serde_json::Value>,
+        new: serde_json::Value,
+    ) -> Result<bool> {
+        let _guard = self
+            .lock_scope(key.scope(), self.lock_timeout)?
+            .ok_or_else(|| Error::LockTimeout(format!("Scope {} already locked", key.scope())))?;
+
+        let mut inner = self.lock()?;
+        if inner.get(&self.namespace, key).as_ref() != expected {
+            return Ok(false);
+        }
+
+        inner.insert(&self.namespace, key, new);
+        Ok(true)
+    }
+
     fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
-        self.lock()?.move_value(&self.effective_namespace, from, to)
+        self.lock()?.move_value(&self.namespace, from, to)
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.lock()?.copy_value(&self.namespace, from, to)
     }
 
     fn delete(&self, key: &Key) -> Result<()> {
-        self.lock()?.delete(&self.effective_namespace, key)
+        self.lock()?.remove(&self.namespace, key);
+        Ok(())
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.lock()?.delete_existing(&self.namespace, key)
     }
 
     fn delete_scope(&self, scope: &Scope) -> Result<()> {
-        self.lock()?.delete_scope(&self.effective_namespace, scope)
+        self.lock()?.delete_scope(&self.namespace, scope)
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.lock()?.delete_scope_exact(&self.namespace, scope)
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.lock()?.delete_scope(&self.namespace, scope)
     }
 
     fn clear(&self) -> Result<()> {
-        self.lock()?.clear(&self.effective_namespace)
+        self.lock()?.clear(&self.namespace)
     }
 
     fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
-        self.lock()?.move_scope(&self.effective_namespace, from, to)
+        self.lock()?.move_scope(&self.namespace, from, to)
     }
 
-    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
-        // We need to preserve the namespace prefix if it was set.
-        // This prefix is used to prevent namespace collisions in the
-        // shared (lazy static) in memory structure.
-        let effective_to = Self::effective_namespace(&self.namespace_prefix, to)?;
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.lock()?.copy_scope(&self.namespace, from, to)
+    }
 
-        self.lock()?
-            .migrate_namespace(&self.effective_namespace, &effective_to)?;
-        self.effective_namespace = effective_to;
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        self.lock()?.migrate_namespace(&self.namespace, &to)?;
+        self.namespace = to;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{mpsc, Barrier},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::Value;
+    use url::Url;
+
+    use crate::{
+        ChangeEvent, Error, Key, KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore, Scope,
+        WriteStore,
+    };
+
+    fn store(uri: &str) -> KeyValueStore {
+        KeyValueStore::new(
+            &Url::parse(uri).unwrap(),
+            Namespace::parse("test_instances").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_track_changes_param() {
+        let key: Key = "key".parse().unwrap();
+
+        let untracked = store("memory://track_changes");
+        untracked.store(&key, Value::from(1)).unwrap();
+        untracked.delete(&key).unwrap();
+        assert!(matches!(
+            untracked.changes_since(std::time::SystemTime::UNIX_EPOCH),
+            Err(Error::Other(_))
+        ));
+
+        // Only deletes after enabling change tracking get a tombstone.
+        let tracked = store("memory://track_changes?track_changes=true");
+        assert!(tracked
+            .changes_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .is_empty());
+        tracked.store(&key, Value::from(1)).unwrap();
+        tracked.delete(&key).unwrap();
+        assert_eq!(
+            tracked
+                .changes_since(std::time::SystemTime::UNIX_EPOCH)
+                .unwrap(),
+            vec![(key, crate::ChangeKind::Deleted)]
+        );
+
+        assert!(matches!(
+            KeyValueStore::new(
+                &Url::parse("memory://track_changes?track_changes=yes").unwrap(),
+                Namespace::parse("test_instances").unwrap(),
+            ),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_instances_are_isolated() {
+        let a = store("memory://a");
+        let b = store("memory://b");
+        let key: Key = "key".parse().unwrap();
+
+        a.store(&key, Value::from("a")).unwrap();
+        b.store(&key, Value::from("b")).unwrap();
+
+        assert_eq!(a.get(&key).unwrap(), Some(Value::from("a")));
+        assert_eq!(b.get(&key).unwrap(), Some(Value::from("b")));
+        assert_eq!(
+            store("memory://a").get(&key).unwrap(),
+            Some(Value::from("a"))
+        );
+
+        a.clear().unwrap();
+        assert!(a.is_empty().unwrap());
+        assert!(!b.is_empty().unwrap());
+
+        b.clear().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_restore_is_namespaced() {
+        let a = store("memory://snapshots");
+        let b = KeyValueStore::new(
+            &Url::parse("memory://snapshots").unwrap(),
+            Namespace::parse("test_instances_other").unwrap(),
+        )
+        .unwrap();
+        let key: Key = "key".parse().unwrap();
+
+        a.store(&key, Value::from("a")).unwrap();
+        b.store(&key, Value::from("b")).unwrap();
+
+        let snapshot = a.snapshot().unwrap();
+        assert_eq!(snapshot, HashMap::from([(key.clone(), Value::from("a"))]));
+
+        a.restore(HashMap::new()).unwrap();
+        assert!(a.is_empty().unwrap());
+        assert_eq!(b.get(&key).unwrap(), Some(Value::from("b")));
+
+        a.restore(snapshot).unwrap();
+        assert_eq!(a.get(&key).unwrap(), Some(Value::from("a")));
+
+        a.clear().unwrap();
+        b.clear().unwrap();
+    }
+
+    #[test]
+    fn test_isolated_stores() {
+        let namespace = Namespace::parse("test_instances").unwrap();
+        let a = KeyValueStore::new_isolated(namespace);
+        let b = KeyValueStore::new_isolated(namespace);
+        let shared = store("memory://");
+        let key: Key = "isolated_key".parse().unwrap();
+        let scope: Scope = "scope".parse().unwrap();
+
+        a.store(&key, Value::from("a")).unwrap();
+        assert!(!b.has(&key).unwrap());
+        assert!(!shared.has(&key).unwrap());
+
+        // Transactions do not exclude transactions on other stores either.
+        a.transaction(&scope, &mut |_| {
+            b.transaction(&scope, &mut |t| t.store(&key, Value::from("b")))
+        })
+        .unwrap();
+        assert_eq!(a.get(&key).unwrap(), Some(Value::from("a")));
+        assert_eq!(b.get(&key).unwrap(), Some(Value::from("b")));
+    }
+
+    #[test]
+    fn test_default_instance_is_shared() {
+        let store1 = store("memory://");
+        let store2 = store("memory://");
+        let key: Key = "default_instance_key".parse().unwrap();
+
+        store1.store(&key, Value::from(1)).unwrap();
+
+        assert_eq!(store2.get(&key).unwrap(), Some(Value::from(1)));
+        assert!(!store("memory://a").has(&key).unwrap());
+
+        store2.delete(&key).unwrap();
+    }
+
+    /// Returns whether a transaction on `other` waits for a transaction on
+    /// `held` in a store for the URL.
+    ///
+    /// A free lock is taken at the first attempt, so the timeout only delays
+    /// the result if the transaction waits.
+    fn blocks(uri: &str, held: &str, other: &str) -> bool {
+        let held: Scope = held.parse().unwrap();
+        let other: Scope = other.parse().unwrap();
+        let barrier = Barrier::new(2);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                store(uri)
+                    .transaction(&held, &mut |_| {
+                        barrier.wait();
+                        barrier.wait();
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+
+            barrier.wait();
+            let ran =
+                store(uri).try_transaction(&other, Duration::from_millis(50), &mut |_| Ok(()));
+            barrier.wait();
+            !ran.unwrap()
+        })
+    }
+
+    #[test]
+    fn test_hierarchical_locking() {
+        let uri = "memory://hierarchical-locking";
+        assert!(!blocks(uri, "a/b", "a/c"));
+        assert!(blocks(uri, "a", "a/b"));
+        assert!(blocks(uri, "a/b", "a"));
+        assert!(blocks(uri, "a/b", "a/b"));
+    }
+
+    #[test]
+    fn test_exact_locking() {
+        let uri = "memory://exact-locking?locking=exact";
+        assert!(!blocks(uri, "a/b", "a/c"));
+        assert!(!blocks(uri, "a", "a/b"));
+        assert!(!blocks(uri, "a/b", "a"));
+        assert!(blocks(uri, "a/b", "a/b"));
+    }
+
+    #[test]
+    fn test_compare_and_swap_locks_scope() {
+        let store = KeyValueStore::builder(
+            &Url::parse("memory://compare-and-swap-lock").unwrap(),
+            Namespace::parse("test_instances").unwrap(),
+        )
+        .lock_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+        let scope: Scope = "a".parse().unwrap();
+        let key: Key = "a/b/key".parse().unwrap();
+
+        // A transaction on a super scope does not block its own thread.
+        store
+            .transaction(&scope, &mut |_| {
+                assert!(store.compare_and_swap(&key, None, Value::from(1))?);
+                Ok(())
+            })
+            .unwrap();
+
+        // But it does block other threads.
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                store
+                    .transaction(&scope, &mut |_| {
+                        barrier.wait();
+                        barrier.wait();
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+
+            barrier.wait();
+            let result = store.compare_and_swap(&key, Some(&Value::from(1)), Value::from(2));
+            barrier.wait();
+            assert!(matches!(result, Err(Error::LockTimeout(_))));
+        });
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(1)));
+
+        store.clear().unwrap();
+    }
+
+    #[test]
+    fn test_read_only_transaction_locks_scope() {
+        let store = KeyValueStore::builder(
+            &Url::parse("memory://read-only-lock").unwrap(),
+            Namespace::parse("test_instances").unwrap(),
+        )
+        .lock_timeout(Duration::from_millis(50))
+        .build()
+        .unwrap();
+        let scope: Scope = "a/b".parse().unwrap();
+        let barrier = Barrier::new(2);
+
+        // A read-only transaction waits for a transaction on a super scope.
+        thread::scope(|s| {
+            s.spawn(|| {
+                store
+                    .transaction(&"a".parse().unwrap(), &mut |_| {
+                        barrier.wait();
+                        barrier.wait();
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+
+            barrier.wait();
+            let result = store.read_only_transaction(&scope, &mut |_| Ok(()));
+            barrier.wait();
+            assert!(matches!(result, Err(Error::LockTimeout(_))));
+        });
+
+        // And a transaction waits for a read-only transaction.
+        thread::scope(|s| {
+            s.spawn(|| {
+                store
+                    .read_only_transaction(&scope, &mut |_| {
+                        barrier.wait();
+                        barrier.wait();
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+
+            barrier.wait();
+            let ran = store.try_transaction(&scope, Duration::from_millis(50), &mut |_| Ok(()));
+            barrier.wait();
+            assert!(!ran.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_lock_released_on_panic() {
+        let store = store("memory://lock-released-on-panic");
+        let scope: Scope = "scope".parse().unwrap();
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            store
+                .transaction(&scope, &mut |_| panic!("transaction failed"))
+                .unwrap();
+        }));
+        assert!(panicked.is_err());
+
+        // This would wait for the lock for 10 seconds and fail if the lock
+        // was not released.
+        let start = Instant::now();
+        store.transaction(&scope, &mut |_| Ok(())).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_try_transaction() {
+        let uri = "memory://try-transaction";
+        let scope: Scope = "a/b".parse().unwrap();
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        let holder = {
+            let store = store(uri);
+            let scope = scope.clone();
+            thread::spawn(move || {
+                store
+                    .transaction(&scope, &mut |_| {
+                        locked_tx.send(()).unwrap();
+                        release_rx.recv().unwrap();
+                        Ok(())
+                    })
+                    .unwrap();
+            })
+        };
+
+        locked_rx.recv().unwrap();
+        let store = store(uri);
+        let timeout = Duration::from_millis(50);
+        let mut called = false;
+
+        let start = Instant::now();
+        let ran = store
+            .try_transaction(&"a".parse().unwrap(), timeout, &mut |_| {
+                called = true;
+                Ok(())
+            })
+            .unwrap();
+        assert!(!ran);
+        assert!(!called);
+        assert!(start.elapsed() >= timeout);
+
+        // A transaction on a sibling scope is not blocked.
+        assert!(store
+            .try_transaction(&"a/c".parse().unwrap(), timeout, &mut |_| Ok(()))
+            .unwrap());
+
+        release_tx.send(()).unwrap();
+        holder.join().unwrap();
+
+        assert!(store
+            .try_transaction(&scope, timeout, &mut |_| Ok(()))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_invalid_locking() {
+        assert!(matches!(
+            KeyValueStore::new(
+                &Url::parse("memory://?locking=none").unwrap(),
+                Namespace::parse("test_instances").unwrap(),
+            ),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_watch_scope() {
+        let store = KeyValueStore::new_isolated(Namespace::parse("test_watch").unwrap());
+        let watched: Key = "a/b/key".parse().unwrap();
+        let other: Key = "c/key".parse().unwrap();
+        let receiver = store.watch_scope(&"a".parse().unwrap()).unwrap();
+
+        store.store(&watched, Value::from(1)).unwrap();
+        store.store(&other, Value::from(2)).unwrap();
+        store
+            .move_value(&other, &"a/moved".parse().unwrap())
+            .unwrap();
+        store.delete(&watched).unwrap();
+        store.delete(&watched).unwrap();
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                ChangeEvent::Stored(watched.clone()),
+                ChangeEvent::Moved {
+                    from: other,
+                    to: "a/moved".parse().unwrap()
+                },
+                ChangeEvent::Deleted(watched),
+            ]
+        );
+
+        drop(receiver);
+        store
+            .store(&"a/key".parse().unwrap(), Value::from(3))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_watch_scope_migrate_namespace() {
+        let url = Url::parse("memory://watch_migrate").unwrap();
+        let mut from = KeyValueStore::new(&url, Namespace::parse("from").unwrap()).unwrap();
+        let to = KeyValueStore::new(&url, Namespace::parse("to").unwrap()).unwrap();
+        let from_receiver = from.watch_scope(&Scope::global()).unwrap();
+        let to_receiver = to.watch_scope(&Scope::global()).unwrap();
+
+        from.store(&"a".parse().unwrap(), Value::from(1)).unwrap();
+        from.migrate_namespace("to".parse().unwrap()).unwrap();
+
+        // The watchers of both namespaces are notified of changes in the
+        // target namespace.
+        let key: Key = "b".parse().unwrap();
+        to.store(&key, Value::from(2)).unwrap();
+        assert_eq!(
+            from_receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                ChangeEvent::Stored("a".parse().unwrap()),
+                ChangeEvent::Stored(key.clone())
+            ]
+        );
+        assert_eq!(
+            to_receiver.try_iter().collect::<Vec<_>>(),
+            vec![ChangeEvent::Stored(key)]
+        );
+
+        to.clear().unwrap();
+    }
+
+    #[test]
+    fn test_watch_scope_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyValueStore::new(
+            &Url::parse(&format!("local://{}", dir.path().display())).unwrap(),
+            Namespace::parse("test_watch").unwrap(),
+        )
+        .unwrap();
+
+        assert!(store.watch_scope(&Scope::global()).is_err());
+    }
+}
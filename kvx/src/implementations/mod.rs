@@ -1,20 +1,39 @@
+pub(crate) mod buffered;
 pub(crate) mod disk;
 pub(crate) mod memory;
 
 #[cfg(feature = "postgres")]
 pub(crate) mod postgres;
 
+#[cfg(feature = "redis")]
+pub(crate) mod redis;
+
+#[cfg(feature = "sqlite")]
+pub(crate) mod sqlite;
+
 #[cfg(test)]
 mod tests {
-    use std::{fs, iter};
+    use std::{
+        collections::HashMap,
+        fs, iter,
+        time::{Duration, SystemTime},
+    };
 
     use rand::{distributions::Alphanumeric, Rng};
-    use serde_json::Value;
+    use serde_json::{json, Value};
 
     use super::{disk::Disk, memory::Memory};
     #[cfg(feature = "postgres")]
     use crate::implementations::postgres::{PgPool, Postgres};
-    use crate::{Key, KeyValueStoreBackend, NamespaceBuf, Scope, SegmentBuf};
+    #[cfg(feature = "redis")]
+    use crate::implementations::redis::Redis;
+    #[cfg(feature = "sqlite")]
+    use crate::implementations::sqlite::Sqlite;
+    use crate::{
+        ChangeKind, Error, Key, KeyValueStore, KeyValueStoreBackend, NamespaceBuf,
+        PubKeyValueStoreBackend, ReadStore, Result, Scope, Segment, SegmentBuf,
+        TransactionCallback, WriteStore,
+    };
 
     fn random_value(length: usize) -> Value {
         Value::from(
@@ -66,6 +85,172 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_store_edge_values(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let nested = (0..32).fold(json!("leaf"), |value, i| {
+            if i % 2 == 0 {
+                json!([value])
+            } else {
+                json!({ "nested": value })
+            }
+        });
+        let values = [
+            json!(""),
+            json!("   \t\n "),
+            json!({}),
+            json!([]),
+            json!([{}, [], ""]),
+            json!(0),
+            json!(false),
+            Value::Null,
+            nested,
+        ];
+
+        for value in values {
+            let key = Key::new_scoped(scope.clone(), random_segment());
+            store.store(&key, value.clone()).unwrap();
+            assert!(store.has(&key).unwrap());
+            assert_eq!(store.get(&key).unwrap(), Some(value.clone()));
+            assert_eq!(
+                store
+                    .get_raw(&key)
+                    .unwrap()
+                    .map(|raw| serde_json::from_slice::<Value>(&raw).unwrap()),
+                Some(value.clone())
+            );
+            assert_eq!(
+                store
+                    .list_entries(&scope)
+                    .unwrap()
+                    .iter()
+                    .find(|(k, _)| k == &key)
+                    .map(|(_, v)| v),
+                Some(&value)
+            );
+        }
+
+        // Unlike a stored null, a missing key has no value at all.
+        let missing = Key::new_scoped(scope.clone(), random_segment());
+        assert!(!store.has(&missing).unwrap());
+        assert_eq!(store.get(&missing).unwrap(), None);
+        assert_eq!(store.get_raw(&missing).unwrap(), None);
+
+        store.clear().unwrap();
+    }
+
+    fn test_store_bytes(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let key = Key::new_scoped(scope.clone(), random_segment());
+        let bytes = b"\0\x01\xffnot json {".to_vec();
+
+        store.store_bytes(&key, &bytes).unwrap();
+        assert_eq!(store.get_bytes(&key).unwrap(), Some(bytes.clone()));
+        assert_eq!(
+            store.get(&key).unwrap(),
+            Some(json!({ "kvx_bytes": "AAH/bm90IGpzb24gew==" }))
+        );
+        assert_eq!(store.list_entries(&scope).unwrap().len(), 1);
+
+        store.store_bytes(&key, &[]).unwrap();
+        assert_eq!(store.get_bytes(&key).unwrap(), Some(vec![]));
+
+        store.store(&key, json!("AAE=")).unwrap();
+        assert!(matches!(store.get_bytes(&key), Err(Error::NotBytes)));
+
+        store.delete(&key).unwrap();
+        assert_eq!(store.get_bytes(&key).unwrap(), None);
+    }
+
+    fn test_store_many(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let key1 = Key::new_scoped(scope.with_sub_scope(random_segment()), random_segment());
+        let key2 = Key::new_scoped(scope.clone(), random_segment());
+        let value1 = random_value(8);
+        let value2 = random_value(8);
+        let value3 = random_value(8);
+
+        store.store_many(&[]).unwrap();
+        store
+            .store_many(&[
+                (key1.clone(), value1.clone()),
+                (key2.clone(), value2),
+                (key2.clone(), value3.clone()),
+            ])
+            .unwrap();
+
+        assert_eq!(store.get(&key1).unwrap(), Some(value1));
+        assert_eq!(store.get(&key2).unwrap(), Some(value3));
+        assert_eq!(store.list_keys(&scope).unwrap().len(), 2);
+
+        store.clear().unwrap();
+    }
+
+    fn test_snapshot_restore(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let key1 = Key::new_scoped(scope.with_sub_scope(random_segment()), random_segment());
+        let key2 = Key::new_global(random_segment());
+        let value1 = random_value(8);
+        let value2 = random_value(8);
+
+        assert!(store.snapshot().unwrap().is_empty());
+
+        store.store(&key1, value1.clone()).unwrap();
+        store.store(&key2, value2.clone()).unwrap();
+        let snapshot = store.snapshot().unwrap();
+        assert_eq!(
+            snapshot,
+            HashMap::from([(key1.clone(), value1), (key2.clone(), value2)])
+        );
+
+        // Restoring removes keys that are not in the snapshot.
+        let key3 = Key::new_scoped(scope.clone(), random_segment());
+        store.store(&key3, random_value(8)).unwrap();
+        store.delete(&key2).unwrap();
+        store.restore(snapshot.clone()).unwrap();
+        assert_eq!(store.snapshot().unwrap(), snapshot);
+        assert!(!store.has(&key3).unwrap());
+
+        store.restore(HashMap::new()).unwrap();
+        assert!(store.is_empty().unwrap());
+    }
+
+    fn test_store_with_expiry(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let expired = Key::new_scoped(scope.clone(), random_segment());
+        let live = Key::new_scoped(scope.clone(), random_segment());
+        let value = random_value(8);
+        let past = SystemTime::now() - Duration::from_secs(60);
+        let future = SystemTime::now() + Duration::from_secs(3600);
+
+        store
+            .store_with_expiry(&expired, random_value(8), past)
+            .unwrap();
+        store
+            .store_with_expiry(&live, value.clone(), future)
+            .unwrap();
+
+        assert_eq!(store.get(&expired).unwrap(), None);
+        assert!(!store.has(&expired).unwrap());
+        assert_eq!(store.get(&live).unwrap(), Some(value.clone()));
+        assert_eq!(store.list_keys(&scope).unwrap(), vec![live.clone()]);
+        assert_eq!(store.count_keys(&scope).unwrap(), 1);
+
+        // Storing a value without expiry removes the expiry.
+        store.store(&expired, value.clone()).unwrap();
+        assert_eq!(store.get(&expired).unwrap(), Some(value.clone()));
+        store.delete(&expired).unwrap();
+
+        store
+            .store_with_expiry(&expired, random_value(8), past)
+            .unwrap();
+        store.purge_expired().unwrap();
+        assert_eq!(store.purge_expired().unwrap(), 0);
+        assert_eq!(store.list_keys(&scope).unwrap(), vec![live.clone()]);
+        assert_eq!(store.get(&live).unwrap(), Some(value));
+
+        store.clear().unwrap();
+    }
+
     fn test_has(store: impl KeyValueStoreBackend) {
         let key = random_key(1);
         let value = random_value(8);
@@ -77,6 +262,86 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_has_each(store: impl KeyValueStoreBackend) {
+        let present = [random_key(0), random_key(1), random_key(2)];
+        for key in present.iter() {
+            store.store(key, random_value(8)).unwrap();
+        }
+
+        let keys = vec![
+            present[0].clone(),
+            random_key(1),
+            present[1].clone(),
+            random_key(0),
+            present[2].clone(),
+        ];
+
+        assert_eq!(
+            store.has_each(&keys).unwrap(),
+            vec![true, false, true, false, true]
+        );
+        assert!(store.has_each(&[]).unwrap().is_empty());
+
+        store.clear().unwrap();
+    }
+
+    fn test_has_value(store: impl KeyValueStoreBackend) {
+        let key = random_key(1);
+        let value = serde_json::json!({ "a": 1, "b": [true, null] });
+
+        assert!(!store.has_value(&key, &value).unwrap());
+        store.store(&key, value.clone()).unwrap();
+        assert!(store.has_value(&key, &value).unwrap());
+        assert!(store
+            .has_value(&key, &serde_json::json!({ "b": [true, null], "a": 1 }))
+            .unwrap());
+        assert!(!store.has_value(&key, &random_value(8)).unwrap());
+        assert!(!store.has_value(&random_key(1), &value).unwrap());
+
+        store.clear().unwrap();
+    }
+
+    fn test_get_many(store: impl KeyValueStoreBackend) {
+        let key1 = random_key(1);
+        let key2 = random_key(2);
+        let value1 = random_value(8);
+        let value2 = random_value(8);
+        store.store(&key1, value1.clone()).unwrap();
+        store.store(&key2, value2.clone()).unwrap();
+
+        assert!(store.get_many(&[]).unwrap().is_empty());
+        assert_eq!(
+            store
+                .get_many(&[key2.clone(), random_key(1), key1.clone(), key2])
+                .unwrap(),
+            vec![Some(value2.clone()), None, Some(value1), Some(value2)]
+        );
+
+        store.clear().unwrap();
+    }
+
+    fn test_get_or(store: impl KeyValueStoreBackend) {
+        let key = random_key(1);
+        let value = random_value(8);
+        let default = random_value(8);
+
+        assert_eq!(store.get_or(&key, default.clone()).unwrap(), default);
+        assert_eq!(
+            store.get_or_else(&key, || default.clone()).unwrap(),
+            default
+        );
+        assert!(!store.has(&key).unwrap());
+
+        store.store(&key, value.clone()).unwrap();
+        assert_eq!(store.get_or(&key, default.clone()).unwrap(), value);
+        assert_eq!(
+            store.get_or_else(&key, || panic!("key exists")).unwrap(),
+            value
+        );
+
+        store.clear().unwrap();
+    }
+
     fn test_has_scope(store: impl KeyValueStoreBackend) {
         let scope = random_scope(2);
         let key = Key::new_scoped(scope.clone(), random_segment());
@@ -116,6 +381,65 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_list_keys_matching_ci(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let upper = Key::new_scoped(
+            scope.with_sub_scope(random_segment()),
+            Segment::parse("FOO").unwrap(),
+        );
+        let lower = Key::new_scoped(scope.clone(), Segment::parse("foo").unwrap());
+        let other = Key::new_scoped(scope.clone(), Segment::parse("food").unwrap());
+        for key in [&upper, &lower, &other] {
+            store.store(key, random_value(8)).unwrap();
+        }
+
+        let mut result = store
+            .list_keys_matching_ci(&scope, Segment::parse("Foo").unwrap())
+            .unwrap();
+        let mut expected = vec![upper.clone(), lower];
+
+        result.sort();
+        expected.sort();
+
+        assert_eq!(result, expected);
+        assert!(store.has(&upper).unwrap());
+        assert!(!store
+            .has(&Key::new_scoped(
+                upper.scope().clone(),
+                Segment::parse("foo").unwrap()
+            ))
+            .unwrap());
+
+        store.clear().unwrap();
+    }
+
+    fn test_list_entries(store: impl KeyValueStoreBackend) {
+        let ns = random_segment();
+        let entries: Vec<(Key, Value)> = vec![
+            (random_key(1), random_value(8)),
+            (random_key(1).with_super_scope(ns.clone()), random_value(8)),
+            (random_key(2).with_super_scope(ns.clone()), random_value(8)),
+        ];
+
+        for (key, value) in entries.iter() {
+            store.store(key, value.clone()).unwrap();
+        }
+
+        let mut result = store.list_entries(&Scope::from_segment(ns)).unwrap();
+        let mut expected = entries[1..].to_vec();
+
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        expected.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(result, expected);
+        assert!(store
+            .list_entries(&Scope::from_segment(random_segment()))
+            .unwrap()
+            .is_empty());
+
+        store.clear().unwrap();
+    }
+
     fn test_list_scopes(store: impl KeyValueStoreBackend) {
         let name = random_segment();
         let scope = random_scope(1);
@@ -146,6 +470,266 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_list_scopes_under(store: impl KeyValueStoreBackend) {
+        let parent = random_scope(1);
+        let child = parent.with_sub_scope(random_segment());
+        let grandchild = child.with_sub_scope(random_segment());
+        let other_child = parent.with_sub_scope(random_segment());
+        let outside = random_scope(2);
+
+        for scope in [&parent, &grandchild, &other_child, &outside] {
+            store
+                .store(
+                    &Key::new_scoped(scope.clone(), random_segment()),
+                    random_value(8),
+                )
+                .unwrap();
+        }
+
+        let mut result = store.list_scopes_under(&parent).unwrap();
+        let mut expected = vec![child.clone(), grandchild.clone(), other_child];
+        result.sort();
+        expected.sort();
+        assert_eq!(result, expected);
+
+        assert_eq!(
+            store.list_scopes_under(&child).unwrap(),
+            vec![grandchild.clone()]
+        );
+        assert!(store.list_scopes_under(&grandchild).unwrap().is_empty());
+        assert!(store
+            .list_scopes_under(&random_scope(1))
+            .unwrap()
+            .is_empty());
+
+        let mut all = store.list_scopes_under(&Scope::global()).unwrap();
+        let mut expected = store.list_scopes().unwrap();
+        all.sort();
+        expected.sort();
+        assert_eq!(all, expected);
+
+        store.clear().unwrap();
+    }
+
+    fn test_list_child_scopes(store: impl KeyValueStoreBackend) {
+        let parent = random_scope(1);
+        let child = parent.with_sub_scope(random_segment());
+        let grandchild = child.with_sub_scope(random_segment());
+        let other_child = parent.with_sub_scope(random_segment());
+        let outside = random_scope(2);
+
+        for scope in [&parent, &grandchild, &other_child, &outside] {
+            store
+                .store(
+                    &Key::new_scoped(scope.clone(), random_segment()),
+                    random_value(8),
+                )
+                .unwrap();
+        }
+
+        let mut result = store.list_child_scopes(&parent).unwrap();
+        let mut expected = vec![child.clone(), other_child];
+        result.sort();
+        expected.sort();
+        assert_eq!(result, expected);
+
+        assert_eq!(
+            store.list_child_scopes(&child).unwrap(),
+            vec![grandchild.clone()]
+        );
+        assert!(store.list_child_scopes(&grandchild).unwrap().is_empty());
+        assert!(store
+            .list_child_scopes(&random_scope(1))
+            .unwrap()
+            .is_empty());
+
+        let mut top = store.list_child_scopes(&Scope::global()).unwrap();
+        let mut expected = vec![parent, Scope::new(vec![outside.as_vec()[0].clone()])];
+        top.sort();
+        expected.sort();
+        assert_eq!(top, expected);
+
+        store.clear().unwrap();
+    }
+
+    fn test_find_keys_where_value(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let owned_by =
+            |name: &str| serde_json::json!({ "owner": { "name": name }, "tags": [name, "other"] });
+
+        let alice_1 = Key::new_scoped(scope.clone(), random_segment());
+        let alice_2 = Key::new_scoped(scope.with_sub_scope(random_segment()), random_segment());
+        let bob = Key::new_scoped(scope.clone(), random_segment());
+        let outside = random_key(1);
+
+        store.store(&alice_1, owned_by("alice")).unwrap();
+        store.store(&alice_2, owned_by("alice")).unwrap();
+        store.store(&bob, owned_by("bob")).unwrap();
+        store.store(&outside, owned_by("alice")).unwrap();
+
+        let mut result = store
+            .find_keys_where_value(&scope, "owner.name", &Value::from("alice"))
+            .unwrap();
+        let mut expected = vec![alice_1, alice_2];
+
+        result.sort();
+        expected.sort();
+
+        assert_eq!(result, expected);
+        assert_eq!(
+            store
+                .find_keys_where_value(&scope, "tags.0", &Value::from("bob"))
+                .unwrap(),
+            vec![bob]
+        );
+        assert!(store
+            .find_keys_where_value(&scope, "owner.missing", &Value::from("alice"))
+            .unwrap()
+            .is_empty());
+
+        store.clear().unwrap();
+    }
+
+    fn test_compare_and_swap(store: impl KeyValueStoreBackend + Sync) {
+        let key = random_key(1);
+        let (value1, value2) = (random_value(8), random_value(8));
+
+        assert!(store.compare_and_swap(&key, None, value1.clone()).unwrap());
+        assert!(!store.compare_and_swap(&key, None, value2.clone()).unwrap());
+        assert!(!store
+            .compare_and_swap(&key, Some(&value2), value2.clone())
+            .unwrap());
+        assert_eq!(store.get(&key).unwrap(), Some(value1.clone()));
+        assert!(store
+            .compare_and_swap(&key, Some(&value1), value2.clone())
+            .unwrap());
+        assert_eq!(store.get(&key).unwrap(), Some(value2));
+
+        let counter = random_key(1);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        loop {
+                            let current = store.get(&counter).unwrap();
+                            let next = current.as_ref().and_then(|v| v.as_i64()).unwrap_or(0) + 1;
+                            if store
+                                .compare_and_swap(&counter, current.as_ref(), Value::from(next))
+                                .unwrap()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(store.get(&counter).unwrap(), Some(Value::from(20)));
+
+        store.clear().unwrap();
+    }
+
+    fn test_copy_value(store: impl KeyValueStoreBackend) {
+        let from = random_key(3);
+        let to = random_key(2);
+        let value = random_value(8);
+
+        store.store(&from, value.clone()).unwrap();
+        store.store(&to, random_value(8)).unwrap();
+        store.copy_value(&from, &to).unwrap();
+
+        assert_eq!(store.get(&from).unwrap(), Some(value.clone()));
+        assert_eq!(store.get(&to).unwrap(), Some(value));
+
+        assert!(matches!(
+            store.copy_value(&random_key(1), &to),
+            Err(Error::UnknownKey)
+        ));
+
+        store.clear().unwrap();
+    }
+
+    fn test_changes_since(store: impl KeyValueStoreBackend) {
+        let created = random_key(1);
+        let updated = random_key(2);
+        let deleted = random_key(1);
+        let moved_from = random_key(2);
+        let moved_to = random_key(1);
+        let unchanged = random_key(1);
+
+        for key in [&updated, &deleted, &moved_from, &unchanged] {
+            store.store(key, random_value(8)).unwrap();
+        }
+
+        // Changes are tracked with millisecond precision.
+        std::thread::sleep(Duration::from_millis(20));
+        let since = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(20));
+
+        store.store(&created, random_value(8)).unwrap();
+        store.store(&created, random_value(8)).unwrap();
+        store.store(&updated, random_value(8)).unwrap();
+        store.delete(&deleted).unwrap();
+        store.move_value(&moved_from, &moved_to).unwrap();
+
+        let mut changes = store.changes_since(since).unwrap();
+        changes.sort();
+        let mut expected = vec![
+            (created.clone(), ChangeKind::Created),
+            (updated.clone(), ChangeKind::Updated),
+            (deleted.clone(), ChangeKind::Deleted),
+            (moved_from.clone(), ChangeKind::Deleted),
+            (moved_to.clone(), ChangeKind::Created),
+        ];
+        expected.sort();
+        assert_eq!(changes, expected);
+
+        // A deleted key that is stored again is no longer reported as
+        // deleted.
+        store.store(&deleted, random_value(8)).unwrap();
+        assert!(store
+            .changes_since(since)
+            .unwrap()
+            .contains(&(deleted.clone(), ChangeKind::Created)));
+
+        std::thread::sleep(Duration::from_millis(20));
+        let later = SystemTime::now();
+        assert!(store.changes_since(later).unwrap().is_empty());
+
+        store.delete(&updated).unwrap();
+        assert_eq!(
+            store.changes_since(later).unwrap(),
+            vec![(updated, ChangeKind::Deleted)]
+        );
+
+        store.clear().unwrap();
+    }
+
+    fn test_prune_changes(store: impl KeyValueStoreBackend) {
+        let pruned = random_key(1);
+        let kept = random_key(1);
+
+        store.store(&pruned, random_value(8)).unwrap();
+        store.store(&kept, random_value(8)).unwrap();
+        store.delete(&pruned).unwrap();
+
+        std::thread::sleep(Duration::from_millis(20));
+        let before = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(20));
+
+        store.delete(&kept).unwrap();
+
+        assert!(store.prune_changes(before).unwrap() >= 1);
+        assert_eq!(store.prune_changes(before).unwrap(), 0);
+
+        let changes = store.changes_since(SystemTime::UNIX_EPOCH).unwrap();
+        assert!(!changes.contains(&(pruned, ChangeKind::Deleted)));
+        assert!(changes.contains(&(kept, ChangeKind::Deleted)));
+
+        store.clear().unwrap();
+    }
+
     fn test_move_value(store: impl KeyValueStoreBackend) {
         let from = random_key(1);
         let to = random_key(1);
@@ -173,6 +757,33 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_move_value_unknown_key(store: impl KeyValueStoreBackend) {
+        let from = random_key(1);
+        let to = random_key(1);
+
+        assert!(matches!(
+            store.move_value(&from, &to),
+            Err(Error::UnknownKey)
+        ));
+        assert!(!store.has(&to).unwrap());
+
+        let expired = random_key(1);
+        store
+            .store_with_expiry(
+                &expired,
+                random_value(8),
+                SystemTime::now() - Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!(matches!(
+            store.move_value(&expired, &to),
+            Err(Error::UnknownKey)
+        ));
+        assert!(!store.has(&to).unwrap());
+
+        store.clear().unwrap();
+    }
+
     fn test_delete(store: impl KeyValueStoreBackend) {
         let key = random_key(1);
         store.store(&key, random_value(8)).unwrap();
@@ -183,6 +794,42 @@ mod tests {
         assert_eq!(result, None);
     }
 
+    fn test_delete_missing(store: impl KeyValueStoreBackend) {
+        let key = random_key(1);
+
+        // Deleting is idempotent, deleting existing values is not.
+        store.delete(&key).unwrap();
+        assert!(matches!(
+            store.delete_existing(&key),
+            Err(Error::UnknownKey)
+        ));
+
+        store.store(&key, random_value(8)).unwrap();
+        store.delete_existing(&key).unwrap();
+        assert!(!store.has(&key).unwrap());
+        store.delete(&key).unwrap();
+        assert!(matches!(
+            store.delete_existing(&key),
+            Err(Error::UnknownKey)
+        ));
+
+        let expired = random_key(2);
+        store
+            .store_with_expiry(
+                &expired,
+                random_value(8),
+                SystemTime::now() - Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!(matches!(
+            store.delete_existing(&expired),
+            Err(Error::UnknownKey)
+        ));
+        store.delete(&expired).unwrap();
+
+        store.clear().unwrap();
+    }
+
     fn test_delete_scope(store: impl KeyValueStoreBackend) {
         let key = random_key(0);
         let scope = random_scope(1);
@@ -211,28 +858,202 @@ mod tests {
         store.clear().unwrap();
     }
 
-    fn test_clear(store: impl KeyValueStoreBackend) {
-        for i in 1..=4 {
-            store.store(&random_key(i), random_value(8)).unwrap();
-        }
+    fn test_delete_scope_nested(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let sub_scope = scope.with_sub_scope(random_segment());
+        let key = Key::new_scoped(scope.clone(), random_segment());
+        let sub_key = Key::new_scoped(sub_scope.clone(), random_segment());
+        let other = random_key(1);
 
-        store.clear().unwrap();
+        for key in [&key, &sub_key, &other] {
+            store.store(key, random_value(8)).unwrap();
+        }
 
-        let result = store.list_keys(&Scope::global()).unwrap();
+        store.delete_scope_exact(&scope).unwrap();
+        let mut result = store.list_keys(&Scope::global()).unwrap();
+        let mut expected = vec![sub_key.clone(), other.clone()];
+        result.sort();
+        expected.sort();
+        assert_eq!(result, expected);
 
-        assert_eq!(result, vec![]);
+        store.store(&key, random_value(8)).unwrap();
+        store.delete_scope(&scope).unwrap();
+        assert_eq!(store.list_keys(&Scope::global()).unwrap(), vec![other]);
+        assert!(!store.has_scope(&sub_scope).unwrap());
 
         store.clear().unwrap();
     }
 
-    fn test_is_empty(store: impl KeyValueStoreBackend) {
-        assert!(store.is_empty().unwrap());
+    fn test_clear_scope(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let outside = random_key(1);
+        let keys = [
+            Key::new_scoped(scope.clone(), random_segment()),
+            Key::new_scoped(scope.with_sub_scope(random_segment()), random_segment()),
+            outside.clone(),
+        ];
 
-        store.store(&random_key(1), random_value(8)).unwrap();
+        for key in keys.iter() {
+            store.store(key, random_value(8)).unwrap();
+        }
+
+        store.clear_scope(&scope).unwrap();
+
+        assert!(store.list_keys(&scope).unwrap().is_empty());
+        assert_eq!(store.list_keys(&Scope::global()).unwrap(), vec![outside]);
+
+        // clearing an unknown scope is not an error
+        store.clear_scope(&random_scope(1)).unwrap();
+
+        store.clear().unwrap();
+    }
+
+    fn test_clear(store: impl KeyValueStoreBackend) {
+        for i in 1..=4 {
+            store.store(&random_key(i), random_value(8)).unwrap();
+        }
+
+        store.clear().unwrap();
+
+        let result = store.list_keys(&Scope::global()).unwrap();
+
+        assert_eq!(result, vec![]);
+
+        store.clear().unwrap();
+    }
+
+    fn test_is_empty(store: impl KeyValueStoreBackend) {
+        assert!(store.is_empty().unwrap());
+
+        store.store(&random_key(1), random_value(8)).unwrap();
 
         assert!(!store.is_empty().unwrap());
     }
 
+    fn test_total_keys(store: impl KeyValueStoreBackend) {
+        assert_eq!(store.total_keys().unwrap(), 0);
+
+        for i in 0..=4 {
+            store.store(&random_key(i), random_value(8)).unwrap();
+            store.store(&random_key(i), random_value(8)).unwrap();
+        }
+
+        // locks used by transactions are not keys
+        store
+            .transaction(&random_scope(1), &mut |_| Ok(()))
+            .unwrap();
+
+        assert_eq!(store.total_keys().unwrap(), 10);
+
+        store.clear().unwrap();
+    }
+
+    fn test_stream_keys(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        assert_eq!(store.stream_keys(&scope).unwrap().count(), 0);
+
+        for i in 0..=2 {
+            let key = Key::new_scoped(scope.clone(), random_segment());
+            store
+                .store(&key.with_sub_scope(random_segment()), random_value(8))
+                .unwrap();
+            store
+                .store(&key.with_sub_scope(random_segment()), random_value(8))
+                .unwrap();
+            store.store(&random_key(i), random_value(8)).unwrap();
+        }
+
+        // locks used by transactions are not keys
+        store.transaction(&scope, &mut |_| Ok(())).unwrap();
+
+        let mut keys = store
+            .stream_keys(&scope)
+            .unwrap()
+            .collect::<crate::Result<Vec<Key>>>()
+            .unwrap();
+        keys.sort();
+
+        let mut expected = store.list_keys(&scope).unwrap();
+        expected.sort();
+
+        assert_eq!(keys.len(), 6);
+        assert_eq!(keys, expected);
+
+        store.clear().unwrap();
+    }
+
+    fn test_count_keys(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let sub_scope = scope.with_sub_scope(random_segment());
+        assert_eq!(store.count_keys(&scope).unwrap(), 0);
+
+        store
+            .store(
+                &Key::new_scoped(scope.clone(), random_segment()),
+                random_value(8),
+            )
+            .unwrap();
+        store
+            .store(
+                &Key::new_scoped(sub_scope.clone(), random_segment()),
+                random_value(8),
+            )
+            .unwrap();
+        store.store(&random_key(1), random_value(8)).unwrap();
+
+        assert_eq!(store.count_keys(&scope).unwrap(), 2);
+        assert_eq!(store.count_keys(&sub_scope).unwrap(), 1);
+        assert_eq!(store.count_keys(&random_scope(1)).unwrap(), 0);
+        assert_eq!(store.count_keys(&Scope::global()).unwrap(), 3);
+
+        store.clear().unwrap();
+    }
+
+    fn test_copy_scope(store: impl KeyValueStoreBackend) {
+        let from = random_scope(1);
+        let to = random_scope(2);
+        let name = random_segment();
+        let sub = random_segment();
+        let value = random_value(8);
+        let sub_value = random_value(8);
+        let kept_value = random_value(8);
+
+        let key = Key::new_scoped(from.clone(), name.clone());
+        let sub_key = Key::new_scoped(from.with_sub_scope(sub.clone()), name.clone());
+        let kept = Key::new_scoped(to.clone(), random_segment());
+        store.store(&key, value.clone()).unwrap();
+        store.store(&sub_key, sub_value.clone()).unwrap();
+        store.store(&kept, kept_value.clone()).unwrap();
+        store
+            .store(&Key::new_scoped(to.clone(), name.clone()), random_value(8))
+            .unwrap();
+
+        store.copy_scope(&from, &to).unwrap();
+
+        assert_eq!(store.get(&key).unwrap(), Some(value.clone()));
+        assert_eq!(store.get(&sub_key).unwrap(), Some(sub_value.clone()));
+        assert_eq!(
+            store
+                .get(&Key::new_scoped(to.clone(), name.clone()))
+                .unwrap(),
+            Some(value)
+        );
+        assert_eq!(
+            store
+                .get(&Key::new_scoped(to.with_sub_scope(sub), name))
+                .unwrap(),
+            Some(sub_value)
+        );
+        assert_eq!(store.get(&kept).unwrap(), Some(kept_value));
+        assert_eq!(store.count_keys(&to).unwrap(), 3);
+        assert_eq!(store.count_keys(&from).unwrap(), 2);
+
+        store.copy_scope(&random_scope(1), &to).unwrap();
+        assert_eq!(store.count_keys(&to).unwrap(), 3);
+
+        store.clear().unwrap();
+    }
+
     fn test_move_scope(store: impl KeyValueStoreBackend) {
         let key = random_key(0);
         let scope = random_scope(1);
@@ -269,6 +1090,77 @@ mod tests {
         store.clear().unwrap();
     }
 
+    fn test_move_scope_nested(store: impl KeyValueStoreBackend) {
+        let from = random_scope(1);
+        let sub_scope = from.with_sub_scope(random_segment());
+        let key = Key::new_scoped(from.clone(), random_segment());
+        let sub_key = Key::new_scoped(sub_scope.clone(), random_segment());
+        let other = random_key(1);
+        let value = random_value(8);
+
+        for key in [&key, &sub_key, &other] {
+            store.store(key, value.clone()).unwrap();
+        }
+
+        let to = random_scope(2);
+        store.move_scope(&from, &to).unwrap();
+
+        let moved_key = key.rebase(&from, &to).unwrap();
+        let moved_sub_key = sub_key.rebase(&from, &to).unwrap();
+        let mut result = store.list_keys(&Scope::global()).unwrap();
+        let mut expected = vec![moved_key, moved_sub_key.clone(), other];
+        result.sort();
+        expected.sort();
+        assert_eq!(result, expected);
+        assert_eq!(store.get(&moved_sub_key).unwrap(), Some(value));
+        assert!(!store.has_scope(&sub_scope).unwrap());
+
+        store.clear().unwrap();
+    }
+
+    fn test_move_scope_non_empty_target(store: impl KeyValueStoreBackend) {
+        let from = random_scope(1);
+        let to = random_scope(1);
+        let name = random_segment();
+        let sub_name = random_segment();
+        let sub_scope = random_segment();
+
+        let key = Key::new_scoped(from.clone(), name.clone());
+        let sub_key = Key::new_scoped(from.with_sub_scope(sub_scope.clone()), sub_name.clone());
+        let replaced = Key::new_scoped(to.clone(), name);
+        let kept = Key::new_scoped(to.clone(), random_segment());
+        let kept_sub = Key::new_scoped(to.with_sub_scope(sub_scope.clone()), random_segment());
+        let moved_sub = Key::new_scoped(to.with_sub_scope(sub_scope), sub_name);
+
+        store.store(&key, Value::from(1)).unwrap();
+        store.store(&sub_key, Value::from(2)).unwrap();
+        store.store(&replaced, Value::from(3)).unwrap();
+        store.store(&kept, Value::from(4)).unwrap();
+        store.store(&kept_sub, Value::from(5)).unwrap();
+
+        // The values are merged into the target scope, replacing the values
+        // for the same keys.
+        store.move_scope(&from, &to).unwrap();
+
+        assert!(!store.has_scope(&from).unwrap());
+        let mut result = store.list_keys(&to).unwrap();
+        let mut expected = vec![
+            replaced.clone(),
+            kept.clone(),
+            kept_sub.clone(),
+            moved_sub.clone(),
+        ];
+        result.sort();
+        expected.sort();
+        assert_eq!(result, expected);
+        assert_eq!(store.get(&replaced).unwrap(), Some(Value::from(1)));
+        assert_eq!(store.get(&moved_sub).unwrap(), Some(Value::from(2)));
+        assert_eq!(store.get(&kept).unwrap(), Some(Value::from(4)));
+        assert_eq!(store.get(&kept_sub).unwrap(), Some(Value::from(5)));
+
+        store.clear().unwrap();
+    }
+
     fn test_transaction(mut stores: Vec<impl KeyValueStoreBackend + Send>) {
         stores[0]
             .store(&"counter".parse().unwrap(), Value::from(0))
@@ -399,6 +1291,382 @@ mod tests {
         });
     }
 
+    fn test_transaction_overlapping_scopes(mut stores: Vec<impl KeyValueStoreBackend + Send>) {
+        let child = random_scope(2);
+        let parent = child.sub_scopes()[0].clone();
+
+        // Each transaction stores a key in the child scope if it is empty, so
+        // only one of them may do so if they exclude each other.
+        std::thread::scope(|s| {
+            for (index, (store, scope)) in stores.iter_mut().zip([&parent, &child]).enumerate() {
+                let child = child.clone();
+                s.spawn(move || {
+                    store
+                        .transaction(scope, &mut |t: &dyn KeyValueStoreBackend| {
+                            if t.list_keys(&child)?.is_empty() {
+                                std::thread::sleep(Duration::from_millis(50));
+                                let name = format!("key_{index}").parse::<SegmentBuf>().unwrap();
+                                t.store(&Key::new_scoped(child.clone(), name), Value::from(1))?;
+                            }
+                            Ok(())
+                        })
+                        .unwrap();
+                });
+            }
+        });
+
+        assert_eq!(stores[0].list_keys(&child).unwrap().len(), 1);
+
+        stores[0].clear().unwrap();
+    }
+
+    fn test_read_only_transaction(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let key1 = Key::new_scoped(scope.clone(), random_segment());
+        let key2 = Key::new_scoped(scope.clone(), random_segment());
+        store.store(&key1, Value::from(1)).unwrap();
+        store.store(&key2, Value::from(2)).unwrap();
+
+        let mut entries = HashMap::new();
+        store
+            .read_only_transaction(&scope, &mut |r| {
+                for key in r.list_keys(&scope)? {
+                    entries.insert(key.clone(), r.get(&key)?);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            entries,
+            HashMap::from([(key1, Some(Value::from(1))), (key2, Some(Value::from(2)))])
+        );
+
+        let result = store.read_only_transaction(&scope, &mut |_| Err(Error::UnknownKey));
+        assert!(matches!(result, Err(Error::UnknownKey)));
+
+        store.clear().unwrap();
+    }
+
+    fn test_try_transaction(store: impl KeyValueStoreBackend) {
+        let scope = random_scope(1);
+        let key = Key::new_scoped(scope.clone(), random_segment());
+        let value = random_value(8);
+
+        // Without any contention the transaction is always run.
+        let ran = store
+            .try_transaction(&scope, Duration::from_secs(1), &mut |t| {
+                t.store(&key, value.clone())
+            })
+            .unwrap();
+        assert!(ran);
+        assert_eq!(store.get(&key).unwrap(), Some(value));
+
+        store.clear().unwrap();
+    }
+
+    fn test_update(store: impl PubKeyValueStoreBackend + 'static) {
+        let store = KeyValueStore::from_inner(Box::new(store));
+        let key: Key = "counter".parse().unwrap();
+
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        store
+                            .update(&key, |value| {
+                                let c = value.and_then(|v| v.as_i64()).unwrap_or_default();
+                                Ok(Some(Value::from(c + 1)))
+                            })
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(20)));
+
+        store.update(&key, |_| Ok(None)).unwrap();
+
+        assert!(!store.has(&key).unwrap());
+
+        store.clear().unwrap();
+    }
+
+    fn test_transaction_abort(store: impl PubKeyValueStoreBackend + 'static) {
+        let store = KeyValueStore::from_inner(Box::new(store));
+        let scope = random_scope(1);
+        let key = Key::new_scoped(scope.clone(), random_segment());
+        let other = Key::new_scoped(scope.clone(), random_segment());
+        let value = random_value(8);
+        store.store(&key, value.clone()).unwrap();
+
+        let mut add_other = |t: &dyn KeyValueStoreBackend| {
+            if t.get(&key)? == Some(value.clone()) {
+                return Err(Error::Abort);
+            }
+            t.store(&other, random_value(8))
+        };
+
+        store.transaction(&scope, &mut add_other).unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(value.clone()));
+        assert!(!store.has(&other).unwrap());
+
+        assert!(matches!(
+            store.execute(&scope, |_| Err::<(), _>(Error::Abort)),
+            Err(Error::Abort)
+        ));
+
+        store.delete(&key).unwrap();
+        store.transaction(&scope, &mut add_other).unwrap();
+        assert!(store.has(&other).unwrap());
+
+        store.clear().unwrap();
+    }
+
+    fn test_transaction_rollback(stores: Vec<impl PubKeyValueStoreBackend + 'static>) {
+        let mut stores = stores
+            .into_iter()
+            .map(|s| KeyValueStore::from_inner(Box::new(s)));
+        let (store, fresh) = (stores.next().unwrap(), stores.next().unwrap());
+        let scope = random_scope(1);
+        let kept = Key::new_scoped(scope.clone(), random_segment());
+        let deleted = Key::new_scoped(scope.clone(), random_segment());
+        let added = Key::new_scoped(scope.clone(), random_segment());
+        let value = random_value(8);
+        store.store(&kept, value.clone()).unwrap();
+        store.store(&deleted, value.clone()).unwrap();
+
+        for aborted in [true, false] {
+            let res = store.transaction(&scope, &mut |t| {
+                let expires_at = SystemTime::now() + Duration::from_secs(3600);
+                t.store(&kept, random_value(8))?;
+                t.delete(&deleted)?;
+                t.store_with_expiry(&added, random_value(8), expires_at)?;
+                assert!(!t.has(&deleted)?);
+                assert!(t.has(&added)?);
+
+                Err(if aborted {
+                    Error::Abort
+                } else {
+                    Error::UnknownKey
+                })
+            });
+            assert_eq!(res.is_ok(), aborted);
+
+            // Nothing written before the abort or error is persisted.
+            for store in [&store, &fresh] {
+                assert_eq!(store.get(&kept).unwrap(), Some(value.clone()));
+                assert!(store.has(&deleted).unwrap());
+                assert!(!store.has(&added).unwrap());
+            }
+        }
+
+        store.clear().unwrap();
+    }
+
+    fn test_namespace_summaries(storage_uri: &str) {
+        let storage_uri = url::Url::parse(storage_uri).unwrap();
+        let (ns1, ns2) = (random_namespace(), random_namespace());
+        let store1 = KeyValueStore::new(&storage_uri, ns1.clone()).unwrap();
+        let store2 = KeyValueStore::new(&storage_uri, ns2.clone()).unwrap();
+
+        for i in 0..3 {
+            store1.store(&random_key(i), random_value(8)).unwrap();
+        }
+        store2.store(&random_key(1), random_value(8)).unwrap();
+
+        let summaries = KeyValueStore::namespace_summaries(&storage_uri).unwrap();
+        assert!(summaries.contains(&(ns1, 3)));
+        assert!(summaries.contains(&(ns2, 1)));
+
+        store1.clear().unwrap();
+        store2.clear().unwrap();
+    }
+
+    /// A backend that only implements the required methods, like a backend
+    /// outside of this crate may do.
+    struct MinimalStore(Memory);
+
+    impl ReadStore for MinimalStore {
+        fn is_empty(&self) -> Result<bool> {
+            self.0.is_empty()
+        }
+
+        fn has(&self, key: &Key) -> Result<bool> {
+            self.0.has(key)
+        }
+
+        fn has_scope(&self, scope: &Scope) -> Result<bool> {
+            self.0.has_scope(scope)
+        }
+
+        fn get(&self, key: &Key) -> Result<Option<Value>> {
+            self.0.get(key)
+        }
+
+        fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+            self.0.list_keys(scope)
+        }
+
+        fn list_scopes(&self) -> Result<Vec<Scope>> {
+            self.0.list_scopes()
+        }
+    }
+
+    impl WriteStore for MinimalStore {
+        fn store(&self, key: &Key, value: Value) -> Result<()> {
+            self.0.store(key, value)
+        }
+
+        fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+            self.0.move_value(from, to)
+        }
+
+        fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+            self.0.move_scope(from, to)
+        }
+
+        fn delete(&self, key: &Key) -> Result<()> {
+            self.0.delete(key)
+        }
+
+        fn delete_scope(&self, scope: &Scope) -> Result<()> {
+            self.0.delete_scope(scope)
+        }
+
+        fn clear(&self) -> Result<()> {
+            self.0.clear()
+        }
+
+        fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+            self.0.migrate_namespace(to)
+        }
+    }
+
+    impl KeyValueStoreBackend for MinimalStore {
+        fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+            self.0.transaction(scope, &mut |_| callback(self))
+        }
+    }
+
+    #[test]
+    fn test_default_methods() {
+        let store = || MinimalStore(Memory::new_isolated(random_namespace()));
+
+        test_copy_value(store());
+        test_copy_scope(store());
+        test_clear_scope(store());
+
+        let store = store();
+        let key = random_key(2);
+        let value = random_value(8);
+        assert!(store.compare_and_swap(&key, None, value.clone()).unwrap());
+        assert!(!store.compare_and_swap(&key, None, value.clone()).unwrap());
+        assert!(matches!(
+            store.store_with_expiry(&key, value, SystemTime::now()),
+            Err(Error::Other(_))
+        ));
+        assert_eq!(store.purge_expired().unwrap(), 0);
+        assert!(matches!(
+            store.changes_since(SystemTime::now()),
+            Err(Error::Other(_))
+        ));
+
+        store.delete_scope_exact(key.scope()).unwrap();
+        assert!(matches!(
+            store.delete_existing(&key),
+            Err(Error::UnknownKey)
+        ));
+        assert!(store.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_register_scheme() {
+        let url = url::Url::parse("test-register-scheme://instance").unwrap();
+        assert!(matches!(
+            KeyValueStore::new(&url, random_namespace()),
+            Err(Error::UnknownScheme(_))
+        ));
+
+        KeyValueStore::register_scheme(
+            "test-register-scheme",
+            Box::new(|url, namespace| Ok(Box::new(Memory::new(url.host_str(), namespace)?))),
+        )
+        .unwrap();
+
+        let namespace = random_namespace();
+        let key = random_key(1);
+        let value = random_value(8);
+        let store = KeyValueStore::new(&url, namespace.clone()).unwrap();
+        store.store(&key, value.clone()).unwrap();
+
+        let memory = Memory::new(Some("instance"), namespace).unwrap();
+        assert_eq!(memory.get(&key).unwrap(), Some(value));
+
+        memory.clear().unwrap();
+    }
+
+    #[test]
+    fn test_reject_null() {
+        let url = url::Url::parse("memory://test_reject_null").unwrap();
+        let key: Key = "key".parse().unwrap();
+
+        let store = KeyValueStore::new(&url, random_namespace()).unwrap();
+        store.store(&key, Value::Null).unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(Value::Null));
+
+        let store = store.with_reject_null(true);
+        store.store(&key, Value::from(1)).unwrap();
+        assert!(matches!(
+            store.store(&key, Value::Null),
+            Err(Error::NullValue)
+        ));
+        assert!(matches!(
+            store.store_many(&[(key.clone(), Value::Null)]),
+            Err(Error::NullValue)
+        ));
+        assert!(matches!(
+            store.update(&key, |_| Ok(Some(Value::Null))),
+            Err(Error::NullValue)
+        ));
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(1)));
+    }
+
+    #[test]
+    fn test_namespace_summaries_memory() {
+        test_namespace_summaries("memory://test_namespace_summaries");
+    }
+
+    #[test]
+    fn test_namespace_summaries_disk() {
+        let base = tempfile::tempdir().unwrap();
+        test_namespace_summaries(&format!("local://{}", base.path().display()));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_namespace_summaries_sqlite() {
+        let base = tempfile::tempdir().unwrap();
+        test_namespace_summaries(&format!(
+            "sqlite://{}",
+            base.path().join("store.sqlite").display()
+        ));
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    #[serial_test::serial]
+    fn test_namespace_summaries_postgres() {
+        test_namespace_summaries("postgres://postgres@localhost/postgres");
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    #[serial_test::serial]
+    fn test_namespace_summaries_redis() {
+        test_namespace_summaries("redis://localhost");
+    }
+
     macro_rules! generate_tests {
         ($ident:ident, $construct:expr) => {
             mod $ident {
@@ -410,12 +1678,65 @@ mod tests {
                     super::test_store($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_store_edge_values() {
+                    super::test_store_edge_values($construct(super::random_namespace()))
+                }
+                #[test]
+                #[serial]
+                fn test_store_bytes() {
+                    super::test_store_bytes($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_store_many() {
+                    super::test_store_many($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_snapshot_restore() {
+                    super::test_snapshot_restore($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_store_with_expiry() {
+                    super::test_store_with_expiry($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_has() {
                     super::test_has($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_has_each() {
+                    super::test_has_each($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_has_value() {
+                    super::test_has_value($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_get_many() {
+                    super::test_get_many($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_get_or() {
+                    super::test_get_or($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_has_scope() {
@@ -428,30 +1749,108 @@ mod tests {
                     super::test_list_keys($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_list_keys_matching_ci() {
+                    super::test_list_keys_matching_ci($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_list_entries() {
+                    super::test_list_entries($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_list_scopes() {
                     super::test_list_scopes($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_list_scopes_under() {
+                    super::test_list_scopes_under($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_list_child_scopes() {
+                    super::test_list_child_scopes($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_find_keys_where_value() {
+                    super::test_find_keys_where_value($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_compare_and_swap() {
+                    super::test_compare_and_swap($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_move_value() {
                     super::test_move_value($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_move_value_unknown_key() {
+                    super::test_move_value_unknown_key($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_copy_value() {
+                    super::test_copy_value($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_changes_since() {
+                    super::test_changes_since($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_prune_changes() {
+                    super::test_prune_changes($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_delete() {
                     super::test_delete($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_delete_missing() {
+                    super::test_delete_missing($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_delete_scope() {
                     super::test_delete_scope($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_clear_scope() {
+                    super::test_clear_scope($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_delete_scope_nested() {
+                    super::test_delete_scope_nested($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_clear() {
@@ -464,12 +1863,69 @@ mod tests {
                     super::test_is_empty($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_total_keys() {
+                    super::test_total_keys($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_stream_keys() {
+                    super::test_stream_keys($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_count_keys() {
+                    super::test_count_keys($construct(super::random_namespace()))
+                }
+
                 #[test]
                 #[serial]
                 fn test_move_scope() {
                     super::test_move_scope($construct(super::random_namespace()))
                 }
 
+                #[test]
+                #[serial]
+                fn test_move_scope_nested() {
+                    super::test_move_scope_nested($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_move_scope_non_empty_target() {
+                    super::test_move_scope_non_empty_target($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_copy_scope() {
+                    super::test_copy_scope($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_update() {
+                    super::test_update($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_transaction_abort() {
+                    super::test_transaction_abort($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_transaction_rollback() {
+                    let ns = super::random_namespace();
+                    let store1 = $construct(ns.clone());
+                    let store2 = $construct(ns.clone());
+                    super::test_transaction_rollback(vec![store1, store2]);
+                }
+
                 #[test]
                 #[serial]
                 fn test_transaction() {
@@ -478,6 +1934,27 @@ mod tests {
                     let store2 = $construct(ns.clone());
                     super::test_transaction(vec![store1, store2]);
                 }
+
+                #[test]
+                #[serial]
+                fn test_transaction_overlapping_scopes() {
+                    let ns = super::random_namespace();
+                    let store1 = $construct(ns.clone());
+                    let store2 = $construct(ns.clone());
+                    super::test_transaction_overlapping_scopes(vec![store1, store2]);
+                }
+
+                #[test]
+                #[serial]
+                fn test_read_only_transaction() {
+                    super::test_read_only_transaction($construct(super::random_namespace()))
+                }
+
+                #[test]
+                #[serial]
+                fn test_try_transaction() {
+                    super::test_try_transaction($construct(super::random_namespace()))
+                }
             }
         };
     }
@@ -485,7 +1962,7 @@ mod tests {
     #[cfg(feature = "postgres")]
     fn postgres(namespace: NamespaceBuf) -> Postgres<PgPool> {
         let pg = Postgres::new(
-            &url::Url::parse("postgres://postgres@localhost/postgres").unwrap(),
+            &url::Url::parse("postgres://postgres@localhost/postgres?track_changes=true").unwrap(),
             namespace,
         )
         .unwrap();
@@ -495,10 +1972,33 @@ mod tests {
         pg
     }
 
+    #[cfg(feature = "redis")]
+    fn redis(namespace: NamespaceBuf) -> Redis {
+        let store = Redis::new(&url::Url::parse("redis://localhost").unwrap(), namespace)
+            .unwrap()
+            .with_change_tracking(true);
+        store.clear().unwrap();
+        store
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite(namespace: NamespaceBuf) -> Sqlite<std::sync::Mutex<rusqlite::Connection>> {
+        let path = std::env::temp_dir().join("kvx-test.sqlite");
+        let store = Sqlite::new(path, namespace)
+            .unwrap()
+            .with_change_tracking(true)
+            .unwrap();
+        store.clear().unwrap();
+        store
+    }
+
     fn memory(namespace: NamespaceBuf) -> Memory {
         use crate::WriteStore;
 
-        let store = Memory::new(None, namespace).unwrap();
+        let store = Memory::new(None, namespace)
+            .unwrap()
+            .with_change_tracking(true)
+            .unwrap();
         store.clear().unwrap();
         store
     }
@@ -510,11 +2010,17 @@ mod tests {
             fs::remove_dir_all(path).unwrap();
         }
 
-        Disk::new(path, namespace.as_str()).unwrap()
+        Disk::new(path, namespace.as_str())
+            .unwrap()
+            .with_change_tracking(true)
     }
 
     #[cfg(feature = "postgres")]
     generate_tests!(test_postgres, super::postgres);
+    #[cfg(feature = "redis")]
+    generate_tests!(test_redis, super::redis);
+    #[cfg(feature = "sqlite")]
+    generate_tests!(test_sqlite, super::sqlite);
     generate_tests!(test_memory, super::memory);
     generate_tests!(test_fs, super::disk);
 }
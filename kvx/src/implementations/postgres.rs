@@ -1,51 +1,503 @@
+#[cfg(feature = "postgres-tls")]
+use std::path::PathBuf;
 use std::{
     cell::{RefCell, RefMut},
+    collections::{BTreeSet, HashMap, HashSet},
     fmt::{Debug, Display},
+    str::FromStr,
+    time::{Duration, SystemTime},
 };
 
 use kvx_types::NamespaceBuf;
-use postgres::{NoTls, Row, ToStatement, Transaction};
+use postgres::{
+    error::SqlState,
+    tls::{MakeTlsConnect, TlsConnect},
+    Client, NoTls, Row, Socket, ToStatement, Transaction,
+};
+#[cfg(feature = "postgres-tls")]
+use postgres_native_tls::MakeTlsConnector;
 use postgres_types::ToSql;
 use r2d2_postgres::{
-    r2d2::{Pool, PooledConnection},
+    r2d2::{ManageConnection, Pool, PooledConnection},
     PostgresConnectionManager,
 };
 use url::Url;
 
 use crate::{
-    Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope, SegmentBuf, TransactionCallback,
-    WriteStore,
+    json_path_segments, BackendKind, ChangeKind, Error, Key, KeyValueStoreBackend,
+    PubKeyValueStoreBackend, ReadStore, ReadTransactionCallback, Result, Scope, Segment,
+    SegmentBuf, TransactionCallback, WriteStore,
 };
 
-type PostgresClient = PostgresConnectionManager<NoTls>;
+/// Matches the rows that have not expired.
+const LIVE: &str = "(expires_at IS NULL OR expires_at > now())";
+
+/// Returns a statement that runs `remove`, which must return the namespace,
+/// scope, key and expiry time of the rows it removes, and records a
+/// tombstone in the `tombstones` table for each removed row that had not
+/// expired, if there is one. The statement affects as many rows as were
+/// removed without having expired.
+fn with_tombstones(tombstones: Option<&str>, remove: &str) -> String {
+    match tombstones {
+        Some(tombstones) => format!(
+            "WITH removed AS ({remove}) \
+             INSERT INTO {tombstones} (namespace, scope, key, deleted_at) \
+             SELECT namespace, scope, key, now() FROM removed WHERE {LIVE} \
+             ON CONFLICT (namespace, scope, key) DO UPDATE SET deleted_at = now()"
+        ),
+        None => format!("WITH removed AS ({remove}) SELECT 1 FROM removed WHERE {LIVE}"),
+    }
+}
+
+pub type PgPool = Pool<PostgresConnectionManager<NoTls>>;
 
-pub type PgPool = Pool<PostgresClient>;
+#[cfg(feature = "postgres-tls")]
+pub type PgTlsPool = Pool<PostgresConnectionManager<MakeTlsConnector>>;
 
-#[derive(Debug)]
 pub(crate) struct Postgres<E> {
     namespace: NamespaceBuf,
     executor: E,
+    options: Options,
 }
 
-impl Postgres<PgPool> {
-    pub(crate) fn new(connection_str: &Url, namespace: impl Into<NamespaceBuf>) -> Result<Self> {
-        let manager = PostgresConnectionManager::new(connection_str.as_str().parse()?, NoTls);
-        let pool = Pool::new(manager)?;
+impl<E> Debug for Postgres<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Postgres")
+            .field("namespace", &self.namespace)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Options for the Postgres backend, given as query parameters of the
+/// connection URL.
+///
+/// These parameters are removed from the URL before it is used to connect
+/// to Postgres.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Options {
+    /// Whether transactions are retried when they fail, set using
+    /// `transaction_retry=false`. If disabled, serialization conflicts are
+    /// returned immediately as [`Error::TransactionConflict`].
+    transaction_retry: bool,
+
+    /// The tables used to store values, set using `table=name` or
+    /// `table=schema.name`.
+    table: Table,
+
+    /// Whether tombstones are recorded for deleted values, set using
+    /// `track_changes=true`, see [`ReadStore::changes_since`].
+    track_changes: bool,
+
+    /// Whether to connect using TLS, which is the case if the `sslmode`
+    /// parameter is `prefer` or `require`. The parameter is also used by
+    /// Postgres, so it is kept in the URL.
+    #[cfg(feature = "postgres-tls")]
+    tls: bool,
+
+    /// A PEM file with a root certificate to trust in addition to the
+    /// system's root certificates, set using `sslrootcert=path`.
+    #[cfg(feature = "postgres-tls")]
+    ssl_root_cert: Option<PathBuf>,
+
+    /// The maximum number of connections in the pool, set using
+    /// `pool_max=n`.
+    pool_max_size: u32,
+
+    /// The minimum number of idle connections kept in the pool, set using
+    /// `pool_min_idle=n`. By default the pool keeps up to the maximum number
+    /// of connections.
+    pool_min_idle: Option<u32>,
+
+    /// How long to wait for a connection from the pool, set in seconds
+    /// using `pool_timeout=n`.
+    pool_timeout: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            transaction_retry: true,
+            table: Table::default(),
+            track_changes: false,
+            #[cfg(feature = "postgres-tls")]
+            tls: false,
+            #[cfg(feature = "postgres-tls")]
+            ssl_root_cert: None,
+            pool_max_size: 10,
+            pool_min_idle: None,
+            pool_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The names of the tables used by the Postgres backend.
+///
+/// The tables are created when a store connects if they do not exist yet,
+/// with the columns of the `store` and `store_tombstones` tables in
+/// `postgres.sql`, see [`Postgres::initialize_schema`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Table {
+    /// The (possibly schema-qualified) name of the table holding the values.
+    store: String,
+
+    /// The name of the table holding the tombstones of deleted values, which
+    /// is the name of the store table with a `_tombstones` suffix.
+    tombstones: String,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table::new("store")
+    }
+}
+
+impl Table {
+    fn new(store: &str) -> Self {
+        Table {
+            store: store.to_string(),
+            tombstones: format!("{store}_tombstones"),
+        }
+    }
+
+    /// Parses a table name, which is interpolated into SQL statements and
+    /// therefore restricted to a name optionally qualified by a schema, each
+    /// consisting of ASCII letters, digits and underscores.
+    fn parse(name: &str) -> Result<Self> {
+        let valid = |identifier: &str| {
+            identifier
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && identifier
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+
+        match name.split_once('.') {
+            None if valid(name) => Ok(Table::new(name)),
+            Some((schema, table)) if valid(schema) && valid(table) => Ok(Table::new(name)),
+            _ => Err(Error::InvalidConfig(format!(
+                "invalid value for table: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Options for the Postgres backend set using the [`KeyValueStoreBuilder`],
+/// which take precedence over the query parameters of the URL.
+///
+/// [`KeyValueStoreBuilder`]: crate::KeyValueStoreBuilder
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct OptionOverrides {
+    pub(crate) transaction_retry: Option<bool>,
+    pub(crate) table: Option<String>,
+    pub(crate) track_changes: Option<bool>,
+    pub(crate) pool_max_size: Option<u32>,
+    pub(crate) pool_min_idle: Option<u32>,
+    pub(crate) pool_timeout: Option<Duration>,
+}
+
+impl Options {
+    /// Returns the options and the URL without the option parameters.
+    fn from_url(url: &Url) -> Result<(Self, Url)> {
+        let mut options = Options::default();
+        let mut params = vec![];
+
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "transaction_retry" => options.transaction_retry = parse_param(&name, &value)?,
+                "table" => options.table = Table::parse(&value)?,
+                "track_changes" => options.track_changes = parse_param(&name, &value)?,
+                "pool_max" => match parse_param(&name, &value)? {
+                    0 => return Err(invalid_param(&name, &value)),
+                    max => options.pool_max_size = max,
+                },
+                "pool_min_idle" => options.pool_min_idle = Some(parse_param(&name, &value)?),
+                "pool_timeout" => match parse_param(&name, &value)? {
+                    0 => return Err(invalid_param(&name, &value)),
+                    secs => options.pool_timeout = Duration::from_secs(secs),
+                },
+                #[cfg(feature = "postgres-tls")]
+                "sslmode" => {
+                    options.tls = value != "disable";
+                    params.push((name, value));
+                }
+                #[cfg(feature = "postgres-tls")]
+                "sslrootcert" => options.ssl_root_cert = Some(PathBuf::from(value.as_ref())),
+                _ => params.push((name, value)),
+            }
+        }
+
+        options.check_pool()?;
+
+        let mut url = url.clone();
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(params);
+        }
+
+        Ok((options, url))
+    }
+
+    /// Applies the overrides to these options.
+    fn apply(&mut self, overrides: &OptionOverrides) -> Result<()> {
+        if let Some(transaction_retry) = overrides.transaction_retry {
+            self.transaction_retry = transaction_retry;
+        }
+        if let Some(table) = &overrides.table {
+            self.table = Table::parse(table)?;
+        }
+        if let Some(track_changes) = overrides.track_changes {
+            self.track_changes = track_changes;
+        }
+        if let Some(max) = overrides.pool_max_size {
+            if max == 0 {
+                return Err(invalid_param("pool_max", "0"));
+            }
+            self.pool_max_size = max;
+        }
+        if let Some(min_idle) = overrides.pool_min_idle {
+            self.pool_min_idle = Some(min_idle);
+        }
+        if let Some(timeout) = overrides.pool_timeout {
+            if timeout.is_zero() {
+                return Err(invalid_param("pool_timeout", "0"));
+            }
+            self.pool_timeout = timeout;
+        }
+
+        self.check_pool()
+    }
+
+    /// Returns the table to record tombstones in, if changes are tracked.
+    fn tombstones(&self) -> Option<&str> {
+        self.track_changes.then_some(self.table.tombstones.as_str())
+    }
+
+    /// Checks that the minimum number of idle connections does not exceed
+    /// the maximum size of the pool.
+    fn check_pool(&self) -> Result<()> {
+        match self.pool_min_idle {
+            Some(min_idle) if min_idle > self.pool_max_size => Err(Error::InvalidConfig(format!(
+                "pool_min_idle {} exceeds pool_max {}",
+                min_idle, self.pool_max_size
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a pool of connections created by the manager.
+    fn pool<M: ManageConnection>(&self, manager: M) -> Result<Pool<M>> {
+        Ok(Pool::builder()
+            .max_size(self.pool_max_size)
+            .min_idle(self.pool_min_idle)
+            .connection_timeout(self.pool_timeout)
+            .build(manager)?)
+    }
+
+    /// Returns the TLS connector for connections to Postgres.
+    #[cfg(feature = "postgres-tls")]
+    fn tls_connector(&self) -> Result<MakeTlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(path) = &self.ssl_root_cert {
+            let pem = std::fs::read(path).map_err(|e| {
+                Error::IoWithContext(
+                    format!("cannot read root certificate {}", path.display()),
+                    e,
+                )
+            })?;
+            builder.add_root_certificate(native_tls::Certificate::from_pem(&pem)?);
+        }
+
+        Ok(MakeTlsConnector::new(builder.build()?))
+    }
+
+    /// Connects a client to Postgres, using TLS if configured.
+    fn connect(&self, connection_str: &Url) -> Result<Client> {
+        #[cfg(feature = "postgres-tls")]
+        if self.tls {
+            return Ok(Client::connect(
+                connection_str.as_str(),
+                self.tls_connector()?,
+            )?);
+        }
+
+        Ok(Client::connect(connection_str.as_str(), NoTls)?)
+    }
+}
+
+/// Parses the value of a URL parameter.
+fn parse_param<T: FromStr>(name: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| invalid_param(name, value))
+}
+
+fn invalid_param(name: &str, value: &str) -> Error {
+    Error::InvalidConfig(format!("invalid value for {}: {}", name, value))
+}
+
+/// Creates a Postgres backend for the connection URL, with the overrides
+/// applied to the options in the URL.
+///
+/// If the `postgres-tls` feature is enabled, connections use TLS if the
+/// `sslmode` parameter is `prefer` or `require`. Otherwise connections do not
+/// use TLS, and fail if the parameter is `require`.
+pub(crate) fn connect(
+    connection_str: &Url,
+    namespace: impl Into<NamespaceBuf>,
+    overrides: &OptionOverrides,
+) -> Result<Box<dyn PubKeyValueStoreBackend>> {
+    let (mut options, connection_str) = Options::from_url(connection_str)?;
+    options.apply(overrides)?;
+
+    #[cfg(feature = "postgres-tls")]
+    if options.tls {
+        let connector = options.tls_connector()?;
+        let postgres =
+            Postgres::<PgTlsPool>::with_connector(&connection_str, namespace, options, connector)?;
+        return Ok(Box::new(postgres));
+    }
+
+    let postgres = Postgres::<PgPool>::with_connector(&connection_str, namespace, options, NoTls)?;
+    Ok(Box::new(postgres))
+}
 
-        Ok(Postgres {
+impl<T> Postgres<Pool<PostgresConnectionManager<T>>>
+where
+    T: MakeTlsConnect<Socket> + Clone + 'static + Sync + Send,
+    T::TlsConnect: Send,
+    T::Stream: Send,
+    <T::TlsConnect as TlsConnect<Socket>>::Future: Send,
+{
+    fn with_connector(
+        connection_str: &Url,
+        namespace: impl Into<NamespaceBuf>,
+        options: Options,
+        connector: T,
+    ) -> Result<Self> {
+        let manager = PostgresConnectionManager::new(connection_str.as_str().parse()?, connector);
+        let pool = options.pool(manager)?;
+
+        let postgres = Postgres {
             namespace: namespace.into(),
             executor: pool,
-        })
+            options,
+        };
+        postgres.initialize_schema()?;
+
+        Ok(postgres)
+    }
+
+    /// Creates the tables of the store if they do not exist yet, and adds
+    /// the columns that tables created by earlier versions lack.
+    ///
+    /// This does nothing if the tables are up to date, so that a role
+    /// without the privileges to create or alter them can use them.
+    /// Otherwise the changes are made in a transaction holding an advisory
+    /// lock, so that stores connecting at the same time do not conflict.
+    fn initialize_schema(&self) -> Result<()> {
+        let Table { store, tombstones } = &self.options.table;
+        let mut client = self.executor.get()?;
+
+        let up_to_date: bool = client
+            .query_one(
+                "SELECT to_regclass($2) IS NOT NULL AND ( \
+                 SELECT COUNT(*) FROM pg_attribute WHERE attrelid = to_regclass($1) \
+                 AND attname IN ('created_at', 'updated_at', 'expires_at') \
+                 AND NOT attisdropped) = 3",
+                &[store, tombstones],
+            )?
+            .get(0);
+        if up_to_date {
+            return Ok(());
+        }
+
+        // Values stored before change tracking support get the Unix epoch
+        // as their creation and update time, new values get the current time.
+        let mut transaction = client.transaction()?;
+        transaction.batch_execute(&format!(
+            "SELECT pg_advisory_xact_lock(hashtext('kvx_initialize_schema'));
+            CREATE TABLE IF NOT EXISTS {store} (
+                namespace VARCHAR NOT NULL,
+                scope TEXT[] NOT NULL,
+                key VARCHAR NOT NULL,
+                value JSONB NOT NULL,
+                PRIMARY KEY (namespace, scope, key)
+            );
+            ALTER TABLE {store}
+                ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT 'epoch',
+                ADD COLUMN IF NOT EXISTS updated_at TIMESTAMPTZ NOT NULL DEFAULT 'epoch',
+                ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ,
+                ALTER COLUMN created_at SET DEFAULT now(),
+                ALTER COLUMN updated_at SET DEFAULT now();
+            CREATE TABLE IF NOT EXISTS {tombstones} (
+                namespace VARCHAR NOT NULL,
+                scope TEXT[] NOT NULL,
+                key VARCHAR NOT NULL,
+                deleted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                PRIMARY KEY (namespace, scope, key)
+            );"
+        ))?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
+
+impl Postgres<PgPool> {
+    #[cfg(test)]
+    pub(crate) fn new(connection_str: &Url, namespace: impl Into<NamespaceBuf>) -> Result<Self> {
+        let (options, connection_str) = Options::from_url(connection_str)?;
+        Postgres::with_connector(&connection_str, namespace, options, NoTls)
+    }
+
+    /// Returns the namespaces in the database with the number of keys in
+    /// each.
+    pub(crate) fn namespace_summaries(connection_str: &Url) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let (options, connection_str) = Options::from_url(connection_str)?;
+        let store = &options.table.store;
+        let mut client = options.connect(&connection_str)?;
+
+        Ok(client
+            .query(
+                &format!("SELECT namespace, COUNT(*) FROM {store} WHERE {LIVE} GROUP BY namespace"),
+                &[],
+            )?
+            .into_iter()
+            .map(|row| {
+                let count: i64 = row.get(1);
+                (row.get(0), count as usize)
+            })
+            .collect())
     }
 
     #[cfg(test)]
     pub(crate) fn truncate(&self) -> Result<()> {
+        let store = &self.options.table.store;
+        let tombstones = &self.options.table.tombstones;
         self.executor
             .executor()?
-            .exec_query("TRUNCATE table store", &[])?;
+            .exec_query(&format!("TRUNCATE table {store}, {tombstones}"), &[])?;
 
         Ok(())
     }
+
+    #[cfg(test)]
+    pub(crate) fn updated_at(&self, key: &Key) -> Result<Option<std::time::SystemTime>> {
+        let store = &self.options.table.store;
+        Ok(self
+            .executor
+            .executor()?
+            .exec_query_opt(
+                &format!(
+                    "SELECT updated_at FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3"
+                ),
+                &[&self.namespace, key.scope().as_vec(), &key.name()],
+            )?
+            .map(|row| row.get(0)))
+    }
 }
 
 impl<E: HasExecutor> Display for Postgres<E> {
@@ -55,10 +507,28 @@ impl<E: HasExecutor> Display for Postgres<E> {
 }
 
 impl<E: HasExecutor> KeyValueStoreBackend for Postgres<E> {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Postgres
+    }
+
+    /// Runs the callback in a serializable Postgres transaction.
+    ///
+    /// Transactions that fail because of a conflict with a concurrent
+    /// transaction, i.e. a serialization failure or a deadlock, are retried
+    /// up to 10 times, unless retries are disabled using the
+    /// `transaction_retry=false` URL parameter. If they keep failing this is
+    /// returned as [`Error::TransactionConflict`]. Other errors are returned
+    /// immediately.
     fn transaction(&self, _scope: &Scope, callback: TransactionCallback) -> Result<()> {
         const TRIES: usize = 10;
 
-        for i in 0..=TRIES {
+        let tries = if self.options.transaction_retry {
+            TRIES
+        } else {
+            0
+        };
+
+        for i in 0..=tries {
             let mut client = self.executor.executor()?;
             let mut transaction = client.exec_transaction()?;
             transaction.execute("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE", &[])?;
@@ -66,77 +536,234 @@ impl<E: HasExecutor> KeyValueStoreBackend for Postgres<E> {
             let mut postgres = Postgres {
                 namespace: self.namespace.clone(),
                 executor: RefCell::new(transaction),
+                options: self.options.clone(),
             };
 
-            if let Err(e) = callback(&mut postgres) {
-                postgres.executor.into_inner().rollback()?;
-
-                if i == TRIES {
-                    Err(e)?;
+            let result = match callback(&mut postgres) {
+                Ok(()) => postgres.executor.into_inner().commit().map_err(Error::from),
+                Err(e) => {
+                    postgres.executor.into_inner().rollback()?;
+                    Err(e)
                 }
-            } else {
-                postgres.executor.into_inner().commit()?;
-                break;
+            };
+
+            match result.map_err(conflict_error) {
+                Ok(()) => break,
+                Err(Error::TransactionConflict) if i < tries => {}
+                Err(e) => return Err(e),
             }
         }
 
         Ok(())
     }
+
+    /// Runs the callback in a read-only repeatable read Postgres
+    /// transaction, which sees a snapshot of the data as of its first query.
+    fn read_only_transaction(
+        &self,
+        _scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        let mut client = self.executor.executor()?;
+        let mut transaction = client.exec_transaction()?;
+        transaction.execute(
+            "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY",
+            &[],
+        )?;
+
+        let postgres = Postgres {
+            namespace: self.namespace.clone(),
+            executor: RefCell::new(transaction),
+            options: self.options.clone(),
+        };
+
+        let result = callback(&postgres);
+        postgres.executor.into_inner().rollback()?;
+        result
+    }
+}
+
+/// Returns [`Error::TransactionConflict`] for serialization failures and
+/// deadlocks, and the given error otherwise.
+fn conflict_error(e: Error) -> Error {
+    match &e {
+        Error::Postgres(pg)
+            if pg.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE)
+                || pg.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED) =>
+        {
+            Error::TransactionConflict
+        }
+        _ => e,
+    }
 }
 
 impl<E: HasExecutor> ReadStore for Postgres<E> {
     fn is_empty(&self) -> Result<bool> {
         // We use a shared table for multiple namespaces. We consider this
         // instance empty if there are no entries for this namespace.
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT DISTINCT namespace FROM store WHERE namespace = $1",
+                &format!("SELECT 1 FROM {store} WHERE namespace = $1 AND {LIVE} LIMIT 1"),
                 &[&self.namespace],
             )?
             .is_none())
     }
 
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        let store = &self.options.table.store;
+        let count: i64 = self
+            .executor
+            .executor()?
+            .exec_query_opt(
+                &format!(
+                    "SELECT COUNT(*) FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 AND {LIVE}"
+                ),
+                &[&self.namespace, scope.as_vec(), &scope.len()],
+            )?
+            .map(|row| row.get(0))
+            .unwrap_or_default();
+
+        Ok(count as usize)
+    }
+
     fn has(&self, key: &Key) -> Result<bool> {
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT 1 FROM store WHERE namespace = $1 AND scope = $2 AND key = $3",
+                &format!(
+                    "SELECT 1 FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3 AND {LIVE}"
+                ),
                 &[&self.namespace, key.scope().as_vec(), &key.name()],
             )?
             .is_some())
     }
 
+    fn has_value(&self, key: &Key, value: &serde_json::Value) -> Result<bool> {
+        let store = &self.options.table.store;
+        Ok(self
+            .executor
+            .executor()?
+            .exec_query_opt(
+                &format!(
+                    "SELECT 1 FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3 AND value = $4 AND {LIVE}"
+                ),
+                &[&self.namespace, key.scope().as_vec(), &key.name(), value],
+            )?
+            .is_some())
+    }
+
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        let store = &self.options.table.store;
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let names: Vec<&Segment> = keys.iter().map(|key| key.name()).collect();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&self.namespace];
+        for (key, name) in keys.iter().zip(names.iter()) {
+            params.push(key.scope().as_vec());
+            params.push(name);
+        }
+
+        let existing = self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT scope, key FROM {store} WHERE namespace = $1 AND (scope, key) IN ({}) AND {LIVE}",
+                    key_params(keys.len(), 1)
+                ),
+                &params,
+            )?
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+
+                Key::new_scoped(scope, name)
+            })
+            .collect::<HashSet<Key>>();
+
+        Ok(keys.iter().map(|key| existing.contains(key)).collect())
+    }
+
     fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT DISTINCT scope FROM store WHERE namespace = $1 AND scope[:$3]  = $2",
+                &format!(
+                    "SELECT 1 FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 AND {LIVE} LIMIT 1"
+                ),
                 &[&self.namespace, scope.as_vec(), &scope.len()],
             )?
             .is_some())
     }
 
     fn get(&self, key: &Key) -> Result<Option<serde_json::Value>> {
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT value FROM store WHERE namespace = $1 AND scope = $2 AND key = $3",
+                &format!(
+                    "SELECT value FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3 AND {LIVE}"
+                ),
                 &[&self.namespace, key.scope().as_vec(), &key.name()],
             )?
             .and_then(|row| row.get(0)))
     }
 
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<serde_json::Value>>> {
+        let store = &self.options.table.store;
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let names: Vec<&Segment> = keys.iter().map(|key| key.name()).collect();
+        let mut params: Vec<&(dyn ToSql + Sync)> = vec![&self.namespace];
+        for (key, name) in keys.iter().zip(names.iter()) {
+            params.push(key.scope().as_vec());
+            params.push(name);
+        }
+
+        let values = self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT scope, key, value FROM {store} WHERE namespace = $1 AND (scope, key) IN ({}) AND {LIVE}",
+                    key_params(keys.len(), 1)
+                ),
+                &params,
+            )?
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+
+                (Key::new_scoped(scope, name), row.get(2))
+            })
+            .collect::<HashMap<Key, serde_json::Value>>();
+
+        Ok(keys.iter().map(|key| values.get(key).cloned()).collect())
+    }
+
     fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query(
-                "SELECT scope, key FROM store WHERE namespace = $1 AND scope[:$3] = $2",
+                &format!(
+                    "SELECT scope, key FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 AND {LIVE}"
+                ),
                 &[&self.namespace, scope.as_vec(), &scope.len()],
             )?
             .into_iter()
@@ -149,34 +776,302 @@ impl<E: HasExecutor> ReadStore for Postgres<E> {
             .collect::<Vec<Key>>())
     }
 
-    fn list_scopes(&self) -> Result<Vec<Scope>> {
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, serde_json::Value)>> {
+        let store = &self.options.table.store;
         Ok(self
             .executor
             .executor()?
             .exec_query(
-                "SELECT DISTINCT scope FROM store WHERE namespace = $1",
-                &[&self.namespace],
+                &format!(
+                    "SELECT scope, key, value FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 AND {LIVE}"
+                ),
+                &[&self.namespace, scope.as_vec(), &scope.len()],
+            )?
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+
+                (Key::new_scoped(scope, name), row.get(2))
+            })
+            .collect::<Vec<(Key, serde_json::Value)>>())
+    }
+
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        Ok(Box::new(KeyPages::new(
+            self,
+            scope.clone(),
+            STREAM_KEYS_PAGE_SIZE,
+        )))
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        self.list_scopes_under(&Scope::global())
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let store = &self.options.table.store;
+        let scopes: BTreeSet<Scope> = self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT DISTINCT scope FROM {store} \
+                     WHERE namespace = $1 AND scope[:$3] = $2 AND cardinality(scope) > $3 AND {LIVE}"
+                ),
+                &[&self.namespace, parent.as_vec(), &parent.len()],
             )?
             .into_iter()
             .flat_map(|row| Scope::new(row.get(0)).sub_scopes())
-            .collect::<Vec<Scope>>())
+            .filter(|scope| scope.depth() > parent.depth())
+            .collect();
+
+        Ok(scopes.into_iter().collect())
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let store = &self.options.table.store;
+        Ok(self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT DISTINCT scope[$3 + 1] FROM {store} \
+                     WHERE namespace = $1 AND scope[:$3] = $2 AND cardinality(scope) > $3 AND {LIVE}"
+                ),
+                &[&self.namespace, parent.as_vec(), &parent.len()],
+            )?
+            .into_iter()
+            .map(|row| parent.with_sub_scope(row.get::<_, SegmentBuf>(0)))
+            .collect())
+    }
+
+    /// Returns the changes using the `created_at` and `updated_at` columns
+    /// and the tombstones table.
+    ///
+    /// The times of changes are set by the database server, using the start
+    /// time of the transaction making the change.
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.options.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        let store = &self.options.table.store;
+        let tombstones = &self.options.table.tombstones;
+        Ok(self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT scope, key, updated_at, created_at >= $2 FROM {store} \
+                     WHERE namespace = $1 AND updated_at >= $2 AND {LIVE} \
+                     UNION ALL \
+                     SELECT scope, key, deleted_at, NULL FROM {tombstones} t \
+                     WHERE namespace = $1 AND deleted_at >= $2 AND NOT EXISTS ( \
+                     SELECT 1 FROM {store} s WHERE s.namespace = t.namespace AND s.scope = t.scope \
+                     AND s.key = t.key AND {LIVE}) \
+                     ORDER BY 3"
+                ),
+                &[&self.namespace, &since],
+            )?
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+                let kind = match row.get(3) {
+                    Some(true) => ChangeKind::Created,
+                    Some(false) => ChangeKind::Updated,
+                    None => ChangeKind::Deleted,
+                };
+
+                (Key::new_scoped(scope, name), kind)
+            })
+            .collect::<Vec<(Key, ChangeKind)>>())
+    }
+
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &serde_json::Value,
+    ) -> Result<Vec<Key>> {
+        let store = &self.options.table.store;
+        Ok(self
+            .executor
+            .executor()?
+            .exec_query(
+                &format!(
+                    "SELECT scope, key FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 \
+                     AND value #> $4 = $5 AND {LIVE}"
+                ),
+                &[
+                    &self.namespace,
+                    scope.as_vec(),
+                    &scope.len(),
+                    &json_path_segments(json_path),
+                    equals,
+                ],
+            )?
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+
+                Key::new_scoped(scope, name)
+            })
+            .collect::<Vec<Key>>())
     }
 }
 
 impl<E: HasExecutor> WriteStore for Postgres<E> {
     fn store(&self, key: &Key, value: serde_json::Value) -> Result<()> {
+        let store = &self.options.table.store;
         self.executor.executor()?.exec_execute(
-            "INSERT INTO store (namespace, scope, key, value) VALUES ($1, $2, $3, $4) ON CONFLICT (namespace, scope, key) \
-             DO UPDATE SET value = $4",
+            &format!(
+                "INSERT INTO {store} (namespace, scope, key, value, updated_at) VALUES ($1, $2, $3, $4, now()) \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = $4, updated_at = now(), expires_at = NULL"
+            ),
             &[&self.namespace, key.scope().as_vec(), &key.name(), &value],
         )?;
 
         Ok(())
     }
 
-    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+    fn store_many(&self, entries: &[(Key, serde_json::Value)]) -> Result<()> {
+        let store = &self.options.table.store;
+        // A single INSERT cannot update the same row twice, so only the last
+        // value for each key is inserted.
+        let mut seen = HashSet::new();
+        let entries: Vec<&(Key, serde_json::Value)> = entries
+            .iter()
+            .rev()
+            .filter(|(key, _)| seen.insert(key))
+            .collect();
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // Use multiple statements in a single transaction if the number of
+        // parameters would exceed the maximum for a single statement.
+        let mut client = self.executor.executor()?;
+        let mut transaction = client.exec_transaction()?;
+
+        for chunk in entries.chunks(STORE_MANY_MAX_ROWS) {
+            let names: Vec<&Segment> = chunk.iter().map(|(key, _)| key.name()).collect();
+            let mut params: Vec<&(dyn ToSql + Sync)> = vec![&self.namespace];
+            for ((key, value), name) in chunk.iter().copied().zip(names.iter()) {
+                params.push(key.scope().as_vec());
+                params.push(name);
+                params.push(value);
+            }
+
+            transaction.execute(
+                &format!(
+                    "INSERT INTO {store} (namespace, scope, key, value, updated_at) VALUES {} \
+                     ON CONFLICT (namespace, scope, key) DO UPDATE SET value = EXCLUDED.value, updated_at = now(), \
+                     expires_at = NULL",
+                    value_params(chunk.len())
+                ),
+                &params,
+            )?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn store_with_expiry(
+        &self,
+        key: &Key,
+        value: serde_json::Value,
+        expires_at: SystemTime,
+    ) -> Result<()> {
+        let store = &self.options.table.store;
         self.executor.executor()?.exec_execute(
-            "UPDATE store SET scope = $4, key = $5 WHERE namespace = $1 AND scope = $2 AND key = $3",
+            &format!(
+                "INSERT INTO {store} (namespace, scope, key, value, updated_at, expires_at) \
+                 VALUES ($1, $2, $3, $4, now(), $5) \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = $4, updated_at = now(), expires_at = $5"
+            ),
+            &[
+                &self.namespace,
+                key.scope().as_vec(),
+                &key.name(),
+                &value,
+                &expires_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        let store = &self.options.table.store;
+        let purged = self.executor.executor()?.exec_execute(
+            &format!("DELETE FROM {store} WHERE namespace = $1 AND expires_at <= now()"),
+            &[&self.namespace],
+        )?;
+
+        Ok(purged as usize)
+    }
+
+    /// Removes the tombstones of keys deleted before `before`, the times keys
+    /// were created and updated are kept in their rows.
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        let tombstones = &self.options.table.tombstones;
+        let pruned = self.executor.executor()?.exec_execute(
+            &format!("DELETE FROM {tombstones} WHERE namespace = $1 AND deleted_at < $2"),
+            &[&self.namespace, &before],
+        )?;
+
+        Ok(pruned as usize)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &Key,
+        expected: Option<&serde_json::Value>,
+        new: serde_json::Value,
+    ) -> Result<bool> {
+        let store = &self.options.table.store;
+        let mut executor = self.executor.executor()?;
+        let updated = match expected {
+            // An expired value is replaced, as if the key did not exist.
+            None => executor.exec_execute(
+                &format!(
+                    "INSERT INTO {store} AS store (namespace, scope, key, value, updated_at) \
+                     VALUES ($1, $2, $3, $4, now()) \
+                     ON CONFLICT (namespace, scope, key) DO UPDATE SET value = $4, updated_at = now(), expires_at = NULL \
+                     WHERE store.expires_at <= now()"
+                ),
+                &[&self.namespace, key.scope().as_vec(), &key.name(), &new],
+            )?,
+            Some(expected) => executor.exec_execute(
+                &format!(
+                    "UPDATE {store} SET value = $4, updated_at = now(), expires_at = NULL \
+                     WHERE namespace = $1 AND scope = $2 AND key = $3 AND value = $5 AND {LIVE}"
+                ),
+                &[&self.namespace, key.scope().as_vec(), &key.name(), &new, expected],
+            )?,
+        };
+
+        Ok(updated == 1)
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let store = &self.options.table.store;
+        // Each moved value results in a tombstone for the original key.
+        let moved = self.executor.executor()?.exec_execute(
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "UPDATE {store} SET scope = $4, key = $5, created_at = now(), updated_at = now() \
+                     WHERE namespace = $1 AND scope = $2 AND key = $3 AND {LIVE} \
+                     RETURNING namespace, $2 AS scope, $3 AS key, expires_at"
+                ),
+            ),
             &[
                 &self.namespace,
                 from.scope().as_vec(),
@@ -186,45 +1081,186 @@ impl<E: HasExecutor> WriteStore for Postgres<E> {
             ],
         )?;
 
+        if moved == 0 {
+            return Err(Error::UnknownKey);
+        }
+
         Ok(())
     }
 
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let store = &self.options.table.store;
+        let copied = self.executor.executor()?.exec_execute(
+            &format!(
+                "INSERT INTO {store} (namespace, scope, key, value, updated_at, expires_at) \
+                 SELECT namespace, $4, $5, value, now(), expires_at \
+                 FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3 AND {LIVE} \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                 updated_at = now(), expires_at = excluded.expires_at"
+            ),
+            &[
+                &self.namespace,
+                from.scope().as_vec(),
+                &from.name(),
+                to.scope().as_vec(),
+                &to.name(),
+            ],
+        )?;
+
+        if copied == 0 {
+            return Err(Error::UnknownKey);
+        }
+
+        Ok(())
+    }
+
+    /// Values in the target scope for the same keys are replaced.
     fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        let store = &self.options.table.store;
+        if from == to {
+            return Ok(());
+        }
+
+        let mut client = self.executor.executor()?;
+        let mut transaction = client.exec_transaction()?;
+
+        // Remove the values that are replaced, unless they are moved
+        // themselves.
+        transaction.execute(
+            &format!(
+                "DELETE FROM {store} t WHERE namespace = $1 AND NOT t.scope[:$3] = $2 \
+                 AND EXISTS (SELECT 1 FROM {store} s WHERE s.namespace = $1 \
+                 AND s.scope[:$3] = $2 AND t.scope = $4::text[] || s.scope[$3 + 1:] \
+                 AND t.key = s.key)"
+            ),
+            &[&self.namespace, from.as_vec(), &from.len(), to.as_vec()],
+        )?;
+
+        // The original scope is `from` followed by the rest of the new scope
+        // after `to`, which is needed for the tombstones.
+        transaction.execute(
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "UPDATE {store} SET scope = $4::text[] || scope[$3 + 1:], \
+                     created_at = now(), updated_at = now() \
+                     WHERE namespace = $1 AND scope[:$3] = $2 \
+                     RETURNING namespace, $2::text[] || scope[$5 + 1:] AS scope, key, expires_at"
+                ),
+            ),
+            &[
+                &self.namespace,
+                from.as_vec(),
+                &from.len(),
+                to.as_vec(),
+                &to.len(),
+            ],
+        )?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        let store = &self.options.table.store;
         self.executor.executor()?.exec_execute(
-            "UPDATE store SET scope = $3 WHERE namespace = $1 AND scope = $2",
-            &[&self.namespace, &from.as_vec(), &to.as_vec()],
+            &format!(
+                "INSERT INTO {store} (namespace, scope, key, value, updated_at, expires_at) \
+                 SELECT namespace, $4::text[] || scope[$3 + 1:], key, value, now(), expires_at \
+                 FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 AND {LIVE} \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                 updated_at = now(), expires_at = excluded.expires_at"
+            ),
+            &[&self.namespace, from.as_vec(), &from.len(), to.as_vec()],
         )?;
 
         Ok(())
     }
 
     fn delete(&self, key: &Key) -> Result<()> {
-        self.executor.executor()?.exec_execute(
-            "DELETE FROM store WHERE namespace = $1 AND scope = $2 AND key = $3",
+        match self.delete_existing(key) {
+            Err(Error::UnknownKey) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Deletes the row of the key. An expired value is deleted, but results
+    /// in an [`Error::UnknownKey`] like a missing row.
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        let store = &self.options.table.store;
+        // Only deleted values that had not expired result in a tombstone.
+        let deleted = self.executor.executor()?.exec_execute(
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "DELETE FROM {store} WHERE namespace = $1 AND scope = $2 AND key = $3 \
+                     RETURNING namespace, scope, key, expires_at"
+                ),
+            ),
             &[&self.namespace, key.scope().as_vec(), &key.name()],
         )?;
 
+        if deleted == 0 {
+            return Err(Error::UnknownKey);
+        }
+
         Ok(())
     }
 
     fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.clear_scope(scope)
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        let store = &self.options.table.store;
         self.executor.executor()?.exec_execute(
-            "DELETE FROM store WHERE namespace = $1 AND scope = $2",
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "DELETE FROM {store} WHERE namespace = $1 AND scope = $2 \
+                     RETURNING namespace, scope, key, expires_at"
+                ),
+            ),
             &[&self.namespace, &scope.as_vec()],
         )?;
 
         Ok(())
     }
 
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        let store = &self.options.table.store;
+        self.executor.executor()?.exec_execute(
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "DELETE FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 \
+                     RETURNING namespace, scope, key, expires_at"
+                ),
+            ),
+            &[&self.namespace, scope.as_vec(), &scope.len()],
+        )?;
+
+        Ok(())
+    }
+
     fn clear(&self) -> Result<()> {
-        self.executor
-            .executor()?
-            .exec_execute("DELETE FROM store WHERE namespace = $1", &[&self.namespace])?;
+        let store = &self.options.table.store;
+        self.executor.executor()?.exec_execute(
+            &with_tombstones(
+                self.options.tombstones(),
+                &format!(
+                    "DELETE FROM {store} WHERE namespace = $1 RETURNING namespace, scope, key, expires_at"
+                ),
+            ),
+            &[&self.namespace],
+        )?;
 
         Ok(())
     }
 
     fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        let store = &self.options.table.store;
+        let tombstones = &self.options.table.tombstones;
         let mut client = self.executor.executor()?;
         let mut transaction = client.exec_transaction()?;
         transaction.execute("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE", &[])?;
@@ -232,13 +1268,14 @@ impl<E: HasExecutor> WriteStore for Postgres<E> {
         let postgres = Postgres {
             namespace: self.namespace.clone(),
             executor: RefCell::new(transaction),
+            options: self.options.clone(),
         };
 
         if postgres
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT DISTINCT namespace FROM store WHERE namespace = $1",
+                &format!("SELECT DISTINCT namespace FROM {store} WHERE namespace = $1"),
                 &[&self.namespace],
             )?
             .is_none()
@@ -255,7 +1292,7 @@ impl<E: HasExecutor> WriteStore for Postgres<E> {
             .executor
             .executor()?
             .exec_query_opt(
-                "SELECT DISTINCT namespace FROM store WHERE namespace = $1",
+                &format!("SELECT DISTINCT namespace FROM {store} WHERE namespace = $1"),
                 &[&to],
             )?
             .is_some()
@@ -269,7 +1306,15 @@ impl<E: HasExecutor> WriteStore for Postgres<E> {
         }
 
         postgres.executor.executor()?.exec_execute(
-            "UPDATE store SET namespace = $2 WHERE namespace = $1",
+            &format!("UPDATE {store} SET namespace = $2 WHERE namespace = $1"),
+            &[&self.namespace, &to],
+        )?;
+        postgres.executor.executor()?.exec_execute(
+            &format!("DELETE FROM {tombstones} WHERE namespace = $1"),
+            &[&to],
+        )?;
+        postgres.executor.executor()?.exec_execute(
+            &format!("UPDATE {tombstones} SET namespace = $2 WHERE namespace = $1"),
             &[&self.namespace, &to],
         )?;
         postgres.executor.into_inner().commit()?;
@@ -280,6 +1325,135 @@ impl<E: HasExecutor> WriteStore for Postgres<E> {
     }
 }
 
+/// Returns the `($n, $n+1), ...` parameter list for `count` (scope, key)
+/// pairs, numbered after the first `offset` parameters.
+fn key_params(count: usize, offset: usize) -> String {
+    (0..count)
+        .map(|i| format!("(${}, ${})", offset + 2 * i + 1, offset + 2 * i + 2))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The maximum number of rows inserted by a single statement in
+/// [`WriteStore::store_many`], keeping the number of parameters below the
+/// limit of 65535 imposed by Postgres.
+const STORE_MANY_MAX_ROWS: usize = 10_000;
+
+/// Returns the `($1, $n, $n+1, $n+2, now()), ...` value list for `count` rows
+/// of (namespace, scope, key, value, updated_at), where the namespace is the
+/// first parameter.
+fn value_params(count: usize) -> String {
+    (0..count)
+        .map(|i| {
+            format!(
+                "($1, ${}, ${}, ${}, now())",
+                3 * i + 2,
+                3 * i + 3,
+                3 * i + 4
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The number of keys retrieved per query by [`ReadStore::stream_keys`].
+const STREAM_KEYS_PAGE_SIZE: i64 = 1_000;
+
+/// Iterates over the keys in a scope, retrieving them in pages ordered by
+/// scope and key.
+struct KeyPages<'a, E> {
+    store: &'a Postgres<E>,
+    scope: Scope,
+    page_size: i64,
+    page: std::vec::IntoIter<Key>,
+    last: Option<Key>,
+    done: bool,
+}
+
+impl<'a, E: HasExecutor> KeyPages<'a, E> {
+    fn new(store: &'a Postgres<E>, scope: Scope, page_size: i64) -> Self {
+        KeyPages {
+            store,
+            scope,
+            page_size,
+            page: Vec::new().into_iter(),
+            last: None,
+            done: false,
+        }
+    }
+
+    /// Returns the page of keys following the last key of the previous page.
+    fn next_page(&self) -> Result<Vec<Key>> {
+        let store = &self.store.options.table.store;
+        let mut executor = self.store.executor.executor()?;
+        let rows = match &self.last {
+            None => executor.exec_query(
+                &format!(
+                    "SELECT scope, key FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 \
+                     AND {LIVE} ORDER BY scope, key LIMIT $4"
+                ),
+                &[
+                    &self.store.namespace,
+                    self.scope.as_vec(),
+                    &self.scope.len(),
+                    &self.page_size,
+                ],
+            )?,
+            Some(last) => executor.exec_query(
+                &format!(
+                    "SELECT scope, key FROM {store} WHERE namespace = $1 AND scope[:$3] = $2 \
+                     AND (scope, key) > ($5, $6) AND {LIVE} ORDER BY scope, key LIMIT $4"
+                ),
+                &[
+                    &self.store.namespace,
+                    self.scope.as_vec(),
+                    &self.scope.len(),
+                    &self.page_size,
+                    last.scope().as_vec(),
+                    &last.name(),
+                ],
+            )?,
+        };
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let scope = Scope::new(row.get(0));
+                let name: SegmentBuf = row.get(1);
+
+                Key::new_scoped(scope, name)
+            })
+            .collect())
+    }
+}
+
+impl<E: HasExecutor> Iterator for KeyPages<'_, E> {
+    type Item = Result<Key>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(key) = self.page.next() {
+            return Some(Ok(key));
+        }
+
+        if self.done {
+            return None;
+        }
+
+        match self.next_page() {
+            Ok(keys) => {
+                self.done = (keys.len() as i64) < self.page_size;
+                self.last = keys.last().cloned();
+                self.page = keys.into_iter();
+                self.page.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
 trait HasExecutor {
     type Executor<'a>: Executor
     where
@@ -288,8 +1462,11 @@ trait HasExecutor {
     fn executor(&self) -> Result<Self::Executor<'_>>;
 }
 
-impl HasExecutor for PgPool {
-    type Executor<'a> = PooledConnection<PostgresClient> where Self: 'a;
+impl<M: ManageConnection<Connection = Client>> HasExecutor for Pool<M> {
+    type Executor<'a>
+        = PooledConnection<M>
+    where
+        Self: 'a;
 
     fn executor(&self) -> Result<Self::Executor<'_>> {
         Ok(self.get()?)
@@ -297,7 +1474,10 @@ impl HasExecutor for PgPool {
 }
 
 impl<'b> HasExecutor for RefCell<Transaction<'b>> {
-    type Executor<'a> = RefMut<'a, Transaction<'b>> where Self: 'a;
+    type Executor<'a>
+        = RefMut<'a, Transaction<'b>>
+    where
+        Self: 'a;
 
     fn executor(&self) -> Result<Self::Executor<'_>> {
         Ok(self.borrow_mut())
@@ -324,7 +1504,7 @@ pub trait Executor {
         T: ?Sized + ToStatement;
 }
 
-impl Executor for PooledConnection<PostgresClient> {
+impl<M: ManageConnection<Connection = Client>> Executor for PooledConnection<M> {
     fn exec_transaction(&mut self) -> Result<Transaction<'_>> {
         Ok(self.transaction()?)
     }
@@ -385,3 +1565,436 @@ impl Executor for RefMut<'_, Transaction<'_>> {
         Ok(self.execute(query, params)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use serde_json::Value;
+    use serial_test::serial;
+    use url::Url;
+
+    use super::{KeyPages, OptionOverrides, Options, Postgres};
+    use crate::{Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope, WriteStore};
+
+    #[test]
+    fn test_options_from_url() {
+        let url =
+            Url::parse("postgres://user@host/db?transaction_retry=false&sslmode=disable").unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert!(!options.transaction_retry);
+        assert_eq!(url.as_str(), "postgres://user@host/db?sslmode=disable");
+
+        let url = Url::parse("postgres://user@host/db?transaction_retry=true").unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert_eq!(options, Options::default());
+        assert_eq!(url.as_str(), "postgres://user@host/db");
+
+        let url = Url::parse("postgres://user@host/db?transaction_retry=maybe").unwrap();
+        assert!(matches!(
+            Options::from_url(&url),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let url = Url::parse("postgres://user@host/db?track_changes=true").unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert_eq!(options.tombstones(), Some("store_tombstones"));
+        assert_eq!(url.as_str(), "postgres://user@host/db");
+
+        let url = Url::parse("postgres://user@host/db?pool_max=32&pool_min_idle=2&pool_timeout=5")
+            .unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert_eq!(options.pool_max_size, 32);
+        assert_eq!(options.pool_min_idle, Some(2));
+        assert_eq!(options.pool_timeout, Duration::from_secs(5));
+        assert_eq!(url.as_str(), "postgres://user@host/db");
+
+        for params in [
+            "pool_max=0",
+            "pool_max=-1",
+            "pool_max=many",
+            "pool_min_idle=11",
+            "pool_max=2&pool_min_idle=3",
+            "pool_timeout=0",
+            "pool_timeout=1.5",
+        ] {
+            let url = Url::parse(&format!("postgres://user@host/db?{params}")).unwrap();
+            assert!(
+                matches!(Options::from_url(&url), Err(Error::InvalidConfig(_))),
+                "accepted {params}"
+            );
+        }
+
+        let url = Url::parse("postgres://user@host/db?table=myapp.kvx").unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert_eq!(options.table.store, "myapp.kvx");
+        assert_eq!(options.table.tombstones, "myapp.kvx_tombstones");
+        assert_eq!(url.as_str(), "postgres://user@host/db");
+
+        for table in [
+            "", ".kvx", "myapp.", "a.b.c", "1kvx", "kvx;drop", "\"kvx\"", "kv x",
+        ] {
+            let url = Url::parse("postgres://user@host/db").unwrap();
+            let url = Url::parse_with_params(url.as_str(), [("table", table)]).unwrap();
+            assert!(
+                matches!(Options::from_url(&url), Err(Error::InvalidConfig(_))),
+                "accepted {table:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let url = Url::parse("postgres://user@host/db?pool_max=4&table=kvx").unwrap();
+        let (mut options, _) = Options::from_url(&url).unwrap();
+        options
+            .apply(&OptionOverrides {
+                transaction_retry: Some(false),
+                pool_max_size: Some(32),
+                pool_timeout: Some(Duration::from_secs(5)),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!options.transaction_retry);
+        assert_eq!(options.table.store, "kvx");
+        assert_eq!(options.pool_max_size, 32);
+        assert_eq!(options.pool_timeout, Duration::from_secs(5));
+
+        for overrides in [
+            OptionOverrides {
+                pool_max_size: Some(0),
+                ..Default::default()
+            },
+            OptionOverrides {
+                pool_min_idle: Some(5),
+                ..Default::default()
+            },
+            OptionOverrides {
+                pool_timeout: Some(Duration::ZERO),
+                ..Default::default()
+            },
+            OptionOverrides {
+                table: Some("kvx;drop".to_string()),
+                ..Default::default()
+            },
+        ] {
+            let (mut options, _) = Options::from_url(&url).unwrap();
+            assert!(
+                matches!(options.apply(&overrides), Err(Error::InvalidConfig(_))),
+                "accepted {overrides:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "postgres-tls")]
+    #[test]
+    fn test_tls_options_from_url() {
+        let url =
+            Url::parse("postgres://user@host/db?sslmode=require&sslrootcert=/tmp/ca.pem").unwrap();
+        let (options, url) = Options::from_url(&url).unwrap();
+        assert!(options.tls);
+        assert_eq!(
+            options.ssl_root_cert,
+            Some(std::path::PathBuf::from("/tmp/ca.pem"))
+        );
+        assert_eq!(url.as_str(), "postgres://user@host/db?sslmode=require");
+
+        let url = Url::parse("postgres://user@host/db?sslmode=disable").unwrap();
+        let (options, _) = Options::from_url(&url).unwrap();
+        assert!(!options.tls);
+
+        let url = Url::parse("postgres://user@host/db").unwrap();
+        let (options, _) = Options::from_url(&url).unwrap();
+        assert!(!options.tls);
+
+        let url = Url::parse(
+            "postgres://postgres@localhost/postgres?sslmode=require&sslrootcert=/nonexistent.pem",
+        )
+        .unwrap();
+        assert!(matches!(
+            super::connect(
+                &url,
+                "test_tls".parse::<crate::NamespaceBuf>().unwrap(),
+                &Default::default(),
+            ),
+            Err(Error::IoWithContext(..))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_initialize_schema() {
+        let mut client =
+            postgres::Client::connect("postgresql://postgres@localhost/postgres", postgres::NoTls)
+                .unwrap();
+
+        // The schema of the first versions, without expiry and change
+        // tracking support.
+        client
+            .batch_execute(
+                "DROP SCHEMA IF EXISTS kvx_migrate CASCADE; \
+                 CREATE SCHEMA kvx_migrate; \
+                 CREATE TABLE kvx_migrate.store ( \
+                 namespace VARCHAR NOT NULL, scope TEXT[] NOT NULL, key VARCHAR NOT NULL, \
+                 value JSONB NOT NULL, PRIMARY KEY (namespace, scope, key)); \
+                 INSERT INTO kvx_migrate.store VALUES ('migrate', '{scope}', 'old', '1')",
+            )
+            .unwrap();
+
+        let url = Url::parse(
+            "postgres://postgres@localhost/postgres?table=kvx_migrate.store&track_changes=true",
+        )
+        .unwrap();
+        let since = std::time::SystemTime::now();
+        let store = Postgres::new(&url, "migrate".parse::<crate::NamespaceBuf>().unwrap()).unwrap();
+
+        let old: Key = "scope/old".parse().unwrap();
+        let new: Key = "scope/new".parse().unwrap();
+        assert_eq!(store.get(&old).unwrap(), Some(Value::from(1)));
+        assert!(store.updated_at(&old).unwrap().unwrap() < since);
+
+        store
+            .store_with_expiry(
+                &new,
+                Value::from(2),
+                std::time::SystemTime::now() + Duration::from_secs(60),
+            )
+            .unwrap();
+        store.delete(&old).unwrap();
+
+        // Connecting again leaves the migrated tables as they are.
+        let store = Postgres::new(&url, "migrate".parse::<crate::NamespaceBuf>().unwrap()).unwrap();
+        let mut changes = store.changes_since(since).unwrap();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (new, crate::ChangeKind::Created),
+                (old, crate::ChangeKind::Deleted)
+            ]
+        );
+
+        client
+            .batch_execute("DROP SCHEMA kvx_migrate CASCADE")
+            .unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_table() {
+        let mut client =
+            postgres::Client::connect("postgresql://postgres@localhost/postgres", postgres::NoTls)
+                .unwrap();
+        client
+            .batch_execute(
+                "CREATE SCHEMA IF NOT EXISTS kvx_test; \
+                 CREATE TABLE IF NOT EXISTS kvx_test.store (LIKE store INCLUDING ALL); \
+                 CREATE TABLE IF NOT EXISTS kvx_test.store_tombstones \
+                 (LIKE store_tombstones INCLUDING ALL)",
+            )
+            .unwrap();
+
+        let namespace = "test_table".parse::<crate::NamespaceBuf>().unwrap();
+        let url = Url::parse("postgres://postgres@localhost/postgres?track_changes=true").unwrap();
+        let default = Postgres::new(&url, namespace.clone()).unwrap();
+        let url = Url::parse(
+            "postgres://postgres@localhost/postgres?table=kvx_test.store&track_changes=true",
+        )
+        .unwrap();
+        let store = Postgres::new(&url, namespace).unwrap();
+        store.truncate().unwrap();
+
+        let key: Key = "scope/key".parse().unwrap();
+        store.store(&key, Value::from(1)).unwrap();
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(1)));
+        assert_eq!(default.get(&key).unwrap(), None);
+
+        store.delete(&key).unwrap();
+        assert_eq!(store.changes_since(std::time::UNIX_EPOCH).unwrap().len(), 1);
+        assert!(default
+            .changes_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .is_empty());
+
+        store.clear().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_transaction_conflict_without_retry() {
+        let url =
+            Url::parse("postgres://postgres@localhost/postgres?transaction_retry=false").unwrap();
+        let namespace: crate::NamespaceBuf = "test_transaction_conflict".parse().unwrap();
+        let store = Postgres::new(&url, namespace.clone()).unwrap();
+        store.truncate().unwrap();
+
+        let key: Key = "scope/counter".parse().unwrap();
+        store.store(&key, Value::from(0)).unwrap();
+
+        // Both transactions read the value before either writes it, so one of
+        // them must fail to serialize.
+        let barrier = std::sync::Barrier::new(2);
+        let results: Vec<Result<()>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let store = Postgres::new(&url, namespace.clone()).unwrap();
+                    let (barrier, key) = (&barrier, &key);
+                    s.spawn(move || {
+                        let mut calls = 0;
+                        let result = store.transaction(key.scope(), &mut |t| {
+                            calls += 1;
+                            let value = t.get(key)?.and_then(|v| v.as_i64()).unwrap_or_default();
+                            barrier.wait();
+                            t.store(key, Value::from(value + 1))
+                        });
+                        assert_eq!(calls, 1);
+                        result
+                    })
+                })
+                .collect();
+
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .any(|r| matches!(r, Err(Error::TransactionConflict))));
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(1)));
+
+        store.clear().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_transaction_error_without_retry() {
+        let store = Postgres::new(
+            &Url::parse("postgres://postgres@localhost/postgres").unwrap(),
+            "test_transaction_error"
+                .parse::<crate::NamespaceBuf>()
+                .unwrap(),
+        )
+        .unwrap();
+        store.truncate().unwrap();
+
+        let key: Key = "scope/key".parse().unwrap();
+
+        // Postgres cannot store a NUL character in a JSON value, which fails
+        // the same way every time, so it is not retried.
+        let mut calls = 0;
+        let result = store.transaction(key.scope(), &mut |t| {
+            calls += 1;
+            t.store(&key, Value::from("\u{0}"))
+        });
+        assert!(matches!(result, Err(Error::Postgres(_))));
+        assert_eq!(calls, 1);
+
+        // Neither are errors returned by the callback.
+        let mut calls = 0;
+        let result = store.transaction(key.scope(), &mut |_| {
+            calls += 1;
+            Err(Error::Abort)
+        });
+        assert!(matches!(result, Err(Error::Abort)));
+        assert_eq!(calls, 1);
+
+        assert!(!store.has(&key).unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn test_read_only_transaction_is_consistent() {
+        let url = Url::parse("postgres://postgres@localhost/postgres").unwrap();
+        let namespace = "test_read_only_transaction"
+            .parse::<crate::NamespaceBuf>()
+            .unwrap();
+        let store = Postgres::new(&url, namespace.clone()).unwrap();
+        let other = Postgres::new(&url, namespace).unwrap();
+        store.truncate().unwrap();
+
+        let key: Key = "scope/key".parse().unwrap();
+        store.store(&key, Value::from(1)).unwrap();
+
+        store
+            .read_only_transaction(key.scope(), &mut |r| {
+                assert_eq!(r.get(&key)?, Some(Value::from(1)));
+
+                other.store(&key, Value::from(2))?;
+                other.store(&"scope/other".parse().unwrap(), Value::from(3))?;
+
+                assert_eq!(r.get(&key)?, Some(Value::from(1)));
+                assert_eq!(r.list_keys(key.scope())?, vec![key.clone()]);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(store.get(&key).unwrap(), Some(Value::from(2)));
+    }
+
+    #[test]
+    #[serial]
+    fn test_key_pages() {
+        let store = Postgres::new(
+            &Url::parse("postgres://postgres@localhost/postgres").unwrap(),
+            "test_key_pages".parse::<crate::NamespaceBuf>().unwrap(),
+        )
+        .unwrap();
+        store.truncate().unwrap();
+
+        let scope: Scope = "scope".parse().unwrap();
+        let entries: Vec<(Key, Value)> = [
+            "scope/a/k1",
+            "scope/a/k2",
+            "scope/b/k1",
+            "scope/k1",
+            "scope/k2",
+        ]
+        .iter()
+        .map(|key| (key.parse().unwrap(), Value::from(1)))
+        .collect();
+        store.store_many(&entries).unwrap();
+        store
+            .store(&"other/k1".parse().unwrap(), Value::from(1))
+            .unwrap();
+
+        let mut keys = KeyPages::new(&store, scope.clone(), 2)
+            .collect::<Result<Vec<Key>>>()
+            .unwrap();
+        keys.sort();
+
+        let mut expected = store.list_keys(&scope).unwrap();
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(keys.len(), 5);
+
+        store.clear().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn test_updated_at() {
+        let store = Postgres::new(
+            &Url::parse("postgres://postgres@localhost/postgres").unwrap(),
+            "test_updated_at".parse::<crate::NamespaceBuf>().unwrap(),
+        )
+        .unwrap();
+        store.truncate().unwrap();
+
+        let key: Key = "scope/key".parse().unwrap();
+        let moved: Key = "scope/moved".parse().unwrap();
+
+        store.store(&key, Value::from(1)).unwrap();
+        let stored = store.updated_at(&key).unwrap().unwrap();
+
+        thread::sleep(Duration::from_millis(10));
+        store.store(&key, Value::from(2)).unwrap();
+        let restored = store.updated_at(&key).unwrap().unwrap();
+        assert!(restored > stored);
+
+        thread::sleep(Duration::from_millis(10));
+        store.move_value(&key, &moved).unwrap();
+        assert!(store.updated_at(&moved).unwrap().unwrap() > restored);
+
+        store.clear().unwrap();
+    }
+}
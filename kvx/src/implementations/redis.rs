@@ -0,0 +1,945 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    iter,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use kvx_types::NamespaceBuf;
+use r2d2::Pool;
+use redis::{Client, Commands, Connection};
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    BackendKind, ChangeKind, Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope,
+    TransactionCallback, WriteStore,
+};
+
+/// How often a transaction is retried when a watched key was changed before
+/// it could be committed.
+const TRANSACTION_TRIES: usize = 10;
+
+/// A store using Redis.
+///
+/// Values are stored as JSON strings under `namespace:scope/.../name`.
+/// Redis has no notion of scopes, so operations on scopes use `SCAN` with a
+/// match pattern on the keys.
+///
+/// Cross-key atomicity is best-effort on Redis. Transactions are emulated
+/// using `WATCH` and `MULTI`/`EXEC`, see [`Redis::transaction`], and
+/// operations that touch more than one key are run in such a transaction.
+///
+/// If changes are tracked, the times keys were created, updated and deleted
+/// are kept in the hashes `namespace.created`, `namespace.updated` and
+/// `namespace.deleted`, see [`changes_key`].
+#[derive(Debug)]
+pub(crate) struct Redis {
+    namespace: NamespaceBuf,
+    pool: Pool<Client>,
+    track_changes: bool,
+}
+
+impl Redis {
+    pub(crate) fn new(connection_str: &Url, namespace: impl Into<NamespaceBuf>) -> Result<Self> {
+        let client = Client::open(connection_str.as_str())?;
+        let pool = Pool::new(client).map_err(Error::RedisPool)?;
+
+        Ok(Redis {
+            namespace: namespace.into(),
+            pool,
+            track_changes: false,
+        })
+    }
+
+    /// Sets whether the times keys change are recorded for
+    /// [`ReadStore::changes_since`].
+    pub(crate) fn with_change_tracking(mut self, track_changes: bool) -> Self {
+        self.track_changes = track_changes;
+        self
+    }
+
+    /// Returns the namespaces in the Redis database at the URL with the
+    /// number of keys in each.
+    pub(crate) fn namespace_summaries(connection_str: &Url) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let mut connection = Client::open(connection_str.as_str())?.get_connection()?;
+
+        let mut summaries: HashMap<NamespaceBuf, usize> = HashMap::new();
+        for redis_key in connection.scan::<String>()? {
+            let Some((namespace, key)) = redis_key.split_once(':') else {
+                continue;
+            };
+            let (Ok(namespace), Ok(_)) = (NamespaceBuf::from_str(namespace), Key::from_str(key))
+            else {
+                continue;
+            };
+
+            *summaries.entry(namespace).or_default() += 1;
+        }
+
+        Ok(summaries.into_iter().collect())
+    }
+
+    fn connection(&self) -> Result<r2d2::PooledConnection<Client>> {
+        self.pool.get().map_err(Error::RedisPool)
+    }
+
+    /// Adds the commands recording that a value was stored for the key to
+    /// the pipeline, if changes are tracked. The creation time is only set
+    /// if the key had none.
+    fn record_stored(&self, pipe: &mut redis::Pipeline, key: &Key) {
+        if !self.track_changes {
+            return;
+        }
+
+        let now = as_millis(SystemTime::now());
+        pipe.hset_nx(
+            changes_key(&self.namespace, "created"),
+            key.to_string(),
+            now,
+        )
+        .ignore()
+        .hset(
+            changes_key(&self.namespace, "updated"),
+            key.to_string(),
+            now,
+        )
+        .ignore();
+    }
+
+    /// Adds the commands recording that the key was deleted to the
+    /// pipeline, if changes are tracked.
+    fn record_deleted(&self, pipe: &mut redis::Pipeline, key: &Key) {
+        if !self.track_changes {
+            return;
+        }
+
+        let now = as_millis(SystemTime::now());
+        pipe.hdel(changes_key(&self.namespace, "created"), key.to_string())
+            .ignore()
+            .hdel(changes_key(&self.namespace, "updated"), key.to_string())
+            .ignore()
+            .hset(
+                changes_key(&self.namespace, "deleted"),
+                key.to_string(),
+                now,
+            )
+            .ignore();
+    }
+
+    /// Runs an operation on the store in a transaction on the given scope.
+    fn execute(
+        &self,
+        scope: &Scope,
+        mut op: impl FnMut(&dyn KeyValueStoreBackend) -> Result<()>,
+    ) -> Result<()> {
+        self.transaction(scope, &mut op)
+    }
+}
+
+impl Display for Redis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KeyValueStore::Redis({})", self.namespace)
+    }
+}
+
+impl KeyValueStoreBackend for Redis {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Redis
+    }
+
+    /// Runs the callback in an optimistic Redis transaction.
+    ///
+    /// The lock keys of the scope, see [`lock_keys`], and every key read
+    /// or listed in the callback, are watched. Writes are buffered and
+    /// applied with `MULTI`/`EXEC`, together with an update of the lock
+    /// keys. If a watched key was changed in the meantime nothing is written
+    /// and the callback is run again, up to 10 times, after which
+    /// [`Error::TransactionConflict`] is returned.
+    ///
+    /// This serializes transactions on overlapping scopes, like a
+    /// transaction on `a` and one on `a/b`, while transactions on sibling
+    /// scopes do not conflict. Keys stored outside of transactions in a
+    /// scope after it was listed in the callback may go unnoticed.
+    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        let mut connection = self.connection()?;
+        let (watched, updated) = lock_keys(&self.namespace, scope);
+
+        for _ in 0..TRANSACTION_TRIES {
+            redis::cmd("WATCH").arg(&watched).exec(&mut *connection)?;
+
+            let transaction = RedisTransaction {
+                namespace: &self.namespace,
+                connection: RefCell::new(&mut *connection),
+                writes: RefCell::new(BTreeMap::new()),
+            };
+
+            let result = callback(&transaction);
+            let writes = transaction.writes.into_inner();
+
+            if let Err(e) = result {
+                redis::cmd("UNWATCH").exec(&mut *connection)?;
+                return Err(e);
+            }
+
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for lock_key in &updated {
+                pipe.incr(lock_key, 1).ignore();
+            }
+            for (key, write) in writes {
+                let redis_key = redis_key(&self.namespace, &key);
+                match &write {
+                    Some(_) => self.record_stored(&mut pipe, &key),
+                    None => self.record_deleted(&mut pipe, &key),
+                }
+
+                match write {
+                    Some((value, None)) => pipe.set(redis_key, value.to_string()),
+                    Some((value, Some(expires_at))) => {
+                        pipe.set_options(redis_key, value.to_string(), expiry_options(expires_at))
+                    }
+                    None => pipe.del(redis_key),
+                }
+                .ignore();
+            }
+
+            if pipe.query::<Option<()>>(&mut *connection)?.is_some() {
+                return Ok(());
+            }
+        }
+
+        Err(Error::TransactionConflict)
+    }
+}
+
+impl ReadStore for Redis {
+    fn is_empty(&self) -> Result<bool> {
+        Ok(!has_keys(
+            &mut *self.connection()?,
+            &self.namespace,
+            &Scope::global(),
+        )?)
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        Ok(self.connection()?.exists(redis_key(&self.namespace, key))?)
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        has_keys(&mut *self.connection()?, &self.namespace, scope)
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        get(&mut *self.connection()?, &self.namespace, key)
+    }
+
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let redis_keys: Vec<String> = keys
+            .iter()
+            .map(|key| redis_key(&self.namespace, key))
+            .collect();
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(redis_keys)
+            .query(&mut *self.connection()?)?;
+
+        values
+            .into_iter()
+            .map(|value| Ok(value.map(|v| serde_json::from_str(&v)).transpose()?))
+            .collect()
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        list_keys(&mut *self.connection()?, &self.namespace, scope)
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        let keys = self.list_keys(scope)?;
+        let values = self.get_many(&keys)?;
+
+        Ok(keys
+            .into_iter()
+            .zip(values)
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect())
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        Ok(scopes(self.list_keys(&Scope::global())?.iter()))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        changes_since(&mut *self.connection()?, &self.namespace, since)
+    }
+}
+
+impl WriteStore for Redis {
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set(redis_key(&self.namespace, key), value.to_string())
+            .ignore();
+        self.record_stored(&mut pipe, key);
+        pipe.query::<()>(&mut *self.connection()?)?;
+        Ok(())
+    }
+
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // MSET keeps the last value for keys that occur more than once.
+        let items: Vec<(String, String)> = entries
+            .iter()
+            .map(|(key, value)| (redis_key(&self.namespace, key), value.to_string()))
+            .collect();
+        let mut pipe = redis::pipe();
+        pipe.atomic().mset(&items).ignore();
+        for (key, _) in entries {
+            self.record_stored(&mut pipe, key);
+        }
+        pipe.query::<()>(&mut *self.connection()?)?;
+
+        Ok(())
+    }
+
+    /// Stores the value with a Redis expiry, so Redis removes it once it
+    /// has expired.
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .set_options(
+                redis_key(&self.namespace, key),
+                value.to_string(),
+                expiry_options(expires_at),
+            )
+            .ignore();
+        self.record_stored(&mut pipe, key);
+        pipe.query::<()>(&mut *self.connection()?)?;
+        Ok(())
+    }
+
+    /// Does nothing, Redis removes expired values itself.
+    fn purge_expired(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Removes the times of the keys that were deleted, or last stored,
+    /// before `before`.
+    ///
+    /// The hashes with the times are watched while the old times are
+    /// removed, so if a key changes in the meantime this is tried again, up
+    /// to 10 times, after which [`Error::TransactionConflict`] is returned.
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        let before = as_millis(before);
+        let mut connection = self.connection()?;
+        let [created, updated, deleted] =
+            CHANGE_KINDS.map(|kind| changes_key(&self.namespace, kind));
+
+        for _ in 0..TRANSACTION_TRIES {
+            redis::cmd("WATCH")
+                .arg(&updated)
+                .arg(&deleted)
+                .exec(&mut *connection)?;
+
+            let old = |times: HashMap<String, u64>| -> Vec<String> {
+                times
+                    .into_iter()
+                    .filter(|(_, at)| *at < before)
+                    .map(|(key, _)| key)
+                    .collect()
+            };
+            let old_updated = old(connection.hgetall(&updated)?);
+            let old_deleted = old(connection.hgetall(&deleted)?);
+            if old_updated.is_empty() && old_deleted.is_empty() {
+                redis::cmd("UNWATCH").exec(&mut *connection)?;
+                return Ok(0);
+            }
+
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if !old_updated.is_empty() {
+                pipe.hdel(&created, &old_updated)
+                    .ignore()
+                    .hdel(&updated, &old_updated)
+                    .ignore();
+            }
+            if !old_deleted.is_empty() {
+                pipe.hdel(&deleted, &old_deleted).ignore();
+            }
+
+            if pipe.query::<Option<()>>(&mut *connection)?.is_some() {
+                return Ok(old_updated.len() + old_deleted.len());
+            }
+        }
+
+        Err(Error::TransactionConflict)
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        let mut connection = self.connection()?;
+        let redis_key = redis_key(&self.namespace, key);
+
+        // Watching the key makes the write fail if the key was changed after
+        // it was read, in which case the comparison is done again.
+        loop {
+            redis::cmd("WATCH").arg(&redis_key).exec(&mut *connection)?;
+
+            if get(&mut connection, &self.namespace, key)?.as_ref() != expected {
+                redis::cmd("UNWATCH").exec(&mut *connection)?;
+                return Ok(false);
+            }
+
+            let mut pipe = redis::pipe();
+            pipe.atomic().set(&redis_key, new.to_string()).ignore();
+            self.record_stored(&mut pipe, key);
+            let swapped: Option<()> = pipe.query(&mut *connection)?;
+
+            if swapped.is_some() {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.execute(from.scope(), |t| t.move_value(from, to))
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.execute(from.scope(), |t| t.copy_value(from, to))
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.execute(from, |t| t.move_scope(from, to))
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.execute(from, |t| t.copy_scope(from, to))
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        match self.delete_existing(key) {
+            Err(Error::UnknownKey) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Deletes the key, and then records a tombstone if it existed.
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        let mut connection = self.connection()?;
+        let deleted: usize = connection.del(redis_key(&self.namespace, key))?;
+        if deleted == 0 {
+            return Err(Error::UnknownKey);
+        }
+
+        if self.track_changes {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            self.record_deleted(&mut pipe, key);
+            pipe.query::<()>(&mut *connection)?;
+        }
+        Ok(())
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.clear_scope(scope)
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.execute(scope, |t| t.delete_scope_exact(scope))
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.execute(scope, |t| t.clear_scope(scope))
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.execute(&Scope::global(), |t| t.clear())
+    }
+
+    /// Renames all keys of the namespace.
+    ///
+    /// The keys are renamed atomically, but keys stored in the original
+    /// namespace while the migration runs may be left behind.
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        let mut connection = self.connection()?;
+
+        let keys = list_keys(&mut connection, &self.namespace, &Scope::global())?;
+        if keys.is_empty() {
+            return Err(Error::NamespaceMigration(format!(
+                "original namespace {} not found in redis",
+                self.namespace
+            )));
+        }
+        if has_keys(&mut connection, &to, &Scope::global())? {
+            return Err(Error::NamespaceMigration(format!(
+                "target namespace {} already exists in redis",
+                to
+            )));
+        }
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for key in &keys {
+            pipe.rename(redis_key(&self.namespace, key), redis_key(&to, key))
+                .ignore();
+        }
+        for kind in CHANGE_KINDS {
+            let from = changes_key(&self.namespace, kind);
+            let to = changes_key(&to, kind);
+            pipe.del(&to).ignore();
+            if connection.exists(&from)? {
+                pipe.rename(from, to).ignore();
+            }
+        }
+        pipe.query::<()>(&mut *connection)?;
+        drop(connection);
+
+        self.namespace = to;
+
+        Ok(())
+    }
+}
+
+/// The store passed to the callback of [`Redis::transaction`].
+///
+/// Reads go to Redis directly, writes are kept in `writes` until the
+/// transaction is committed. Reads take these pending writes into account.
+struct RedisTransaction<'a> {
+    namespace: &'a NamespaceBuf,
+    connection: RefCell<&'a mut Connection>,
+    writes: RefCell<BTreeMap<Key, PendingWrite>>,
+}
+
+/// A value to be written with its expiry time, if any, or `None` if the key
+/// is deleted.
+type PendingWrite = Option<(Value, Option<SystemTime>)>;
+
+impl RedisTransaction<'_> {
+    /// Returns the expiry time of the value for a key, if any.
+    fn expires_at(&self, key: &Key) -> Result<Option<SystemTime>> {
+        if let Some(write) = self.writes.borrow().get(key) {
+            return Ok(write.as_ref().and_then(|(_, expires_at)| *expires_at));
+        }
+
+        // PTTL returns a negative value for keys without an expiry.
+        let ttl: i64 = self
+            .connection
+            .borrow_mut()
+            .pttl(redis_key(self.namespace, key))?;
+
+        Ok(u64::try_from(ttl)
+            .ok()
+            .map(|ttl| SystemTime::now() + Duration::from_millis(ttl)))
+    }
+}
+
+impl KeyValueStoreBackend for RedisTransaction<'_> {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Redis
+    }
+
+    /// Runs the callback as part of this transaction.
+    fn transaction(&self, _scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        callback(self)
+    }
+}
+
+impl ReadStore for RedisTransaction<'_> {
+    fn is_empty(&self) -> Result<bool> {
+        Ok(!self.has_scope(&Scope::global())?)
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        Ok(!self.list_keys(scope)?.is_empty())
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        if let Some(write) = self.writes.borrow().get(key) {
+            return Ok(write.as_ref().map(|(value, _)| value.clone()));
+        }
+
+        let mut connection = self.connection.borrow_mut();
+        redis::cmd("WATCH")
+            .arg(redis_key(self.namespace, key))
+            .exec(&mut **connection)?;
+
+        get(&mut connection, self.namespace, key)
+    }
+
+    /// Lists the keys in the scope, and watches them so that the
+    /// transaction conflicts if one of them is changed or deleted.
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        let mut connection = self.connection.borrow_mut();
+        let listed = list_keys(&mut connection, self.namespace, scope)?;
+        if !listed.is_empty() {
+            let redis_keys: Vec<String> = listed
+                .iter()
+                .map(|key| redis_key(self.namespace, key))
+                .collect();
+            redis::cmd("WATCH")
+                .arg(redis_keys)
+                .exec(&mut **connection)?;
+        }
+        drop(connection);
+
+        let mut keys: BTreeSet<Key> = listed.into_iter().collect();
+
+        for (key, value) in self.writes.borrow().iter() {
+            if key.scope().starts_with(scope) {
+                match value {
+                    Some(_) => keys.insert(key.clone()),
+                    None => keys.remove(key),
+                };
+            }
+        }
+
+        Ok(keys.into_iter().collect())
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        Ok(scopes(self.list_keys(&Scope::global())?.iter()))
+    }
+
+    /// Returns the changes committed before this transaction, the pending
+    /// writes of this transaction are not included.
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        changes_since(&mut self.connection.borrow_mut(), self.namespace, since)
+    }
+}
+
+impl WriteStore for RedisTransaction<'_> {
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .insert(key.clone(), Some((value, None)));
+        Ok(())
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .insert(key.clone(), Some((value, Some(expires_at))));
+        Ok(())
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let value = self.get(from)?.ok_or(Error::UnknownKey)?;
+        let expires_at = self.expires_at(from)?;
+        self.delete(from)?;
+        self.writes
+            .borrow_mut()
+            .insert(to.clone(), Some((value, expires_at)));
+        Ok(())
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let value = self.get(from)?.ok_or(Error::UnknownKey)?;
+        let expires_at = self.expires_at(from)?;
+        self.writes
+            .borrow_mut()
+            .insert(to.clone(), Some((value, expires_at)));
+        Ok(())
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        for key in self.list_keys(from)? {
+            if let Some(target) = key.rebase(from, to) {
+                self.move_value(&key, &target)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        for key in self.list_keys(from)? {
+            let (Some(value), Some(target)) = (self.get(&key)?, key.rebase(from, to)) else {
+                continue;
+            };
+            let expires_at = self.expires_at(&key)?;
+            self.writes
+                .borrow_mut()
+                .insert(target, Some((value, expires_at)));
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.writes.borrow_mut().insert(key.clone(), None);
+        Ok(())
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.clear_scope(scope)
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        for key in self.list_keys(scope)? {
+            self.delete(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.clear_scope(&Scope::global())
+    }
+
+    fn migrate_namespace(&mut self, _to: NamespaceBuf) -> Result<()> {
+        Err(Error::NamespaceMigration(
+            "cannot migrate a namespace in a redis transaction".to_string(),
+        ))
+    }
+}
+
+/// Returns the Redis key for a key in a namespace.
+fn redis_key(namespace: &NamespaceBuf, key: &Key) -> String {
+    format!("{}:{}", namespace, key)
+}
+
+/// Returns the options for `SET` to let the value expire at the given time.
+fn expiry_options(expires_at: SystemTime) -> redis::SetOptions {
+    redis::SetOptions::default().with_expiration(redis::SetExpiry::PXAT(as_millis(expires_at)))
+}
+
+/// Returns the number of milliseconds since the Unix epoch for a time.
+fn as_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// The kinds of times kept for the keys in a namespace, see [`changes_key`].
+const CHANGE_KINDS: [&str; 3] = ["created", "updated", "deleted"];
+
+/// Returns the Redis key of the hash with the times keys in the namespace
+/// were created, updated or deleted, in milliseconds since the Unix epoch.
+///
+/// Like lock keys, these never match the pattern for the keys in a
+/// namespace.
+fn changes_key(namespace: &NamespaceBuf, kind: &str) -> String {
+    format!("{}.{}", namespace, kind)
+}
+
+/// Returns the changes in the namespace at or after `since`, see
+/// [`ReadStore::changes_since`].
+///
+/// Keys that expired are skipped, their times are kept until they are
+/// stored or deleted again.
+fn changes_since(
+    connection: &mut Connection,
+    namespace: &NamespaceBuf,
+    since: SystemTime,
+) -> Result<Vec<(Key, ChangeKind)>> {
+    let since = as_millis(since);
+    let created: HashMap<String, u64> = connection.hgetall(changes_key(namespace, "created"))?;
+    let updated: HashMap<String, u64> = connection.hgetall(changes_key(namespace, "updated"))?;
+    let deleted: HashMap<String, u64> = connection.hgetall(changes_key(namespace, "deleted"))?;
+
+    let mut candidates = vec![];
+    for (key, updated_at) in updated {
+        if updated_at >= since {
+            let kind = if created.get(&key).is_some_and(|at| *at >= since) {
+                ChangeKind::Created
+            } else {
+                ChangeKind::Updated
+            };
+            candidates.push((updated_at, Key::from_str(&key)?, kind));
+        }
+    }
+    for (key, deleted_at) in deleted {
+        if deleted_at >= since {
+            candidates.push((deleted_at, Key::from_str(&key)?, ChangeKind::Deleted));
+        }
+    }
+
+    if candidates.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Only report keys that still exist as created or updated, and keys that
+    // do not exist as deleted.
+    let mut pipe = redis::pipe();
+    for (_, key, _) in &candidates {
+        pipe.exists(redis_key(namespace, key));
+    }
+    let exists: Vec<bool> = pipe.query(connection)?;
+
+    let mut changes: Vec<(u64, Key, ChangeKind)> = candidates
+        .into_iter()
+        .zip(exists)
+        .filter(|((_, _, kind), exists)| *exists == (*kind != ChangeKind::Deleted))
+        .map(|(change, _)| change)
+        .collect();
+
+    changes.sort_by_key(|(at, _, _)| *at);
+    Ok(changes
+        .into_iter()
+        .map(|(_, key, kind)| (key, kind))
+        .collect())
+}
+
+/// Returns the lock keys watched and updated by a transaction on a scope.
+///
+/// Every scope has two lock keys: `namespace.lock:scope`, updated by the
+/// transactions on the scope itself, and `namespace.tree_lock:scope`,
+/// updated by the transactions on the scope or any of its sub scopes. A
+/// transaction watches the tree lock key of its scope and the lock keys of
+/// its super scopes, so it conflicts with transactions on overlapping
+/// scopes, but not with transactions on sibling scopes.
+///
+/// Namespaces cannot contain a `.`, so lock keys never match the pattern for
+/// the keys in a namespace.
+fn lock_keys(namespace: &NamespaceBuf, scope: &Scope) -> (Vec<String>, Vec<String>) {
+    let lock_key = |scope: &Scope| format!("{}.lock:{}", namespace, scope);
+    let tree_lock_key = |scope: &Scope| format!("{}.tree_lock:{}", namespace, scope);
+
+    let super_scopes: Vec<Scope> = iter::once(Scope::global())
+        .chain(scope.sub_scopes())
+        .take(scope.depth())
+        .collect();
+
+    let watched = iter::once(tree_lock_key(scope))
+        .chain(super_scopes.iter().map(lock_key))
+        .collect();
+    let updated = iter::once(lock_key(scope))
+        .chain(iter::once(scope).chain(&super_scopes).map(tree_lock_key))
+        .collect();
+
+    (watched, updated)
+}
+
+/// Returns the `SCAN` pattern matching all keys in a scope, including keys
+/// in its sub scopes.
+fn scope_pattern(namespace: &NamespaceBuf, scope: &Scope) -> String {
+    if scope.is_global() {
+        format!("{}:*", namespace)
+    } else {
+        let mut escaped = String::new();
+        for c in scope.to_string().chars() {
+            if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+
+        format!("{}:{}{}*", namespace, escaped, Scope::SEPARATOR)
+    }
+}
+
+fn get(connection: &mut Connection, namespace: &NamespaceBuf, key: &Key) -> Result<Option<Value>> {
+    let value: Option<String> = connection.get(redis_key(namespace, key))?;
+    Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+}
+
+fn list_keys(
+    connection: &mut Connection,
+    namespace: &NamespaceBuf,
+    scope: &Scope,
+) -> Result<Vec<Key>> {
+    let prefix = format!("{}:", namespace);
+    let redis_keys: Vec<String> = connection
+        .scan_match(scope_pattern(namespace, scope))?
+        .collect();
+
+    redis_keys
+        .iter()
+        .filter_map(|redis_key| redis_key.strip_prefix(&prefix))
+        .map(|key| Ok(Key::from_str(key)?))
+        .collect()
+}
+
+fn has_keys(connection: &mut Connection, namespace: &NamespaceBuf, scope: &Scope) -> Result<bool> {
+    let mut redis_keys = connection.scan_match::<_, String>(scope_pattern(namespace, scope))?;
+    Ok(redis_keys.next().is_some())
+}
+
+/// Returns the scopes of the keys, and all their super scopes.
+fn scopes<'a>(keys: impl Iterator<Item = &'a Key>) -> Vec<Scope> {
+    let scopes: BTreeSet<Scope> = keys.flat_map(|key| key.scope().sub_scopes()).collect();
+    scopes.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock_keys, redis_key, scope_pattern};
+    use crate::{Key, NamespaceBuf, Scope};
+
+    fn namespace() -> NamespaceBuf {
+        "ns".parse().unwrap()
+    }
+
+    #[test]
+    fn test_redis_key() {
+        let key: Key = "a/b/name".parse().unwrap();
+        assert_eq!(redis_key(&namespace(), &key), "ns:a/b/name");
+
+        let key: Key = "name".parse().unwrap();
+        assert_eq!(redis_key(&namespace(), &key), "ns:name");
+    }
+
+    #[test]
+    fn test_scope_pattern() {
+        assert_eq!(scope_pattern(&namespace(), &Scope::global()), "ns:*");
+
+        let scope: Scope = "a/b".parse().unwrap();
+        assert_eq!(scope_pattern(&namespace(), &scope), "ns:a/b/*");
+
+        let scope: Scope = "a*/[b]?".parse().unwrap();
+        assert_eq!(scope_pattern(&namespace(), &scope), r"ns:a\*/\[b\]\?/*");
+    }
+
+    #[test]
+    fn test_lock_key_is_not_in_namespace() {
+        let (watched, updated) = lock_keys(&namespace(), &"a".parse().unwrap());
+        assert!(watched
+            .iter()
+            .chain(&updated)
+            .all(|lock_key| !lock_key.starts_with("ns:")));
+    }
+
+    #[test]
+    fn test_lock_keys_overlapping_scopes() {
+        // Whether a transaction on `a` conflicts with one committed on `b`.
+        let scope = |scope: &str| match scope {
+            "" => Scope::global(),
+            scope => scope.parse().unwrap(),
+        };
+        let conflicts = |a: &str, b: &str| {
+            let (watched, _) = lock_keys(&namespace(), &scope(a));
+            let (_, updated) = lock_keys(&namespace(), &scope(b));
+            watched.iter().any(|lock_key| updated.contains(lock_key))
+        };
+
+        for (a, b) in [("a", "a"), ("a", "a/b"), ("", "a/b"), ("a", "a/b/c")] {
+            assert!(conflicts(a, b), "{a} and {b}");
+            assert!(conflicts(b, a), "{b} and {a}");
+        }
+
+        for (a, b) in [("a/b", "a/c"), ("a", "b"), ("a/b", "ab")] {
+            assert!(!conflicts(a, b), "{a} and {b}");
+            assert!(!conflicts(b, a), "{b} and {a}");
+        }
+    }
+}
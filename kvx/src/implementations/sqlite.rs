@@ -0,0 +1,952 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{Debug, Display},
+    ops::Deref,
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use kvx_types::NamespaceBuf;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_json::Value;
+
+use crate::{
+    BackendKind, ChangeKind, Error, Key, KeyValueStoreBackend, ReadStore, Result, Scope,
+    SegmentBuf, TransactionCallback, WriteStore,
+};
+
+/// How long to wait for a lock held by another connection to the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Matches the rows in a scope (`?2`) and its sub scopes, using the `LIKE`
+/// pattern (`?3`) returned by [`sub_scope_pattern`].
+const IN_SCOPE: &str = "(?2 = '' OR scope = ?2 OR scope LIKE ?3 ESCAPE '\\')";
+
+/// Matches the rows that have not expired. Expiry times are stored in
+/// milliseconds since the Unix epoch.
+const LIVE: &str =
+    "(expires_at IS NULL OR expires_at > (julianday('now') - 2440587.5) * 86400000.0)";
+
+/// The current time in milliseconds since the Unix epoch, used for the
+/// `created_at` and `updated_at` columns.
+const NOW: &str = "CAST(ROUND((julianday('now') - 2440587.5) * 86400000.0) AS INTEGER)";
+
+/// A store using a single SQLite database file.
+///
+/// The layout mirrors the Postgres backend, but scopes are stored as text
+/// with segments joined by [`Scope::SEPARATOR`].
+#[derive(Debug)]
+pub(crate) struct Sqlite<C> {
+    namespace: NamespaceBuf,
+    connection: C,
+    track_changes: bool,
+}
+
+impl Sqlite<Mutex<Connection>> {
+    /// Opens (or creates) the database at the given path.
+    pub(crate) fn new(path: impl AsRef<Path>, namespace: impl Into<NamespaceBuf>) -> Result<Self> {
+        Ok(Sqlite {
+            namespace: namespace.into(),
+            connection: Mutex::new(open(path)?),
+            track_changes: false,
+        })
+    }
+
+    /// Enables or disables change tracking for this store.
+    ///
+    /// Tombstones are recorded by temporary triggers on the connection of
+    /// this store, expired values do not get one. Writes through stores
+    /// without change tracking are not recorded.
+    pub(crate) fn with_change_tracking(mut self, track_changes: bool) -> Result<Self> {
+        if track_changes {
+            self.connection.connection()?.execute_batch(&format!(
+                "CREATE TEMP TRIGGER IF NOT EXISTS store_deleted AFTER DELETE ON main.store
+                WHEN old.expires_at IS NULL OR old.expires_at > {NOW}
+                BEGIN
+                    INSERT OR REPLACE INTO store_tombstones (namespace, scope, key, deleted_at)
+                    VALUES (old.namespace, old.scope, old.key, {NOW});
+                END;
+                CREATE TEMP TRIGGER IF NOT EXISTS store_moved AFTER UPDATE OF scope, key
+                ON main.store
+                WHEN old.scope != new.scope OR old.key != new.key
+                BEGIN
+                    INSERT OR REPLACE INTO store_tombstones (namespace, scope, key, deleted_at)
+                    VALUES (old.namespace, old.scope, old.key, {NOW});
+                END;"
+            ))?;
+        } else {
+            self.connection.connection()?.execute_batch(
+                "DROP TRIGGER IF EXISTS temp.store_deleted;
+                DROP TRIGGER IF EXISTS temp.store_moved;",
+            )?;
+        }
+
+        self.track_changes = track_changes;
+        Ok(self)
+    }
+}
+
+impl Sqlite<Mutex<Connection>> {
+    /// Returns the namespaces in the database at the given path with the
+    /// number of keys in each.
+    pub(crate) fn namespace_summaries(
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let connection = open(path)?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT namespace, COUNT(*) FROM store WHERE {LIVE} GROUP BY namespace"
+        ))?;
+        let summaries = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as usize))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(summaries
+            .into_iter()
+            .filter_map(|(namespace, count)| {
+                Some((NamespaceBuf::from_str(&namespace).ok()?, count))
+            })
+            .collect())
+    }
+}
+
+impl<C> Display for Sqlite<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KeyValueStore::Sqlite({})", self.namespace)
+    }
+}
+
+impl<C: HasConnection> KeyValueStoreBackend for Sqlite<C> {
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Sqlite
+    }
+
+    /// Runs the callback in an immediate SQLite transaction.
+    ///
+    /// SQLite allows a single writer per database, so this serializes the
+    /// transaction with all other transactions, regardless of their scope.
+    fn transaction(&self, _scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        let connection = self.connection.connection()?;
+
+        // Transactions started within a transaction use a savepoint.
+        let nested = !connection.is_autocommit();
+        let (begin, commit, rollback) = if nested {
+            (
+                "SAVEPOINT kvx",
+                "RELEASE kvx",
+                "ROLLBACK TO kvx; RELEASE kvx",
+            )
+        } else {
+            ("BEGIN IMMEDIATE", "COMMIT", "ROLLBACK")
+        };
+
+        connection.execute_batch(begin)?;
+
+        let sqlite = Sqlite {
+            namespace: self.namespace.clone(),
+            connection: &*connection,
+            track_changes: self.track_changes,
+        };
+
+        match callback(&sqlite) {
+            Ok(()) => {
+                connection.execute_batch(commit)?;
+                Ok(())
+            }
+            Err(e) => {
+                connection.execute_batch(rollback)?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<C: HasConnection> ReadStore for Sqlite<C> {
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self
+            .connection
+            .connection()?
+            .query_row(
+                &format!("SELECT 1 FROM store WHERE namespace = ?1 AND {LIVE} LIMIT 1"),
+                params![self.namespace.as_str()],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_none())
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        Ok(self
+            .connection
+            .connection()?
+            .query_row(
+                &format!(
+                    "SELECT 1 FROM store WHERE namespace = ?1 AND scope = ?2 AND key = ?3 AND {LIVE}"
+                ),
+                params![
+                    self.namespace.as_str(),
+                    key.scope().to_string(),
+                    key.name().as_str()
+                ],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        Ok(self
+            .connection
+            .connection()?
+            .query_row(
+                &format!(
+                    "SELECT 1 FROM store WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE} LIMIT 1"
+                ),
+                params![
+                    self.namespace.as_str(),
+                    scope.to_string(),
+                    sub_scope_pattern(scope)
+                ],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        let value: Option<String> = self
+            .connection
+            .connection()?
+            .query_row(
+                &format!(
+                    "SELECT value FROM store WHERE namespace = ?1 AND scope = ?2 AND key = ?3 \
+                     AND {LIVE}"
+                ),
+                params![
+                    self.namespace.as_str(),
+                    key.scope().to_string(),
+                    key.name().as_str()
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        query_keys(
+            &*self.connection.connection()?,
+            &format!("SELECT scope, key FROM store WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE}"),
+            params![
+                self.namespace.as_str(),
+                scope.to_string(),
+                sub_scope_pattern(scope)
+            ],
+        )
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        let connection = self.connection.connection()?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT scope, key, value FROM store WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE}"
+        ))?;
+        let entries = statement
+            .query_map(
+                params![
+                    self.namespace.as_str(),
+                    scope.to_string(),
+                    sub_scope_pattern(scope)
+                ],
+                |row| Ok((key_from_row(row), row.get::<_, String>(2)?)),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        entries
+            .into_iter()
+            .map(|(key, value)| Ok((key?, serde_json::from_str(&value)?)))
+            .collect()
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        let count: i64 = self.connection.connection()?.query_row(
+            &format!("SELECT COUNT(*) FROM store WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE}"),
+            params![
+                self.namespace.as_str(),
+                scope.to_string(),
+                sub_scope_pattern(scope)
+            ],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as usize)
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        self.list_scopes_under(&Scope::global())
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let connection = self.connection.connection()?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT DISTINCT scope FROM store WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE}"
+        ))?;
+        let scopes = statement
+            .query_map(
+                params![
+                    self.namespace.as_str(),
+                    parent.to_string(),
+                    sub_scope_pattern(parent)
+                ],
+                |row| row.get::<_, String>(0),
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut result = BTreeSet::new();
+        for scope in scopes {
+            result.extend(
+                parse_scope(&scope)?
+                    .sub_scopes()
+                    .into_iter()
+                    .filter(|scope| scope.depth() > parent.depth()),
+            );
+        }
+
+        Ok(result.into_iter().collect())
+    }
+
+    /// Returns the changes using the `created_at` and `updated_at` columns
+    /// and the `store_tombstones` table, which is maintained by triggers.
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        if !self.track_changes {
+            return Err(crate::changes_not_tracked());
+        }
+
+        let since = since
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let connection = self.connection.connection()?;
+        let mut statement = connection.prepare(&format!(
+            "SELECT scope, key, updated_at, created_at >= ?2 FROM store \
+             WHERE namespace = ?1 AND updated_at >= ?2 AND {LIVE} \
+             UNION ALL \
+             SELECT scope, key, deleted_at, NULL FROM store_tombstones t \
+             WHERE namespace = ?1 AND deleted_at >= ?2 AND NOT EXISTS ( \
+             SELECT 1 FROM store s WHERE s.namespace = t.namespace AND s.scope = t.scope \
+             AND s.key = t.key AND {LIVE}) \
+             ORDER BY 3"
+        ))?;
+        let changes = statement
+            .query_map(params![self.namespace.as_str(), since], |row| {
+                let kind = match row.get::<_, Option<bool>>(3)? {
+                    Some(true) => ChangeKind::Created,
+                    Some(false) => ChangeKind::Updated,
+                    None => ChangeKind::Deleted,
+                };
+                Ok((key_from_row(row), kind))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        changes
+            .into_iter()
+            .map(|(key, kind)| Ok((key?, kind)))
+            .collect()
+    }
+}
+
+impl<C: HasConnection> WriteStore for Sqlite<C> {
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.connection.connection()?.execute(
+            &format!(
+                "INSERT INTO store (namespace, scope, key, value, created_at, updated_at) \
+                 VALUES (?1, ?2, ?3, ?4, {NOW}, {NOW}) \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                 updated_at = excluded.updated_at, expires_at = NULL"
+            ),
+            params![
+                self.namespace.as_str(),
+                key.scope().to_string(),
+                key.name().as_str(),
+                value.to_string()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        let connection = self.connection.connection()?;
+        connection.execute_batch("SAVEPOINT kvx_store_many")?;
+
+        let sqlite = Sqlite {
+            namespace: self.namespace.clone(),
+            connection: &*connection,
+            track_changes: self.track_changes,
+        };
+
+        match entries
+            .iter()
+            .try_for_each(|(key, value)| sqlite.store(key, value.clone()))
+        {
+            Ok(()) => {
+                connection.execute_batch("RELEASE kvx_store_many")?;
+                Ok(())
+            }
+            Err(e) => {
+                connection.execute_batch("ROLLBACK TO kvx_store_many; RELEASE kvx_store_many")?;
+                Err(e)
+            }
+        }
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        let expires_at = expires_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        self.connection.connection()?.execute(
+            &format!(
+                "INSERT INTO store (namespace, scope, key, value, expires_at, created_at, updated_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, {NOW}, {NOW}) \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                 updated_at = excluded.updated_at, expires_at = excluded.expires_at"
+            ),
+            params![
+                self.namespace.as_str(),
+                key.scope().to_string(),
+                key.name().as_str(),
+                value.to_string(),
+                expires_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        let purged = self.connection.connection()?.execute(
+            &format!("DELETE FROM store WHERE namespace = ?1 AND NOT {LIVE}"),
+            params![self.namespace.as_str()],
+        )?;
+
+        Ok(purged)
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        let before = before
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+
+        let pruned = self.connection.connection()?.execute(
+            "DELETE FROM store_tombstones WHERE namespace = ?1 AND deleted_at < ?2",
+            params![self.namespace.as_str(), before],
+        )?;
+
+        Ok(pruned)
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        let connection = self.connection.connection()?;
+        let updated = match expected {
+            // An expired value is replaced, as if the key did not exist.
+            None => connection.execute(
+                &format!(
+                    "INSERT INTO store (namespace, scope, key, value, created_at, updated_at) \
+                     VALUES (?1, ?2, ?3, ?4, {NOW}, {NOW}) \
+                     ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                     created_at = excluded.created_at, updated_at = excluded.updated_at, \
+                     expires_at = NULL WHERE NOT {LIVE}"
+                ),
+                params![
+                    self.namespace.as_str(),
+                    key.scope().to_string(),
+                    key.name().as_str(),
+                    new.to_string()
+                ],
+            )?,
+            Some(expected) => connection.execute(
+                &format!(
+                    "UPDATE store SET value = ?4, updated_at = {NOW}, expires_at = NULL \
+                     WHERE namespace = ?1 AND scope = ?2 AND key = ?3 AND value = ?5 AND {LIVE}"
+                ),
+                params![
+                    self.namespace.as_str(),
+                    key.scope().to_string(),
+                    key.name().as_str(),
+                    new.to_string(),
+                    expected.to_string()
+                ],
+            )?,
+        };
+
+        Ok(updated == 1)
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let moved = self.connection.connection()?.execute(
+            &format!(
+                "UPDATE store SET scope = ?4, key = ?5, created_at = {NOW}, updated_at = {NOW} \
+                 WHERE namespace = ?1 AND scope = ?2 AND key = ?3 AND {LIVE}"
+            ),
+            params![
+                self.namespace.as_str(),
+                from.scope().to_string(),
+                from.name().as_str(),
+                to.scope().to_string(),
+                to.name().as_str()
+            ],
+        )?;
+
+        if moved == 0 {
+            return Err(Error::UnknownKey);
+        }
+
+        Ok(())
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let copied = self.connection.connection()?.execute(
+            &format!(
+                "INSERT INTO store (namespace, scope, key, value, expires_at, created_at, updated_at) \
+                 SELECT namespace, ?4, ?5, value, expires_at, {NOW}, {NOW} FROM store \
+                 WHERE namespace = ?1 AND scope = ?2 AND key = ?3 AND {LIVE} \
+                 ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                 updated_at = excluded.updated_at, expires_at = excluded.expires_at"
+            ),
+            params![
+                self.namespace.as_str(),
+                from.scope().to_string(),
+                from.name().as_str(),
+                to.scope().to_string(),
+                to.name().as_str()
+            ],
+        )?;
+
+        if copied == 0 {
+            return Err(Error::UnknownKey);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the `from` prefix of the scopes in `from` and its sub scopes
+    /// by `to`, which is done in text as that is how scopes are stored.
+    /// Values in the target scope for the same keys are replaced.
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        // The rest of a sub scope after `from` and its separator, if any.
+        let rest_start = match from.is_global() {
+            true => 1,
+            false => from.to_string().chars().count() + 2,
+        };
+        let to_prefix = match to.is_global() {
+            true => String::new(),
+            false => format!("{}{}", to, Scope::SEPARATOR),
+        };
+
+        self.connection.connection()?.execute(
+            &format!(
+                "UPDATE OR REPLACE store SET scope = CASE WHEN scope = ?2 THEN ?4 \
+                 ELSE ?5 || substr(scope, ?6) END, created_at = {NOW}, updated_at = {NOW} \
+                 WHERE namespace = ?1 AND {IN_SCOPE}"
+            ),
+            params![
+                self.namespace.as_str(),
+                from.to_string(),
+                sub_scope_pattern(from),
+                to.to_string(),
+                to_prefix,
+                rest_start
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        let connection = self.connection.connection()?;
+        connection.execute_batch("SAVEPOINT kvx_copy_scope")?;
+
+        let copy = || -> Result<()> {
+            let mut statement = connection.prepare(&format!(
+                "SELECT scope, key, value, expires_at FROM store \
+                 WHERE namespace = ?1 AND {IN_SCOPE} AND {LIVE}"
+            ))?;
+            let rows = statement
+                .query_map(
+                    params![
+                        self.namespace.as_str(),
+                        from.to_string(),
+                        sub_scope_pattern(from)
+                    ],
+                    |row| {
+                        Ok((
+                            key_from_row(row),
+                            row.get::<_, String>(2)?,
+                            row.get::<_, Option<i64>>(3)?,
+                        ))
+                    },
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            for (key, value, expires_at) in rows {
+                let Some(key) = key?.rebase(from, to) else {
+                    continue;
+                };
+
+                connection.execute(
+                    &format!(
+                        "INSERT INTO store (namespace, scope, key, value, expires_at, created_at, \
+                         updated_at) VALUES (?1, ?2, ?3, ?4, ?5, {NOW}, {NOW}) \
+                         ON CONFLICT (namespace, scope, key) DO UPDATE SET value = excluded.value, \
+                         updated_at = excluded.updated_at, expires_at = excluded.expires_at"
+                    ),
+                    params![
+                        self.namespace.as_str(),
+                        key.scope().to_string(),
+                        key.name().as_str(),
+                        value,
+                        expires_at
+                    ],
+                )?;
+            }
+
+            Ok(())
+        };
+
+        match copy() {
+            Ok(()) => {
+                connection.execute_batch("RELEASE kvx_copy_scope")?;
+                Ok(())
+            }
+            Err(e) => {
+                connection.execute_batch("ROLLBACK TO kvx_copy_scope; RELEASE kvx_copy_scope")?;
+                Err(e)
+            }
+        }
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.connection.connection()?.execute(
+            "DELETE FROM store WHERE namespace = ?1 AND scope = ?2 AND key = ?3",
+            params![
+                self.namespace.as_str(),
+                key.scope().to_string(),
+                key.name().as_str()
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        let deleted = self.connection.connection()?.execute(
+            &format!(
+                "DELETE FROM store WHERE namespace = ?1 AND scope = ?2 AND key = ?3 AND {LIVE}"
+            ),
+            params![
+                self.namespace.as_str(),
+                key.scope().to_string(),
+                key.name().as_str()
+            ],
+        )?;
+
+        if deleted == 0 {
+            return Err(Error::UnknownKey);
+        }
+
+        Ok(())
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.clear_scope(scope)
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.connection.connection()?.execute(
+            "DELETE FROM store WHERE namespace = ?1 AND scope = ?2",
+            params![self.namespace.as_str(), scope.to_string()],
+        )?;
+
+        Ok(())
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.connection.connection()?.execute(
+            &format!("DELETE FROM store WHERE namespace = ?1 AND {IN_SCOPE}"),
+            params![
+                self.namespace.as_str(),
+                scope.to_string(),
+                sub_scope_pattern(scope)
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.connection.connection()?.execute(
+            "DELETE FROM store WHERE namespace = ?1",
+            params![self.namespace.as_str()],
+        )?;
+
+        Ok(())
+    }
+
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        let connection = self.connection.connection()?;
+        let namespace_exists = |namespace: &NamespaceBuf| -> Result<bool> {
+            Ok(connection
+                .query_row(
+                    "SELECT 1 FROM store WHERE namespace = ?1 LIMIT 1",
+                    params![namespace.as_str()],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+        };
+
+        connection.execute_batch("SAVEPOINT kvx_migrate")?;
+
+        let result = if !namespace_exists(&self.namespace)? {
+            Err(Error::NamespaceMigration(format!(
+                "original namespace {} not found in database",
+                self.namespace
+            )))
+        } else if namespace_exists(&to)? {
+            Err(Error::NamespaceMigration(format!(
+                "target namespace {} already exists in database",
+                to
+            )))
+        } else {
+            let namespaces = params![self.namespace.as_str(), to.as_str()];
+            connection
+                .execute(
+                    "UPDATE store SET namespace = ?2 WHERE namespace = ?1",
+                    namespaces,
+                )
+                .and_then(|_| {
+                    connection.execute(
+                        "DELETE FROM store_tombstones WHERE namespace = ?1",
+                        params![to.as_str()],
+                    )
+                })
+                .and_then(|_| {
+                    connection.execute(
+                        "UPDATE store_tombstones SET namespace = ?2 WHERE namespace = ?1",
+                        namespaces,
+                    )
+                })
+                .map_err(Error::from)
+        };
+
+        match result {
+            Ok(_) => connection.execute_batch("RELEASE kvx_migrate")?,
+            Err(e) => {
+                connection.execute_batch("ROLLBACK TO kvx_migrate; RELEASE kvx_migrate")?;
+                return Err(e);
+            }
+        }
+        drop(connection);
+
+        self.namespace = to;
+
+        Ok(())
+    }
+}
+
+/// Opens the database at the given path and creates the table for the store
+/// if it does not exist yet.
+fn open(path: impl AsRef<Path>) -> Result<Connection> {
+    let connection = Connection::open(path)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS store (
+            namespace TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            expires_at INTEGER,
+            created_at INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (namespace, scope, key)
+        )",
+    )?;
+
+    // Databases created before expiry and change tracking support lack
+    // these columns.
+    for (column, definition) in [
+        ("expires_at", "INTEGER"),
+        ("created_at", "INTEGER NOT NULL DEFAULT 0"),
+        ("updated_at", "INTEGER NOT NULL DEFAULT 0"),
+    ] {
+        let has_column = connection
+            .prepare("SELECT 1 FROM pragma_table_info('store') WHERE name = ?1")?
+            .exists([column])?;
+        if !has_column {
+            connection.execute_batch(&format!(
+                "ALTER TABLE store ADD COLUMN {column} {definition}"
+            ))?;
+        }
+    }
+
+    // Deleted keys, and the original keys of moved values, get a tombstone
+    // for `changes_since` if change tracking is enabled.
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS store_tombstones (
+            namespace TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            key TEXT NOT NULL,
+            deleted_at INTEGER NOT NULL,
+            PRIMARY KEY (namespace, scope, key)
+        )",
+    )?;
+
+    Ok(connection)
+}
+
+/// Returns the `LIKE` pattern matching the sub scopes of a scope.
+fn sub_scope_pattern(scope: &Scope) -> String {
+    let escaped = scope
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("{}{}%", escaped, Scope::SEPARATOR)
+}
+
+fn parse_scope(scope: &str) -> Result<Scope> {
+    if scope.is_empty() {
+        Ok(Scope::global())
+    } else {
+        Ok(Scope::from_str(scope)?)
+    }
+}
+
+fn query_keys(
+    connection: &Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> Result<Vec<Key>> {
+    let mut statement = connection.prepare(sql)?;
+    let keys = statement
+        .query_map(params, |row| Ok(key_from_row(row)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    keys.into_iter().collect()
+}
+
+fn key_from_row(row: &Row) -> Result<Key> {
+    let scope: String = row.get(0)?;
+    let name: String = row.get(1)?;
+
+    Ok(Key::new_scoped(
+        parse_scope(&scope)?,
+        SegmentBuf::from_str(&name)?,
+    ))
+}
+
+trait HasConnection {
+    type Connection<'a>: Deref<Target = Connection>
+    where
+        Self: 'a;
+
+    fn connection(&self) -> Result<Self::Connection<'_>>;
+}
+
+impl HasConnection for Mutex<Connection> {
+    type Connection<'a>
+        = MutexGuard<'a, Connection>
+    where
+        Self: 'a;
+
+    fn connection(&self) -> Result<Self::Connection<'_>> {
+        self.lock().map_err(|e| Error::MutexLock(e.to_string()))
+    }
+}
+
+impl HasConnection for &Connection {
+    type Connection<'a>
+        = &'a Connection
+    where
+        Self: 'a;
+
+    fn connection(&self) -> Result<Self::Connection<'_>> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::Sqlite;
+    use crate::{Key, ReadStore, Scope, WriteStore};
+
+    #[test]
+    fn test_scope_wildcards_are_escaped() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Sqlite::new(
+            dir.path().join("store.sqlite"),
+            "ns".parse::<crate::NamespaceBuf>().unwrap(),
+        )
+        .unwrap();
+
+        for key in ["a_b/k1", "axb/k2", "a%/k3", "a%b/k4"] {
+            store.store(&key.parse().unwrap(), Value::from(1)).unwrap();
+        }
+
+        let scope: Scope = "a_b".parse().unwrap();
+        assert_eq!(
+            store.list_keys(&scope).unwrap(),
+            vec!["a_b/k1".parse::<Key>().unwrap()]
+        );
+
+        let scope: Scope = "a%".parse().unwrap();
+        assert_eq!(
+            store.list_keys(&scope).unwrap(),
+            vec!["a%/k3".parse::<Key>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_move_scope_from_and_to_global() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Sqlite::new(
+            dir.path().join("store.sqlite"),
+            "ns".parse::<crate::NamespaceBuf>().unwrap(),
+        )
+        .unwrap();
+        let keys = |store: &Sqlite<_>| {
+            let mut keys = store
+                .list_keys(&Scope::global())
+                .unwrap()
+                .iter()
+                .map(Key::to_string)
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        for key in ["k1", "a/k2", "a/b/k3"] {
+            store.store(&key.parse().unwrap(), Value::from(1)).unwrap();
+        }
+
+        store
+            .move_scope(&Scope::global(), &"x/y".parse().unwrap())
+            .unwrap();
+        assert_eq!(keys(&store), vec!["x/y/a/b/k3", "x/y/a/k2", "x/y/k1"]);
+
+        store
+            .move_scope(&"x/y".parse().unwrap(), &Scope::global())
+            .unwrap();
+        assert_eq!(keys(&store), vec!["a/b/k3", "a/k2", "k1"]);
+    }
+}
@@ -1,48 +1,513 @@
-use std::fmt::{Debug, Display};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    sync::{mpsc::Receiver, Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
 use implementations::{disk::Disk, memory::Memory};
 #[cfg(feature = "macros")]
 pub use kvx_macros::{namespace, segment};
 pub use kvx_types::{Key, Namespace, NamespaceBuf, Scope, Segment, SegmentBuf};
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 use url::Url;
 
-pub use crate::error::Error;
+pub use crate::{
+    builder::KeyValueStoreBuilder,
+    dump::{migrate, migrate_missing},
+    error::Error,
+    implementations::disk::{Compression, Durability, TMP_FILE_MAX_AGE},
+    retry::{RetryPolicy, RetryingStore},
+    scoped::ScopedStore,
+};
 
+mod builder;
+mod bytes;
+mod dump;
 mod error;
 mod implementations;
+pub mod metrics;
 #[cfg(feature = "queue")]
 pub mod queue;
+mod retry;
+mod scoped;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How a key changed, as reported by [`ReadStore::changes_since`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ChangeKind {
+    /// The key did not have a value before.
+    Created,
+
+    /// The value of an existing key was replaced.
+    Updated,
+
+    /// The key was deleted and has not been stored again.
+    Deleted,
+}
+
+/// The kind of backend of a store, see
+/// [`KeyValueStoreBackend::backend_kind`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum BackendKind {
+    /// An in-memory store, for the `memory://` scheme.
+    Memory,
+
+    /// A store with a file per value, for the `local://` scheme.
+    Disk,
+
+    /// A Postgres database, for the `postgres://` scheme.
+    Postgres,
+
+    /// A SQLite database, for the `sqlite://` scheme.
+    Sqlite,
+
+    /// A Redis server, for the `redis://` scheme.
+    Redis,
+
+    /// A backend created by a factory registered using
+    /// [`KeyValueStore::register_scheme`].
+    Other,
+}
+
+/// A change to a key, as sent to the receiver returned by
+/// [`KeyValueStoreBackend::watch_scope`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ChangeEvent {
+    /// A value was stored for the key.
+    Stored(Key),
+
+    /// The value of the key was deleted.
+    Deleted(Key),
+
+    /// The value of a key was moved to another key.
+    Moved { from: Key, to: Key },
+}
+
 /// Read operations of a store
 pub trait ReadStore {
     fn is_empty(&self) -> Result<bool>;
+
+    /// Returns the number of keys in the namespace of this store.
+    fn total_keys(&self) -> Result<usize> {
+        self.count_keys(&Scope::global())
+    }
+
+    /// Returns whether the key exists, also if its value is
+    /// [`Value::Null`].
     fn has(&self, key: &Key) -> Result<bool>;
+
+    /// Returns for each key whether it exists, in the order of the given keys.
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        keys.iter().map(|key| self.has(key)).collect()
+    }
+
     fn has_scope(&self, scope: &Scope) -> Result<bool>;
+
+    /// Returns whether the key exists and holds the given value, e.g. to
+    /// skip a write that would not change anything.
+    fn has_value(&self, key: &Key, value: &Value) -> Result<bool> {
+        Ok(self.get(key)?.as_ref() == Some(value))
+    }
+
+    /// Returns the value for a key, or `None` if the key does not exist. A
+    /// stored [`Value::Null`] is returned as `Some(Value::Null)`.
     fn get(&self, key: &Key) -> Result<Option<Value>>;
+
+    /// Returns the value for a key as JSON bytes, or `None` if the key does
+    /// not exist.
+    ///
+    /// By default the value is serialized again, the Disk backend returns
+    /// the (decompressed) contents of the file.
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(serde_json::to_vec(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the bytes stored for a key using [`WriteStore::store_bytes`],
+    /// or `None` if the key does not exist.
+    ///
+    /// Returns [`Error::NotBytes`] if the key has a value that was not
+    /// stored as bytes.
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        match self.get(key)? {
+            Some(value) => bytes::from_value(&value).map(Some).ok_or(Error::NotBytes),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the values for the keys, in the order of the given keys.
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Returns the value for a key, or `default` if the key does not exist.
+    ///
+    /// The default is not stored.
+    fn get_or(&self, key: &Key, default: Value) -> Result<Value> {
+        Ok(self.get(key)?.unwrap_or(default))
+    }
+
+    /// Returns the value for a key, or the result of `f` if the key does not
+    /// exist.
+    ///
+    /// The default is not stored, and `f` is only called if the key does not
+    /// exist.
+    fn get_or_else(&self, key: &Key, f: impl FnOnce() -> Value) -> Result<Value>
+    where
+        Self: Sized,
+    {
+        Ok(self.get(key)?.unwrap_or_else(f))
+    }
+
     fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>>;
+
+    /// Returns the keys in a scope, including keys in its sub scopes,
+    /// together with their values.
+    ///
+    /// Keys that are deleted between listing and reading them are skipped.
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        let mut entries = vec![];
+        for key in self.list_keys(scope)? {
+            if let Some(value) = self.get(&key)? {
+                entries.push((key, value));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Returns all keys in the namespace of this store with their values,
+    /// e.g. to capture the state of a store in a test.
+    ///
+    /// See [`WriteStore::restore`] to restore the entries. Backends read all
+    /// entries atomically where feasible.
+    fn snapshot(&self) -> Result<HashMap<Key, Value>> {
+        Ok(self.list_entries(&Scope::global())?.into_iter().collect())
+    }
+
+    /// Returns the keys in a scope, including keys in its sub scopes, whose
+    /// name equals `name` ignoring the case of ASCII letters.
+    ///
+    /// The keys are returned as stored, see
+    /// [`Segment::eq_ignore_ascii_case`].
+    fn list_keys_matching_ci(&self, scope: &Scope, name: &Segment) -> Result<Vec<Key>> {
+        Ok(self
+            .list_keys(scope)?
+            .into_iter()
+            .filter(|key| key.name().eq_ignore_ascii_case(name))
+            .collect())
+    }
+
+    /// Returns an iterator over the keys in a scope, including keys in its
+    /// sub scopes.
+    ///
+    /// Unlike [`ReadStore::list_keys`] backends may retrieve the keys lazily,
+    /// so keys stored or deleted while iterating may or may not be returned.
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        Ok(Box::new(self.list_keys(scope)?.into_iter().map(Ok)))
+    }
+
+    /// Returns the number of keys in a scope, including keys in its sub
+    /// scopes.
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        self.list_keys(scope).map(|keys| keys.len())
+    }
+
     fn list_scopes(&self) -> Result<Vec<Scope>>;
+
+    /// Returns the scopes beneath `parent`, i.e. the scopes with keys that
+    /// start with `parent` and have more segments, including their super
+    /// scopes up to `parent`. For the global scope this returns the same as
+    /// [`list_scopes`].
+    ///
+    /// [`list_scopes`]: ReadStore::list_scopes
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self
+            .list_scopes()?
+            .into_iter()
+            .filter(|scope| scope.starts_with(parent) && scope.depth() > parent.depth())
+            .collect())
+    }
+
+    /// Returns the scopes directly beneath `parent`, i.e. the scopes
+    /// returned by [`list_scopes_under`] that have exactly one more segment.
+    ///
+    /// [`list_scopes_under`]: ReadStore::list_scopes_under
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        Ok(self
+            .list_scopes_under(parent)?
+            .into_iter()
+            .filter(|scope| scope.depth() == parent.depth() + 1)
+            .collect())
+    }
+
+    /// Returns the keys in the namespace that changed at or after `since`,
+    /// ordered by the time of their last change.
+    ///
+    /// A key that was created and then updated after `since` is reported
+    /// as [`ChangeKind::Created`]. A key that was deleted is only reported
+    /// if it has not been stored again, deletes are tracked using
+    /// tombstones which are kept until they are removed by
+    /// [`WriteStore::prune_changes`]. Values that expire are not reported as
+    /// deleted.
+    ///
+    /// Tracking changes costs extra writes, so the built-in backends only
+    /// track them when enabled using `track_changes=true` in the URL or
+    /// [`KeyValueStoreBuilder::track_changes`], and fail with
+    /// [`Error::Other`] otherwise. Only changes made by stores that track
+    /// them are reported.
+    ///
+    /// To poll for changes, take the time before calling this and use it as
+    /// `since` for the next call, see [`KeyValueStore::subscribe_namespace`].
+    /// Changes made around that time may be reported twice.
+    fn changes_since(&self, _since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        Err(changes_not_tracked())
+    }
+
+    /// Returns the keys in a scope for which the value at `json_path` equals
+    /// `equals`.
+    ///
+    /// The path is a dot separated list of object fields and array indices,
+    /// e.g. `owner.name` or `items.0`. An empty path refers to the value
+    /// itself.
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &Value,
+    ) -> Result<Vec<Key>> {
+        let mut keys = vec![];
+        for key in self.list_keys(scope)? {
+            if let Some(value) = self.get(&key)? {
+                if value_at_path(&value, json_path) == Some(equals) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Returns the segments of a dot separated path into a JSON value.
+pub(crate) fn json_path_segments(json_path: &str) -> Vec<&str> {
+    json_path
+        .split('.')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Returns the part of a JSON value at a dot separated path, if it exists.
+fn value_at_path<'a>(value: &'a Value, json_path: &str) -> Option<&'a Value> {
+    json_path_segments(json_path)
+        .into_iter()
+        .try_fold(value, |value, segment| match value {
+            Value::Object(map) => map.get(segment),
+            Value::Array(values) => segment.parse().ok().and_then(|i: usize| values.get(i)),
+            _ => None,
+        })
 }
 
 /// Write operations of a store
-pub trait WriteStore {
+///
+/// Only the original operations are required, the others have default
+/// implementations that use these and the read operations. Backends
+/// override them where they can do better, e.g. atomically.
+pub trait WriteStore: ReadStore {
     /// Store a value.
     fn store(&self, key: &Key, value: Value) -> Result<()>;
 
-    /// Move a value to a new key. Fails if the original value does not exist.
+    /// Store multiple values.
+    ///
+    /// Entries are stored in order, so if a key occurs more than once the
+    /// last value for that key is kept. Backends store all entries atomically
+    /// where feasible.
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        for (key, value) in entries {
+            self.store(key, value.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// Store opaque bytes, which can be read using [`ReadStore::get_bytes`].
+    ///
+    /// The Disk backend writes the bytes to the file as they are. Other
+    /// backends store them as JSON, as an object with a `kvx_bytes` field
+    /// holding the bytes encoded as base64, which is also what
+    /// [`ReadStore::get`] returns for them on any backend.
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        self.store(key, bytes::to_value(bytes))
+    }
+
+    /// Replace all keys in the namespace of this store with the given
+    /// entries, e.g. as returned by [`ReadStore::snapshot`].
+    ///
+    /// Other namespaces are not affected. Backends replace the entries
+    /// atomically where feasible.
+    fn restore(&self, entries: HashMap<Key, Value>) -> Result<()> {
+        self.clear()?;
+        self.store_many(&entries.into_iter().collect::<Vec<_>>())
+    }
+
+    /// Store a value that expires at the given time.
+    ///
+    /// Once expired the key is skipped by reads, as if it does not exist, until
+    /// it is removed by [`WriteStore::purge_expired`]. Storing a value for the
+    /// key using [`WriteStore::store`] removes the expiry.
+    ///
+    /// By default values cannot expire, so this fails with [`Error::Other`].
+    fn store_with_expiry(&self, _key: &Key, _value: Value, _expires_at: SystemTime) -> Result<()> {
+        Err(Error::Other(
+            "values cannot expire in this backend".to_string(),
+        ))
+    }
+
+    /// Remove all expired values in the namespace of this store. Returns the
+    /// number of values removed.
+    ///
+    /// By default values cannot expire, so nothing is removed.
+    fn purge_expired(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Remove the tombstones of keys deleted before `before`, and the other
+    /// records kept to report changes made before it, see
+    /// [`ReadStore::changes_since`]. Returns the number of records removed.
+    ///
+    /// Afterwards [`ReadStore::changes_since`] may miss changes made before
+    /// `before`, so it should be earlier than the cursors of all consumers.
+    ///
+    /// By default changes are not tracked, so nothing is removed.
+    fn prune_changes(&self, _before: SystemTime) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Store a value if the current value for the key equals `expected`,
+    /// where `None` means that the key must not exist. Returns whether the
+    /// value was stored.
+    ///
+    /// The comparison and the write are atomic with respect to other calls of
+    /// this method, and to transactions on the scope of the key. The Disk and
+    /// Memory backends take the locks of a transaction on that scope, so it
+    /// also excludes the transactions on super scopes that such a
+    /// transaction excludes, and it can be used in a transaction on the
+    /// scope or a super scope.
+    ///
+    /// By default the value is read and then stored without any locking, so
+    /// this is only atomic when used in a transaction on the scope of the
+    /// key. All built-in backends override this.
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        if self.get(key)?.as_ref() != expected {
+            return Ok(false);
+        }
+
+        self.store(key, new)?;
+        Ok(true)
+    }
+
+    /// Move a value to a new key. Fails with [`Error::UnknownKey`] if the
+    /// original value does not exist.
     fn move_value(&self, from: &Key, to: &Key) -> Result<()>;
 
-    /// Move all values from one scope to another.
+    /// Copy a value to a new key, keeping the original. Fails if the original
+    /// value does not exist.
+    ///
+    /// By default the value is read and stored again, which does not keep
+    /// its expiry time.
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        let value = self.get(from)?.ok_or(Error::UnknownKey)?;
+        self.store(to, value)
+    }
+
+    /// Move all values from one scope to another, including the values in
+    /// its sub scopes.
+    ///
+    /// Keys are moved relative to the scopes, so with `from` `a` and `to`
+    /// `x` the key `a/b/name` is moved to `x/b/name`. The values are merged
+    /// into the target scope: values that are already there are kept,
+    /// unless a value is moved to the same key, which replaces them.
     fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()>;
 
-    /// Delete a value for a key.
+    /// Copy all values in a scope, including its sub scopes, to another
+    /// scope, keeping the originals.
+    ///
+    /// Keys are copied relative to the scopes, so with `from` `a` and `to`
+    /// `x` the key `a/b/name` is copied to `x/b/name`. Keys already in the
+    /// destination scope are kept, unless a copied key replaces them.
+    ///
+    /// By default the values are copied one by one using
+    /// [`WriteStore::copy_value`].
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        for key in self.list_keys(from)? {
+            if let Some(target) = key.rebase(from, to) {
+                match self.copy_value(&key, &target) {
+                    // The key was deleted after it was listed.
+                    Err(Error::UnknownKey) => {}
+                    res => res?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete a value for a key. Succeeds if there is no value for the key,
+    /// see [`WriteStore::delete_existing`] to fail instead.
     fn delete(&self, key: &Key) -> Result<()>;
 
-    /// Delete all values for a scope.
+    /// Delete a value for a key. Fails with [`Error::UnknownKey`] if there is
+    /// no value for the key.
+    ///
+    /// By default this checks whether the key exists before deleting it, so
+    /// it is only atomic when used in a transaction on the scope of the key.
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        if !self.has(key)? {
+            return Err(Error::UnknownKey);
+        }
+
+        self.delete(key)
+    }
+
+    /// Delete all values for a scope, including values in its sub scopes.
+    ///
+    /// On the Disk backend this also removes the directory for the scope, see
+    /// [`WriteStore::clear_scope`] to keep it.
     fn delete_scope(&self, scope: &Scope) -> Result<()>;
 
+    /// Delete the values directly in a scope, but keep those in its sub
+    /// scopes.
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        for key in self.list_keys(scope)? {
+            if key.scope() == scope {
+                self.delete(&key)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Delete all values in a scope, including values in its sub scopes, but
+    /// keep the scope itself.
+    ///
+    /// Only the Disk backend has a notion of an empty scope: it keeps the
+    /// (now empty) directory for the scope, so [`ReadStore::has_scope`]
+    /// still returns `true`. Other backends only know scopes through their
+    /// keys, so for them the scope no longer exists afterwards.
+    ///
+    /// By default this uses [`WriteStore::delete_scope`].
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.delete_scope(scope)
+    }
+
     /// Delete all values within the namespace of this store.
     fn clear(&self) -> Result<()>;
 
@@ -53,15 +518,106 @@ pub trait WriteStore {
 pub(crate) type TransactionCallback<'s> =
     &'s mut dyn FnMut(&dyn KeyValueStoreBackend) -> Result<()>;
 
+pub(crate) type ReadTransactionCallback<'s> = &'s mut dyn FnMut(&dyn ReadStore) -> Result<()>;
+
 /// Read, Write and Transaction operations of a store
 pub trait KeyValueStoreBackend: ReadStore + WriteStore {
+    /// Returns the kind of backend of this store, e.g. to decide whether
+    /// syncing to disk matters or to log it.
+    ///
+    /// Wrappers such as [`RetryingStore`] return the kind of the store they
+    /// wrap. By default this returns [`BackendKind::Other`].
+    fn backend_kind(&self) -> BackendKind {
+        BackendKind::Other
+    }
+
+    /// Run the callback in a transaction on the given scope.
+    ///
+    /// Transactions on the same scope are mutually exclusive. The Disk and
+    /// Memory backends also exclude transactions on super- and sub-scopes,
+    /// but not on sibling scopes, so a transaction on `a` blocks one on
+    /// `a/b`, while `a/b` and `a/c` run concurrently. The callback should
+    /// therefore only touch keys within the given scope. With
+    /// `locking=exact` in the URL the Memory backend only locks the given
+    /// scope. Their writes are buffered and applied when the callback
+    /// succeeds, so they are discarded if it fails. Memory applies them
+    /// atomically. Disk first writes all of them to temporary files, so
+    /// nothing is applied if that fails, but a failure to rename or remove
+    /// a file afterwards leaves the writes before it applied. The Postgres
+    /// backend runs the callback in a serializable database transaction.
+    /// The Redis backend buffers the writes and applies them atomically,
+    /// running the callback again if a key it read was changed in the
+    /// meantime.
     fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()>;
+
+    /// Run the callback in a transaction on the given scope, like
+    /// [`transaction`], unless the scope cannot be locked within `timeout`.
+    /// Returns whether the callback was run.
+    ///
+    /// Only the Disk and Memory backends wait for a lock on the scope, by
+    /// default the transaction is run and `true` is returned.
+    ///
+    /// [`transaction`]: KeyValueStoreBackend::transaction
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        _timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        self.transaction(scope, callback).map(|()| true)
+    }
+
+    /// Run the callback with a consistent, read-only view of the given
+    /// scope, so that e.g. listing keys and then getting their values does
+    /// not see changes made in between.
+    ///
+    /// The Postgres backend runs the callback in a read-only repeatable read
+    /// transaction. The Memory backend takes a shared lock on the scope, so
+    /// it waits for transactions on overlapping scopes, but not for other
+    /// read-only transactions. It also holds the lock on all its data while
+    /// the callback runs, so the callback must not use the store itself. By
+    /// default, and so for the Disk backend, the callback is run in a
+    /// [`transaction`] on the scope.
+    ///
+    /// [`transaction`]: KeyValueStoreBackend::transaction
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        self.transaction(scope, &mut |store| callback(store))
+    }
+
+    /// Returns a receiver for the changes to the keys in a scope, including
+    /// its sub scopes, made through any store for the same namespace.
+    ///
+    /// A move is reported to the watchers of both scopes involved. Values
+    /// that expire are not reported as deleted. The watch ends when the
+    /// receiver is dropped.
+    ///
+    /// Only the Memory backend supports watching a scope for now, by default
+    /// this fails with [`Error::Other`].
+    fn watch_scope(&self, _scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        Err(Error::Other(
+            "watching a scope is only supported by the Memory backend".to_string(),
+        ))
+    }
 }
 
 pub trait PubKeyValueStoreBackend: KeyValueStoreBackend + Debug + Send + Sync + Display {}
 
 impl<T> PubKeyValueStoreBackend for T where T: KeyValueStoreBackend + Debug + Send + Sync + Display {}
 
+/// Creates a backend for a storage URL and namespace, see
+/// [`KeyValueStore::register_scheme`].
+pub type SchemeFactory =
+    Box<dyn Fn(&Url, NamespaceBuf) -> Result<Box<dyn PubKeyValueStoreBackend>> + Send + Sync>;
+
+lazy_static! {
+    /// The factories of the registered URL schemes by scheme.
+    static ref SCHEMES: RwLock<HashMap<String, Arc<SchemeFactory>>> = RwLock::new(HashMap::new());
+}
+
 /// Represents a key-value store, wraps a backend
 ///
 /// # Example
@@ -78,36 +634,240 @@ impl<T> PubKeyValueStoreBackend for T where T: KeyValueStoreBackend + Debug + Se
 /// # Ok(())
 /// # }
 /// ```
+///
+/// # Thread safety
+/// A store is `Send` and `Sync`, so it can be shared between threads.
+/// Concurrent transactions on the same scope are serialized.
+/// ```
+/// use kvx::{Key, KeyValueStore, Namespace, ReadStore};
+/// use serde_json::Value;
+/// use url::Url;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("threads")?)?;
+/// let key: Key = "counter".parse()?;
+///
+/// std::thread::scope(|s| {
+///     for _ in 0..4 {
+///         s.spawn(|| {
+///             store.update(&key, |value| {
+///                 let counter = value.and_then(|v| v.as_i64()).unwrap_or_default();
+///                 Ok(Some(Value::from(counter + 1)))
+///             })
+///         });
+///     }
+/// });
+///
+/// assert_eq!(store.get(&key)?, Some(Value::from(4)));
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct KeyValueStore {
     inner: Box<dyn PubKeyValueStoreBackend>,
+    reject_null: bool,
+    #[cfg(feature = "queue")]
+    queue_config: queue::QueueConfig,
+    #[cfg(feature = "queue")]
+    queue_observer: Option<Arc<dyn queue::QueueObserver>>,
 }
 
 impl KeyValueStore {
-    pub fn new(storage_uri: &Url, namespace: impl Into<NamespaceBuf>) -> Result<KeyValueStore> {
-        let namespace = namespace.into();
-        let inner: Box<dyn PubKeyValueStoreBackend> = match storage_uri.scheme() {
-            "local" => {
-                let path = format!(
-                    "{}{}",
-                    storage_uri.host_str().unwrap_or_default(),
-                    storage_uri.path()
-                );
-
-                Box::new(Disk::new(&path, namespace.as_str())?)
-            }
-            "memory" => Box::new(Memory::new(storage_uri.host_str(), namespace)?),
-            #[cfg(feature = "postgres")]
-            "postgres" => Box::new(crate::implementations::postgres::Postgres::new(
+    /// Creates a store for the URL and namespace, using the options in the
+    /// query parameters of the URL, see [`KeyValueStore::builder`].
+    ///
+    /// The namespace can be given as a string, which fails with
+    /// [`Error::Namespace`] if it is not a valid namespace.
+    pub fn new<N>(storage_uri: &Url, namespace: N) -> Result<KeyValueStore>
+    where
+        N: TryInto<NamespaceBuf>,
+        Error: From<N::Error>,
+    {
+        KeyValueStore::builder(storage_uri, namespace.try_into()?).build()
+    }
+
+    /// Creates an in-memory store that does not share its values or locks
+    /// with any other store, not even stores for the same namespace created
+    /// using this function or a `memory://` URL.
+    ///
+    /// This is useful in tests that should not see each other's data.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+    /// use serde_json::Value;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let namespace = Namespace::parse("isolated")?;
+    /// let store = KeyValueStore::new_isolated(namespace);
+    /// let key: Key = "key".parse()?;
+    /// store.store(&key, Value::from(1))?;
+    ///
+    /// assert!(!KeyValueStore::new_isolated(namespace).has(&key)?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_isolated(namespace: impl Into<NamespaceBuf>) -> KeyValueStore {
+        KeyValueStore::from_inner(Box::new(Memory::new_isolated(namespace.into())))
+    }
+
+    /// Returns all non-empty namespaces in the storage at the URL with the
+    /// number of keys in each, ordered by namespace.
+    pub fn namespace_summaries(storage_uri: &Url) -> Result<Vec<(NamespaceBuf, usize)>> {
+        let mut summaries = match storage_uri.scheme() {
+            "local" => Disk::namespace_summaries(&local_path(storage_uri))?,
+            "memory" => Memory::namespace_summaries(storage_uri.host_str())?,
+            #[cfg(feature = "sqlite")]
+            "sqlite" => crate::implementations::sqlite::Sqlite::namespace_summaries(local_path(
                 storage_uri,
-                namespace,
-            )?),
+            ))?,
+            #[cfg(feature = "postgres")]
+            "postgres" => {
+                crate::implementations::postgres::Postgres::namespace_summaries(storage_uri)?
+            }
+            #[cfg(feature = "redis")]
+            "redis" => crate::implementations::redis::Redis::namespace_summaries(storage_uri)?,
             scheme => Err(crate::error::Error::UnknownScheme(scheme.to_owned()))?,
         };
 
-        Ok(KeyValueStore { inner })
+        summaries.sort();
+        Ok(summaries)
+    }
+
+    /// Removes the temporary files of the `local://` storage at the URL that
+    /// were last modified longer than `older_than` ago, and returns the
+    /// number of files removed.
+    ///
+    /// These files are shared by all namespaces in the storage. They are
+    /// normally removed when a value is persisted, but can be left behind
+    /// by a process that crashed while writing. Other backends do not use
+    /// temporary files, so nothing is removed for them.
+    pub fn sweep_tmp(storage_uri: &Url, older_than: Duration) -> Result<usize> {
+        match storage_uri.scheme() {
+            "local" => Disk::sweep_tmp_at(&local_path(storage_uri), older_than),
+            _ => Ok(0),
+        }
+    }
+
+    /// Registers a factory for the backends of a URL scheme.
+    ///
+    /// [`KeyValueStore::new`] uses the registered factory for URLs with the
+    /// scheme, taking precedence over the built-in schemes. Registering a
+    /// scheme again replaces the previous factory.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+    /// use serde_json::Value;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// // An alias for the shared in-memory instance.
+    /// KeyValueStore::register_scheme(
+    ///     "mem",
+    ///     Box::new(|_, namespace| {
+    ///         let url = Url::parse("memory://").unwrap();
+    ///         Ok(Box::new(KeyValueStore::new(&url, namespace)?))
+    ///     }),
+    /// )?;
+    ///
+    /// let key: Key = "key".parse()?;
+    /// let store = KeyValueStore::new(&Url::parse("mem://")?, Namespace::parse("alias")?)?;
+    /// store.store(&key, Value::from(1))?;
+    ///
+    /// let memory = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("alias")?)?;
+    /// assert_eq!(memory.get(&key)?, Some(Value::from(1)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn register_scheme(scheme: &str, factory: SchemeFactory) -> Result<()> {
+        SCHEMES
+            .write()
+            .map_err(|e| Error::MutexLock(e.to_string()))?
+            .insert(scheme.to_owned(), Arc::new(factory));
+
+        Ok(())
+    }
+
+    fn from_inner(inner: Box<dyn PubKeyValueStoreBackend>) -> KeyValueStore {
+        KeyValueStore {
+            inner,
+            reject_null: false,
+            #[cfg(feature = "queue")]
+            queue_config: queue::QueueConfig::default(),
+            #[cfg(feature = "queue")]
+            queue_observer: None,
+        }
+    }
+
+    /// Sets whether storing a JSON `null` value returns [`Error::NullValue`].
+    ///
+    /// This helps catching callers that store `null` where they meant to
+    /// delete the key. It is disabled by default. Note that it applies to
+    /// the write operations and [`KeyValueStore::update`] of this store, but
+    /// not to writes within [`KeyValueStoreBackend::transaction`] callbacks.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Error, Key, KeyValueStore, Namespace, WriteStore};
+    /// use serde_json::Value;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("null")?)?
+    ///     .with_reject_null(true);
+    /// let key: Key = "key".parse()?;
+    ///
+    /// assert!(matches!(store.store(&key, Value::Null), Err(Error::NullValue)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_reject_null(mut self, reject_null: bool) -> Self {
+        self.reject_null = reject_null;
+        self
+    }
+
+    /// Retries operations on this store that fail with a transient error,
+    /// see [`RetryingStore`].
+    ///
+    /// Errors for which [`Error::is_retryable`] is false are returned
+    /// immediately.
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        KeyValueStore {
+            inner: Box::new(RetryingStore::new(self.inner, policy)),
+            ..self
+        }
     }
 
+    /// Reports the operations performed on this store to the metrics, see
+    /// [`MeteredStore`].
+    ///
+    /// If this store also retries operations, then whether retried
+    /// operations are reported once or for every attempt depends on whether
+    /// this is called before or after [`KeyValueStore::with_retry`].
+    ///
+    /// [`MeteredStore`]: metrics::MeteredStore
+    pub fn with_metrics(self, metrics: Arc<dyn metrics::StoreMetrics>) -> Self {
+        KeyValueStore {
+            inner: Box::new(metrics::MeteredStore::new(self.inner, metrics)),
+            ..self
+        }
+    }
+
+    /// Returns [`Error::NullValue`] if the value is `null` and this store
+    /// rejects them.
+    fn check_value(&self, value: &Value) -> Result<()> {
+        if self.reject_null && value.is_null() {
+            Err(Error::NullValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs the operation in a transaction on the given scope and returns its
+    /// result.
+    ///
+    /// If the operation returns [`Error::Abort`] there is no result, so
+    /// unlike [`KeyValueStoreBackend::transaction`] this returns the error.
     pub fn execute<F, T>(&self, scope: &Scope, mut op: F) -> Result<T>
     where
         F: FnMut(&dyn KeyValueStoreBackend) -> Result<T, Error>,
@@ -117,8 +877,215 @@ impl KeyValueStore {
             res = Some(op(store)?);
             Ok(())
         })?;
-        Ok(res.unwrap())
+        res.ok_or(Error::Abort)
+    }
+
+    /// Runs the operation with a consistent, read-only view of the given
+    /// scope and returns its result, see
+    /// [`KeyValueStoreBackend::read_only_transaction`].
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Key, KeyValueStore, Namespace, ReadStore, Scope, WriteStore};
+    /// use serde_json::Value;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("read")?)?;
+    /// let scope: Scope = "scope".parse()?;
+    /// store.store(&"scope/key".parse()?, Value::from(1))?;
+    ///
+    /// let values = store.read_transaction(&scope, |r| {
+    ///     r.list_keys(&scope)?
+    ///         .iter()
+    ///         .map(|key| r.get(key))
+    ///         .collect::<Result<Vec<_>, _>>()
+    /// })?;
+    /// assert_eq!(values, vec![Some(Value::from(1))]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_transaction<F, T>(&self, scope: &Scope, mut op: F) -> Result<T>
+    where
+        F: FnMut(&dyn ReadStore) -> Result<T, Error>,
+    {
+        let mut res = None;
+        self.read_only_transaction(scope, &mut |store| {
+            res = Some(op(store)?);
+            Ok(())
+        })?;
+        res.ok_or(Error::Abort)
+    }
+
+    /// Returns a feed of the changes in the namespace of this store at or
+    /// after `since`, see [`ReadStore::changes_since`].
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{ChangeKind, Key, KeyValueStore, Namespace, WriteStore};
+    /// use serde_json::Value;
+    /// use std::time::SystemTime;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(
+    ///     &Url::parse("memory://?track_changes=true")?,
+    ///     Namespace::parse("feed")?,
+    /// )?;
+    /// let mut feed = store.subscribe_namespace(SystemTime::now());
+    ///
+    /// let key: Key = "key".parse()?;
+    /// store.store(&key, Value::from(1))?;
+    /// assert_eq!(feed.poll()?, vec![(key.clone(), ChangeKind::Created)]);
+    ///
+    /// store.delete(&key)?;
+    /// assert!(feed.poll()?.contains(&(key, ChangeKind::Deleted)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_namespace(&self, since: SystemTime) -> ChangeFeed<'_> {
+        ChangeFeed {
+            store: self,
+            cursor: since,
+        }
     }
+
+    /// Returns the value for a key, deserialized into `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Key, KeyValueStore, Namespace};
+    /// use serde::{Deserialize, Serialize};
+    /// use url::Url;
+    ///
+    /// #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    /// struct Counter {
+    ///     count: u64,
+    /// }
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("typed")?)?;
+    /// let key: Key = "counter".parse()?;
+    ///
+    /// store.store_as(&key, &Counter { count: 1 })?;
+    /// assert_eq!(store.get_as::<Counter>(&key)?, Some(Counter { count: 1 }));
+    /// assert!(matches!(store.get_as::<String>(&key), Err(kvx::Error::Json(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_as<T: DeserializeOwned>(&self, key: &Key) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serializes a value and stores it for a key.
+    pub fn store_as<T: Serialize>(&self, key: &Key, value: &T) -> Result<()> {
+        self.store(key, serde_json::to_value(value)?)
+    }
+
+    /// Read, transform and write back the value for a key in a transaction on
+    /// the scope of the key.
+    ///
+    /// The closure receives the current value, if any. If it returns a value,
+    /// that value is stored, if it returns `None` the key is deleted. Note that
+    /// the closure may be called more than once if the backend retries the
+    /// transaction, which is why it is an `FnMut` rather than an `FnOnce`.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Key, KeyValueStore, Namespace, ReadStore};
+    /// use serde_json::Value;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("update")?)?;
+    /// let key: Key = "counter".parse()?;
+    ///
+    /// let increment = |value: Option<Value>| {
+    ///     let count = value.and_then(|v| v.as_i64()).unwrap_or_default();
+    ///     Ok(Some(Value::from(count + 1)))
+    /// };
+    /// store.update(&key, increment)?;
+    /// store.update(&key, increment)?;
+    /// assert_eq!(store.get(&key)?, Some(Value::from(2)));
+    ///
+    /// store.update(&key, |_| Ok(None))?;
+    /// assert!(!store.has(&key)?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update<F>(&self, key: &Key, mut f: F) -> Result<()>
+    where
+        F: FnMut(Option<Value>) -> Result<Option<Value>>,
+    {
+        self.execute(key.scope(), |kv| match f(kv.get(key)?)? {
+            Some(value) => {
+                self.check_value(&value)?;
+                kv.store(key, value)
+            }
+            None if kv.has(key)? => kv.delete(key),
+            None => Ok(()),
+        })
+    }
+}
+
+/// Polls a [`KeyValueStore`] for changes, created using
+/// [`KeyValueStore::subscribe_namespace`].
+#[derive(Debug)]
+pub struct ChangeFeed<'a> {
+    store: &'a KeyValueStore,
+    cursor: SystemTime,
+}
+
+impl ChangeFeed<'_> {
+    /// Returns the changes since the previous poll, or since the time the
+    /// feed was created for the first poll.
+    ///
+    /// Changes made while polling may be returned again by the next poll.
+    pub fn poll(&mut self) -> Result<Vec<(Key, ChangeKind)>> {
+        let next = SystemTime::now();
+        let changes = self.store.changes_since(self.cursor)?;
+        self.cursor = next;
+
+        Ok(changes)
+    }
+
+    /// Returns the time from which the next poll reports changes.
+    pub fn cursor(&self) -> SystemTime {
+        self.cursor
+    }
+}
+
+/// Returns whether a store for the storage URL tracks changes, set using
+/// the `track_changes` parameter, see [`ReadStore::changes_since`].
+fn tracks_changes(storage_uri: &Url) -> Result<bool> {
+    let mut track_changes = false;
+    for (name, value) in storage_uri.query_pairs() {
+        if name == "track_changes" {
+            track_changes = value.parse().map_err(|_| {
+                Error::InvalidConfig(format!("invalid value for track_changes: {}", value))
+            })?;
+        }
+    }
+
+    Ok(track_changes)
+}
+
+/// Returns the error for [`ReadStore::changes_since`] on a store that does
+/// not track changes.
+fn changes_not_tracked() -> Error {
+    Error::Other("changes are not tracked by this store".to_string())
+}
+
+/// Returns the path for a `local://` or `sqlite://` storage URL.
+fn local_path(storage_uri: &Url) -> String {
+    format!(
+        "{}{}",
+        storage_uri.host_str().unwrap_or_default(),
+        storage_uri.path()
+    )
 }
 
 impl Display for KeyValueStore {
@@ -128,8 +1095,79 @@ impl Display for KeyValueStore {
 }
 
 impl KeyValueStoreBackend for KeyValueStore {
+    fn backend_kind(&self) -> BackendKind {
+        self.inner.backend_kind()
+    }
+
+    /// Runs the callback in a transaction on the given scope.
+    ///
+    /// The callback can return [`Error::Abort`] to abort the transaction,
+    /// in which case `Ok(())` is returned. The writes done by the callback
+    /// are then discarded, as they are if it returns any other error:
+    /// Postgres and SQLite roll back the database transaction, the other
+    /// backends buffer the writes and only apply them when the callback
+    /// succeeds.
+    ///
+    /// # Example
+    /// ```
+    /// use kvx::{Error, Key, KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore, Scope, WriteStore};
+    /// use serde_json::Value;
+    /// use url::Url;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("abort")?)?;
+    /// let key: Key = "limit".parse()?;
+    /// store.store(&key, Value::from(10))?;
+    ///
+    /// store.transaction(&Scope::global(), &mut |t| {
+    ///     let count = t.get(&key)?.and_then(|v| v.as_i64()).unwrap_or_default();
+    ///     if count >= 10 {
+    ///         return Err(Error::Abort);
+    ///     }
+    ///     t.store(&key, Value::from(count + 1))
+    /// })?;
+    ///
+    /// assert_eq!(store.get(&key)?, Some(Value::from(10)));
+    /// # Ok(())
+    /// # }
+    /// ```
     fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
-        self.inner.transaction(scope, callback)
+        match self.inner.transaction(scope, callback) {
+            Err(Error::Abort) => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Runs the callback in a transaction on the given scope, unless the
+    /// scope cannot be locked within `timeout`, see
+    /// [`KeyValueStoreBackend::try_transaction`].
+    ///
+    /// Like for [`transaction`], the callback can return [`Error::Abort`],
+    /// in which case `Ok(true)` is returned.
+    ///
+    /// [`transaction`]: KeyValueStore::transaction
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        match self.inner.try_transaction(scope, timeout, callback) {
+            Err(Error::Abort) => Ok(true),
+            res => res,
+        }
+    }
+
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        self.inner.read_only_transaction(scope, callback)
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        self.inner.watch_scope(scope)
     }
 }
 
@@ -138,48 +1176,174 @@ impl ReadStore for KeyValueStore {
         self.inner.is_empty()
     }
 
+    fn total_keys(&self) -> Result<usize> {
+        self.inner.total_keys()
+    }
+
     fn has(&self, key: &Key) -> Result<bool> {
         self.inner.has(key)
     }
 
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        self.inner.has_each(keys)
+    }
+
     fn has_scope(&self, scope: &Scope) -> Result<bool> {
         self.inner.has_scope(scope)
     }
 
+    fn has_value(&self, key: &Key, value: &Value) -> Result<bool> {
+        self.inner.has_value(key, value)
+    }
+
     fn get(&self, key: &Key) -> Result<Option<Value>> {
         self.inner.get(key)
     }
 
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.inner.get_raw(key)
+    }
+
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.inner.get_bytes(key)
+    }
+
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn get_or(&self, key: &Key, default: Value) -> Result<Value> {
+        self.inner.get_or(key, default)
+    }
+
     fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
         self.inner.list_keys(scope)
     }
 
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        self.inner.list_entries(scope)
+    }
+
+    fn snapshot(&self) -> Result<HashMap<Key, Value>> {
+        self.inner.snapshot()
+    }
+
+    fn list_keys_matching_ci(&self, scope: &Scope, name: &Segment) -> Result<Vec<Key>> {
+        self.inner.list_keys_matching_ci(scope, name)
+    }
+
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        self.inner.stream_keys(scope)
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        self.inner.count_keys(scope)
+    }
+
     fn list_scopes(&self) -> Result<Vec<Scope>> {
         self.inner.list_scopes()
     }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.inner.list_scopes_under(parent)
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.inner.list_child_scopes(parent)
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        self.inner.changes_since(since)
+    }
+
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &Value,
+    ) -> Result<Vec<Key>> {
+        self.inner.find_keys_where_value(scope, json_path, equals)
+    }
 }
 
 impl WriteStore for KeyValueStore {
     fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.check_value(&value)?;
         self.inner.store(key, value)
     }
 
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        for (_, value) in entries {
+            self.check_value(value)?;
+        }
+        self.inner.store_many(entries)
+    }
+
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        self.inner.store_bytes(key, bytes)
+    }
+
+    fn restore(&self, entries: HashMap<Key, Value>) -> Result<()> {
+        for value in entries.values() {
+            self.check_value(value)?;
+        }
+        self.inner.restore(entries)
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.check_value(&value)?;
+        self.inner.store_with_expiry(key, value, expires_at)
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        self.inner.purge_expired()
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        self.inner.prune_changes(before)
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.check_value(&new)?;
+        self.inner.compare_and_swap(key, expected, new)
+    }
+
     fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
         self.inner.move_value(from, to)
     }
 
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.inner.copy_value(from, to)
+    }
+
     fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
         self.inner.move_scope(from, to)
     }
 
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.inner.copy_scope(from, to)
+    }
+
     fn delete(&self, key: &Key) -> Result<()> {
         self.inner.delete(key)
     }
 
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.inner.delete_existing(key)
+    }
+
     fn delete_scope(&self, scope: &Scope) -> Result<()> {
         self.inner.delete_scope(scope)
     }
 
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.inner.delete_scope_exact(scope)
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.inner.clear_scope(scope)
+    }
+
     fn clear(&self) -> Result<()> {
         self.inner.clear()
     }
@@ -0,0 +1,514 @@
+//! Metrics for the operations performed on a store.
+
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    ops::{Deref, DerefMut},
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use kvx_types::NamespaceBuf;
+use serde_json::Value;
+
+use crate::{
+    BackendKind, ChangeEvent, ChangeKind, Key, KeyValueStoreBackend, ReadStore,
+    ReadTransactionCallback, Result, Scope, Segment, TransactionCallback, WriteStore,
+};
+
+/// The kind of operation reported to [`StoreMetrics`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum OpKind {
+    /// `has`, `has_each`, `has_scope` and `has_value`.
+    Has,
+
+    /// `is_empty`, `total_keys` and `count_keys`.
+    Count,
+
+    /// `get` and its variants, such as `get_many` and `get_bytes`.
+    Get,
+
+    /// Listing keys, entries or scopes, including `snapshot`,
+    /// `changes_since` and `find_keys_where_value`.
+    List,
+
+    /// `store` and its variants, such as `store_many`, `restore` and
+    /// `compare_and_swap`.
+    Store,
+
+    /// `move_value` and `move_scope`.
+    Move,
+
+    /// `copy_value` and `copy_scope`.
+    Copy,
+
+    /// `delete` and its variants, such as `delete_scope`, `clear` and
+    /// `purge_expired`.
+    Delete,
+
+    /// `migrate_namespace`.
+    Migrate,
+
+    /// A (read-only) transaction, including waiting for locks and retries.
+    Transaction,
+
+    /// The time a transaction waited before its callback was called for the
+    /// first time, i.e. mostly waiting for a lock on the scope.
+    LockWait,
+
+    /// A transaction callback was called again, e.g. after a conflict with
+    /// another transaction. The duration is that of the failed attempt.
+    TransactionRetry,
+}
+
+/// Receives the operations performed on a store, see
+/// [`KeyValueStore::with_metrics`].
+///
+/// Operations are reported after they completed, whether they succeeded or
+/// not.
+///
+/// [`KeyValueStore::with_metrics`]: crate::KeyValueStore::with_metrics
+pub trait StoreMetrics: Debug + Send + Sync {
+    /// An operation of the given kind took `duration`.
+    fn on_op(&self, op: OpKind, duration: Duration);
+}
+
+/// The number and total duration of the operations of a kind.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OpStats {
+    pub count: u64,
+    pub total_duration: Duration,
+}
+
+/// Tallies the operations of each kind in memory.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+///
+/// use kvx::{
+///     metrics::{CountingMetrics, OpKind},
+///     Key, KeyValueStore, Namespace, ReadStore, WriteStore,
+/// };
+/// use serde_json::Value;
+/// use url::Url;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let metrics = Arc::new(CountingMetrics::default());
+/// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("metrics")?)?
+///     .with_metrics(metrics.clone());
+/// let key: Key = "key".parse()?;
+///
+/// store.store(&key, Value::from(1))?;
+/// store.get(&key)?;
+/// store.get(&key)?;
+///
+/// assert_eq!(metrics.snapshot()[&OpKind::Get].count, 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct CountingMetrics {
+    stats: Mutex<HashMap<OpKind, OpStats>>,
+}
+
+impl CountingMetrics {
+    /// Returns the stats of the operations reported so far, by kind.
+    pub fn snapshot(&self) -> HashMap<OpKind, OpStats> {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Resets all stats.
+    pub fn reset(&self) {
+        self.stats.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+impl StoreMetrics for CountingMetrics {
+    fn on_op(&self, op: OpKind, duration: Duration) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = stats.entry(op).or_default();
+        stats.count += 1;
+        stats.total_duration += duration;
+    }
+}
+
+/// Wraps a store and reports all operations performed on it to a
+/// [`StoreMetrics`].
+///
+/// Operations within a transaction callback are not reported individually,
+/// they are part of the [`OpKind::Transaction`].
+#[derive(Debug)]
+pub struct MeteredStore<S> {
+    inner: S,
+    metrics: Arc<dyn StoreMetrics>,
+}
+
+impl<S> MeteredStore<S> {
+    /// Create a metered store that delegates to `inner`.
+    pub fn new(inner: S, metrics: Arc<dyn StoreMetrics>) -> Self {
+        MeteredStore { inner, metrics }
+    }
+
+    /// Runs the operation and reports its duration.
+    fn measure<T>(&self, op: OpKind, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let res = f();
+        self.metrics.on_op(op, started.elapsed());
+        res
+    }
+
+    /// Reports the lock wait before the first attempt of a transaction that
+    /// started at `started`, or a retry for any further attempt.
+    fn attempt(&self, started: Instant, last_attempt: &mut Option<Instant>) {
+        let now = Instant::now();
+        match last_attempt {
+            None => self.metrics.on_op(OpKind::LockWait, now - started),
+            Some(last) => self.metrics.on_op(OpKind::TransactionRetry, now - *last),
+        }
+        *last_attempt = Some(now);
+    }
+}
+
+impl<S> Display for MeteredStore<S>
+where
+    S: Deref,
+    S::Target: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Metered({})", self.inner.deref())
+    }
+}
+
+impl<S> ReadStore for MeteredStore<S>
+where
+    S: Deref,
+    S::Target: ReadStore,
+{
+    fn is_empty(&self) -> Result<bool> {
+        self.measure(OpKind::Count, || self.inner.is_empty())
+    }
+
+    fn total_keys(&self) -> Result<usize> {
+        self.measure(OpKind::Count, || self.inner.total_keys())
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        self.measure(OpKind::Has, || self.inner.has(key))
+    }
+
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        self.measure(OpKind::Has, || self.inner.has_each(keys))
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        self.measure(OpKind::Has, || self.inner.has_scope(scope))
+    }
+
+    fn has_value(&self, key: &Key, value: &Value) -> Result<bool> {
+        self.measure(OpKind::Has, || self.inner.has_value(key, value))
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        self.measure(OpKind::Get, || self.inner.get(key))
+    }
+
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.measure(OpKind::Get, || self.inner.get_raw(key))
+    }
+
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.measure(OpKind::Get, || self.inner.get_bytes(key))
+    }
+
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        self.measure(OpKind::Get, || self.inner.get_many(keys))
+    }
+
+    fn get_or(&self, key: &Key, default: Value) -> Result<Value> {
+        self.measure(OpKind::Get, || self.inner.get_or(key, default))
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        self.measure(OpKind::List, || self.inner.list_keys(scope))
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        self.measure(OpKind::List, || self.inner.list_entries(scope))
+    }
+
+    fn snapshot(&self) -> Result<HashMap<Key, Value>> {
+        self.measure(OpKind::List, || self.inner.snapshot())
+    }
+
+    fn list_keys_matching_ci(&self, scope: &Scope, name: &Segment) -> Result<Vec<Key>> {
+        self.measure(OpKind::List, || {
+            self.inner.list_keys_matching_ci(scope, name)
+        })
+    }
+
+    /// Only creating the iterator is measured, not iterating over it.
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        self.measure(OpKind::List, || self.inner.stream_keys(scope))
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        self.measure(OpKind::Count, || self.inner.count_keys(scope))
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        self.measure(OpKind::List, || self.inner.list_scopes())
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.measure(OpKind::List, || self.inner.list_scopes_under(parent))
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.measure(OpKind::List, || self.inner.list_child_scopes(parent))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        self.measure(OpKind::List, || self.inner.changes_since(since))
+    }
+
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &Value,
+    ) -> Result<Vec<Key>> {
+        self.measure(OpKind::List, || {
+            self.inner.find_keys_where_value(scope, json_path, equals)
+        })
+    }
+}
+
+impl<S> WriteStore for MeteredStore<S>
+where
+    S: DerefMut,
+    S::Target: WriteStore,
+{
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.measure(OpKind::Store, || self.inner.store(key, value))
+    }
+
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        self.measure(OpKind::Store, || self.inner.store_many(entries))
+    }
+
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        self.measure(OpKind::Store, || self.inner.store_bytes(key, bytes))
+    }
+
+    fn restore(&self, entries: HashMap<Key, Value>) -> Result<()> {
+        self.measure(OpKind::Store, || self.inner.restore(entries))
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.measure(OpKind::Store, || {
+            self.inner.store_with_expiry(key, value, expires_at)
+        })
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        self.measure(OpKind::Delete, || self.inner.purge_expired())
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        self.measure(OpKind::Delete, || self.inner.prune_changes(before))
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.measure(OpKind::Store, || {
+            self.inner.compare_and_swap(key, expected, new)
+        })
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.measure(OpKind::Move, || self.inner.move_value(from, to))
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.measure(OpKind::Copy, || self.inner.copy_value(from, to))
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.measure(OpKind::Move, || self.inner.move_scope(from, to))
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.measure(OpKind::Copy, || self.inner.copy_scope(from, to))
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.delete(key))
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.delete_existing(key))
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.delete_scope(scope))
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.delete_scope_exact(scope))
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.clear_scope(scope))
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.measure(OpKind::Delete, || self.inner.clear())
+    }
+
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        let started = Instant::now();
+        let res = self.inner.migrate_namespace(to);
+        self.metrics.on_op(OpKind::Migrate, started.elapsed());
+        res
+    }
+}
+
+impl<S> KeyValueStoreBackend for MeteredStore<S>
+where
+    S: DerefMut,
+    S::Target: KeyValueStoreBackend,
+{
+    fn backend_kind(&self) -> BackendKind {
+        self.inner.backend_kind()
+    }
+
+    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        self.measure(OpKind::Transaction, || {
+            let started = Instant::now();
+            let mut last_attempt = None;
+            self.inner.transaction(scope, &mut |store| {
+                self.attempt(started, &mut last_attempt);
+                callback(store)
+            })
+        })
+    }
+
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        self.measure(OpKind::Transaction, || {
+            let started = Instant::now();
+            let mut last_attempt = None;
+            self.inner.try_transaction(scope, timeout, &mut |store| {
+                self.attempt(started, &mut last_attempt);
+                callback(store)
+            })
+        })
+    }
+
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        self.measure(OpKind::Transaction, || {
+            let started = Instant::now();
+            let mut last_attempt = None;
+            self.inner.read_only_transaction(scope, &mut |store| {
+                self.attempt(started, &mut last_attempt);
+                callback(store)
+            })
+        })
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        self.inner.watch_scope(scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use serde_json::Value;
+    use url::Url;
+
+    use super::{CountingMetrics, MeteredStore, OpKind};
+    use crate::{
+        Error, Key, KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore, RetryPolicy,
+        RetryingStore, WriteStore,
+    };
+
+    #[test]
+    fn test_metered_store() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let store = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_metered_store").unwrap(),
+        )
+        .unwrap()
+        .with_metrics(metrics.clone());
+        let key: Key = "scope/key".parse().unwrap();
+
+        store.store(&key, Value::from(1)).unwrap();
+        store.get(&key).unwrap();
+        store.has(&key).unwrap();
+        store.list_keys(key.scope()).unwrap();
+        store
+            .transaction(key.scope(), &mut |t| {
+                t.get(&key)?;
+                t.delete(&key)
+            })
+            .unwrap();
+
+        let snapshot = metrics.snapshot();
+        for op in [
+            OpKind::Store,
+            OpKind::Get,
+            OpKind::Has,
+            OpKind::List,
+            OpKind::Transaction,
+            OpKind::LockWait,
+        ] {
+            assert_eq!(snapshot[&op].count, 1, "{:?}", op);
+        }
+        assert!(!snapshot.contains_key(&OpKind::Delete));
+        assert!(!snapshot.contains_key(&OpKind::TransactionRetry));
+
+        metrics.reset();
+        assert!(metrics.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_metered_retries() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let mut store = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_metered_retries").unwrap(),
+        )
+        .unwrap();
+        let mut retrying =
+            RetryingStore::new(&mut store, RetryPolicy::new(3, Duration::from_millis(1)));
+        let metered = MeteredStore::new(&mut retrying, metrics.clone());
+        let key: Key = "scope/key".parse().unwrap();
+
+        let mut calls = 0;
+        metered
+            .transaction(key.scope(), &mut |_| {
+                calls += 1;
+                if calls < 3 {
+                    return Err(Error::TransactionConflict);
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot[&OpKind::Transaction].count, 1);
+        assert_eq!(snapshot[&OpKind::LockWait].count, 1);
+        assert_eq!(snapshot[&OpKind::TransactionRetry].count, 2);
+    }
+}
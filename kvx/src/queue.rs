@@ -1,16 +1,230 @@
 use std::{
     borrow::Cow,
-    fmt::{Display, Formatter},
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display, Formatter},
     str::FromStr,
+    sync::Arc,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use rand::Rng;
+use serde_json::Value;
+
 use crate::{
     segment, Error, Key, KeyValueStore, KeyValueStoreBackend, Result, Scope, Segment, SegmentBuf,
 };
 
 const SEPARATOR: char = '-';
 
+/// The priority of tasks scheduled without an explicit priority.
+///
+/// Tasks with a lower priority value are claimed first, so this leaves room
+/// for both more and less urgent tasks.
+pub const DEFAULT_PRIORITY: u8 = 128;
+
+/// The fields used in stored task values that keep track of attempts, and
+/// of the worker that claimed a running task.
+const ATTEMPTS_FIELD: &str = "kvx_attempts";
+const VALUE_FIELD: &str = "kvx_value";
+const WORKER_FIELD: &str = "kvx_worker";
+
+/// Configures how queue operations on a [`KeyValueStore`] are performed.
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    /// The number of shards the queue is partitioned in.
+    ///
+    /// Tasks are assigned to a shard by hashing their name, and every shard
+    /// has its own lock scope (`lock/shard-N`). This way operations on tasks
+    /// in different shards do not have to wait for each other, while all
+    /// operations for a given task name are still serialized. With a single
+    /// shard all operations use [`Queue::lock_scope`].
+    ///
+    /// Note that with multiple shards [`Queue::claim_scheduled_pending_task`]
+    /// claims the soonest scheduled task of the first shard that has a task
+    /// due, which is not necessarily the soonest scheduled task overall.
+    ///
+    /// All users of a queue must use the same number of shards.
+    pub shards: usize,
+
+    /// The scope under which the queue stores its tasks, e.g. `_queue`, so
+    /// that the `pending`, `running` and `dead-letter` scopes of the queue
+    /// do not collide with application keys in the same namespace.
+    ///
+    /// This is also the [`Queue::lock_scope`]. The default is the global
+    /// scope, as used by earlier versions. All users of a queue must use
+    /// the same base scope.
+    pub base_scope: Scope,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            shards: 1,
+            base_scope: Scope::global(),
+        }
+    }
+}
+
+/// Receives the state transitions of tasks in a queue, see
+/// [`KeyValueStore::with_observer`].
+///
+/// The methods are called after the transaction that made the change was
+/// committed, with the name of the task and a timestamp in milliseconds.
+pub trait QueueObserver: Debug + Send + Sync {
+    /// A task was scheduled to run at the given time.
+    fn on_scheduled(&self, name: &Segment, timestamp_millis: u128);
+
+    /// A task was claimed at the given time.
+    fn on_claimed(&self, name: &Segment, timestamp_millis: u128);
+
+    /// A task that was claimed at the given time was finished.
+    fn on_finished(&self, name: &Segment, timestamp_millis: u128);
+
+    /// A running or dead-letter task was rescheduled to run at the given
+    /// time.
+    fn on_rescheduled(&self, name: &Segment, timestamp_millis: u128);
+}
+
+impl KeyValueStore {
+    /// Use the given configuration for queue operations on this store.
+    pub fn with_queue_config(mut self, config: QueueConfig) -> Self {
+        self.queue_config = config;
+        self
+    }
+
+    /// Notify the given observer of the tasks scheduled, claimed, finished
+    /// and rescheduled using this store.
+    ///
+    /// Only changes made through this store are observed, not those made
+    /// by other stores or processes using the same queue.
+    pub fn with_observer(mut self, observer: Arc<dyn QueueObserver>) -> Self {
+        self.queue_observer = Some(observer);
+        self
+    }
+
+    /// Calls the function with the observer, if any.
+    fn observe(&self, f: impl FnOnce(&dyn QueueObserver)) {
+        if let Some(observer) = &self.queue_observer {
+            f(observer.as_ref())
+        }
+    }
+
+    /// Notifies the observer of the changes made by scheduling a task.
+    fn observe_scheduled(&self, task: &PendingTask, scheduled: &Scheduled) {
+        self.observe(|o| {
+            if let Some(claimed) = scheduled.finished {
+                o.on_finished(&task.name, claimed);
+            }
+            if scheduled.stored {
+                o.on_scheduled(&task.name, task.timestamp_millis);
+            }
+        })
+    }
+
+    /// Returns the shard that tasks with the given name are assigned to.
+    fn queue_shard(&self, name: &Segment) -> usize {
+        // FNV-1a, because the assignment must be stable across processes
+        // and compiler versions.
+        let hash = name
+            .as_str()
+            .bytes()
+            .fold(0xcbf29ce484222325_u64, |hash, b| {
+                (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+            });
+
+        (hash % self.queue_shards() as u64) as usize
+    }
+
+    fn queue_shards(&self) -> usize {
+        self.queue_config.shards.max(1)
+    }
+
+    /// Returns the lock scope for the given shard.
+    fn shard_lock_scope(&self, shard: usize) -> Scope {
+        if self.queue_shards() == 1 {
+            self.lock_scope()
+        } else {
+            self.lock_scope()
+                .with_sub_scope(segment!("lock"))
+                .with_sub_scope(Segment::parse(&format!("shard-{shard}")).unwrap())
+        }
+    }
+
+    /// Returns the lock scope for tasks with the given name.
+    fn task_lock_scope(&self, name: &Segment) -> Scope {
+        self.shard_lock_scope(self.queue_shard(name))
+    }
+
+    /// Returns the lock scope for the task with the given key, or the global
+    /// lock scope if the key is not a task key.
+    fn task_key_lock_scope(&self, key: &Key) -> Scope {
+        TaskKey::try_from(key)
+            .map(|tk| self.task_lock_scope(&tk.name))
+            .unwrap_or_else(|_| self.lock_scope())
+    }
+
+    /// Claims the next scheduled pending task in the given shard, if any.
+    fn claim_scheduled_pending_task_in_shard(
+        &self,
+        shard: usize,
+        worker_id: Option<&SegmentBuf>,
+    ) -> Result<Option<RunningTask>> {
+        self.execute(&self.shard_lock_scope(shard), |kv| {
+            let tasks_before = now();
+
+            let entries = kv.list_entries(&self.pending_scope())?;
+
+            if let Some((pending, pending_key, stored)) = entries
+                .iter()
+                .filter_map(|(k, v)| Some((TaskKey::try_from(k).ok()?, k, v)))
+                .filter(|(tk, _, _)| tk.timestamp_millis <= tasks_before)
+                .filter(|(tk, _, _)| self.queue_shard(&tk.name) == shard)
+                .min_by_key(|(tk, _, _)| (tk.priority, tk.timestamp_millis))
+            {
+                let (value, attempts) = split_task_value(stored.clone());
+
+                let mut running_task = RunningTask {
+                    name: pending.name.into_owned(),
+                    timestamp_millis: tasks_before,
+                    value,
+                    scheduled_at: pending.timestamp_millis,
+                    attempts,
+                    priority: pending.priority,
+                    worker_id: worker_id.cloned(),
+                    base_scope: self.base_scope(),
+                };
+                let mut running_key = Key::from(&running_task);
+
+                if kv.has(&running_key)? {
+                    // It's not pretty to sleep blocking, even if it's
+                    // for 1 ms, but if we don't then get a name collision
+                    // with an existing running task.
+                    std::thread::sleep(Duration::from_millis(1));
+                    running_task.timestamp_millis = now();
+                    running_key = Key::from(&running_task);
+                }
+
+                match worker_id {
+                    Some(worker_id) => {
+                        let value = running_task_value(
+                            running_task.value.clone(),
+                            attempts,
+                            Some(worker_id),
+                        );
+                        kv.store(&running_key, value)?;
+                        kv.delete(pending_key)?;
+                    }
+                    None => kv.move_value(pending_key, &running_key)?,
+                }
+
+                Ok(Some(running_task))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+}
+
 fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -18,30 +232,189 @@ fn now() -> u128 {
         .as_millis()
 }
 
-struct TaskKey<'a> {
-    pub name: Cow<'a, SegmentBuf>,
-    pub timestamp_millis: u128,
+/// Returns the value to store for a task.
+///
+/// Tasks without failed attempts are stored as their plain value, so they
+/// can still be read by older versions. Otherwise the value is wrapped in an
+/// object that also holds the number of attempts.
+fn task_value(value: Value, attempts: u32) -> Value {
+    running_task_value(value, attempts, None)
 }
 
-impl<'a> TaskKey<'a> {
-    fn key(&self) -> Key {
-        Key::from_str(&format!(
-            "{}{}{}",
-            self.timestamp_millis, SEPARATOR, self.name
-        ))
-        .unwrap()
+/// Returns the value to store for a running task, which also holds the id
+/// of the worker that claimed it, if any.
+fn running_task_value(value: Value, attempts: u32, worker_id: Option<&SegmentBuf>) -> Value {
+    match worker_id {
+        None if attempts == 0 => value,
+        None => serde_json::json!({ ATTEMPTS_FIELD: attempts, VALUE_FIELD: value }),
+        Some(worker_id) => serde_json::json!({
+            ATTEMPTS_FIELD: attempts,
+            VALUE_FIELD: value,
+            WORKER_FIELD: worker_id.as_str(),
+        }),
+    }
+}
+
+/// Returns the task value and the number of failed attempts for a stored
+/// task value.
+fn split_task_value(stored: Value) -> (Value, u32) {
+    let (value, attempts, _) = split_running_task_value(stored);
+    (value, attempts)
+}
+
+/// Returns the task value, the number of failed attempts and the id of the
+/// worker that claimed the task, if any, for a stored task value.
+fn split_running_task_value(stored: Value) -> (Value, u32, Option<SegmentBuf>) {
+    if let Value::Object(map) = &stored {
+        let worker_id = map
+            .get(WORKER_FIELD)
+            .and_then(Value::as_str)
+            .and_then(|w| SegmentBuf::from_str(w).ok());
+        let fields = if worker_id.is_some() { 3 } else { 2 };
+
+        if map.len() == fields {
+            if let (Some(attempts), Some(value)) = (
+                map.get(ATTEMPTS_FIELD)
+                    .and_then(Value::as_u64)
+                    .and_then(|a| u32::try_from(a).ok()),
+                map.get(VALUE_FIELD),
+            ) {
+                return (value.clone(), attempts, worker_id);
+            }
+        }
+    }
+
+    (stored, 0, None)
+}
+
+/// Returns the keys of the tasks in the given scope by task name.
+fn task_keys_by_name(
+    s: &dyn KeyValueStoreBackend,
+    scope: &Scope,
+) -> Result<HashMap<SegmentBuf, Key>> {
+    let mut keys = HashMap::new();
+    for key in s.list_keys(scope)? {
+        if let Ok(tk) = TaskKey::try_from(&key) {
+            keys.entry(tk.name.into_owned()).or_insert(key);
+        }
     }
+    Ok(keys)
+}
+
+/// The changes made by scheduling a task.
+#[derive(Default)]
+struct Scheduled {
+    /// Whether the new task was stored.
+    stored: bool,
+
+    /// The time the running task that was finished was claimed, if any.
+    finished: Option<u128>,
+}
+
+/// Schedules a task within a transaction on its lock scope, given the keys
+/// of the running and pending tasks by name. These are updated to reflect
+/// the changes made, so that several tasks can be scheduled in the same
+/// transaction.
+fn schedule_in_transaction(
+    s: &dyn KeyValueStoreBackend,
+    running: &mut HashMap<SegmentBuf, Key>,
+    pending: &mut HashMap<SegmentBuf, Key>,
+    new_task: &mut PendingTask,
+    mode: ScheduleMode,
+) -> Result<Scheduled> {
+    let running_key_opt = running.get(&new_task.name).cloned();
+    let pending_key_opt = pending.get(&new_task.name).cloned();
+
+    let store = |task: &PendingTask| -> Result<Option<Key>> {
+        let key = Key::from(task);
+        s.store(&key, task.value.clone())?;
+        Ok(Some(key))
+    };
+
+    let stored = match mode {
+        ScheduleMode::IfMissing => {
+            if pending_key_opt.is_some() || running_key_opt.is_some() {
+                // nothing to do, there is something
+                None
+            } else {
+                // no pending or running task exists, just add the new task
+                store(new_task)?
+            }
+        }
+        ScheduleMode::ReplaceExisting => {
+            if let Some(pending) = pending_key_opt {
+                s.delete(&pending)?;
+            }
+            store(new_task)?
+        }
+        ScheduleMode::ReplaceExistingSoonest => {
+            if let Some(pending) = pending_key_opt {
+                if let Ok(tk) = TaskKey::try_from(&pending) {
+                    new_task.timestamp_millis = new_task.timestamp_millis.min(tk.timestamp_millis);
+                }
+                s.delete(&pending)?;
+            }
+
+            store(new_task)?
+        }
+        ScheduleMode::FinishOrReplaceExisting => {
+            if let Some(running) = running_key_opt {
+                s.delete(&running)?;
+            }
+            if let Some(pending) = pending_key_opt {
+                s.delete(&pending)?;
+            }
+            store(new_task)?
+        }
+        ScheduleMode::FinishOrReplaceExistingSoonest => {
+            if let Some(running) = running_key_opt {
+                s.delete(&running)?;
+            }
+
+            if let Some(pending) = pending_key_opt {
+                if let Ok(tk) = TaskKey::try_from(&pending) {
+                    new_task.timestamp_millis = new_task.timestamp_millis.min(tk.timestamp_millis);
+                }
+                s.delete(&pending)?;
+            }
+
+            store(new_task)?
+        }
+    };
+
+    let mut scheduled = Scheduled::default();
 
-    fn running_key(&self) -> Key {
-        let mut key = self.key();
-        key.add_super_scope(RunningTask::SEGMENT);
-        key
+    if let ScheduleMode::FinishOrReplaceExisting | ScheduleMode::FinishOrReplaceExistingSoonest =
+        mode
+    {
+        scheduled.finished = running
+            .remove(&new_task.name)
+            .and_then(|k| TaskKey::try_from(&k).ok())
+            .map(|tk| tk.timestamp_millis);
     }
+    if let Some(key) = stored {
+        pending.insert(new_task.name.clone(), key);
+        scheduled.stored = true;
+    }
+
+    Ok(scheduled)
+}
+
+/// The name of a task key: `priority-timestamp-name`, where the priority is
+/// zero-padded to three digits so that keys sort by priority first.
+///
+/// Keys without a priority, `timestamp-name`, as used by earlier versions,
+/// have the [`DEFAULT_PRIORITY`].
+struct TaskKey<'a> {
+    pub name: Cow<'a, SegmentBuf>,
+    pub timestamp_millis: u128,
+    pub priority: u8,
+}
 
-    fn pending_key(&self) -> Key {
-        let mut key = self.key();
-        key.add_super_scope(PendingTask::SEGMENT);
-        key
+impl<'a> TaskKey<'a> {
+    /// Returns the key for the task in the given scope.
+    fn key_in(&self, scope: Scope) -> Key {
+        task_key(scope, self)
     }
 }
 
@@ -49,31 +422,63 @@ impl TryFrom<&Key> for TaskKey<'_> {
     type Error = Error;
 
     fn try_from(key: &Key) -> Result<Self, Self::Error> {
-        let (ts, name) = key
+        let (first, rest) = key
             .name()
             .as_str()
             .split_once(SEPARATOR)
             .ok_or(Error::InvalidKey)?;
+
+        if first.len() == 3 {
+            if let Some((ts, name)) = rest.split_once(SEPARATOR) {
+                if let (Ok(priority), Ok(timestamp_millis)) = (first.parse(), ts.parse()) {
+                    return Ok(TaskKey {
+                        name: Cow::Owned(Segment::parse(name)?.into()),
+                        timestamp_millis,
+                        priority,
+                    });
+                }
+            }
+        }
+
         Ok(TaskKey {
-            name: Cow::Owned(Segment::parse(name)?.into()),
-            timestamp_millis: ts.parse().map_err(|_| Error::InvalidKey)?,
+            name: Cow::Owned(Segment::parse(rest)?.into()),
+            timestamp_millis: first.parse().map_err(|_| Error::InvalidKey)?,
+            priority: DEFAULT_PRIORITY,
         })
     }
 }
 
+impl Display for TaskKey<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:03}{}{}{}{}",
+            self.priority, SEPARATOR, self.timestamp_millis, SEPARATOR, self.name,
+        )
+    }
+}
+
+/// Returns the key in the given scope for a task, displayed as its
+/// [`TaskKey`].
+fn task_key(scope: Scope, task: &impl Display) -> Key {
+    Key::new_scoped(scope, SegmentBuf::from_str(&task.to_string()).unwrap())
+}
+
 impl From<&PendingTask> for Key {
     fn from(p: &PendingTask) -> Self {
-        let mut key = Key::from_str(&p.to_string()).unwrap();
-        key.add_super_scope(PendingTask::SEGMENT);
-        key
+        task_key(p.base_scope.with_sub_scope(PendingTask::SEGMENT), p)
     }
 }
 
 impl From<&RunningTask> for Key {
     fn from(p: &RunningTask) -> Self {
-        let mut key = Key::from_str(&p.to_string()).unwrap();
-        key.add_super_scope(RunningTask::SEGMENT);
-        key
+        task_key(p.base_scope.with_sub_scope(RunningTask::SEGMENT), p)
+    }
+}
+
+impl From<&DeadLetterTask> for Key {
+    fn from(p: &DeadLetterTask) -> Self {
+        task_key(p.base_scope.with_sub_scope(DeadLetterTask::SEGMENT), p)
     }
 }
 
@@ -82,6 +487,16 @@ pub struct PendingTask {
     pub name: SegmentBuf,
     pub timestamp_millis: u128,
     pub value: serde_json::Value,
+
+    /// The number of times the task failed or timed out before.
+    pub attempts: u32,
+
+    /// Due tasks with a lower priority value are claimed first.
+    pub priority: u8,
+
+    /// The base scope of the queue the task is in, see
+    /// [`QueueConfig::base_scope`].
+    pub base_scope: Scope,
 }
 
 impl PendingTask {
@@ -96,21 +511,40 @@ impl PartialEq for PendingTask {
 
 impl Display for PendingTask {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{}",
-            self.timestamp_millis,
-            SEPARATOR.encode_utf8(&mut [0; 4]),
-            self.name,
-        )
+        TaskKey {
+            name: Cow::Borrowed(&self.name),
+            timestamp_millis: self.timestamp_millis,
+            priority: self.priority,
+        }
+        .fmt(f)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct RunningTask {
     pub name: SegmentBuf,
+
+    /// The time the task was claimed, which is used in the task key.
     pub timestamp_millis: u128,
     pub value: serde_json::Value,
+
+    /// The time the task was scheduled to run, so that the time a task
+    /// waited in the queue is `timestamp_millis - scheduled_at`.
+    pub scheduled_at: u128,
+
+    /// The number of times the task failed or timed out before.
+    pub attempts: u32,
+
+    /// Due tasks with a lower priority value are claimed first.
+    pub priority: u8,
+
+    /// The id of the worker that claimed the task, see
+    /// [`Queue::claim_scheduled_pending_task_for_worker`].
+    pub worker_id: Option<SegmentBuf>,
+
+    /// The base scope of the queue the task is in, see
+    /// [`QueueConfig::base_scope`].
+    pub base_scope: Scope,
 }
 
 impl RunningTask {
@@ -119,16 +553,64 @@ impl RunningTask {
 
 impl Display for RunningTask {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}{}{}",
-            self.timestamp_millis,
-            SEPARATOR.encode_utf8(&mut [0; 4]),
-            self.name,
-        )
+        TaskKey {
+            name: Cow::Borrowed(&self.name),
+            timestamp_millis: self.timestamp_millis,
+            priority: self.priority,
+        }
+        .fmt(f)
+    }
+}
+
+/// A task that was moved aside because it ran out of attempts.
+#[derive(Clone, Debug)]
+pub struct DeadLetterTask {
+    pub name: SegmentBuf,
+
+    /// The time the task was moved to the dead-letter scope.
+    pub timestamp_millis: u128,
+    pub value: serde_json::Value,
+
+    /// The number of times the task failed or timed out.
+    pub attempts: u32,
+
+    /// The priority the task had when it was scheduled.
+    pub priority: u8,
+
+    /// The base scope of the queue the task is in, see
+    /// [`QueueConfig::base_scope`].
+    pub base_scope: Scope,
+}
+
+impl DeadLetterTask {
+    const SEGMENT: &'static Segment = segment!("dead-letter");
+}
+
+impl Display for DeadLetterTask {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        TaskKey {
+            name: Cow::Borrowed(&self.name),
+            timestamp_millis: self.timestamp_millis,
+            priority: self.priority,
+        }
+        .fmt(f)
     }
 }
 
+/// The state of a queue at one moment, see [`Queue::queue_stats`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QueueStats {
+    /// The number of pending tasks.
+    pub pending: usize,
+
+    /// The number of running tasks.
+    pub running: usize,
+
+    /// The time in milliseconds the soonest scheduled pending task was
+    /// scheduled to run at, if any.
+    pub oldest_pending_millis: Option<u128>,
+}
+
 /// Defines scheduling behaviour in case a task by the same name already exists.
 #[derive(Clone, Copy, Debug)]
 pub enum ScheduleMode {
@@ -159,19 +641,42 @@ pub enum ScheduleMode {
     IfMissing,
 }
 
+/// A queue of named tasks that are scheduled, claimed and finished.
+///
+/// Every operation runs in a transaction that locks a scope. By default all
+/// operations lock [`Queue::lock_scope`], so the queue is serialized across
+/// all task names. With [`QueueConfig::shards`] set to more than one, tasks
+/// are assigned to a shard by the hash of their name, and operations on a
+/// single task only lock the scope of its shard, so tasks in different
+/// shards can be scheduled and claimed concurrently.
+///
+/// The trade-off is in claiming: [`Queue::claim_scheduled_pending_task`]
+/// then only looks at one shard at a time, so it claims the most urgent due
+/// task of a shard rather than of the whole queue. Operations that read the
+/// whole queue, such as [`Queue::queue_stats`], still lock the global lock
+/// scope.
 pub trait Queue {
     const RESCHEDULE_AFTER: Duration = Duration::from_secs(15 * 60);
 
-    fn lock_scope() -> Scope {
-        Scope::global()
+    /// Returns the scope under which the queue stores its tasks, see
+    /// [`QueueConfig::base_scope`].
+    fn base_scope(&self) -> Scope;
+
+    /// Returns the scope that is locked by operations on all tasks.
+    fn lock_scope(&self) -> Scope {
+        self.base_scope()
     }
 
-    fn pending_scope() -> Scope {
-        Scope::from_segment(PendingTask::SEGMENT)
+    fn pending_scope(&self) -> Scope {
+        self.base_scope().with_sub_scope(PendingTask::SEGMENT)
     }
 
-    fn running_scope() -> Scope {
-        Scope::from_segment(RunningTask::SEGMENT)
+    fn running_scope(&self) -> Scope {
+        self.base_scope().with_sub_scope(RunningTask::SEGMENT)
+    }
+
+    fn dead_letter_scope(&self) -> Scope {
+        self.base_scope().with_sub_scope(DeadLetterTask::SEGMENT)
     }
 
     /// Returns the number of pending tasks remaining
@@ -180,16 +685,76 @@ pub trait Queue {
     /// Returns the number of running tasks
     fn running_tasks_remaining(&self) -> Result<usize>;
 
+    /// Returns the number of pending and running tasks and the time the
+    /// oldest pending task was scheduled at, read in a single transaction.
+    fn queue_stats(&self) -> Result<QueueStats>;
+
+    /// Returns how long the oldest pending task is overdue, i.e. the time
+    /// since it was scheduled to run, or `None` if there are no pending
+    /// tasks. This is zero if no pending task is due yet.
+    fn oldest_pending_task_age(&self) -> Result<Option<Duration>> {
+        Ok(self.queue_stats()?.oldest_pending_millis.map(|oldest| {
+            let age = now().saturating_sub(oldest);
+            Duration::from_millis(u64::try_from(age).unwrap_or(u64::MAX))
+        }))
+    }
+
     /// Returns the currently running tasks
     fn running_tasks_keys(&self) -> Result<Vec<Key>>;
 
-    /// Schedule a task.
+    /// Returns the running tasks that were claimed by the worker with the
+    /// given id, e.g. to reschedule them when that worker died.
+    fn running_tasks_for_worker(&self, worker_id: &Segment) -> Result<Vec<Key>>;
+
+    /// Returns the tasks that were moved to the dead-letter scope because
+    /// they ran out of attempts.
+    fn dead_letter_tasks_keys(&self) -> Result<Vec<Key>>;
+
+    /// Returns the tasks in the dead-letter scope.
+    fn list_dead_letter_tasks(&self) -> Result<Vec<DeadLetterTask>>;
+
+    /// Moves a task from the dead-letter scope back to pending, scheduled at
+    /// the given time or now, with its attempts reset. A pending task with
+    /// the same name is replaced. Fails if the task is not in the
+    /// dead-letter scope.
+    fn requeue_dead_letter_task(&self, dead: &Key, timestamp_millis: Option<u128>) -> Result<()>;
+
+    /// Schedule a task with the [`DEFAULT_PRIORITY`].
     fn schedule_task(
         &self,
         name: SegmentBuf,
         value: serde_json::Value,
         timestamp_millis: Option<u128>,
         existing: ScheduleMode,
+    ) -> Result<()> {
+        self.schedule_task_with_priority(name, value, timestamp_millis, DEFAULT_PRIORITY, existing)
+    }
+
+    /// Schedule a task with the given priority.
+    ///
+    /// Of the tasks that are due, the task with the lowest priority value
+    /// is claimed first, and of tasks with the same priority the one that
+    /// was scheduled soonest. Tasks that are not due yet are never claimed,
+    /// whatever their priority.
+    fn schedule_task_with_priority(
+        &self,
+        name: SegmentBuf,
+        value: serde_json::Value,
+        timestamp_millis: Option<u128>,
+        priority: u8,
+        existing: ScheduleMode,
+    ) -> Result<()>;
+
+    /// Schedule several tasks with the [`DEFAULT_PRIORITY`] at once.
+    ///
+    /// The mode applies to every task as if they were scheduled one by one,
+    /// so a later task in the list may replace an earlier one with the same
+    /// name. All tasks are scheduled in a single transaction, or with
+    /// multiple shards one transaction per shard.
+    fn schedule_tasks(
+        &self,
+        tasks: Vec<(SegmentBuf, serde_json::Value, Option<u128>)>,
+        mode: ScheduleMode,
     ) -> Result<()>;
 
     /// Returns the scheduled timestamp in ms for the named task, if any.
@@ -198,126 +763,267 @@ pub trait Queue {
     /// Marks a running task as finished. Fails if the task is not running.
     fn finish_running_task(&self, running: &Key) -> Result<()>;
 
-    /// Reschedules a running task as pending. Fails if the task is not running.
+    /// Marks a running task as finished and schedules a follow-up task, if
+    /// given, with the [`DEFAULT_PRIORITY`] in a single transaction, so that
+    /// there is no moment where neither task exists. Fails if the task is
+    /// not running.
+    ///
+    /// The follow-up task is given as its name, value and the time to run
+    /// it, and replaces a pending task with the same name as with
+    /// [`ScheduleMode::ReplaceExisting`].
+    fn finish_and_schedule(
+        &self,
+        running: &Key,
+        next: Option<(SegmentBuf, serde_json::Value, Option<u128>)>,
+    ) -> Result<()>;
+
+    /// Reschedules a running task as pending, counting this as a failed
+    /// attempt. Fails if the task is not running.
     fn reschedule_running_task(&self, running: &Key, timestamp_millis: Option<u128>) -> Result<()>;
 
     /// Claims the next scheduled pending task, if any.
-    fn claim_scheduled_pending_task(&self) -> Result<Option<RunningTask>>;
+    fn claim_scheduled_pending_task(&self) -> Result<Option<RunningTask>> {
+        self.claim_scheduled_pending_task_for_worker(None)
+    }
+
+    /// Claims the next scheduled pending task, if any, on behalf of the
+    /// worker with the given id.
+    ///
+    /// The id is stored with the running task, so that the tasks of a worker
+    /// can be found using [`Queue::running_tasks_for_worker`]. It is removed
+    /// when the task is rescheduled.
+    fn claim_scheduled_pending_task_for_worker(
+        &self,
+        worker_id: Option<SegmentBuf>,
+    ) -> Result<Option<RunningTask>>;
+
+    /// Returns the pending task that will be claimed next without claiming
+    /// it: the task that would be claimed now if any task is due, and the
+    /// task that is scheduled soonest otherwise.
+    fn peek_next_pending_task(&self) -> Result<Option<PendingTask>>;
+
+    /// Reschedules running tasks that have timed out, counting this as a
+    /// failed attempt.
+    ///
+    /// If `max_attempts` is given, tasks that have now failed that many
+    /// times are moved to the dead-letter scope instead, see
+    /// [`Queue::dead_letter_tasks_keys`].
+    fn reschedule_long_running_tasks(
+        &self,
+        reschedule_after: Option<&Duration>,
+        max_attempts: Option<u32>,
+    ) -> Result<()>;
 
-    /// Reschedules running tasks that have timed out.
-    fn reschedule_long_running_tasks(&self, reschedule_after: Option<&Duration>) -> Result<()>;
+    /// Claims and handles all due pending tasks until none remain, e.g. for a
+    /// graceful shutdown. Returns the number of tasks handled.
+    ///
+    /// Tasks are finished after they are handled successfully. If the
+    /// handler fails, the task is rescheduled as pending and the error is
+    /// returned without claiming further tasks.
+    fn drain_pending(&self, handler: impl FnMut(RunningTask) -> Result<()>) -> Result<usize>;
 }
 
 impl Queue for KeyValueStore {
+    fn base_scope(&self) -> Scope {
+        self.queue_config.base_scope.clone()
+    }
+
     fn pending_tasks_remaining(&self) -> Result<usize> {
-        self.execute(&Self::lock_scope(), |kv| {
-            kv.list_keys(&Self::pending_scope()).map(|list| list.len())
+        self.execute(&self.lock_scope(), |kv| {
+            kv.count_keys(&self.pending_scope())
         })
     }
 
     fn running_tasks_remaining(&self) -> Result<usize> {
-        self.execute(&Self::lock_scope(), |kv| {
-            kv.list_keys(&Self::running_scope()).map(|list| list.len())
+        self.execute(&self.lock_scope(), |kv| {
+            kv.count_keys(&self.running_scope())
+        })
+    }
+
+    fn queue_stats(&self) -> Result<QueueStats> {
+        self.execute(&self.lock_scope(), |kv| {
+            let pending = kv.list_keys(&self.pending_scope())?;
+
+            Ok(QueueStats {
+                pending: pending.len(),
+                running: kv.count_keys(&self.running_scope())?,
+                oldest_pending_millis: pending
+                    .iter()
+                    .filter_map(|k| TaskKey::try_from(k).ok())
+                    .map(|tk| tk.timestamp_millis)
+                    .min(),
+            })
         })
     }
 
     fn running_tasks_keys(&self) -> Result<Vec<Key>> {
-        self.execute(&Self::lock_scope(), |kv| {
-            kv.list_keys(&Self::running_scope())
+        self.execute(&self.lock_scope(), |kv| kv.list_keys(&self.running_scope()))
+    }
+
+    fn running_tasks_for_worker(&self, worker_id: &Segment) -> Result<Vec<Key>> {
+        self.execute(&self.lock_scope(), |kv| {
+            Ok(kv
+                .list_entries(&self.running_scope())?
+                .into_iter()
+                .filter(|(_, stored)| {
+                    let (_, _, worker) = split_running_task_value(stored.clone());
+                    worker.is_some_and(|w| w.as_ref() == worker_id)
+                })
+                .map(|(k, _)| k)
+                .collect())
         })
     }
 
-    fn schedule_task(
+    fn dead_letter_tasks_keys(&self) -> Result<Vec<Key>> {
+        self.execute(&self.lock_scope(), |kv| {
+            kv.list_keys(&self.dead_letter_scope())
+        })
+    }
+
+    fn list_dead_letter_tasks(&self) -> Result<Vec<DeadLetterTask>> {
+        self.execute(&self.lock_scope(), |kv| {
+            Ok(kv
+                .list_entries(&self.dead_letter_scope())?
+                .into_iter()
+                .filter_map(|(k, stored)| {
+                    let tk = TaskKey::try_from(&k).ok()?;
+                    let (value, attempts) = split_task_value(stored);
+                    Some(DeadLetterTask {
+                        name: tk.name.into_owned(),
+                        timestamp_millis: tk.timestamp_millis,
+                        value,
+                        attempts,
+                        priority: tk.priority,
+                        base_scope: self.base_scope(),
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn requeue_dead_letter_task(&self, dead: &Key, timestamp_millis: Option<u128>) -> Result<()> {
+        let mut task_key = TaskKey::try_from(dead)?;
+        task_key.timestamp_millis = timestamp_millis.unwrap_or_else(now);
+        let pending_key = task_key.key_in(self.pending_scope());
+
+        self.execute(&self.task_key_lock_scope(dead), |kv| {
+            let stored = if dead.scope() == &self.dead_letter_scope() {
+                kv.get(dead)?
+            } else {
+                None
+            };
+            let stored = stored.ok_or_else(|| {
+                Error::Other(format!(
+                    "Cannot requeue task {}. It is not a dead-letter task.",
+                    dead
+                ))
+            })?;
+            let (value, _) = split_task_value(stored);
+
+            let existing = kv
+                .list_keys(&self.pending_scope())?
+                .into_iter()
+                .filter(|k| TaskKey::try_from(k).is_ok_and(|p| p.name == task_key.name));
+            for pending in existing {
+                kv.delete(&pending)?;
+            }
+
+            kv.store(&pending_key, value)?;
+            kv.delete(dead)
+        })?;
+
+        self.observe(|o| o.on_rescheduled(&task_key.name, task_key.timestamp_millis));
+        Ok(())
+    }
+
+    fn schedule_task_with_priority(
         &self,
         name: SegmentBuf,
         value: serde_json::Value,
         timestamp_millis: Option<u128>,
+        priority: u8,
         mode: ScheduleMode,
     ) -> Result<()> {
         let mut new_task = PendingTask {
             name,
             timestamp_millis: timestamp_millis.unwrap_or(now()),
             value,
+            attempts: 0,
+            priority,
+            base_scope: self.base_scope(),
         };
-        let new_task_key = Key::from(&new_task);
-
-        self.transaction(
-            &Self::lock_scope(),
-            &mut move |s: &dyn KeyValueStoreBackend| {
-                let running_key_opt = s
-                    .list_keys(&Self::running_scope())?
-                    .into_iter()
-                    .filter_map(|k| TaskKey::try_from(&k).ok())
-                    .find(|running| running.name.as_ref() == &new_task.name)
-                    .map(|tk| tk.running_key());
+        let lock_scope = self.task_lock_scope(&new_task.name);
+        let mut scheduled = Scheduled::default();
 
-                let pending_key_opt = s
-                    .list_keys(&Self::pending_scope())?
-                    .into_iter()
-                    .filter_map(|k| TaskKey::try_from(&k).ok())
-                    .find(|p| p.name.as_ref() == &new_task.name)
-                    .map(|tk| tk.pending_key());
-
-                match mode {
-                    ScheduleMode::IfMissing => {
-                        if pending_key_opt.is_some() || running_key_opt.is_some() {
-                            // nothing to do, there is something
-                            Ok(())
-                        } else {
-                            // no pending or running task exists, just add the new task
-                            s.store(&new_task_key, new_task.value.clone())
-                        }
-                    }
-                    ScheduleMode::ReplaceExisting => {
-                        if let Some(pending) = pending_key_opt {
-                            s.delete(&pending)?;
-                        }
-                        s.store(&new_task_key, new_task.value.clone())
-                    }
-                    ScheduleMode::ReplaceExistingSoonest => {
-                        if let Some(pending) = pending_key_opt {
-                            if let Ok(tk) = TaskKey::try_from(&pending) {
-                                new_task.timestamp_millis =
-                                    new_task.timestamp_millis.min(tk.timestamp_millis);
-                            }
-                            s.delete(&pending)?;
-                        }
+        self.transaction(&lock_scope, &mut |s: &dyn KeyValueStoreBackend| {
+            let mut running = task_keys_by_name(s, &self.running_scope())?;
+            let mut pending = task_keys_by_name(s, &self.pending_scope())?;
 
-                        let new_task_key = Key::from(&new_task);
-                        s.store(&new_task_key, new_task.value.clone())
-                    }
-                    ScheduleMode::FinishOrReplaceExisting => {
-                        if let Some(running) = running_key_opt {
-                            s.delete(&running)?;
-                        }
-                        if let Some(pending) = pending_key_opt {
-                            s.delete(&pending)?;
-                        }
-                        s.store(&new_task_key, new_task.value.clone())
-                    }
-                    ScheduleMode::FinishOrReplaceExistingSoonest => {
-                        if let Some(running) = running_key_opt {
-                            s.delete(&running)?;
-                        }
+            scheduled =
+                schedule_in_transaction(s, &mut running, &mut pending, &mut new_task, mode)?;
+            Ok(())
+        })?;
 
-                        if let Some(pending) = pending_key_opt {
-                            if let Ok(tk) = TaskKey::try_from(&pending) {
-                                new_task.timestamp_millis =
-                                    new_task.timestamp_millis.min(tk.timestamp_millis);
-                            }
-                            s.delete(&pending)?;
-                        }
+        self.observe_scheduled(&new_task, &scheduled);
+        Ok(())
+    }
+
+    fn schedule_tasks(
+        &self,
+        tasks: Vec<(SegmentBuf, serde_json::Value, Option<u128>)>,
+        mode: ScheduleMode,
+    ) -> Result<()> {
+        let now = now();
 
-                        let new_task_key = Key::from(&new_task);
-                        s.store(&new_task_key, new_task.value.clone())
+        let mut shards: BTreeMap<usize, Vec<PendingTask>> = BTreeMap::new();
+        for (name, value, timestamp_millis) in tasks {
+            shards
+                .entry(self.queue_shard(&name))
+                .or_default()
+                .push(PendingTask {
+                    name,
+                    timestamp_millis: timestamp_millis.unwrap_or(now),
+                    value,
+                    attempts: 0,
+                    priority: DEFAULT_PRIORITY,
+                    base_scope: self.base_scope(),
+                });
+        }
+
+        for (shard, mut tasks) in shards {
+            let mut scheduled = Vec::with_capacity(tasks.len());
+
+            self.transaction(
+                &self.shard_lock_scope(shard),
+                &mut |s: &dyn KeyValueStoreBackend| {
+                    let mut running = task_keys_by_name(s, &self.running_scope())?;
+                    let mut pending = task_keys_by_name(s, &self.pending_scope())?;
+
+                    scheduled.clear();
+                    for task in tasks.iter_mut() {
+                        scheduled.push(schedule_in_transaction(
+                            s,
+                            &mut running,
+                            &mut pending,
+                            task,
+                            mode,
+                        )?);
                     }
-                }
-            },
-        )
+
+                    Ok(())
+                },
+            )?;
+
+            for (task, scheduled) in tasks.iter().zip(&scheduled) {
+                self.observe_scheduled(task, scheduled);
+            }
+        }
+
+        Ok(())
     }
 
     fn finish_running_task(&self, running_key: &Key) -> Result<()> {
-        self.execute(&Self::lock_scope(), |kv| {
+        self.execute(&self.task_key_lock_scope(running_key), |kv| {
             if kv.has(running_key)? {
                 kv.delete(running_key)
             } else {
@@ -326,103 +1032,232 @@ impl Queue for KeyValueStore {
                     running_key
                 )))
             }
-        })
-    }
+        })?;
 
-    fn reschedule_running_task(&self, running: &Key, timestamp_millis: Option<u128>) -> Result<()> {
-        let pending_key = {
-            let mut task_key = TaskKey::try_from(running)?;
-            task_key.timestamp_millis = timestamp_millis.unwrap_or_else(now);
+        if let Ok(tk) = TaskKey::try_from(running_key) {
+            self.observe(|o| o.on_finished(&tk.name, tk.timestamp_millis));
+        }
+        Ok(())
+    }
+
+    fn finish_and_schedule(
+        &self,
+        running_key: &Key,
+        next: Option<(SegmentBuf, serde_json::Value, Option<u128>)>,
+    ) -> Result<()> {
+        let mut next = next.map(|(name, value, timestamp_millis)| PendingTask {
+            name,
+            timestamp_millis: timestamp_millis.unwrap_or_else(now),
+            value,
+            attempts: 0,
+            priority: DEFAULT_PRIORITY,
+            base_scope: self.base_scope(),
+        });
 
-            task_key.pending_key()
+        // Both tasks must be covered by the lock, so use the global lock
+        // scope if they are in different shards.
+        let lock_scope = self.task_key_lock_scope(running_key);
+        let lock_scope = match &next {
+            Some(task) if self.task_lock_scope(&task.name) != lock_scope => self.lock_scope(),
+            _ => lock_scope,
         };
+        let mut scheduled = Scheduled::default();
 
-        self.execute(&Self::lock_scope(), |kv| {
-            kv.move_value(running, &pending_key)
-        })
+        self.transaction(&lock_scope, &mut |s: &dyn KeyValueStoreBackend| {
+            if !s.has(running_key)? {
+                return Err(Error::Other(format!(
+                    "Cannot finish task {}. It is not running.",
+                    running_key
+                )));
+            }
+            s.delete(running_key)?;
+
+            if let Some(task) = next.as_mut() {
+                let mut running = task_keys_by_name(s, &self.running_scope())?;
+                let mut pending = task_keys_by_name(s, &self.pending_scope())?;
+
+                scheduled = schedule_in_transaction(
+                    s,
+                    &mut running,
+                    &mut pending,
+                    task,
+                    ScheduleMode::ReplaceExisting,
+                )?;
+            }
+            Ok(())
+        })?;
+
+        if let Ok(tk) = TaskKey::try_from(running_key) {
+            self.observe(|o| o.on_finished(&tk.name, tk.timestamp_millis));
+        }
+        if let Some(task) = &next {
+            self.observe_scheduled(task, &scheduled);
+        }
+        Ok(())
     }
 
-    fn claim_scheduled_pending_task(&self) -> Result<Option<RunningTask>> {
-        self.execute(&Self::lock_scope(), |kv| {
-            let tasks_before = now();
+    fn reschedule_running_task(&self, running: &Key, timestamp_millis: Option<u128>) -> Result<()> {
+        let mut task_key = TaskKey::try_from(running)?;
+        task_key.timestamp_millis = timestamp_millis.unwrap_or_else(now);
+        let pending_key = task_key.key_in(self.pending_scope());
+
+        self.execute(&self.task_key_lock_scope(running), |kv| {
+            let stored = kv.get(running)?.ok_or_else(|| {
+                Error::Other(format!(
+                    "Cannot reschedule task {}. It is not running.",
+                    running
+                ))
+            })?;
+            let (value, attempts) = split_task_value(stored);
+
+            kv.store(&pending_key, task_value(value, attempts.saturating_add(1)))?;
+            kv.delete(running)
+        })?;
+
+        self.observe(|o| o.on_rescheduled(&task_key.name, task_key.timestamp_millis));
+        Ok(())
+    }
 
-            if let Some(pending) = kv
-                .list_keys(&Self::pending_scope())?
-                .into_iter()
-                .filter_map(|k| TaskKey::try_from(&k).ok())
-                .filter(|tk| tk.timestamp_millis <= tasks_before)
-                .min_by_key(|tk| tk.timestamp_millis)
+    fn claim_scheduled_pending_task_for_worker(
+        &self,
+        worker_id: Option<SegmentBuf>,
+    ) -> Result<Option<RunningTask>> {
+        // Start at a random shard so that concurrent workers are spread
+        // over the shards.
+        let shards = self.queue_shards();
+        let first = rand::thread_rng().gen_range(0..shards);
+
+        for shard in (first..shards).chain(0..first) {
+            if let Some(task) =
+                self.claim_scheduled_pending_task_in_shard(shard, worker_id.as_ref())?
             {
-                let pending_key = pending.pending_key();
+                self.observe(|o| o.on_claimed(&task.name, task.timestamp_millis));
+                return Ok(Some(task));
+            }
+        }
 
-                if let Some(value) = kv.get(&pending_key)? {
-                    let mut running_task = RunningTask {
-                        name: pending.name.into_owned(),
-                        timestamp_millis: tasks_before,
-                        value,
-                    };
-                    let mut running_key = Key::from(&running_task);
-
-                    if kv.has(&running_key)? {
-                        // It's not pretty to sleep blocking, even if it's
-                        // for 1 ms, but if we don't then get a name collision
-                        // with an existing running task.
-                        std::thread::sleep(Duration::from_millis(1));
-                        running_task.timestamp_millis = now();
-                        running_key = Key::from(&running_task);
-                    }
+        Ok(None)
+    }
 
-                    kv.move_value(&pending_key, &running_key)?;
+    fn peek_next_pending_task(&self) -> Result<Option<PendingTask>> {
+        self.execute(&self.lock_scope(), |kv| {
+            let tasks_before = now();
 
-                    Ok(Some(running_task))
-                } else {
-                    Ok(None)
-                }
-            } else {
-                Ok(None)
-            }
+            Ok(kv
+                .list_entries(&self.pending_scope())?
+                .into_iter()
+                .filter_map(|(k, stored)| Some((TaskKey::try_from(&k).ok()?, stored)))
+                .min_by_key(|(tk, _)| {
+                    if tk.timestamp_millis <= tasks_before {
+                        (false, tk.priority, tk.timestamp_millis)
+                    } else {
+                        (true, 0, tk.timestamp_millis)
+                    }
+                })
+                .map(|(tk, stored)| {
+                    let (value, attempts) = split_task_value(stored);
+                    PendingTask {
+                        name: tk.name.into_owned(),
+                        timestamp_millis: tk.timestamp_millis,
+                        value,
+                        attempts,
+                        priority: tk.priority,
+                        base_scope: self.base_scope(),
+                    }
+                }))
         })
     }
 
-    fn reschedule_long_running_tasks(&self, reschedule_after: Option<&Duration>) -> Result<()> {
+    fn reschedule_long_running_tasks(
+        &self,
+        reschedule_after: Option<&Duration>,
+        max_attempts: Option<u32>,
+    ) -> Result<()> {
         let now = now();
 
         let reschedule_after = reschedule_after.unwrap_or(&KeyValueStore::RESCHEDULE_AFTER);
         let reschedule_timeout = now - reschedule_after.as_millis();
 
-        self.transaction(
-            &Self::lock_scope(),
-            &mut move |s: &dyn KeyValueStoreBackend| {
-                s.list_keys(&Self::running_scope())?
-                    .into_iter()
-                    .filter_map(|k| {
-                        let task = TaskKey::try_from(&k).ok()?;
-                        if task.timestamp_millis <= reschedule_timeout {
-                            Some(task)
-                        } else {
-                            None
-                        }
-                    })
-                    .for_each(|tk| {
-                        let running_key = tk.running_key();
-
-                        let pending_key = TaskKey {
+        for shard in 0..self.queue_shards() {
+            let mut rescheduled = vec![];
+
+            self.transaction(
+                &self.shard_lock_scope(shard),
+                &mut |s: &dyn KeyValueStoreBackend| {
+                    rescheduled.clear();
+
+                    let timed_out =
+                        s.list_keys(&self.running_scope())?
+                            .into_iter()
+                            .filter_map(|k| {
+                                let task = TaskKey::try_from(&k).ok()?;
+                                if task.timestamp_millis <= reschedule_timeout
+                                    && self.queue_shard(&task.name) == shard
+                                {
+                                    Some((task, k))
+                                } else {
+                                    None
+                                }
+                            });
+
+                    for (tk, running_key) in timed_out {
+                        let Some(stored) = s.get(&running_key)? else {
+                            continue;
+                        };
+                        let (value, attempts) = split_task_value(stored);
+                        let attempts = attempts.saturating_add(1);
+
+                        let new_key = TaskKey {
                             name: Cow::Borrowed(&tk.name),
                             timestamp_millis: now,
-                        }
-                        .pending_key();
+                            priority: tk.priority,
+                        };
+                        let new_key = if max_attempts.is_some_and(|max| attempts >= max) {
+                            new_key.key_in(self.dead_letter_scope())
+                        } else {
+                            rescheduled.push(tk.name.clone().into_owned());
+                            new_key.key_in(self.pending_scope())
+                        };
 
-                        let _ = s.move_value(&running_key, &pending_key);
-                    });
+                        s.store(&new_key, task_value(value, attempts))?;
+                        s.delete(&running_key)?;
+                    }
 
-                Ok(())
-            },
-        )
+                    Ok(())
+                },
+            )?;
+
+            self.observe(|o| {
+                for name in &rescheduled {
+                    o.on_rescheduled(name, now);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn drain_pending(&self, mut handler: impl FnMut(RunningTask) -> Result<()>) -> Result<usize> {
+        let mut handled = 0;
+
+        while let Some(task) = self.claim_scheduled_pending_task()? {
+            let running_key = Key::from(&task);
+
+            if let Err(e) = handler(task) {
+                self.reschedule_running_task(&running_key, None)?;
+                return Err(e);
+            }
+
+            self.finish_running_task(&running_key)?;
+            handled += 1;
+        }
+
+        Ok(handled)
     }
 
     fn pending_task_scheduled(&self, name: SegmentBuf) -> Result<Option<u128>> {
-        self.execute(&Self::lock_scope(), |kv| {
-            kv.list_keys(&Self::pending_scope()).map(|keys| {
+        self.execute(&self.task_lock_scope(&name), |kv| {
+            kv.list_keys(&self.pending_scope()).map(|keys| {
                 keys.into_iter()
                     .filter_map(|k| TaskKey::try_from(&k).ok())
                     .find(|p| p.name.as_ref() == &name)
@@ -434,17 +1269,25 @@ impl Queue for KeyValueStore {
 
 #[cfg(test)]
 mod tests {
-    use std::{thread, time::Duration};
+    use std::{
+        str::FromStr,
+        sync::{Arc, Barrier, Mutex},
+        thread,
+        time::Duration,
+    };
 
     use kvx_macros::segment;
     use kvx_types::{Key, SegmentBuf};
     use serde_json::Value;
     use url::Url;
 
-    use super::{PendingTask, Queue};
+    use super::{
+        DeadLetterTask, PendingTask, Queue, QueueConfig, QueueObserver, QueueStats,
+        DEFAULT_PRIORITY,
+    };
     use crate::{
         queue::{now, ScheduleMode},
-        KeyValueStore, Namespace, ReadStore, Scope, Segment,
+        KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore, Scope, Segment, WriteStore,
     };
 
     fn queue_store(ns: &str) -> KeyValueStore {
@@ -509,6 +1352,139 @@ mod tests {
         assert_eq!(running, 0);
     }
 
+    #[test]
+    fn test_sharded_queue() {
+        let queue = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_sharded_queue").unwrap(),
+        )
+        .unwrap()
+        .with_queue_config(QueueConfig {
+            shards: 4,
+            ..Default::default()
+        });
+        queue.clear().unwrap();
+
+        let names: Vec<SegmentBuf> = (1..=20)
+            .map(|i| Segment::parse(&format!("job-{i}")).unwrap().into())
+            .collect();
+        let locked_shard = queue.queue_shard(&names[0]);
+        let other = names
+            .iter()
+            .find(|name| queue.queue_shard(name) != locked_shard)
+            .unwrap()
+            .clone();
+
+        // Hold the lock of one shard, while scheduling a task in another. If
+        // that waited for the lock, it would fail when its lock times out.
+        let barrier = Barrier::new(2);
+        thread::scope(|s| {
+            s.spawn(|| {
+                queue
+                    .transaction(&queue.shard_lock_scope(locked_shard), &mut |_| {
+                        barrier.wait();
+                        barrier.wait();
+                        Ok(())
+                    })
+                    .unwrap();
+            });
+
+            barrier.wait();
+            let scheduled = queue.schedule_task(
+                other.clone(),
+                Value::from("value"),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            );
+            barrier.wait();
+            scheduled.unwrap();
+        });
+
+        for name in names.iter() {
+            queue
+                .schedule_task(
+                    name.clone(),
+                    Value::from("value"),
+                    None,
+                    ScheduleMode::FinishOrReplaceExisting,
+                )
+                .unwrap();
+        }
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 20);
+
+        let mut claimed = vec![];
+        while let Some(task) = queue.claim_scheduled_pending_task().unwrap() {
+            queue.finish_running_task(&Key::from(&task)).unwrap();
+            claimed.push(task.name);
+        }
+        claimed.sort();
+
+        let mut expected = names.clone();
+        expected.sort();
+
+        assert_eq!(claimed, expected);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_drain_pending() {
+        let queue = queue_store("test_drain_pending");
+        queue.inner.clear().unwrap();
+
+        let schedule = |names: &[&str]| {
+            for name in names {
+                queue
+                    .schedule_task(
+                        Segment::parse(name).unwrap().into(),
+                        Value::from(*name),
+                        None,
+                        ScheduleMode::FinishOrReplaceExisting,
+                    )
+                    .unwrap();
+            }
+        };
+
+        schedule(&["job-1", "job-2", "job-3"]);
+
+        let mut handled = vec![];
+        let count = queue
+            .drain_pending(|task| {
+                handled.push(task.name);
+                Ok(())
+            })
+            .unwrap();
+        handled.sort();
+
+        assert_eq!(count, 3);
+        assert_eq!(
+            handled,
+            vec![
+                SegmentBuf::from(segment!("job-1")),
+                SegmentBuf::from(segment!("job-2")),
+                SegmentBuf::from(segment!("job-3")),
+            ]
+        );
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+
+        // A failing handler stops the drain and requeues the task.
+        schedule(&["job-1", "job-2", "job-3"]);
+
+        let mut calls = 0;
+        let result = queue.drain_pending(|_| {
+            calls += 1;
+            if calls == 2 {
+                Err(crate::Error::Other("failed".to_string()))
+            } else {
+                Ok(())
+            }
+        });
+
+        assert!(result.is_err());
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 2);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+    }
+
     #[test]
     fn test_reschedule_long_running() {
         let queue = queue_store("test_reschedule_long_running");
@@ -539,7 +1515,7 @@ mod tests {
         assert!(job.is_none());
 
         queue
-            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)))
+            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)), None)
             .unwrap();
 
         let existing = queue.pending_task_scheduled(segment.into()).unwrap();
@@ -553,6 +1529,596 @@ mod tests {
         assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
     }
 
+    #[test]
+    fn test_max_attempts() {
+        let queue = queue_store("test_max_attempts");
+        queue.inner.clear().unwrap();
+
+        let name: SegmentBuf = segment!("poison").into();
+        let value = Value::from("value");
+
+        queue
+            .schedule_task(
+                name.clone(),
+                value.clone(),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+
+        // Fail once using reschedule_running_task, then time out.
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.attempts, 0);
+        queue
+            .reschedule_running_task(&Key::from(&task), None)
+            .unwrap();
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.attempts, 1);
+        assert_eq!(task.value, value);
+        queue
+            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)), Some(3))
+            .unwrap();
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.attempts, 2);
+        assert!(queue.dead_letter_tasks_keys().unwrap().is_empty());
+
+        // The third failed attempt exhausts the task.
+        queue
+            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)), Some(3))
+            .unwrap();
+
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+        assert!(queue.claim_scheduled_pending_task().unwrap().is_none());
+
+        let dead = queue.dead_letter_tasks_keys().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(
+            queue.get(&dead[0]).unwrap(),
+            Some(super::task_value(value, 3))
+        );
+
+        // Scheduling the task again starts counting from scratch.
+        queue
+            .schedule_task(
+                name,
+                Value::from("value"),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.attempts, 0);
+    }
+
+    #[test]
+    fn test_claim_for_worker() {
+        let queue = queue_store("test_claim_for_worker");
+        queue.inner.clear().unwrap();
+
+        let worker: SegmentBuf = segment!("worker-1").into();
+        let value = Value::from("value");
+        for name in ["a", "b"] {
+            queue
+                .schedule_task(
+                    Segment::parse(name).unwrap().into(),
+                    value.clone(),
+                    None,
+                    ScheduleMode::FinishOrReplaceExisting,
+                )
+                .unwrap();
+        }
+
+        let claimed = queue
+            .claim_scheduled_pending_task_for_worker(Some(worker.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(claimed.worker_id, Some(worker.clone()));
+        assert_eq!(claimed.value, value);
+        let other = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(other.worker_id, None);
+
+        assert_eq!(
+            queue.running_tasks_for_worker(&worker).unwrap(),
+            vec![Key::from(&claimed)]
+        );
+        assert!(queue
+            .running_tasks_for_worker(segment!("worker-2"))
+            .unwrap()
+            .is_empty());
+
+        // Rescheduling keeps the value, but not the worker.
+        queue
+            .reschedule_running_task(&Key::from(&claimed), None)
+            .unwrap();
+        assert!(queue.running_tasks_for_worker(&worker).unwrap().is_empty());
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.name, claimed.name);
+        assert_eq!(task.value, value);
+        assert_eq!(task.attempts, 1);
+        assert_eq!(task.worker_id, None);
+    }
+
+    #[test]
+    fn test_finish_and_schedule() {
+        let queue = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_finish_and_schedule").unwrap(),
+        )
+        .unwrap()
+        .with_queue_config(QueueConfig {
+            shards: 4,
+            ..Default::default()
+        });
+        queue.clear().unwrap();
+
+        let first: SegmentBuf = segment!("first").into();
+        queue
+            .schedule_task(
+                first.clone(),
+                Value::from(1),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+
+        // Chain a task in another shard.
+        let next: SegmentBuf = (1..)
+            .map(|i| SegmentBuf::from(Segment::parse(&format!("next-{i}")).unwrap()))
+            .find(|name| queue.queue_shard(name) != queue.queue_shard(&first))
+            .unwrap();
+        queue
+            .finish_and_schedule(
+                &Key::from(&task),
+                Some((next.clone(), Value::from(2), None)),
+            )
+            .unwrap();
+
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.name, next);
+        assert_eq!(task.value, Value::from(2));
+
+        // Nothing is scheduled if the task is not running.
+        queue.finish_running_task(&Key::from(&task)).unwrap();
+        assert!(queue
+            .finish_and_schedule(&Key::from(&task), Some((first, Value::from(3), None)))
+            .is_err());
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
+
+        // Without a follow-up this is the same as finishing the task.
+        queue
+            .schedule_task(next, Value::from(4), None, ScheduleMode::IfMissing)
+            .unwrap();
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue.finish_and_schedule(&Key::from(&task), None).unwrap();
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_queue_stats() {
+        let queue = queue_store("test_queue_stats");
+        queue.inner.clear().unwrap();
+
+        assert_eq!(queue.queue_stats().unwrap(), QueueStats::default());
+        assert_eq!(queue.oldest_pending_task_age().unwrap(), None);
+
+        let overdue = now() - 60_000;
+        for (name, timestamp) in [("a", overdue + 1_000), ("b", overdue), ("c", now())] {
+            queue
+                .schedule_task(
+                    Segment::parse(name).unwrap().into(),
+                    Value::Null,
+                    Some(timestamp),
+                    ScheduleMode::IfMissing,
+                )
+                .unwrap();
+        }
+        queue.claim_scheduled_pending_task().unwrap().unwrap();
+
+        assert_eq!(
+            queue.queue_stats().unwrap(),
+            QueueStats {
+                pending: 2,
+                running: 1,
+                oldest_pending_millis: Some(overdue + 1_000),
+            }
+        );
+        let age = queue.oldest_pending_task_age().unwrap().unwrap();
+        assert!(age >= Duration::from_millis(59_000), "{age:?}");
+
+        // Tasks that are not due yet have no age.
+        queue.inner.clear().unwrap();
+        queue
+            .schedule_task(
+                segment!("later").into(),
+                Value::Null,
+                Some(now() + 60_000),
+                ScheduleMode::IfMissing,
+            )
+            .unwrap();
+        assert_eq!(
+            queue.oldest_pending_task_age().unwrap(),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_base_scope() {
+        let queue = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_base_scope").unwrap(),
+        )
+        .unwrap()
+        .with_queue_config(QueueConfig {
+            base_scope: Scope::from_segment(segment!("_queue")),
+            ..Default::default()
+        });
+        queue.clear().unwrap();
+
+        // Application keys in scopes with the same names as the queue's.
+        let app_pending: Key = "pending/000-0-settings".parse().unwrap();
+        let app_running: Key = "running/000-0-settings".parse().unwrap();
+        queue.store(&app_pending, Value::from("app")).unwrap();
+        queue.store(&app_running, Value::from("app")).unwrap();
+
+        assert_eq!(queue.pending_scope(), "_queue/pending".parse().unwrap());
+        assert_eq!(queue.lock_scope(), "_queue".parse().unwrap());
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 0);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+
+        let name: SegmentBuf = segment!("task").into();
+        queue
+            .schedule_task(name.clone(), Value::from(1), None, ScheduleMode::IfMissing)
+            .unwrap();
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.name, name);
+        assert_eq!(Key::from(&task).scope(), &queue.running_scope());
+        assert_eq!(queue.running_tasks_keys().unwrap(), vec![Key::from(&task)]);
+
+        queue
+            .reschedule_long_running_tasks(Some(&Duration::ZERO), None)
+            .unwrap();
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 1);
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue.finish_running_task(&Key::from(&task)).unwrap();
+
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+        assert_eq!(queue.get(&app_pending).unwrap(), Some(Value::from("app")));
+        assert_eq!(queue.get(&app_running).unwrap(), Some(Value::from("app")));
+    }
+
+    #[test]
+    fn test_requeue_dead_letter_task() {
+        let queue = queue_store("test_requeue_dead_letter_task");
+        queue.inner.clear().unwrap();
+
+        let name: SegmentBuf = segment!("poison").into();
+        let value = Value::from("value");
+
+        queue
+            .schedule_task(
+                name.clone(),
+                value.clone(),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+        queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue
+            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)), Some(1))
+            .unwrap();
+
+        let dead = queue.list_dead_letter_tasks().unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].name, name);
+        assert_eq!(dead[0].value, value);
+        assert_eq!(dead[0].attempts, 1);
+
+        // Requeuing a task that is not in the dead-letter scope fails.
+        let missing = DeadLetterTask {
+            name: segment!("other").into(),
+            ..dead[0].clone()
+        };
+        assert!(queue
+            .requeue_dead_letter_task(&Key::from(&missing), None)
+            .is_err());
+
+        queue
+            .requeue_dead_letter_task(&Key::from(&dead[0]), None)
+            .unwrap();
+        assert!(queue.list_dead_letter_tasks().unwrap().is_empty());
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 1);
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.name, name);
+        assert_eq!(task.value, value);
+        assert_eq!(task.attempts, 0);
+    }
+
+    #[test]
+    fn test_peek_next_pending_task() {
+        let queue = queue_store("test_peek_next_pending_task");
+        queue.inner.clear().unwrap();
+
+        assert!(queue.peek_next_pending_task().unwrap().is_none());
+
+        let later = now() + 60_000;
+        let soon = now() + 30_000;
+        for (name, timestamp) in [("later", later), ("soon", soon)] {
+            queue
+                .schedule_task(
+                    Segment::parse(name).unwrap().into(),
+                    Value::from(name),
+                    Some(timestamp),
+                    ScheduleMode::FinishOrReplaceExisting,
+                )
+                .unwrap();
+        }
+
+        // The soonest task is returned even though it is not due yet, and
+        // it stays pending.
+        let next = queue.peek_next_pending_task().unwrap().unwrap();
+        assert_eq!(next.name, SegmentBuf::from(segment!("soon")));
+        assert_eq!(next.timestamp_millis, soon);
+        assert_eq!(next.value, Value::from("soon"));
+
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 2);
+        assert!(queue.claim_scheduled_pending_task().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_priority() {
+        let queue = queue_store("test_priority");
+        queue.inner.clear().unwrap();
+
+        let due = now() - 1_000;
+        let schedule = |name: &str, timestamp: u128, priority: u8| {
+            queue
+                .schedule_task_with_priority(
+                    Segment::parse(name).unwrap().into(),
+                    Value::from(name),
+                    Some(timestamp),
+                    priority,
+                    ScheduleMode::FinishOrReplaceExisting,
+                )
+                .unwrap();
+        };
+
+        schedule("urgent-later", now() + 60_000, 0);
+        schedule("low", due - 1_000, 200);
+        schedule("high-late", due, 10);
+        schedule("high-early", due - 500, 10);
+
+        // A task written by an earlier version, without a priority.
+        let legacy = Key::from_str(&format!("pending/{}-legacy", due)).unwrap();
+        queue.store(&legacy, Value::from("legacy")).unwrap();
+
+        assert_eq!(
+            queue.peek_next_pending_task().unwrap().unwrap().name,
+            SegmentBuf::from(segment!("high-early"))
+        );
+
+        let mut claimed = vec![];
+        while let Some(task) = queue.claim_scheduled_pending_task().unwrap() {
+            claimed.push((task.name.to_string(), task.priority));
+        }
+        assert_eq!(
+            claimed,
+            vec![
+                ("high-early".to_string(), 10),
+                ("high-late".to_string(), 10),
+                ("legacy".to_string(), DEFAULT_PRIORITY),
+                ("low".to_string(), 200),
+            ]
+        );
+
+        // The priority is kept when a task is rescheduled.
+        let running = queue.running_tasks_keys().unwrap();
+        let low = running
+            .iter()
+            .find(|k| k.name().as_str().ends_with("low"))
+            .unwrap();
+        queue.reschedule_running_task(low, Some(due)).unwrap();
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.name, SegmentBuf::from(segment!("low")));
+        assert_eq!(task.priority, 200);
+    }
+
+    #[test]
+    fn test_schedule_tasks() {
+        let queue = queue_store("test_schedule_tasks");
+        queue.inner.clear().unwrap();
+
+        let task = |name: &str, value: &str| {
+            (
+                SegmentBuf::from(Segment::parse(name).unwrap()),
+                Value::from(value),
+                None,
+            )
+        };
+
+        // Claim a task, so that there is a running task to finish.
+        queue
+            .schedule_task(
+                segment!("running").into(),
+                Value::from("old"),
+                None,
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+        queue.claim_scheduled_pending_task().unwrap().unwrap();
+
+        queue
+            .schedule_tasks(
+                vec![
+                    task("job-1", "first"),
+                    task("job-2", "value"),
+                    task("job-1", "second"),
+                    task("running", "new"),
+                ],
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 3);
+        assert_eq!(queue.running_tasks_remaining().unwrap(), 0);
+
+        // Existing tasks, and earlier tasks in the batch, are kept.
+        queue
+            .schedule_tasks(
+                vec![
+                    task("job-1", "third"),
+                    task("job-3", "first"),
+                    task("job-3", "second"),
+                ],
+                ScheduleMode::IfMissing,
+            )
+            .unwrap();
+        assert_eq!(queue.pending_tasks_remaining().unwrap(), 4);
+
+        let mut claimed = vec![];
+        while let Some(task) = queue.claim_scheduled_pending_task().unwrap() {
+            claimed.push((task.name.to_string(), task.value));
+        }
+        claimed.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            claimed,
+            vec![
+                ("job-1".to_string(), Value::from("second")),
+                ("job-2".to_string(), Value::from("value")),
+                ("job-3".to_string(), Value::from("first")),
+                ("running".to_string(), Value::from("new")),
+            ]
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<(&'static str, String)>>,
+    }
+
+    impl RecordingObserver {
+        fn record(&self, event: &'static str, name: &Segment) {
+            self.events.lock().unwrap().push((event, name.to_string()));
+        }
+
+        fn take(&self) -> Vec<(&'static str, String)> {
+            std::mem::take(&mut self.events.lock().unwrap())
+        }
+    }
+
+    impl QueueObserver for RecordingObserver {
+        fn on_scheduled(&self, name: &Segment, _timestamp_millis: u128) {
+            self.record("scheduled", name);
+        }
+
+        fn on_claimed(&self, name: &Segment, _timestamp_millis: u128) {
+            self.record("claimed", name);
+        }
+
+        fn on_finished(&self, name: &Segment, _timestamp_millis: u128) {
+            self.record("finished", name);
+        }
+
+        fn on_rescheduled(&self, name: &Segment, _timestamp_millis: u128) {
+            self.record("rescheduled", name);
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let observer = Arc::new(RecordingObserver::default());
+        let queue = queue_store("test_observer").with_observer(observer.clone());
+        queue.inner.clear().unwrap();
+
+        let name: SegmentBuf = segment!("job").into();
+        let schedule = |mode| {
+            queue
+                .schedule_task(name.clone(), Value::from("value"), None, mode)
+                .unwrap();
+        };
+        let event = |event: &'static str| (event, "job".to_string());
+
+        schedule(ScheduleMode::FinishOrReplaceExisting);
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue
+            .reschedule_running_task(&Key::from(&task), None)
+            .unwrap();
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue.finish_running_task(&Key::from(&task)).unwrap();
+        assert_eq!(
+            observer.take(),
+            vec![
+                event("scheduled"),
+                event("claimed"),
+                event("rescheduled"),
+                event("claimed"),
+                event("finished"),
+            ]
+        );
+
+        // Nothing is scheduled if the task exists, and scheduling a task
+        // may finish the running task.
+        schedule(ScheduleMode::FinishOrReplaceExisting);
+        schedule(ScheduleMode::IfMissing);
+        queue.claim_scheduled_pending_task().unwrap().unwrap();
+        queue
+            .reschedule_long_running_tasks(Some(&Duration::from_secs(0)), None)
+            .unwrap();
+        queue.claim_scheduled_pending_task().unwrap().unwrap();
+        schedule(ScheduleMode::FinishOrReplaceExisting);
+        assert_eq!(
+            observer.take(),
+            vec![
+                event("scheduled"),
+                event("claimed"),
+                event("rescheduled"),
+                event("claimed"),
+                event("finished"),
+                event("scheduled"),
+            ]
+        );
+
+        // Failed operations are not observed.
+        assert!(queue
+            .finish_running_task(&Key::from_str("running/128-0-job").unwrap())
+            .is_err());
+        assert!(observer.take().is_empty());
+    }
+
+    #[test]
+    fn test_scheduled_at() {
+        let queue = queue_store("test_scheduled_at");
+        queue.inner.clear().unwrap();
+
+        let scheduled = now() - 5_000;
+        queue
+            .schedule_task(
+                segment!("job").into(),
+                Value::from("value"),
+                Some(scheduled),
+                ScheduleMode::FinishOrReplaceExisting,
+            )
+            .unwrap();
+
+        let task = queue.claim_scheduled_pending_task().unwrap().unwrap();
+        assert_eq!(task.scheduled_at, scheduled);
+        assert!(task.timestamp_millis >= scheduled + 5_000);
+
+        // The key still uses the time the task was claimed.
+        assert_eq!(queue.running_tasks_keys().unwrap(), vec![Key::from(&task)]);
+        assert!(Key::from(&task)
+            .name()
+            .as_str()
+            .contains(&task.timestamp_millis.to_string()));
+    }
+
     #[test]
     fn test_reschedule_finished_task() {
         let queue = queue_store("test_reschedule_finished_task");
@@ -0,0 +1,561 @@
+//! Retrying operations that fail with a transient error.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    ops::{Deref, DerefMut},
+    sync::mpsc::Receiver,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use kvx_types::NamespaceBuf;
+use serde_json::Value;
+
+use crate::{
+    BackendKind, ChangeEvent, ChangeKind, Key, KeyValueStoreBackend, ReadStore,
+    ReadTransactionCallback, Result, Scope, Segment, TransactionCallback, WriteStore,
+};
+
+/// How often and how fast a [`RetryingStore`] retries operations.
+///
+/// The delay before the first retry is the backoff, which is doubled for
+/// each further retry up to the maximum backoff.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, with a backoff of 50 milliseconds.
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(50))
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy that makes at most `max_attempts` attempts, waiting
+    /// `backoff` before the first retry. The backoff is at most 10 seconds
+    /// by default.
+    pub fn new(max_attempts: usize, backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff,
+            max_backoff: Duration::from_secs(10).max(backoff),
+        }
+    }
+
+    /// Sets the maximum delay between two attempts.
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Runs the operation until it succeeds, fails with an error that is not
+    /// retryable, or the maximum number of attempts is reached.
+    ///
+    /// See [`Error::is_retryable`] for the errors that are retried.
+    ///
+    /// [`Error::is_retryable`]: crate::Error::is_retryable
+    pub fn retry<T>(&self, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut backoff = self.backoff;
+
+        for _ in 1..self.max_attempts {
+            match op() {
+                Err(e) if e.is_retryable() => {
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                res => return res,
+            }
+        }
+
+        op()
+    }
+}
+
+/// Wraps a store and retries operations that fail with a transient error,
+/// according to a [`RetryPolicy`].
+///
+/// Transactions are retried as a whole, so the callback may be called more
+/// than once. Operations within a transaction callback are not retried
+/// individually.
+///
+/// # Example
+/// ```
+/// use kvx::{Key, KeyValueStore, Namespace, ReadStore, RetryPolicy, WriteStore};
+/// use serde_json::Value;
+/// use std::time::Duration;
+/// use url::Url;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("retry")?)?
+///     .with_retry(RetryPolicy::new(5, Duration::from_millis(10)));
+/// let key: Key = "key".parse()?;
+///
+/// store.store(&key, Value::from(1))?;
+/// assert_eq!(store.get(&key)?, Some(Value::from(1)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RetryingStore<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S> RetryingStore<S> {
+    /// Create a retrying store that delegates to `inner`.
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        RetryingStore { inner, policy }
+    }
+
+    /// Returns the policy used to retry operations.
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+}
+
+impl<S> Display for RetryingStore<S>
+where
+    S: Deref,
+    S::Target: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Retrying({})", self.inner.deref())
+    }
+}
+
+impl<S> ReadStore for RetryingStore<S>
+where
+    S: Deref,
+    S::Target: ReadStore,
+{
+    fn is_empty(&self) -> Result<bool> {
+        self.policy.retry(|| self.inner.is_empty())
+    }
+
+    fn total_keys(&self) -> Result<usize> {
+        self.policy.retry(|| self.inner.total_keys())
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        self.policy.retry(|| self.inner.has(key))
+    }
+
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        self.policy.retry(|| self.inner.has_each(keys))
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        self.policy.retry(|| self.inner.has_scope(scope))
+    }
+
+    fn has_value(&self, key: &Key, value: &Value) -> Result<bool> {
+        self.policy.retry(|| self.inner.has_value(key, value))
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        self.policy.retry(|| self.inner.get(key))
+    }
+
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.policy.retry(|| self.inner.get_raw(key))
+    }
+
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.policy.retry(|| self.inner.get_bytes(key))
+    }
+
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        self.policy.retry(|| self.inner.get_many(keys))
+    }
+
+    fn get_or(&self, key: &Key, default: Value) -> Result<Value> {
+        self.policy
+            .retry(|| self.inner.get_or(key, default.clone()))
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        self.policy.retry(|| self.inner.list_keys(scope))
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        self.policy.retry(|| self.inner.list_entries(scope))
+    }
+
+    fn snapshot(&self) -> Result<HashMap<Key, Value>> {
+        self.policy.retry(|| self.inner.snapshot())
+    }
+
+    fn list_keys_matching_ci(&self, scope: &Scope, name: &Segment) -> Result<Vec<Key>> {
+        self.policy
+            .retry(|| self.inner.list_keys_matching_ci(scope, name))
+    }
+
+    /// Only creating the iterator is retried, errors returned by the
+    /// iterator are not.
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        self.policy.retry(|| self.inner.stream_keys(scope))
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        self.policy.retry(|| self.inner.count_keys(scope))
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        self.policy.retry(|| self.inner.list_scopes())
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.policy.retry(|| self.inner.list_scopes_under(parent))
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        self.policy.retry(|| self.inner.list_child_scopes(parent))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        self.policy.retry(|| self.inner.changes_since(since))
+    }
+
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &Value,
+    ) -> Result<Vec<Key>> {
+        self.policy
+            .retry(|| self.inner.find_keys_where_value(scope, json_path, equals))
+    }
+}
+
+impl<S> WriteStore for RetryingStore<S>
+where
+    S: DerefMut,
+    S::Target: WriteStore,
+{
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.policy.retry(|| self.inner.store(key, value.clone()))
+    }
+
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        self.policy.retry(|| self.inner.store_many(entries))
+    }
+
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        self.policy.retry(|| self.inner.store_bytes(key, bytes))
+    }
+
+    fn restore(&self, entries: HashMap<Key, Value>) -> Result<()> {
+        self.policy.retry(|| self.inner.restore(entries.clone()))
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.policy
+            .retry(|| self.inner.store_with_expiry(key, value.clone(), expires_at))
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        self.policy.retry(|| self.inner.purge_expired())
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        self.policy.retry(|| self.inner.prune_changes(before))
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.policy
+            .retry(|| self.inner.compare_and_swap(key, expected, new.clone()))
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.policy.retry(|| self.inner.move_value(from, to))
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.policy.retry(|| self.inner.copy_value(from, to))
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.policy.retry(|| self.inner.move_scope(from, to))
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.policy.retry(|| self.inner.copy_scope(from, to))
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.policy.retry(|| self.inner.delete(key))
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.policy.retry(|| self.inner.delete_existing(key))
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.policy.retry(|| self.inner.delete_scope(scope))
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.policy.retry(|| self.inner.delete_scope_exact(scope))
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.policy.retry(|| self.inner.clear_scope(scope))
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.policy.retry(|| self.inner.clear())
+    }
+
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        let policy = self.policy;
+        policy.retry(|| self.inner.migrate_namespace(to.clone()))
+    }
+}
+
+impl<S> KeyValueStoreBackend for RetryingStore<S>
+where
+    S: DerefMut,
+    S::Target: KeyValueStoreBackend,
+{
+    fn backend_kind(&self) -> BackendKind {
+        self.inner.backend_kind()
+    }
+
+    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        self.policy
+            .retry(|| self.inner.transaction(scope, &mut *callback))
+    }
+
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        self.policy
+            .retry(|| self.inner.try_transaction(scope, timeout, &mut *callback))
+    }
+
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        self.policy
+            .retry(|| self.inner.read_only_transaction(scope, &mut *callback))
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        self.policy.retry(|| self.inner.watch_scope(scope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use kvx_types::NamespaceBuf;
+    use serde_json::Value;
+    use url::Url;
+
+    use super::{RetryPolicy, RetryingStore};
+    use crate::{
+        implementations::memory::Memory, Error, Key, KeyValueStore, KeyValueStoreBackend,
+        Namespace, ReadStore, Result, Scope, WriteStore,
+    };
+
+    /// A store of which `get` and `store` fail with an error created by
+    /// `error` while `failures` is not zero, counting the calls.
+    struct FlakyStore {
+        inner: Memory,
+        error: fn() -> Error,
+        failures: Cell<usize>,
+        calls: Cell<usize>,
+    }
+
+    impl FlakyStore {
+        fn new(error: fn() -> Error) -> Self {
+            FlakyStore {
+                inner: Memory::new_isolated("test_flaky_store".parse().unwrap()),
+                error,
+                failures: Cell::new(0),
+                calls: Cell::new(0),
+            }
+        }
+
+        /// Makes the next `failures` calls fail, and resets the count of
+        /// calls.
+        fn fail(&self, failures: usize) {
+            self.failures.set(failures);
+            self.calls.set(0);
+        }
+
+        fn call(&self) -> Result<()> {
+            self.calls.set(self.calls.get() + 1);
+            match self.failures.get() {
+                0 => Ok(()),
+                failures => {
+                    self.failures.set(failures - 1);
+                    Err((self.error)())
+                }
+            }
+        }
+    }
+
+    impl ReadStore for FlakyStore {
+        fn is_empty(&self) -> Result<bool> {
+            self.inner.is_empty()
+        }
+
+        fn has(&self, key: &Key) -> Result<bool> {
+            self.inner.has(key)
+        }
+
+        fn has_scope(&self, scope: &Scope) -> Result<bool> {
+            self.inner.has_scope(scope)
+        }
+
+        fn get(&self, key: &Key) -> Result<Option<Value>> {
+            self.call()?;
+            self.inner.get(key)
+        }
+
+        fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+            self.inner.list_keys(scope)
+        }
+
+        fn list_scopes(&self) -> Result<Vec<Scope>> {
+            self.inner.list_scopes()
+        }
+    }
+
+    impl WriteStore for FlakyStore {
+        fn store(&self, key: &Key, value: Value) -> Result<()> {
+            self.call()?;
+            self.inner.store(key, value)
+        }
+
+        fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+            self.inner.move_value(from, to)
+        }
+
+        fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+            self.inner.move_scope(from, to)
+        }
+
+        fn delete(&self, key: &Key) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn delete_scope(&self, scope: &Scope) -> Result<()> {
+            self.inner.delete_scope(scope)
+        }
+
+        fn clear(&self) -> Result<()> {
+            self.inner.clear()
+        }
+
+        fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+            self.inner.migrate_namespace(to)
+        }
+    }
+
+    fn store() -> KeyValueStore {
+        KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_retrying_store").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut store = store();
+        let retrying =
+            RetryingStore::new(&mut store, RetryPolicy::new(3, Duration::from_millis(1)));
+        let key: Key = "scope/key".parse().unwrap();
+
+        // A transaction that fails twice with a transient error, then
+        // succeeds.
+        let mut calls = 0;
+        retrying
+            .transaction(key.scope(), &mut |t| {
+                calls += 1;
+                if calls < 3 {
+                    return Err(Error::TransactionConflict);
+                }
+                t.store(&key, Value::from(calls))
+            })
+            .unwrap();
+        assert_eq!(calls, 3);
+        assert_eq!(retrying.get(&key).unwrap(), Some(Value::from(3)));
+
+        // Giving up after the maximum number of attempts.
+        let mut calls = 0;
+        let result = retrying.transaction(key.scope(), &mut |_| {
+            calls += 1;
+            Err(Error::TransactionConflict)
+        });
+        assert!(matches!(result, Err(Error::TransactionConflict)));
+        assert_eq!(calls, 3);
+
+        // Errors that are not retryable are returned immediately.
+        let mut calls = 0;
+        let result = retrying.transaction(key.scope(), &mut |_| {
+            calls += 1;
+            Err(Error::UnknownKey)
+        });
+        assert!(matches!(result, Err(Error::UnknownKey)));
+        assert_eq!(calls, 1);
+
+        retrying.clear().unwrap();
+    }
+
+    #[test]
+    fn test_retry_flaky_store() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+        let flaky = FlakyStore::new(|| Error::LockTimeout("Scope already locked".to_string()));
+        let retrying = RetryingStore::new(Box::new(flaky), policy);
+        let key: Key = "scope/key".parse().unwrap();
+
+        // Failing twice, then succeeding.
+        retrying.inner.fail(2);
+        retrying.store(&key, Value::from(1)).unwrap();
+        assert_eq!(retrying.inner.calls.get(), 3);
+
+        retrying.inner.fail(2);
+        assert_eq!(retrying.get(&key).unwrap(), Some(Value::from(1)));
+        assert_eq!(retrying.inner.calls.get(), 3);
+
+        // Giving up after the maximum number of attempts.
+        retrying.inner.fail(3);
+        assert!(matches!(retrying.get(&key), Err(Error::LockTimeout(_))));
+        assert_eq!(retrying.inner.calls.get(), 3);
+
+        // A poisoned lock is not retried.
+        let flaky = FlakyStore::new(|| Error::MutexLock("poisoned".to_string()));
+        let retrying = RetryingStore::new(Box::new(flaky), policy);
+        retrying.inner.fail(1);
+        assert!(matches!(
+            retrying.store(&key, Value::from(2)),
+            Err(Error::MutexLock(_))
+        ));
+        assert_eq!(retrying.inner.calls.get(), 1);
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(Error::TransactionConflict.is_retryable());
+        assert!(Error::LockTimeout(String::new()).is_retryable());
+        assert!(!Error::MutexLock(String::new()).is_retryable());
+        assert!(Error::Io(std::io::ErrorKind::TimedOut.into()).is_retryable());
+        assert!(!Error::Io(std::io::ErrorKind::NotFound.into()).is_retryable());
+        assert!(!Error::UnknownKey.is_retryable());
+        assert!(!Error::Abort.is_retryable());
+    }
+}
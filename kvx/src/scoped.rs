@@ -0,0 +1,330 @@
+//! A view on the keys of a store beneath a scope.
+
+use std::time::SystemTime;
+
+use kvx_types::NamespaceBuf;
+use serde_json::Value;
+
+use crate::{ChangeKind, Error, Key, KeyValueStore, ReadStore, Result, Scope, Segment, WriteStore};
+
+/// A view on a [`KeyValueStore`] that prepends a scope to all keys and
+/// scopes, see [`KeyValueStore::scoped`].
+///
+/// Keys and scopes returned by the view are relative to its prefix, keys
+/// outside the prefix are not visible. [`WriteStore::purge_expired`] and
+/// [`WriteStore::prune_changes`] apply to the whole namespace, and the
+/// namespace cannot be migrated using a view.
+///
+/// # Example
+/// ```
+/// use kvx::{Key, KeyValueStore, Namespace, ReadStore, WriteStore};
+/// use serde_json::Value;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let store = KeyValueStore::new_isolated(Namespace::parse("scoped")?);
+/// let module = store.scoped("modules/a".parse()?);
+///
+/// let key: Key = "settings".parse()?;
+/// module.store(&key, Value::from(1))?;
+///
+/// assert_eq!(module.list_keys(&Default::default())?, vec![key]);
+/// assert!(store.has(&"modules/a/settings".parse()?)?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ScopedStore<'a> {
+    store: &'a KeyValueStore,
+    prefix: Scope,
+}
+
+impl KeyValueStore {
+    /// Returns a view on the keys of this store beneath `prefix`.
+    pub fn scoped(&self, prefix: Scope) -> ScopedStore<'_> {
+        ScopedStore {
+            store: self,
+            prefix,
+        }
+    }
+}
+
+impl ScopedStore<'_> {
+    /// Returns the scope that is prepended to all keys and scopes.
+    pub fn prefix(&self) -> &Scope {
+        &self.prefix
+    }
+
+    fn full_key(&self, key: &Key) -> Key {
+        // Every key is in the global scope, so this always succeeds.
+        key.rebase(&Scope::global(), &self.prefix)
+            .unwrap_or_else(|| key.clone())
+    }
+
+    fn full_keys(&self, keys: &[Key]) -> Vec<Key> {
+        keys.iter().map(|key| self.full_key(key)).collect()
+    }
+
+    fn full_scope(&self, scope: &Scope) -> Scope {
+        self.prefix
+            .clone()
+            .into_iter()
+            .chain(scope.clone())
+            .collect()
+    }
+
+    fn relative_key(&self, key: &Key) -> Option<Key> {
+        key.rebase(&self.prefix, &Scope::global())
+    }
+
+    fn relative_keys(&self, keys: Vec<Key>) -> Vec<Key> {
+        keys.iter()
+            .filter_map(|key| self.relative_key(key))
+            .collect()
+    }
+
+    fn relative_scopes(&self, scopes: Vec<Scope>) -> Vec<Scope> {
+        scopes
+            .iter()
+            .filter_map(|scope| scope.strip_prefix(&self.prefix))
+            .filter(|scope| !scope.is_global())
+            .collect()
+    }
+}
+
+impl ReadStore for ScopedStore<'_> {
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.store.count_keys(&self.prefix)? == 0)
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        self.store.has(&self.full_key(key))
+    }
+
+    fn has_each(&self, keys: &[Key]) -> Result<Vec<bool>> {
+        self.store.has_each(&self.full_keys(keys))
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        self.store.has_scope(&self.full_scope(scope))
+    }
+
+    fn has_value(&self, key: &Key, value: &Value) -> Result<bool> {
+        self.store.has_value(&self.full_key(key), value)
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        self.store.get(&self.full_key(key))
+    }
+
+    fn get_raw(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.store.get_raw(&self.full_key(key))
+    }
+
+    fn get_bytes(&self, key: &Key) -> Result<Option<Vec<u8>>> {
+        self.store.get_bytes(&self.full_key(key))
+    }
+
+    fn get_many(&self, keys: &[Key]) -> Result<Vec<Option<Value>>> {
+        self.store.get_many(&self.full_keys(keys))
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        let keys = self.store.list_keys(&self.full_scope(scope))?;
+        Ok(self.relative_keys(keys))
+    }
+
+    fn list_entries(&self, scope: &Scope) -> Result<Vec<(Key, Value)>> {
+        Ok(self
+            .store
+            .list_entries(&self.full_scope(scope))?
+            .into_iter()
+            .filter_map(|(key, value)| Some((self.relative_key(&key)?, value)))
+            .collect())
+    }
+
+    fn list_keys_matching_ci(&self, scope: &Scope, name: &Segment) -> Result<Vec<Key>> {
+        let keys = self
+            .store
+            .list_keys_matching_ci(&self.full_scope(scope), name)?;
+        Ok(self.relative_keys(keys))
+    }
+
+    fn stream_keys(&self, scope: &Scope) -> Result<Box<dyn Iterator<Item = Result<Key>> + '_>> {
+        let keys = self.store.stream_keys(&self.full_scope(scope))?;
+        Ok(Box::new(keys.filter_map(|key| match key {
+            Ok(key) => self.relative_key(&key).map(Ok),
+            Err(e) => Some(Err(e)),
+        })))
+    }
+
+    fn count_keys(&self, scope: &Scope) -> Result<usize> {
+        self.store.count_keys(&self.full_scope(scope))
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        let scopes = self.store.list_scopes_under(&self.prefix)?;
+        Ok(self.relative_scopes(scopes))
+    }
+
+    fn list_scopes_under(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let scopes = self.store.list_scopes_under(&self.full_scope(parent))?;
+        Ok(self.relative_scopes(scopes))
+    }
+
+    fn list_child_scopes(&self, parent: &Scope) -> Result<Vec<Scope>> {
+        let scopes = self.store.list_child_scopes(&self.full_scope(parent))?;
+        Ok(self.relative_scopes(scopes))
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        Ok(self
+            .store
+            .changes_since(since)?
+            .into_iter()
+            .filter_map(|(key, kind)| Some((self.relative_key(&key)?, kind)))
+            .collect())
+    }
+
+    fn find_keys_where_value(
+        &self,
+        scope: &Scope,
+        json_path: &str,
+        equals: &Value,
+    ) -> Result<Vec<Key>> {
+        let keys = self
+            .store
+            .find_keys_where_value(&self.full_scope(scope), json_path, equals)?;
+        Ok(self.relative_keys(keys))
+    }
+}
+
+impl WriteStore for ScopedStore<'_> {
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.store.store(&self.full_key(key), value)
+    }
+
+    fn store_many(&self, entries: &[(Key, Value)]) -> Result<()> {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(key, value)| (self.full_key(key), value.clone()))
+            .collect();
+        self.store.store_many(&entries)
+    }
+
+    fn store_bytes(&self, key: &Key, bytes: &[u8]) -> Result<()> {
+        self.store.store_bytes(&self.full_key(key), bytes)
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.store
+            .store_with_expiry(&self.full_key(key), value, expires_at)
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        self.store.purge_expired()
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        self.store.prune_changes(before)
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.store
+            .compare_and_swap(&self.full_key(key), expected, new)
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.store
+            .move_value(&self.full_key(from), &self.full_key(to))
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.store
+            .copy_value(&self.full_key(from), &self.full_key(to))
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.store
+            .move_scope(&self.full_scope(from), &self.full_scope(to))
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.store
+            .copy_scope(&self.full_scope(from), &self.full_scope(to))
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.store.delete(&self.full_key(key))
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.store.delete_existing(&self.full_key(key))
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.store.delete_scope(&self.full_scope(scope))
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.store.delete_scope_exact(&self.full_scope(scope))
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.store.clear_scope(&self.full_scope(scope))
+    }
+
+    fn clear(&self) -> Result<()> {
+        if self.prefix.is_global() {
+            self.store.clear()
+        } else {
+            self.store.clear_scope(&self.prefix)
+        }
+    }
+
+    fn migrate_namespace(&mut self, _to: NamespaceBuf) -> Result<()> {
+        Err(Error::Other(
+            "cannot migrate the namespace of a scoped store".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::{Key, KeyValueStore, Namespace, ReadStore, Scope, WriteStore};
+
+    #[test]
+    fn test_scoped_store() {
+        let store = KeyValueStore::new_isolated(Namespace::parse("test_scoped_store").unwrap());
+        let scoped = store.scoped("a/b".parse().unwrap());
+        let key: Key = "c/name".parse().unwrap();
+
+        store
+            .store(&"a/other".parse().unwrap(), Value::from(0))
+            .unwrap();
+        scoped.store(&key, Value::from(1)).unwrap();
+        scoped
+            .store(&"name".parse().unwrap(), Value::from(2))
+            .unwrap();
+
+        assert_eq!(
+            store.get(&"a/b/c/name".parse().unwrap()).unwrap(),
+            Some(Value::from(1))
+        );
+        assert_eq!(scoped.get(&key).unwrap(), Some(Value::from(1)));
+
+        let mut keys = scoped.list_keys(&Scope::global()).unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["name".parse().unwrap(), key.clone()]);
+        assert_eq!(scoped.list_scopes().unwrap(), vec!["c".parse().unwrap()]);
+        assert_eq!(scoped.count_keys(&"c".parse().unwrap()).unwrap(), 1);
+
+        scoped.move_value(&key, &"d/name".parse().unwrap()).unwrap();
+        assert!(store.has(&"a/b/d/name".parse().unwrap()).unwrap());
+
+        scoped.clear().unwrap();
+        assert!(scoped.is_empty().unwrap());
+        assert!(store.has(&"a/other".parse().unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,395 @@
+//! Utilities for testing code that uses a [`KeyValueStore`].
+//!
+//! [`KeyValueStore`]: crate::KeyValueStore
+
+use std::{
+    fmt::Display,
+    ops::Deref,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use kvx_types::NamespaceBuf;
+use serde_json::Value;
+
+use crate::{
+    BackendKind, ChangeEvent, ChangeKind, Key, KeyValueStoreBackend, ReadStore,
+    ReadTransactionCallback, Result, Scope, TransactionCallback, WriteStore,
+};
+
+/// An operation performed on a [`RecordingStore`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RecordedOp {
+    IsEmpty,
+    Has(Key),
+    HasScope(Scope),
+    Get(Key),
+    ListKeys(Scope),
+    ListScopes,
+    ChangesSince(SystemTime),
+    Store(Key, Value),
+    StoreWithExpiry {
+        key: Key,
+        value: Value,
+        expires_at: SystemTime,
+    },
+    PurgeExpired,
+    PruneChanges {
+        before: SystemTime,
+    },
+    CompareAndSwap {
+        key: Key,
+        expected: Option<Value>,
+        new: Value,
+    },
+    MoveValue {
+        from: Key,
+        to: Key,
+    },
+    CopyValue {
+        from: Key,
+        to: Key,
+    },
+    MoveScope {
+        from: Scope,
+        to: Scope,
+    },
+    CopyScope {
+        from: Scope,
+        to: Scope,
+    },
+    Delete(Key),
+    DeleteExisting(Key),
+    DeleteScope(Scope),
+    DeleteScopeExact(Scope),
+    ClearScope(Scope),
+    Clear,
+    MigrateNamespace(NamespaceBuf),
+    Transaction(Scope),
+    ReadOnlyTransaction(Scope),
+    WatchScope(Scope),
+}
+
+/// Wraps a store and records all operations performed on it.
+///
+/// Operations performed inside a transaction are recorded after the
+/// [`RecordedOp::Transaction`] that contains them. Provided trait methods,
+/// such as [`ReadStore::has_each`], are recorded as the operations they are
+/// built on.
+///
+/// # Example
+/// ```
+/// use kvx::{
+///     testing::{RecordedOp, RecordingStore},
+///     Key, KeyValueStore, Namespace, WriteStore,
+/// };
+/// use serde_json::Value;
+/// use url::Url;
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let store = KeyValueStore::new(&Url::parse("memory://")?, Namespace::parse("recording")?)?;
+/// let recording = RecordingStore::new(&store);
+///
+/// let key: Key = "key".parse()?;
+/// recording.store(&key, Value::from(1))?;
+///
+/// assert_eq!(recording.recorded(), vec![RecordedOp::Store(key, Value::from(1))]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RecordingStore<S> {
+    inner: S,
+    log: Arc<Mutex<Vec<RecordedOp>>>,
+}
+
+impl<S> RecordingStore<S> {
+    /// Create a recording store that delegates to `inner`.
+    pub fn new(inner: S) -> Self {
+        RecordingStore {
+            inner,
+            log: Arc::default(),
+        }
+    }
+
+    /// Returns all operations recorded so far, in order.
+    pub fn recorded(&self) -> Vec<RecordedOp> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Removes all recorded operations.
+    pub fn reset(&self) {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn record(&self, op: RecordedOp) {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).push(op);
+    }
+}
+
+impl<S> Display for RecordingStore<S>
+where
+    S: Deref,
+    S::Target: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Recording({})", self.inner.deref())
+    }
+}
+
+impl<S> ReadStore for RecordingStore<S>
+where
+    S: Deref,
+    S::Target: ReadStore,
+{
+    fn is_empty(&self) -> Result<bool> {
+        self.record(RecordedOp::IsEmpty);
+        self.inner.is_empty()
+    }
+
+    fn has(&self, key: &Key) -> Result<bool> {
+        self.record(RecordedOp::Has(key.clone()));
+        self.inner.has(key)
+    }
+
+    fn has_scope(&self, scope: &Scope) -> Result<bool> {
+        self.record(RecordedOp::HasScope(scope.clone()));
+        self.inner.has_scope(scope)
+    }
+
+    fn get(&self, key: &Key) -> Result<Option<Value>> {
+        self.record(RecordedOp::Get(key.clone()));
+        self.inner.get(key)
+    }
+
+    fn list_keys(&self, scope: &Scope) -> Result<Vec<Key>> {
+        self.record(RecordedOp::ListKeys(scope.clone()));
+        self.inner.list_keys(scope)
+    }
+
+    fn list_scopes(&self) -> Result<Vec<Scope>> {
+        self.record(RecordedOp::ListScopes);
+        self.inner.list_scopes()
+    }
+
+    fn changes_since(&self, since: SystemTime) -> Result<Vec<(Key, ChangeKind)>> {
+        self.record(RecordedOp::ChangesSince(since));
+        self.inner.changes_since(since)
+    }
+}
+
+impl<S> WriteStore for RecordingStore<S>
+where
+    S: Deref,
+    S::Target: WriteStore,
+{
+    fn store(&self, key: &Key, value: Value) -> Result<()> {
+        self.record(RecordedOp::Store(key.clone(), value.clone()));
+        self.inner.store(key, value)
+    }
+
+    fn store_with_expiry(&self, key: &Key, value: Value, expires_at: SystemTime) -> Result<()> {
+        self.record(RecordedOp::StoreWithExpiry {
+            key: key.clone(),
+            value: value.clone(),
+            expires_at,
+        });
+        self.inner.store_with_expiry(key, value, expires_at)
+    }
+
+    fn purge_expired(&self) -> Result<usize> {
+        self.record(RecordedOp::PurgeExpired);
+        self.inner.purge_expired()
+    }
+
+    fn prune_changes(&self, before: SystemTime) -> Result<usize> {
+        self.record(RecordedOp::PruneChanges { before });
+        self.inner.prune_changes(before)
+    }
+
+    fn compare_and_swap(&self, key: &Key, expected: Option<&Value>, new: Value) -> Result<bool> {
+        self.record(RecordedOp::CompareAndSwap {
+            key: key.clone(),
+            expected: expected.cloned(),
+            new: new.clone(),
+        });
+        self.inner.compare_and_swap(key, expected, new)
+    }
+
+    fn move_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.record(RecordedOp::MoveValue {
+            from: from.clone(),
+            to: to.clone(),
+        });
+        self.inner.move_value(from, to)
+    }
+
+    fn copy_value(&self, from: &Key, to: &Key) -> Result<()> {
+        self.record(RecordedOp::CopyValue {
+            from: from.clone(),
+            to: to.clone(),
+        });
+        self.inner.copy_value(from, to)
+    }
+
+    fn move_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.record(RecordedOp::MoveScope {
+            from: from.clone(),
+            to: to.clone(),
+        });
+        self.inner.move_scope(from, to)
+    }
+
+    fn copy_scope(&self, from: &Scope, to: &Scope) -> Result<()> {
+        self.record(RecordedOp::CopyScope {
+            from: from.clone(),
+            to: to.clone(),
+        });
+        self.inner.copy_scope(from, to)
+    }
+
+    fn delete(&self, key: &Key) -> Result<()> {
+        self.record(RecordedOp::Delete(key.clone()));
+        self.inner.delete(key)
+    }
+
+    fn delete_existing(&self, key: &Key) -> Result<()> {
+        self.record(RecordedOp::DeleteExisting(key.clone()));
+        self.inner.delete_existing(key)
+    }
+
+    fn delete_scope(&self, scope: &Scope) -> Result<()> {
+        self.record(RecordedOp::DeleteScope(scope.clone()));
+        self.inner.delete_scope(scope)
+    }
+
+    fn delete_scope_exact(&self, scope: &Scope) -> Result<()> {
+        self.record(RecordedOp::DeleteScopeExact(scope.clone()));
+        self.inner.delete_scope_exact(scope)
+    }
+
+    fn clear_scope(&self, scope: &Scope) -> Result<()> {
+        self.record(RecordedOp::ClearScope(scope.clone()));
+        self.inner.clear_scope(scope)
+    }
+
+    fn clear(&self) -> Result<()> {
+        self.record(RecordedOp::Clear);
+        self.inner.clear()
+    }
+
+    /// Migrating the namespace requires exclusive access to the inner store,
+    /// which is not available through a shared reference. The operation is
+    /// recorded, but fails with [`Error::Other`].
+    ///
+    /// [`Error::Other`]: crate::Error::Other
+    fn migrate_namespace(&mut self, to: NamespaceBuf) -> Result<()> {
+        self.record(RecordedOp::MigrateNamespace(to));
+        Err(crate::Error::Other(
+            "cannot migrate the namespace of a recording store".to_string(),
+        ))
+    }
+}
+
+impl<S> KeyValueStoreBackend for RecordingStore<S>
+where
+    S: Deref,
+    S::Target: KeyValueStoreBackend,
+{
+    fn backend_kind(&self) -> BackendKind {
+        self.inner.backend_kind()
+    }
+
+    fn transaction(&self, scope: &Scope, callback: TransactionCallback) -> Result<()> {
+        self.record(RecordedOp::Transaction(scope.clone()));
+        self.inner.transaction(scope, &mut |store| {
+            let recording = RecordingStore {
+                inner: store,
+                log: self.log.clone(),
+            };
+            callback(&recording)
+        })
+    }
+
+    fn try_transaction(
+        &self,
+        scope: &Scope,
+        timeout: Duration,
+        callback: TransactionCallback,
+    ) -> Result<bool> {
+        self.record(RecordedOp::Transaction(scope.clone()));
+        self.inner.try_transaction(scope, timeout, &mut |store| {
+            let recording = RecordingStore {
+                inner: store,
+                log: self.log.clone(),
+            };
+            callback(&recording)
+        })
+    }
+
+    fn read_only_transaction(
+        &self,
+        scope: &Scope,
+        callback: ReadTransactionCallback,
+    ) -> Result<()> {
+        self.record(RecordedOp::ReadOnlyTransaction(scope.clone()));
+        self.inner.read_only_transaction(scope, &mut |store| {
+            let recording = RecordingStore {
+                inner: store,
+                log: self.log.clone(),
+            };
+            callback(&recording)
+        })
+    }
+
+    fn watch_scope(&self, scope: &Scope) -> Result<Receiver<ChangeEvent>> {
+        self.record(RecordedOp::WatchScope(scope.clone()));
+        self.inner.watch_scope(scope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use url::Url;
+
+    use super::{RecordedOp, RecordingStore};
+    use crate::{Key, KeyValueStore, KeyValueStoreBackend, Namespace, ReadStore, WriteStore};
+
+    #[test]
+    fn test_recording_store() {
+        let store = KeyValueStore::new(
+            &Url::parse("memory://").unwrap(),
+            Namespace::parse("test_recording_store").unwrap(),
+        )
+        .unwrap();
+        store.clear().unwrap();
+
+        let recording = RecordingStore::new(&store);
+        let key: Key = "scope/key".parse().unwrap();
+        let other: Key = "scope/other".parse().unwrap();
+
+        recording.store(&key, Value::from(1)).unwrap();
+        assert_eq!(recording.get(&key).unwrap(), Some(Value::from(1)));
+        recording
+            .transaction(key.scope(), &mut |t| t.move_value(&key, &other))
+            .unwrap();
+        recording.delete(&other).unwrap();
+
+        assert_eq!(
+            recording.recorded(),
+            vec![
+                RecordedOp::Store(key.clone(), Value::from(1)),
+                RecordedOp::Get(key.clone()),
+                RecordedOp::Transaction(key.scope().clone()),
+                RecordedOp::MoveValue {
+                    from: key,
+                    to: other.clone()
+                },
+                RecordedOp::Delete(other),
+            ]
+        );
+
+        recording.reset();
+        assert!(recording.recorded().is_empty());
+    }
+}
@@ -4,5 +4,17 @@ CREATE TABLE store (
   "scope" TEXT[] NOT NULL,
   "key" VARCHAR NOT NULL,
   "value" JSONB NOT NULL,
+  "created_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+  "updated_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
+  "expires_at" TIMESTAMPTZ,
+  PRIMARY KEY("namespace", "scope", "key")
+);
+
+DROP TABLE IF EXISTS store_tombstones;
+CREATE TABLE store_tombstones (
+  "namespace" VARCHAR NOT NULL,
+  "scope" TEXT[] NOT NULL,
+  "key" VARCHAR NOT NULL,
+  "deleted_at" TIMESTAMPTZ NOT NULL DEFAULT now(),
   PRIMARY KEY("namespace", "scope", "key")
 );